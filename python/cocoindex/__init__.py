@@ -3,10 +3,11 @@ Cocoindex is a framework for building and running indexing pipelines.
 """
 from . import functions, query, sources, storages, cli
 from .flow import FlowBuilder, DataScope, DataSlice, Flow, flow_def
-from .flow import EvaluateAndDumpOptions, GeneratedField
+from .flow import EvaluateAndDumpOptions, GeneratedField, FunctionCacheOptions, CachedFunctionSpec
 from .flow import update_all_flows, FlowLiveUpdater, FlowLiveUpdaterOptions
 from .llm import LlmSpec, LlmApiType
 from .index import VectorSimilarityMetric, VectorIndexDef, IndexOptions
+from .index import ReducerKind, Reducer, AggregationOptions
 from .auth_registry import AuthEntryReference, add_auth_entry, ref_auth_entry
 from .lib import *
 from ._engine import OpArgSchema
\ No newline at end of file
@@ -109,11 +109,25 @@ def drop(flow_name: tuple[str, ...], drop_all: bool):
 @click.option(
     "-q", "--quiet", is_flag=True, show_default=True, default=False,
     help="Avoid printing anything to the standard output, e.g. statistics.")
-def update(flow_name: str | None, live: bool, quiet: bool):
+@click.option(
+    "--dry-run", is_flag=True, show_default=True, default=False,
+    help="Only report changes to apply, without evaluating or writing anything.")
+@click.option(
+    "--dry-run-sample-size", type=int, show_default=True, default=0,
+    help="In dry-run mode, number of changed source rows to evaluate, to report changes on targets.")
+@click.option(
+    "--allow-mass-deletion", is_flag=True, show_default=True, default=False,
+    help="Delete rows gone from data sources even if it looks like a mass deletion, "
+         "e.g. a source returns no rows at all.")
+def update(flow_name: str | None, live: bool, quiet: bool, dry_run: bool, dry_run_sample_size: int,
+           allow_mass_deletion: bool):
     """
     Update the index to reflect the latest data from data sources.
     """
-    options = flow.FlowLiveUpdaterOptions(live_mode=live, print_stats=not quiet)
+    options = flow.FlowLiveUpdaterOptions(
+        live_mode=live, print_stats=not quiet,
+        dry_run=dry_run, dry_run_sample_size=dry_run_sample_size,
+        allow_mass_deletion=allow_mass_deletion)
     async def _update():
         if flow_name is None:
             await flow.update_all_flows(options)
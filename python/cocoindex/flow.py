@@ -9,7 +9,8 @@ import re
 import inspect
 import datetime
 
-from typing import Any, Callable, Sequence, TypeVar
+from contextlib import contextmanager
+from typing import Any, Callable, Iterator, Sequence, TypeVar
 from threading import Lock
 from enum import Enum
 from dataclasses import dataclass
@@ -120,6 +121,27 @@ class _DataSliceState:
         # TODO: We'll support this by an identity transformer or "aliasing" in the future.
         raise ValueError("DataSlice is already attached to a field")
 
+@dataclass
+class FunctionCacheOptions:
+    """
+    Options for memoized results of a function.
+    """
+    # Memoized results older than this are recomputed. Never expire if not set.
+    ttl: datetime.timedelta | None = None
+    # Results with JSON representation larger than this (in bytes) are not memoized.
+    max_value_size: int | None = None
+    # Memoize results in a cache shared by all source rows of the flow, instead of each row's own.
+    shared: bool = False
+
+@dataclass
+class CachedFunctionSpec:
+    """
+    A function spec with options for its memoized results.
+    Pass it to `DataSlice.transform()` in place of the function spec.
+    """
+    fn_spec: op.FunctionSpec
+    cache_options: FunctionCacheOptions
+
 class DataSlice:
     """A data slice represents a slice of data in a flow. It's readonly."""
 
@@ -140,24 +162,47 @@ class DataSlice:
             raise KeyError(field_name)
         return DataSlice(_DataSliceState(self._state.flow_builder_state, field_slice))
 
-    def row(self) -> DataScope:
+    def row(self, /, *, max_concurrency: int | None = None) -> DataScope:
         """
         Return a scope representing each entry of the collection.
+        `max_concurrency` limits the number of entries evaluated concurrently.
         """
-        row_scope = self._state.engine_data_slice.collection_entry_scope()
+        row_scope = self._state.engine_data_slice.collection_entry_scope(max_concurrency)
         return DataScope(self._state.flow_builder_state, row_scope)
 
-    def for_each(self, f: Callable[[DataScope], None]) -> None:
+    def for_each(self, f: Callable[[DataScope], None], /, *,
+                 max_concurrency: int | None = None) -> None:
         """
         Apply a function to each row of the collection.
         """
-        with self.row() as scope:
+        with self.row(max_concurrency=max_concurrency) as scope:
             f(scope)
 
-    def transform(self, fn_spec: op.FunctionSpec, *args, **kwargs) -> DataSlice:
+    def map(self, f: Callable[[DataScope], DataSlice], /, *,
+            where: Callable[[DataScope], DataSlice] | None = None) -> DataSlice:
+        """
+        Map each row of the collection into a value, skipping rows where `where` isn't true.
+        Basic values are gathered into a vector, and struct values into a list.
+        """
+        with self.row() as scope:
+            value = _data_slice_state(f(scope)).engine_data_slice
+            engine_filter = (_data_slice_state(where(scope)).engine_data_slice
+                             if where is not None else None)
+            return DataSlice(_DataSliceState(
+                self._state.flow_builder_state,
+                self._state.engine_data_slice.collection_mapping(value, engine_filter)))
+
+    def transform(self, fn_spec: op.FunctionSpec | CachedFunctionSpec, *args,
+                  **kwargs) -> DataSlice:
         """
         Apply a function to the data slice.
+        Wrap the function spec by `CachedFunctionSpec` to control how results are memoized,
+        for functions with caching enabled.
         """
+        cache_options = None
+        if isinstance(fn_spec, CachedFunctionSpec):
+            fn_spec, cache_options = fn_spec.fn_spec, fn_spec.cache_options
+
         transform_args: list[tuple[Any, str | None]]
         transform_args = [(self._state.engine_data_slice, None)]
         transform_args += [(self._state.flow_builder_state.get_data_slice(v), None) for v in args]
@@ -175,6 +220,7 @@ class DataSlice:
                     target_scope,
                     flow_builder_state.field_name_builder.build_name(
                         name, prefix=_to_snake_case(_spec_kind(fn_spec))+'_'),
+                    dump_engine_object(cache_options),
                 ))
 
     def call(self, func: Callable[[DataSlice], T]) -> T:
@@ -230,6 +276,78 @@ class DataScope:
             )
         )
 
+    def filter(self, condition: DataSlice) -> None:
+        """
+        Skip remaining operations on the scope if `condition` isn't true.
+        Fields defined by skipped operations are null.
+        """
+        self._flow_builder_state.engine_flow_builder.filter(
+            _data_slice_state(condition).engine_data_slice, self._engine_data_scope)
+
+    def switch(self, value: DataSlice) -> Switch:
+        """
+        Add a switch on the scope, to evaluate different operations based on `value`.
+        Cases must be added right after it.
+        """
+        op_name = self._flow_builder_state.engine_flow_builder.add_switch(
+            _data_slice_state(value).engine_data_slice, self._engine_data_scope)
+        return Switch(self._flow_builder_state, self._engine_data_scope, op_name)
+
+    @contextmanager
+    def if_(self, condition: DataSlice) -> Iterator[None]:
+        """
+        Operations on the scope within the context are only evaluated if `condition` is true.
+        """
+        with self.switch(condition).case(True):
+            yield
+
+class Switch:
+    """
+    A switch on a data scope. Operations on the scope within a case context are only evaluated
+    when the value matches the case, and ones within the default context when no case matches.
+    Fields defined by operations not evaluated are null.
+    """
+    _flow_builder_state: _FlowBuilderState
+    _engine_data_scope: _engine.DataScopeRef
+    _op_name: str
+
+    def __init__(self, flow_builder_state: _FlowBuilderState,
+                 data_scope: _engine.DataScopeRef, op_name: str):
+        self._flow_builder_state = flow_builder_state
+        self._engine_data_scope = data_scope
+        self._op_name = op_name
+
+    @contextmanager
+    def case(self, value: Any) -> Iterator[None]:
+        """
+        Operations on the scope within the context are only evaluated if the value matches `value`.
+        `value` cannot be None; use `default()` for operations evaluated when no case matches.
+        """
+        if value is None:
+            raise ValueError("Case value cannot be None, use `default()` instead")
+        with self._in_case(dump_engine_object(value)):
+            yield
+
+    @contextmanager
+    def default(self) -> Iterator[None]:
+        """
+        Operations on the scope within the context are only evaluated if no case matches.
+        """
+        with self._in_case(None):
+            yield
+
+    @contextmanager
+    def _in_case(self, value: Any) -> Iterator[None]:
+        engine_flow_builder = self._flow_builder_state.engine_flow_builder
+        if value is None:
+            engine_flow_builder.enter_switch_default(self._engine_data_scope, self._op_name)
+        else:
+            engine_flow_builder.enter_switch_case(self._engine_data_scope, self._op_name, value)
+        try:
+            yield
+        finally:
+            engine_flow_builder.exit_switch_case(self._engine_data_scope)
+
 class GeneratedField(Enum):
     """
     A generated field is automatically set by the engine.
@@ -267,14 +385,27 @@ class DataCollector:
         self._flow_builder_state.engine_flow_builder.collect(
             self._engine_data_collector, regular_kwargs, auto_uuid_field)
 
+    def collected_rows(self) -> DataSlice:
+        """
+        Return a data slice of all rows collected into the collector, as a collection.
+        It's only available within the scope where the collector is added, e.g. to transform rows collected
+        from children of a row. No more data can be collected into the collector afterwards.
+        """
+        return DataSlice(_DataSliceState(
+            self._flow_builder_state, self._engine_data_collector.collected_rows()))
+
     def export(self, name: str, target_spec: op.StorageSpec, /, *,
-              primary_key_fields: Sequence[str],
+              primary_key_fields: Sequence[str] | None = None,
               vector_indexes: Sequence[index.VectorIndexDef] = (),
               vector_index: Sequence[tuple[str, index.VectorSimilarityMetric]] = (),
-              setup_by_user: bool = False):
+              setup_by_user: bool = False,
+              aggregation: index.AggregationOptions | None = None):
         """
         Export the collected data to the specified target.
 
+        If `aggregation` is specified, collected rows are aggregated across source rows by its group-by fields,
+        which are also the primary key, so `primary_key_fields` can be omitted.
+
         `vector_index` is for backward compatibility only. Please use `vector_indexes` instead.
         """
         # For backward compatibility only.
@@ -282,13 +413,19 @@ class DataCollector:
             vector_indexes = [index.VectorIndexDef(field_name=field_name, metric=metric)
                              for field_name, metric in vector_index]
 
+        if primary_key_fields is None:
+            if aggregation is None:
+                raise ValueError("primary_key_fields is required unless aggregation is specified")
+            primary_key_fields = aggregation.group_by
+
         index_options = index.IndexOptions(
             primary_key_fields=primary_key_fields,
             vector_indexes=vector_indexes,
         )
         self._flow_builder_state.engine_flow_builder.export(
             name, _spec_kind(target_spec), dump_engine_object(target_spec),
-            dump_engine_object(index_options), self._engine_data_collector, setup_by_user)
+            dump_engine_object(index_options), self._engine_data_collector, setup_by_user,
+            dump_engine_object(aggregation))
 
     def declare(self, spec: op.DeclarationSpec):
         """
@@ -325,6 +462,12 @@ class _SourceRefreshOptions:
     Options for refreshing a source.
     """
     refresh_interval: datetime.timedelta | None = None
+    max_concurrent_rows: int | None = None
+    max_reads_per_second: int | None = None
+    max_row_retries: int | None = None
+    max_deletions: int | None = None
+    max_deletion_ratio: float | None = None
+    key_lease_ttl: datetime.timedelta | None = None
 
 class FlowBuilder:
     """
@@ -344,6 +487,12 @@ class FlowBuilder:
     def add_source(self, spec: op.SourceSpec, /, *,
             name: str | None = None,
             refresh_interval: datetime.timedelta | None = None,
+            max_concurrent_rows: int | None = None,
+            max_reads_per_second: int | None = None,
+            max_row_retries: int | None = None,
+            max_deletions: int | None = None,
+            max_deletion_ratio: float | None = None,
+            key_lease_ttl: datetime.timedelta | None = None,
         ) -> DataSlice:
         """
         Add a source to the flow.
@@ -356,11 +505,25 @@ class FlowBuilder:
                 target_scope,
                 self._state.field_name_builder.build_name(
                     name, prefix=_to_snake_case(_spec_kind(spec))+'_'),
-                dump_engine_object(_SourceRefreshOptions(refresh_interval=refresh_interval)),
+                dump_engine_object(_SourceRefreshOptions(
+                    refresh_interval=refresh_interval,
+                    max_concurrent_rows=max_concurrent_rows,
+                    max_reads_per_second=max_reads_per_second,
+                    max_row_retries=max_row_retries,
+                    max_deletions=max_deletions,
+                    max_deletion_ratio=max_deletion_ratio,
+                    key_lease_ttl=key_lease_ttl)),
             ),
             name
         )
 
+    def set_max_foreach_concurrency(self, max_concurrency: int | None) -> None:
+        """
+        Limit the number of transformations evaluated concurrently within rows of collections
+        (i.e. within `row()` or `for_each()`), across all source rows of the flow.
+        """
+        self._state.engine_flow_builder.set_max_foreach_concurrency(max_concurrency)
+
 @dataclass
 class FlowLiveUpdaterOptions:
     """
@@ -368,6 +531,9 @@ class FlowLiveUpdaterOptions:
     """
     live_mode: bool = True
     print_stats: bool = False
+    dry_run: bool = False
+    dry_run_sample_size: int = 0
+    allow_mass_deletion: bool = False
 
 class FlowLiveUpdater:
     """
@@ -418,6 +584,32 @@ class FlowLiveUpdater:
         """
         self._engine_live_updater.abort()
 
+    def pause(self) -> None:
+        """
+        Pause the live updater. No new source rows are started until it's resumed, while rows in progress are finished.
+        Internal states of the updater are kept.
+        """
+        self._engine_live_updater.pause()
+
+    def resume(self) -> None:
+        """
+        Resume the paused live updater.
+        """
+        self._engine_live_updater.resume()
+
+    async def drain(self) -> None:
+        """
+        Stop the live updater gracefully: no new source rows are started, and it returns after rows in progress are finished.
+        """
+        self._engine_live_updater.drain()
+        await self.wait()
+
+    def status(self) -> str:
+        """
+        Get the status of the live updater: `Running`, `Paused` or `Draining`.
+        """
+        return self._engine_live_updater.status()
+
     def update_stats(self) -> _engine.IndexUpdateInfo:
         """
         Get the index update info.
@@ -464,15 +656,57 @@ class Flow:
         """
         return self._lazy_engine_flow().name()
 
-    async def update(self) -> _engine.IndexUpdateInfo:
+    async def update(
+            self, dry_run: bool = False, dry_run_sample_size: int = 0,
+            allow_mass_deletion: bool = False) -> _engine.IndexUpdateInfo:
         """
         Update the index defined by the flow.
         Once the function returns, the indice is fresh up to the moment when the function is called.
+
+        If `dry_run` is True, only report changes to apply, without evaluating or writing anything.
+        Up to `dry_run_sample_size` changed source rows are evaluated to report changes on targets.
+
+        If `allow_mass_deletion` is True, rows gone from sources are deleted even if it looks like a mass deletion.
         """
-        updater = await FlowLiveUpdater.create(self, FlowLiveUpdaterOptions(live_mode=False))
+        updater = await FlowLiveUpdater.create(
+            self, FlowLiveUpdaterOptions(
+                live_mode=False, dry_run=dry_run, dry_run_sample_size=dry_run_sample_size,
+                allow_mass_deletion=allow_mass_deletion))
         await updater.wait()
         return updater.update_stats()
 
+    async def analyze_logic_changes(self) -> list[dict[str, Any]]:
+        """
+        Compare the current flow logic with the logic source rows were processed with.
+        Returns, for each source, the number of rows to be reprocessed, and the number of rows each changed op touches.
+        """
+        return await (await self.ainternal_flow()).analyze_logic_changes()
+
+    async def list_row_errors(self) -> list[dict[str, Any]]:
+        """
+        List source rows that kept failing after all retries, grouped by source.
+        """
+        return await (await self.ainternal_flow()).list_row_errors()
+
+    async def redrive_row_errors(self) -> _engine.IndexUpdateInfo:
+        """
+        Process source rows that kept failing again.
+        Rows processed successfully are removed from the errors.
+        """
+        return await (await self.ainternal_flow()).redrive_row_errors()
+
+    async def reprocess(
+            self, source_name: str, keys: list[Any] | None = None, *,
+            key_prefix: str | None = None, bypass_cache: bool = False) -> _engine.IndexUpdateInfo:
+        """
+        Process specific rows of a source again, regardless of whether they changed.
+        Rows are specified by their keys, or a prefix of string keys.
+        If `bypass_cache` is True, memoized function results are ignored and evaluated again.
+        """
+        options = {"keys": keys or [], "key_prefix": key_prefix, "bypass_cache": bypass_cache}
+        return await (await self.ainternal_flow()).reprocess_source_rows(
+            source_name, dump_engine_object(options))
+
     def evaluate_and_dump(self, options: EvaluateAndDumpOptions):
         """
         Evaluate the flow and dump flow outputs to files.
@@ -12,11 +12,19 @@ class SplitRecursively(op.FunctionSpec):
     """Split a document (in string) recursively."""
 
 class ExtractByLlm(op.FunctionSpec):
-    """Extract information from a text using a LLM."""
+    """Extract information from a text and/or an image using a LLM."""
 
     llm_spec: llm.LlmSpec
     output_type: type
     instruction: str | None = None
+    max_retries: int = 0
+
+class GenerateText(op.FunctionSpec):
+    """Generate a text using a LLM, with a prompt template interpolating named input fields."""
+
+    llm_spec: llm.LlmSpec
+    prompt: str
+    system_prompt: str | None = None
 
 class SentenceTransformerEmbed(op.FunctionSpec):
     """
@@ -21,3 +21,29 @@ class IndexOptions:
     """
     primary_key_fields: Sequence[str]
     vector_indexes: Sequence[VectorIndexDef] = ()
+
+class ReducerKind(Enum):
+    COUNT = "Count"
+    SUM = "Sum"
+    MIN = "Min"
+    MAX = "Max"
+    COLLECT_SET = "CollectSet"
+    LAST_BY_ORDINAL = "LastByOrdinal"
+
+@dataclass
+class Reducer:
+    """
+    Reduce a field of collected rows within a group into an output field.
+    `input_field` is required except for `COUNT`, which counts all rows if it's not specified.
+    """
+    output_field: str
+    kind: ReducerKind
+    input_field: str | None = None
+
+@dataclass
+class AggregationOptions:
+    """
+    Options to aggregate collected rows across source rows by group-by fields.
+    """
+    group_by: Sequence[str]
+    reducers: Sequence[Reducer] = ()
@@ -8,7 +8,7 @@ import functools
 import inspect
 
 from typing import Callable, Self
-from dataclasses import dataclass
+from dataclasses import dataclass, asdict
 
 from . import _engine
 from . import flow, query, cli
@@ -22,10 +22,37 @@ def _load_field(target: dict[str, str], name: str, env_name: str, required: bool
     else:
         target[name] = value
 
+@dataclass
+class TracingSettings:
+    """Settings for exporting tracing spans of the cocoindex library."""
+
+    # The endpoint of an OTLP/HTTP collector to export spans to, e.g. "http://localhost:4318/v1/traces".
+    otlp_endpoint: str | None = None
+
+    # The path of a file to append spans to, one JSON object per line.
+    file_path: str | None = None
+
+    # The service name reported to the OTLP collector.
+    service_name: str | None = None
+
+    @classmethod
+    def from_env(cls) -> Self | None:
+        """Load settings from environment variables. Returns None if no exporter is configured."""
+
+        kwargs: dict[str, str] = dict()
+        _load_field(kwargs, "otlp_endpoint", "COCOINDEX_TRACING_OTLP_ENDPOINT")
+        _load_field(kwargs, "file_path", "COCOINDEX_TRACING_FILE")
+        _load_field(kwargs, "service_name", "COCOINDEX_TRACING_SERVICE_NAME")
+
+        if "otlp_endpoint" not in kwargs and "file_path" not in kwargs:
+            return None
+        return cls(**kwargs)
+
 @dataclass
 class Settings:
     """Settings for the cocoindex library."""
     database_url: str
+    tracing: TracingSettings | None = None
 
     @classmethod
     def from_env(cls) -> Self:
@@ -34,12 +61,12 @@ class Settings:
         kwargs: dict[str, str] = dict()
         _load_field(kwargs, "database_url", "COCOINDEX_DATABASE_URL", required=True)
 
-        return cls(**kwargs)
+        return cls(**kwargs, tracing=TracingSettings.from_env())
 
 
 def init(settings: Settings):
     """Initialize the cocoindex library."""
-    _engine.init(settings.__dict__)
+    _engine.init(asdict(settings))
 
 @dataclass
 class ServerSettings:
@@ -5,6 +5,8 @@ class LlmApiType(Enum):
     """The type of LLM API to use."""
     OPENAI = "OpenAi"
     OLLAMA = "Ollama"
+    ANTHROPIC = "Anthropic"
+    GEMINI = "Gemini"
 
 @dataclass
 class LlmSpec:
@@ -12,3 +14,6 @@ class LlmSpec:
     api_type: LlmApiType
     model: str
     address: str | None = None
+    max_requests_per_minute: int | None = None
+    max_tokens_per_minute: int | None = None
+    max_inflight_requests: int | None = None
@@ -263,6 +263,147 @@ impl JsonSchemaBuilder {
     }
 }
 
+fn json_value_kind(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "boolean",
+        serde_json::Value::Number(_) => "number",
+        serde_json::Value::String(_) => "string",
+        serde_json::Value::Array(_) => "array",
+        serde_json::Value::Object(_) => "object",
+    }
+}
+
+fn instance_type_name(instance_type: &InstanceType) -> &'static str {
+    match instance_type {
+        InstanceType::Null => "null",
+        InstanceType::Boolean => "boolean",
+        InstanceType::Object => "object",
+        InstanceType::Array => "array",
+        InstanceType::Number => "number",
+        InstanceType::String => "string",
+        InstanceType::Integer => "integer",
+    }
+}
+
+fn matches_instance_type(value: &serde_json::Value, instance_type: &InstanceType) -> bool {
+    match (instance_type, value) {
+        (InstanceType::Null, serde_json::Value::Null)
+        | (InstanceType::Boolean, serde_json::Value::Bool(_))
+        | (InstanceType::Object, serde_json::Value::Object(_))
+        | (InstanceType::Array, serde_json::Value::Array(_))
+        | (InstanceType::Number, serde_json::Value::Number(_))
+        | (InstanceType::String, serde_json::Value::String(_)) => true,
+        (InstanceType::Integer, serde_json::Value::Number(n)) => {
+            n.is_i64() || n.is_u64() || n.as_f64().is_some_and(|f| f.fract() == 0.0)
+        }
+        _ => false,
+    }
+}
+
+fn collect_validation_errors(
+    schema: &SchemaObject,
+    value: &serde_json::Value,
+    path: &str,
+    errors: &mut Vec<String>,
+) {
+    if let Some(instance_type) = &schema.instance_type {
+        let types: &[InstanceType] = match instance_type {
+            SingleOrVec::Single(t) => std::slice::from_ref(t.as_ref()),
+            SingleOrVec::Vec(t) => t.as_slice(),
+        };
+        if !types.iter().any(|t| matches_instance_type(value, t)) {
+            errors.push(format!(
+                "{path}: expected {}, got {}",
+                types.iter().map(instance_type_name).join(" or "),
+                json_value_kind(value)
+            ));
+            return;
+        }
+    }
+    match value {
+        serde_json::Value::Object(fields) => {
+            let Some(object) = &schema.object else {
+                return;
+            };
+            for required_field in object.required.iter() {
+                if !fields.contains_key(required_field) {
+                    errors.push(format!("{path}: missing required field `{required_field}`"));
+                }
+            }
+            for (name, field_value) in fields.iter() {
+                // Optional fields may be omitted or explicitly set to null.
+                if field_value.is_null() && !object.required.contains(name) {
+                    continue;
+                }
+                let field_path = format!("{path}.{name}");
+                match object.properties.get(name) {
+                    Some(Schema::Object(field_schema)) => {
+                        collect_validation_errors(field_schema, field_value, &field_path, errors)
+                    }
+                    Some(Schema::Bool(_)) => {}
+                    None => {
+                        if matches!(
+                            object.additional_properties.as_deref(),
+                            Some(Schema::Bool(false))
+                        ) {
+                            errors.push(format!("{field_path}: unexpected field"));
+                        }
+                    }
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            let Some(array) = &schema.array else {
+                return;
+            };
+            if let Some(min_items) = array.min_items {
+                if items.len() < min_items as usize {
+                    errors.push(format!(
+                        "{path}: expected at least {min_items} items, got {}",
+                        items.len()
+                    ));
+                }
+            }
+            if let Some(max_items) = array.max_items {
+                if items.len() > max_items as usize {
+                    errors.push(format!(
+                        "{path}: expected at most {max_items} items, got {}",
+                        items.len()
+                    ));
+                }
+            }
+            if let Some(SingleOrVec::Single(item_schema)) = &array.items {
+                if let Schema::Object(item_schema) = item_schema.as_ref() {
+                    for (i, item) in items.iter().enumerate() {
+                        collect_validation_errors(
+                            item_schema,
+                            item,
+                            &format!("{path}[{i}]"),
+                            errors,
+                        );
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Validate a JSON value against a JSON schema built by `build_json_schema()`.
+/// Only keywords emitted by the builder are checked.
+pub fn validate_json_value(schema: &SchemaObject, value: &serde_json::Value) -> Result<()> {
+    let mut errors = Vec::new();
+    collect_validation_errors(schema, value, "$", &mut errors);
+    if !errors.is_empty() {
+        bail!(
+            "JSON value doesn't match the schema:\n{}",
+            errors.iter().map(|e| format!("- {e}")).join("\n")
+        );
+    }
+    Ok(())
+}
+
 pub struct ValueExtractor {
     value_type: schema::ValueType,
     object_wrapper_field_name: Option<String>,
@@ -329,3 +470,135 @@ pub fn build_json_schema(
         },
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn field(name: &str, typ: schema::BasicValueType, nullable: bool) -> schema::FieldSchema {
+        schema::FieldSchema::new(
+            name,
+            schema::EnrichedValueType {
+                typ: schema::ValueType::Basic(typ),
+                nullable,
+                attrs: Default::default(),
+            },
+        )
+    }
+
+    fn person_schema(fields_always_required: bool) -> SchemaObject {
+        let struct_schema = schema::StructSchema {
+            fields: Arc::new(vec![
+                field("name", schema::BasicValueType::Str, false),
+                field("age", schema::BasicValueType::Int64, true),
+                field(
+                    "embedding",
+                    schema::BasicValueType::Vector(schema::VectorTypeSchema {
+                        element_type: Box::new(schema::BasicValueType::Float32),
+                        dimension: Some(2),
+                    }),
+                    true,
+                ),
+            ]),
+            description: None,
+        };
+        let output = build_json_schema(
+            schema::EnrichedValueType {
+                typ: schema::ValueType::Struct(struct_schema),
+                nullable: false,
+                attrs: Default::default(),
+            },
+            ToJsonSchemaOptions {
+                fields_always_required,
+                supports_format: true,
+                extract_descriptions: false,
+                top_level_must_be_object: true,
+            },
+        )
+        .unwrap();
+        output.schema
+    }
+
+    fn validation_error(schema: &SchemaObject, value: serde_json::Value) -> String {
+        validate_json_value(schema, &value).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn valid_values() {
+        for fields_always_required in [false, true] {
+            let schema = person_schema(fields_always_required);
+            validate_json_value(
+                &schema,
+                &json!({"name": "Alice", "age": 30, "embedding": [0.5, 1.0]}),
+            )
+            .unwrap();
+            validate_json_value(
+                &schema,
+                &json!({"name": "Bob", "age": null, "embedding": null}),
+            )
+            .unwrap();
+        }
+        validate_json_value(&person_schema(false), &json!({"name": "Carol"})).unwrap();
+    }
+
+    #[test]
+    fn missing_required_field() {
+        let error = validation_error(&person_schema(false), json!({"age": 30}));
+        assert!(
+            error.contains("$: missing required field `name`"),
+            "{error}"
+        );
+
+        let error = validation_error(&person_schema(true), json!({"name": "Alice"}));
+        assert!(error.contains("$: missing required field `age`"), "{error}");
+    }
+
+    #[test]
+    fn mismatched_types() {
+        let error = validation_error(&person_schema(false), json!({"name": 1, "age": "30"}));
+        assert!(
+            error.contains("$.name: expected string, got number"),
+            "{error}"
+        );
+        assert!(
+            error.contains("$.age: expected integer, got string"),
+            "{error}"
+        );
+
+        let error = validation_error(&person_schema(false), json!(["Alice"]));
+        assert!(error.contains("$: expected object, got array"), "{error}");
+    }
+
+    #[test]
+    fn unexpected_field() {
+        let error = validation_error(
+            &person_schema(false),
+            json!({"name": "Alice", "nickname": "Al"}),
+        );
+        assert!(error.contains("$.nickname: unexpected field"), "{error}");
+    }
+
+    #[test]
+    fn array_length_and_items() {
+        let schema = person_schema(false);
+        let error = validation_error(&schema, json!({"name": "Alice", "embedding": [0.5]}));
+        assert!(
+            error.contains("$.embedding: expected at least 2 items, got 1"),
+            "{error}"
+        );
+        let error = validation_error(
+            &schema,
+            json!({"name": "Alice", "embedding": [0.5, 1.0, 1.5]}),
+        );
+        assert!(
+            error.contains("$.embedding: expected at most 2 items, got 3"),
+            "{error}"
+        );
+        let error = validation_error(&schema, json!({"name": "Alice", "embedding": [0.5, "x"]}));
+        assert!(
+            error.contains("$.embedding[1]: expected number, got string"),
+            "{error}"
+        );
+    }
+}
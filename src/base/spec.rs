@@ -89,10 +89,28 @@ pub struct ConstantMapping {
     pub value: serde_json::Value,
 }
 
+/// Map each row of a collection field into a value, giving a new collection.
+/// Rows are collected into a `Vector` if the mapped values are basic values, or a `List` if they're structs.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CollectionMapping {
     pub field: FieldMapping,
+    /// Name of the scope of each row, which `value` and `filter` refer to.
     pub scope_name: ScopeName,
+    pub value: Box<ValueMapping>,
+    /// If specified, only rows with the value being true are kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<Box<ValueMapping>>,
+}
+
+/// Rows collected into a collector of a scope, as a collection.
+/// Only available within the scope itself, and no more rows can be collected into the collector after it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectorMapping {
+    /// If unspecified, means the current scope.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<ScopeName>,
+
+    pub collector_name: FieldName,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -106,7 +124,8 @@ pub enum ValueMapping {
     Constant(ConstantMapping),
     Field(FieldMapping),
     Struct(StructMapping),
-    // TODO: Add support for collections
+    Collection(CollectionMapping),
+    Collector(CollectorMapping),
 }
 
 impl ValueMapping {
@@ -142,6 +161,26 @@ impl std::fmt::Display for ValueMapping {
                     .collect::<Vec<_>>()
                     .join(", ")
             ),
+            ValueMapping::Collection(v) => {
+                write!(
+                    f,
+                    "Collection({}.{} AS {}: {}",
+                    v.field.scope.as_deref().unwrap_or(""),
+                    v.field.field_path,
+                    v.scope_name,
+                    v.value
+                )?;
+                if let Some(filter) = &v.filter {
+                    write!(f, " WHERE {}", filter)?;
+                }
+                write!(f, ")")
+            }
+            ValueMapping::Collector(v) => write!(
+                f,
+                "Collector({}.{})",
+                v.scope.as_deref().unwrap_or(""),
+                v.collector_name
+            ),
         }
     }
 }
@@ -165,6 +204,32 @@ pub struct OpSpec {
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct SourceRefreshOptions {
     pub refresh_interval: Option<std::time::Duration>,
+
+    /// Max number of source rows to process concurrently. Unlimited if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_rows: Option<usize>,
+
+    /// Max number of reads of row values from the source per second. Unlimited if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_reads_per_second: Option<u64>,
+
+    /// Max number of retries with backoff for a source row failed with transient errors, before it's recorded as an error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_row_retries: Option<usize>,
+
+    /// Max number of source rows a single scan is allowed to delete.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_deletions: Option<usize>,
+
+    /// Max ratio (between 0 and 1) of tracked source rows a single scan is allowed to delete.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_deletion_ratio: Option<f64>,
+
+    /// If set, a source row is leased through the internal storage while being processed,
+    /// so multiple worker processes can update the flow without processing the same row concurrently.
+    /// Leases not renewed within this duration expire, e.g. when the worker died.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_lease_ttl: Option<std::time::Duration>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -175,11 +240,31 @@ pub struct ImportOpSpec {
     pub refresh_options: SourceRefreshOptions,
 }
 
+/// Options for memoized results of a transform op.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct FunctionCacheOptions {
+    /// Cached results older than this are recomputed. Never expire if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<std::time::Duration>,
+
+    /// Results with JSON representation larger than this (in bytes) are not kept in the cache.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_value_size: Option<usize>,
+
+    /// If true, results are kept in a cache shared by all source rows of the flow,
+    /// so identical inputs from different rows are only evaluated once.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub shared: bool,
+}
+
 /// Transform data using a given operator.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransformOpSpec {
     pub inputs: Vec<OpArgBinding>,
     pub op: OpSpec,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<FunctionCacheOptions>,
 }
 
 /// Apply reactive operations to each row of the input field.
@@ -188,6 +273,10 @@ pub struct ForEachOpSpec {
     /// Mapping that provides a collection of rows to apply reactive operations to.
     pub field_path: FieldPath,
     pub op_scope: ReactiveOpScope,
+
+    /// Max number of rows in the collection to evaluate concurrently. Unlimited if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrency: Option<usize>,
 }
 
 /// Emit data to a given collector at the given scope.
@@ -204,6 +293,29 @@ pub struct CollectOpSpec {
     pub auto_uuid_field: Option<FieldName>,
 }
 
+/// Skip remaining ops of the scope (including ops in later ForEach ops on the same rows) if the condition isn't true.
+/// Fields defined by skipped ops are null.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilterOpSpec {
+    pub condition: ValueMapping,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwitchCaseSpec {
+    pub value: serde_json::Value,
+    pub ops: Vec<NamedSpec<ReactiveOpSpec>>,
+}
+
+/// Evaluate ops of the case matching the input value, or the default ops if no case matches.
+/// Ops of all cases work on the current scope. Fields defined by ops not evaluated are null.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwitchOpSpec {
+    pub input: ValueMapping,
+    pub cases: Vec<SwitchCaseSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub default_ops: Vec<NamedSpec<ReactiveOpSpec>>,
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum VectorSimilarityMetric {
     CosineSimilarity,
@@ -235,6 +347,46 @@ pub struct IndexOptions {
     pub vector_indexes: Vec<VectorIndexDef>,
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ReducerKind {
+    Count,
+    Sum,
+    Min,
+    Max,
+    CollectSet,
+    LastByOrdinal,
+}
+
+impl std::fmt::Display for ReducerKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReducerKind::Count => write!(f, "Count"),
+            ReducerKind::Sum => write!(f, "Sum"),
+            ReducerKind::Min => write!(f, "Min"),
+            ReducerKind::Max => write!(f, "Max"),
+            ReducerKind::CollectSet => write!(f, "CollectSet"),
+            ReducerKind::LastByOrdinal => write!(f, "LastByOrdinal"),
+        }
+    }
+}
+
+/// Reduce a field of collected rows within a group into an output field.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ReducerSpec {
+    pub output_field: FieldName,
+    pub kind: ReducerKind,
+    /// Required except for `Count`, which counts all rows if it's not specified, or rows with non-null values otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_field: Option<FieldName>,
+}
+
+/// Aggregate collected rows across all source rows by group-by fields, instead of exporting them one by one.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AggregationSpec {
+    pub group_by: Vec<FieldName>,
+    pub reducers: Vec<ReducerSpec>,
+}
+
 /// Store data to a given sink.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExportOpSpec {
@@ -242,6 +394,8 @@ pub struct ExportOpSpec {
     pub target: OpSpec,
     pub index_options: IndexOptions,
     pub setup_by_user: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregation: Option<AggregationSpec>,
 }
 
 /// A reactive operation reacts on given input values.
@@ -251,13 +405,16 @@ pub enum ReactiveOpSpec {
     Transform(TransformOpSpec),
     ForEach(ForEachOpSpec),
     Collect(CollectOpSpec),
+    Filter(FilterOpSpec),
+    Switch(SwitchOpSpec),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReactiveOpScope {
     pub name: ScopeName,
+    /// Collectors of the scope are declared by `Collect` ops with the scope name.
+    /// Rows collected into them can be read within the scope by `ValueMapping::Collector`.
     pub ops: Vec<NamedSpec<ReactiveOpSpec>>,
-    // TODO: Suport collectors
 }
 
 /// A flow defines the rule to sync data from given sources to given sinks with given transformations.
@@ -277,6 +434,10 @@ pub struct FlowInstanceSpec {
 
     #[serde(default = "Vec::new", skip_serializing_if = "Vec::is_empty")]
     pub declarations: Vec<OpSpec>,
+
+    /// Max number of transform ops evaluated concurrently within ForEach ops, across all source rows of the flow.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_foreach_concurrency: Option<usize>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -1,13 +1,13 @@
 use crate::prelude::*;
 
 use super::plan::*;
-use crate::execution::db_tracking_setup;
+use crate::execution::{db_tracking_setup, memoization::CacheEntryOptions};
 use crate::lib_context::get_auth_registry;
 use crate::setup::{
     self, DesiredMode, FlowSetupMetadata, FlowSetupState, ResourceIdentifier, SourceSetupState,
     TargetSetupState, TargetSetupStateCommon,
 };
-use crate::utils::fingerprint::Fingerprinter;
+use crate::utils::fingerprint::{Fingerprint, Fingerprinter};
 use crate::{
     base::{schema::*, spec::*},
     ops::{interface::*, registry::*},
@@ -133,6 +133,10 @@ impl TryFrom<&CollectionSchema> for CollectionSchemaBuilder {
                         .map(|c| (c.name.clone(), CollectorBuilder::new(c.spec.clone())))
                         .collect(),
                 ),
+                filtered: false,
+                conditional_depth: 0,
+                switch_cases: vec![],
+                field_switch_cases: HashMap::new(),
             })),
         })
     }
@@ -351,6 +355,28 @@ impl CollectorBuilder {
 pub(super) struct DataScopeBuilder {
     pub data: StructSchemaBuilder,
     pub collectors: Mutex<IndexMap<FieldName, CollectorBuilder>>,
+
+    /// Set once a Filter op is added to the scope, as remaining ops may be skipped for rows of the scope.
+    pub filtered: bool,
+    /// Number of enclosing conditional contexts being analyzed, e.g. Switch cases on the scope,
+    /// or ops on a parent scope after its Filter op.
+    pub conditional_depth: usize,
+    /// Cases of Switch ops on the scope being analyzed, outermost first.
+    pub switch_cases: SwitchCasePath,
+    /// Switch cases in which each field is defined, for fields defined within Switch cases.
+    field_switch_cases: HashMap<u32, Vec<SwitchCasePath>>,
+}
+
+/// Identifies a case of a Switch op by the op name and the case index, with `None` for the default case.
+pub(super) type SwitchCasePath = Vec<(String, Option<usize>)>;
+
+/// Whether ops in the two Switch cases are never evaluated together, i.e. they're in different cases of the same Switch op.
+fn are_exclusive_switch_cases(path1: &SwitchCasePath, path2: &SwitchCasePath) -> bool {
+    path1
+        .iter()
+        .zip(path2.iter())
+        .find(|(case1, case2)| case1 != case2)
+        .is_some_and(|((op_name1, _), (op_name2, _))| op_name1 == op_name2)
 }
 
 impl DataScopeBuilder {
@@ -358,9 +384,18 @@ impl DataScopeBuilder {
         Self {
             data: Default::default(),
             collectors: Default::default(),
+            filtered: false,
+            conditional_depth: 0,
+            switch_cases: vec![],
+            field_switch_cases: HashMap::new(),
         }
     }
 
+    /// Ops being added to the scope may be skipped, so fields added by them are nullable.
+    pub fn is_conditional(&self) -> bool {
+        self.filtered || self.conditional_depth > 0
+    }
+
     pub fn last_field(&self) -> Option<&FieldSchema<ValueTypeBuilder>> {
         self.data.fields.last()
     }
@@ -370,15 +405,68 @@ impl DataScopeBuilder {
         name: FieldName,
         value_type: &EnrichedValueType,
     ) -> Result<AnalyzedOpOutput> {
-        let field_index = self.data.add_field(FieldSchema {
-            name,
-            value_type: EnrichedValueType::from_alternative(value_type)?,
-        })?;
+        if let Some(field_idx) = self.try_share_switch_case_field(&name, value_type)? {
+            return Ok(AnalyzedOpOutput { field_idx });
+        }
+        let mut value_type = EnrichedValueType::from_alternative(value_type)?;
+        if self.is_conditional() {
+            value_type.nullable = true;
+        }
+        let field_index = self.data.add_field(FieldSchema { name, value_type })?;
+        if !self.switch_cases.is_empty() {
+            self.field_switch_cases
+                .insert(field_index, vec![self.switch_cases.clone()]);
+        }
         Ok(AnalyzedOpOutput {
             field_idx: field_index,
         })
     }
 
+    /// A field with the same name can be defined in different cases of a Switch op, as at most one of them is evaluated.
+    /// They share the same field, which is nullable. Returns the index of the existing field if it's shared.
+    fn try_share_switch_case_field(
+        &mut self,
+        name: &FieldName,
+        value_type: &EnrichedValueType,
+    ) -> Result<Option<u32>> {
+        let Some((field_idx, existing_field)) = self.data.find_field(name) else {
+            return Ok(None);
+        };
+        let Some(defined_cases) = self.field_switch_cases.get_mut(&field_idx) else {
+            return Ok(None);
+        };
+        if !defined_cases
+            .iter()
+            .all(|path| are_exclusive_switch_cases(path, &self.switch_cases))
+        {
+            return Ok(None);
+        }
+        let existing_type: EnrichedValueType =
+            EnrichedValueType::from_alternative(&existing_field.value_type)?;
+        if existing_type.typ.without_attrs() != value_type.typ.without_attrs() {
+            api_bail!(
+                "Field `{name}` is defined with incompatible types in different switch cases: {} vs {}",
+                existing_type.typ,
+                value_type.typ
+            );
+        }
+        defined_cases.push(self.switch_cases.clone());
+        // Only keep attributes shared by all definitions.
+        let field = &mut self.data.fields[field_idx as usize];
+        if field.value_type.attrs != value_type.attrs {
+            field.value_type.attrs = Arc::new(
+                field
+                    .value_type
+                    .attrs
+                    .iter()
+                    .filter(|(k, v)| value_type.attrs.get(*k) == Some(*v))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect(),
+            );
+        }
+        Ok(Some(field_idx))
+    }
+
     pub fn analyze_field_path<'a>(
         &'a self,
         field_path: &'_ FieldPath,
@@ -474,6 +562,8 @@ impl DataScopeBuilder {
 pub(super) struct AnalyzerContext<'a> {
     pub registry: &'a ExecutorFactoryRegistry,
     pub flow_ctx: &'a Arc<FlowInstanceContext>,
+    /// Shared by transform ops within ForEach ops of the flow.
+    pub foreach_concurrency_controller: Option<Arc<tokio::sync::Semaphore>>,
 }
 
 pub(super) struct ExecutionScope<'a> {
@@ -515,6 +605,56 @@ fn analyze_struct_mapping(
     ))
 }
 
+/// Type of the collection given by a collection mapping, from the type of values mapped from rows.
+/// Rows with null values are skipped.
+pub(super) fn collection_mapping_output_type(
+    row_value_type: &EnrichedValueType,
+) -> Result<(AnalyzedCollectionMappingOutput, EnrichedValueType)> {
+    let result = match &row_value_type.typ {
+        ValueType::Basic(basic_type) => (
+            AnalyzedCollectionMappingOutput::Vector,
+            EnrichedValueType {
+                typ: ValueType::Basic(BasicValueType::Vector(VectorTypeSchema {
+                    element_type: Box::new(basic_type.clone()),
+                    dimension: None,
+                })),
+                nullable: false,
+                attrs: Default::default(),
+            },
+        ),
+        ValueType::Struct(struct_type) => (
+            AnalyzedCollectionMappingOutput::List,
+            EnrichedValueType {
+                typ: ValueType::Collection(CollectionSchema::new(
+                    CollectionKind::List,
+                    struct_type.clone(),
+                )),
+                nullable: false,
+                attrs: Default::default(),
+            },
+        ),
+        ValueType::Collection(_) => {
+            api_bail!("Rows of a collection cannot be mapped into collections")
+        }
+    };
+    Ok(result)
+}
+
+/// Type of rows collected into a collector, when read as a value.
+pub(super) fn collector_value_type(collector_schema: &CollectorSchema) -> EnrichedValueType {
+    EnrichedValueType {
+        typ: ValueType::Collection(CollectionSchema::new(
+            CollectionKind::Collection,
+            StructSchema {
+                fields: Arc::new(collector_schema.fields.clone()),
+                description: None,
+            },
+        )),
+        nullable: false,
+        attrs: Default::default(),
+    }
+}
+
 fn analyze_value_mapping(
     value_mapping: &ValueMapping,
     scopes: RefList<'_, &'_ ExecutionScope<'_>>,
@@ -555,6 +695,82 @@ fn analyze_value_mapping(
                 },
             )
         }
+
+        ValueMapping::Collection(v) => {
+            let (scope_up_level, exec_scope) = match &v.field.scope {
+                Some(scope) => find_scope(scope, scopes)?,
+                None => (0, *scopes.head().ok_or_else(|| anyhow!("Scope not found"))?),
+            };
+            let (local_field_ref, value_type) =
+                exec_scope.data.analyze_field_path(&v.field.field_path)?;
+            let sub_scope = match &value_type.typ {
+                ValueTypeBuilder::Collection(collection_type) => &collection_type.sub_scope,
+                _ => api_bail!(
+                    "Collection mapping only works on collection, field {} is not",
+                    v.field.field_path
+                ),
+            };
+            // The sub scope is locked if it's being analyzed, i.e. the mapping is within a ForEach on the same collection.
+            let mut sub_scope = sub_scope.try_lock().map_err(|_| {
+                api_error!(
+                    "Collection mapping on field {} cannot be used within rows of the same collection",
+                    v.field.field_path
+                )
+            })?;
+            let row_scope = ExecutionScope {
+                name: &v.scope_name,
+                data: &mut sub_scope,
+            };
+            let row_scopes = scopes.prepend(&row_scope);
+            let (value, row_value_type) = analyze_value_mapping(&v.value, row_scopes)?;
+            let filter = match &v.filter {
+                Some(filter) => {
+                    let (filter, filter_type) = analyze_value_mapping(filter, row_scopes)?;
+                    if !matches!(filter_type.typ, ValueType::Basic(BasicValueType::Bool)) {
+                        api_bail!(
+                            "Filter of collection mapping must be a boolean, got {}",
+                            filter_type
+                        );
+                    }
+                    Some(Box::new(filter))
+                }
+                None => None,
+            };
+            let (output, mut output_type) = collection_mapping_output_type(&row_value_type)?;
+            output_type.nullable = value_type.nullable;
+            (
+                AnalyzedValueMapping::Collection(AnalyzedCollectionMapping {
+                    field: AnalyzedFieldReference {
+                        local: local_field_ref,
+                        scope_up_level,
+                    },
+                    value: Box::new(value),
+                    filter,
+                    output,
+                }),
+                output_type,
+            )
+        }
+
+        ValueMapping::Collector(v) => {
+            let (scope_up_level, exec_scope) = match &v.scope {
+                Some(scope) => find_scope(scope, scopes)?,
+                None => (0, *scopes.head().ok_or_else(|| anyhow!("Scope not found"))?),
+            };
+            if scope_up_level != 0 {
+                api_bail!(
+                    "Collector {} can only be read within its own scope {}",
+                    v.collector_name,
+                    exec_scope.name
+                );
+            }
+            let (local_collector_ref, collector_schema) =
+                exec_scope.data.consume_collector(&v.collector_name)?;
+            (
+                AnalyzedValueMapping::Collector(local_collector_ref),
+                collector_value_type(&collector_schema),
+            )
+        }
     };
     Ok(result)
 }
@@ -595,6 +811,134 @@ struct ExportDataFieldsInfo {
     primary_key_type: ValueType,
     value_fields_idx: Vec<u32>,
     value_stable: bool,
+    aggregation: Option<AnalyzedAggregation>,
+}
+
+/// Returns the analyzed reducer and the type of its output field.
+fn analyze_reducer(
+    reducer: &ReducerSpec,
+    collector_schema: &CollectorSchema,
+) -> Result<(AnalyzedReducer, EnrichedValueType)> {
+    let input = match &reducer.input_field {
+        Some(input_field) => {
+            let idx = collector_schema
+                .fields
+                .iter()
+                .position(|field| &field.name == input_field)
+                .ok_or_else(|| api_error!("field not found: {}", input_field))?;
+            Some((idx as u32, &collector_schema.fields[idx].value_type))
+        }
+        None => {
+            if reducer.kind != ReducerKind::Count {
+                api_bail!(
+                    "Input field must be specified for reducer {} of output field {}",
+                    reducer.kind,
+                    reducer.output_field
+                );
+            }
+            None
+        }
+    };
+    let input_basic_type = match input.map(|(_, t)| &t.typ) {
+        Some(ValueType::Basic(basic_type)) => Some(basic_type),
+        _ => None,
+    };
+    // `CollectSet` takes the union of elements for vector inputs, and of the only field for list inputs.
+    let collect_set_element_type = match input.map(|(_, t)| &t.typ) {
+        Some(ValueType::Basic(BasicValueType::Vector(vector_schema))) => {
+            Some(vector_schema.element_type.as_ref())
+        }
+        Some(ValueType::Collection(collection_schema))
+            if collection_schema.kind == CollectionKind::List
+                && collection_schema.row.fields.len() == 1 =>
+        {
+            match &collection_schema.row.fields[0].value_type.typ {
+                ValueType::Basic(basic_type) => Some(basic_type),
+                _ => None,
+            }
+        }
+        _ => input_basic_type,
+    };
+    let type_supported = match reducer.kind {
+        ReducerKind::Count | ReducerKind::LastByOrdinal => true,
+        ReducerKind::Sum => matches!(
+            input_basic_type,
+            Some(BasicValueType::Int64 | BasicValueType::Float32 | BasicValueType::Float64)
+        ),
+        ReducerKind::Min | ReducerKind::Max => matches!(
+            input_basic_type,
+            Some(
+                BasicValueType::Bytes
+                    | BasicValueType::Str
+                    | BasicValueType::Bool
+                    | BasicValueType::Int64
+                    | BasicValueType::Float32
+                    | BasicValueType::Float64
+                    | BasicValueType::Range
+                    | BasicValueType::Uuid
+                    | BasicValueType::Date
+                    | BasicValueType::Time
+                    | BasicValueType::LocalDateTime
+                    | BasicValueType::OffsetDateTime
+                    | BasicValueType::TimeDelta
+            )
+        ),
+        ReducerKind::CollectSet => matches!(
+            collect_set_element_type,
+            Some(
+                BasicValueType::Bytes
+                    | BasicValueType::Str
+                    | BasicValueType::Bool
+                    | BasicValueType::Int64
+                    | BasicValueType::Range
+                    | BasicValueType::Uuid
+                    | BasicValueType::Date
+            )
+        ),
+    };
+    if !type_supported {
+        api_bail!(
+            "Reducer {} doesn't support type of input field {}: {}",
+            reducer.kind,
+            reducer.input_field.as_deref().unwrap_or_default(),
+            input.map(|(_, t)| t.to_string()).unwrap_or_default()
+        );
+    }
+    let output_type = match (reducer.kind, input) {
+        (ReducerKind::Count, _) => EnrichedValueType {
+            typ: ValueType::Basic(BasicValueType::Int64),
+            nullable: false,
+            attrs: Default::default(),
+        },
+        (ReducerKind::CollectSet, Some(_)) => EnrichedValueType {
+            typ: ValueType::Basic(BasicValueType::Vector(VectorTypeSchema {
+                element_type: Box::new(collect_set_element_type.unwrap().clone()),
+                dimension: None,
+            })),
+            nullable: false,
+            attrs: Default::default(),
+        },
+        (ReducerKind::LastByOrdinal, Some((_, input_type))) => input_type.clone(),
+        // Sum, min and max are null if no row in the group has a non-null value.
+        (_, Some((_, input_type))) => EnrichedValueType {
+            nullable: true,
+            ..input_type.clone()
+        },
+        (_, None) => unreachable!(),
+    };
+    Ok((
+        AnalyzedReducer {
+            kind: reducer.kind,
+            input_field_idx: input.map(|(idx, _)| idx),
+            value_type: match reducer.kind {
+                ReducerKind::CollectSet => {
+                    collect_set_element_type.map(|t| ValueType::Basic(t.clone()))
+                }
+                _ => input.map(|(_, t)| t.typ.clone()),
+            },
+        },
+        output_type,
+    ))
 }
 
 impl AnalyzerContext<'_> {
@@ -615,6 +959,8 @@ impl AnalyzerContext<'_> {
                 ))
             }
         };
+        // Refresh options don't affect results of source rows.
+        let logic_fingerprinter = Fingerprinter::default().with(&import_op.spec.source)?;
         let (output_type, executor) = source_factory.build(
             serde_json::Value::Object(import_op.spec.source.spec),
             self.flow_ctx.clone(),
@@ -658,6 +1004,9 @@ impl AnalyzerContext<'_> {
             source_id
         });
 
+        let logic_fingerprint = logic_fingerprinter
+            .with(&output_type.without_attrs())?
+            .into_fingerprint();
         let op_name = import_op.name.clone();
         let output = scope.add_field(import_op.name, &output_type)?;
         let result_fut = async move {
@@ -676,6 +1025,7 @@ impl AnalyzerContext<'_> {
                     .clone(),
                 name: op_name,
                 refresh_options: import_op.spec.refresh_options,
+                logic_fingerprint,
             })
         };
         Ok(result_fut)
@@ -717,8 +1067,14 @@ impl AnalyzerContext<'_> {
                             .add_field(reactive_op.name.clone(), &output_type)?;
                         let reactive_op = reactive_op.clone();
                         let logic_fingerprinter = Fingerprinter::default()
-                            .with(&op.op)?
+                            .with(&logic_op_spec(&op.op, self.registry))?
                             .with(&output_type.without_attrs())?;
+                        let cache_spec = op.cache.clone().unwrap_or_default();
+                        let cache_options = CacheEntryOptions {
+                            ttl: cache_spec.ttl.map(chrono::Duration::from_std).transpose()?,
+                            max_value_size: cache_spec.max_value_size,
+                            shared: cache_spec.shared,
+                        };
                         async move {
                             trace!("Start building executor for transform op `{}`", reactive_op.name);
                             let executor = executor.await.with_context(|| {
@@ -733,6 +1089,7 @@ impl AnalyzerContext<'_> {
                                 fingerprinter: logic_fingerprinter
                                     .with(&behavior_version)?,
                                 output_type: output_type.typ.clone(),
+                                cache_options,
                             };
                             if function_exec_info.enable_cache
                                 && function_exec_info.behavior_version.is_none()
@@ -742,6 +1099,16 @@ impl AnalyzerContext<'_> {
                                     reactive_op.name
                                 );
                             }
+                            if !function_exec_info.enable_cache
+                                && (cache_options.ttl.is_some()
+                                    || cache_options.max_value_size.is_some()
+                                    || cache_options.shared)
+                            {
+                                api_bail!(
+                                    "Cache options are specified, but caching is not enabled for transform op: {}",
+                                    reactive_op.name
+                                );
+                            }
                             Ok(AnalyzedReactiveOp::Transform(AnalyzedTransformOp {
                                 name: reactive_op.name,
                                 inputs: input_value_mappings,
@@ -770,22 +1137,35 @@ impl AnalyzerContext<'_> {
                         op.field_path
                     ),
                 };
+                let conditional = scope.data.is_conditional();
                 let op_scope_fut = {
                     let mut sub_scope = sub_scope.lock().unwrap();
                     let mut exec_scope = ExecutionScope {
                         name: &op.op_scope.name,
                         data: &mut sub_scope,
                     };
-                    self.analyze_op_scope(
+                    if conditional {
+                        exec_scope.data.conditional_depth += 1;
+                    }
+                    let op_scope_fut = self.analyze_op_scope(
                         &mut exec_scope,
                         &op.op_scope.ops,
                         parent_scopes.prepend(scope),
-                    )?
+                    );
+                    if conditional {
+                        exec_scope.data.conditional_depth -= 1;
+                    }
+                    op_scope_fut?
                 };
                 let op_name = reactive_op.name.clone();
+                let max_concurrency = op.max_concurrency;
+                if max_concurrency == Some(0) {
+                    api_bail!("`max_concurrency` of ForEach op `{op_name}` must be positive");
+                }
                 async move {
                     Ok(AnalyzedReactiveOp::ForEach(AnalyzedForEachOp {
                         local_field_ref,
+                        max_concurrency,
                         op_scope: op_scope_fut
                             .await
                             .with_context(|| format!("Analyzing foreach op: {op_name}"))?,
@@ -814,6 +1194,102 @@ impl AnalyzerContext<'_> {
                 });
                 async move { Ok(collect_op) }.boxed()
             }
+
+            ReactiveOpSpec::Filter(op) => {
+                let (condition, condition_type) =
+                    analyze_value_mapping(&op.condition, parent_scopes.prepend(scope))?;
+                if !matches!(condition_type.typ, ValueType::Basic(BasicValueType::Bool)) {
+                    api_bail!(
+                        "Condition of filter op `{}` must be a boolean, got {}",
+                        reactive_op.name,
+                        condition_type
+                    );
+                }
+                scope.data.filtered = true;
+                let filter_op = AnalyzedReactiveOp::Filter(AnalyzedFilterOp {
+                    name: reactive_op.name.clone(),
+                    condition,
+                });
+                async move { Ok(filter_op) }.boxed()
+            }
+
+            ReactiveOpSpec::Switch(op) => {
+                let (input, input_type) =
+                    analyze_value_mapping(&op.input, parent_scopes.prepend(scope))?;
+                if !matches!(
+                    input_type.typ,
+                    ValueType::Basic(
+                        BasicValueType::Bytes
+                            | BasicValueType::Str
+                            | BasicValueType::Bool
+                            | BasicValueType::Int64
+                            | BasicValueType::Uuid
+                            | BasicValueType::Date
+                    )
+                ) {
+                    api_bail!(
+                        "Input of switch op `{}` doesn't support type {}",
+                        reactive_op.name,
+                        input_type
+                    );
+                }
+                let case_values = op
+                    .cases
+                    .iter()
+                    .map(|case| {
+                        value::Value::<value::ScopeValue>::from_json(
+                            case.value.clone(),
+                            &input_type.typ,
+                        )?
+                        .into_key()
+                    })
+                    .collect::<Result<Vec<_>>>()
+                    .with_context(|| {
+                        format!("Invalid case values for switch op `{}`", reactive_op.name)
+                    })?;
+                if case_values.iter().collect::<HashSet<_>>().len() != case_values.len() {
+                    api_bail!("Duplicate case values for switch op `{}`", reactive_op.name);
+                }
+
+                scope.data.conditional_depth += 1;
+                let op_scope_futs = op
+                    .cases
+                    .iter()
+                    .enumerate()
+                    .map(|(i, case)| (Some(i), &case.ops))
+                    .chain(std::iter::once((None, &op.default_ops)))
+                    .map(|(case_idx, ops)| {
+                        scope
+                            .data
+                            .switch_cases
+                            .push((reactive_op.name.clone(), case_idx));
+                        let op_scope_fut = self.analyze_op_scope(scope, ops, parent_scopes);
+                        scope.data.switch_cases.pop();
+                        op_scope_fut
+                    })
+                    .collect::<Result<Vec<_>>>();
+                scope.data.conditional_depth -= 1;
+                let op_scope_futs = op_scope_futs?;
+
+                let op_name = reactive_op.name.clone();
+                async move {
+                    let mut op_scopes = try_join_all(op_scope_futs)
+                        .await
+                        .with_context(|| format!("Analyzing switch op: {op_name}"))?;
+                    let default_op_scope = op_scopes.pop().unwrap();
+                    Ok(AnalyzedReactiveOp::Switch(AnalyzedSwitchOp {
+                        name: op_name,
+                        input,
+                        cases: case_values
+                            .into_iter()
+                            .zip(op_scopes)
+                            .map(|(value, op_scope)| AnalyzedSwitchCase { value, op_scope })
+                            .collect(),
+                        default_op_scope,
+                    }))
+                }
+                .boxed()
+            }
         };
         Ok(result_fut)
     }
@@ -921,6 +1397,7 @@ impl AnalyzerContext<'_> {
                 primary_key_type: data_fields_info.primary_key_type,
                 value_fields: data_fields_info.value_fields_idx,
                 value_stable: data_fields_info.value_stable,
+                aggregation: data_fields_info.aggregation,
             })
         })
     }
@@ -940,69 +1417,113 @@ impl AnalyzerContext<'_> {
             let export_op = &flow_inst.export_ops[*idx];
             let (local_collector_ref, collector_schema) =
                 scope.consume_collector(&export_op.spec.collector_name)?;
-            let (key_fields_schema, value_fields_schema, data_collection_info) =
-                match &export_op.spec.index_options.primary_key_fields {
-                    Some(fields) => {
-                        let pk_fields_idx = fields
-                            .iter()
-                            .map(|f| {
-                                collector_schema
-                                    .fields
-                                    .iter()
-                                    .position(|field| &field.name == f)
-                                    .map(|idx| idx as u32)
-                                    .ok_or_else(|| anyhow!("field not found: {}", f))
-                            })
-                            .collect::<Result<Vec<_>>>()?;
-
-                        let key_fields_schema = pk_fields_idx
-                            .iter()
-                            .map(|idx| collector_schema.fields[*idx as usize].clone())
-                            .collect::<Vec<_>>();
-                        let primary_key_type = if pk_fields_idx.len() == 1 {
-                            key_fields_schema[0].value_type.typ.clone()
-                        } else {
-                            ValueType::Struct(StructSchema {
-                                fields: Arc::from(key_fields_schema.clone()),
-                                description: None,
-                            })
-                        };
-                        let mut value_fields_schema: Vec<FieldSchema> = vec![];
-                        let mut value_fields_idx = vec![];
-                        for (idx, field) in collector_schema.fields.iter().enumerate() {
-                            if !pk_fields_idx.contains(&(idx as u32)) {
-                                value_fields_schema.push(field.clone());
-                                value_fields_idx.push(idx as u32);
-                            }
+            let aggregation_spec = export_op.spec.aggregation.as_ref();
+            let pk_fields = match (
+                aggregation_spec,
+                &export_op.spec.index_options.primary_key_fields,
+            ) {
+                (Some(aggregation), Some(fields)) if fields != &aggregation.group_by => {
+                    api_bail!(
+                        "Primary key fields must be the same as group-by fields for aggregation: {} vs {}",
+                        fields.join(", "),
+                        aggregation.group_by.join(", ")
+                    )
+                }
+                (Some(aggregation), _) => {
+                    if aggregation.group_by.is_empty() {
+                        api_bail!("Group-by fields must be specified for aggregation")
+                    }
+                    &aggregation.group_by
+                }
+                (None, Some(fields)) => fields,
+                (None, None) => {
+                    // TODO: Support auto-generate primary key
+                    api_bail!("Primary key fields must be specified")
+                }
+            };
+            let pk_fields_idx = pk_fields
+                .iter()
+                .map(|f| {
+                    collector_schema
+                        .fields
+                        .iter()
+                        .position(|field| &field.name == f)
+                        .map(|idx| idx as u32)
+                        .ok_or_else(|| anyhow!("field not found: {}", f))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            let key_fields_schema = pk_fields_idx
+                .iter()
+                .map(|idx| collector_schema.fields[*idx as usize].clone())
+                .collect::<Vec<_>>();
+            let primary_key_type = if pk_fields_idx.len() == 1 {
+                key_fields_schema[0].value_type.typ.clone()
+            } else {
+                ValueType::Struct(StructSchema {
+                    fields: Arc::from(key_fields_schema.clone()),
+                    description: None,
+                })
+            };
+            let mut value_fields_schema: Vec<FieldSchema> = vec![];
+            let mut value_fields_idx = vec![];
+            let value_stable;
+            let aggregation = match aggregation_spec {
+                Some(aggregation) => {
+                    let mut reducers = Vec::with_capacity(aggregation.reducers.len());
+                    for reducer in aggregation.reducers.iter() {
+                        if pk_fields.contains(&reducer.output_field)
+                            || value_fields_schema
+                                .iter()
+                                .any(|f| f.name == reducer.output_field)
+                        {
+                            api_bail!("Duplicated output field: {}", reducer.output_field);
                         }
-                        let value_stable = collector_schema
-                            .auto_uuid_field_idx
-                            .as_ref()
-                            .map(|uuid_idx| pk_fields_idx.contains(uuid_idx))
-                            .unwrap_or(false);
-                        (
-                            key_fields_schema,
-                            value_fields_schema,
-                            ExportDataFieldsInfo {
-                                local_collector_ref,
-                                primary_key_def: AnalyzedPrimaryKeyDef::Fields(pk_fields_idx),
-                                primary_key_type,
-                                value_fields_idx,
-                                value_stable,
-                            },
-                        )
+                        let (analyzed_reducer, output_type) =
+                            analyze_reducer(reducer, &collector_schema)?;
+                        reducers.push(analyzed_reducer);
+                        value_fields_schema
+                            .push(FieldSchema::new(&reducer.output_field, output_type));
                     }
-                    None => {
-                        // TODO: Support auto-generate primary key
-                        api_bail!("Primary key fields must be specified")
+                    value_stable = false;
+                    Some(AnalyzedAggregation {
+                        reducers,
+                        group_locks: Default::default(),
+                    })
+                }
+                None => {
+                    for (idx, field) in collector_schema.fields.iter().enumerate() {
+                        if !pk_fields_idx.contains(&(idx as u32)) {
+                            value_fields_schema.push(field.clone());
+                            value_fields_idx.push(idx as u32);
+                        }
                     }
-                };
+                    value_stable = collector_schema
+                        .auto_uuid_field_idx
+                        .as_ref()
+                        .map(|uuid_idx| pk_fields_idx.contains(uuid_idx))
+                        .unwrap_or(false);
+                    None
+                }
+            };
+            let index_options = IndexOptions {
+                primary_key_fields: Some(pk_fields.clone()),
+                ..export_op.spec.index_options.clone()
+            };
+            let data_collection_info = ExportDataFieldsInfo {
+                local_collector_ref,
+                primary_key_def: AnalyzedPrimaryKeyDef::Fields(pk_fields_idx),
+                primary_key_type,
+                value_fields_idx,
+                value_stable,
+                aggregation,
+            };
             collection_specs.push(interface::ExportDataCollectionSpec {
                 name: export_op.name.clone(),
                 spec: serde_json::Value::Object(export_op.spec.target.spec.clone()),
                 key_fields_schema,
                 value_fields_schema,
-                index_options: export_op.spec.index_options.clone(),
+                index_options,
             });
             data_fields_infos.push(data_collection_info);
         }
@@ -1050,15 +1571,285 @@ impl AnalyzerContext<'_> {
             .iter()
             .map(|reactive_op| self.analyze_reactive_op(scope, reactive_op, parent_scopes))
             .collect::<Result<Vec<_>>>()?;
+        // Only scopes within ForEach ops are limited.
+        let concurrency_controller = match parent_scopes {
+            RefList::Nil => None,
+            RefList::Cons(..) => self.foreach_concurrency_controller.clone(),
+        };
         let result_fut = async move {
             Ok(AnalyzedOpScope {
                 reactive_ops: try_join_all(op_futs).await?,
+                concurrency_controller,
             })
         };
         Ok(result_fut)
     }
 }
 
+/// The function op spec to fingerprint as its logic, without options the function reports as not affecting results.
+fn logic_op_spec(op: &OpSpec, registry: &ExecutorFactoryRegistry) -> OpSpec {
+    let mut op = op.clone();
+    if let Some(ExecutorFactory::SimpleFunction(factory)) = registry.get(&op.kind) {
+        factory.strip_non_logic_options(&mut op.spec);
+    }
+    op
+}
+
+/// Collects fingerprints of reactive ops' logic, keyed by op kind and name, prefixed by keys of enclosing ForEach and
+/// Switch ops. Each op's fingerprint also covers fingerprints of ops it depends on, i.e. ops producing its inputs and
+/// enclosing ForEach, Filter and Switch ops deciding whether it's evaluated. So fingerprints of changed ops and all ops
+/// downstream of them change, while removing an op nothing depends on leaves all fingerprints unchanged.
+/// Options not affecting results (e.g. concurrency, cache and rate limit options) are excluded,
+/// so changing them doesn't cause source rows to be reprocessed.
+struct OpLogicFingerprintsCollector<'a> {
+    registry: &'a ExecutorFactoryRegistry,
+    /// Fingerprints of ops producing each field, keyed by scope name and field name.
+    /// Multiple ops produce the same field if it's shared by cases of a Switch op, or on rows of a collection field.
+    field_deps: BTreeMap<(String, FieldName), Vec<Fingerprint>>,
+    /// Fingerprints of ops producing rows of each row scope, for fields not produced by ops in the scope.
+    scope_deps: HashMap<String, Vec<Fingerprint>>,
+    /// Fingerprints of ops collecting into each collector, keyed by scope name and collector name.
+    collector_deps: HashMap<(String, FieldName), Vec<Fingerprint>>,
+    result: BTreeMap<String, Fingerprint>,
+}
+
+impl<'a> OpLogicFingerprintsCollector<'a> {
+    fn new(registry: &'a ExecutorFactoryRegistry) -> Self {
+        Self {
+            registry,
+            field_deps: BTreeMap::new(),
+            scope_deps: HashMap::new(),
+            collector_deps: HashMap::new(),
+            result: BTreeMap::new(),
+        }
+    }
+
+    fn add_field_deps(&self, field: &FieldMapping, scope_name: &str, deps: &mut Vec<Fingerprint>) {
+        let scope_name = field.scope.as_deref().unwrap_or(scope_name);
+        match field.field_path.first() {
+            Some(field_name) => {
+                match self
+                    .field_deps
+                    .get(&(scope_name.to_string(), field_name.clone()))
+                {
+                    Some(field_deps) => deps.extend(field_deps.iter().copied()),
+                    None => deps.extend(self.scope_deps.get(scope_name).into_iter().flatten()),
+                }
+            }
+            // The entire scope.
+            None => {
+                deps.extend(self.scope_deps.get(scope_name).into_iter().flatten());
+                for ((field_scope_name, _), field_deps) in self.field_deps.iter() {
+                    if field_scope_name == scope_name {
+                        deps.extend(field_deps.iter().copied());
+                    }
+                }
+            }
+        }
+    }
+
+    fn add_value_mapping_deps(
+        &mut self,
+        mapping: &ValueMapping,
+        scope_name: &str,
+        deps: &mut Vec<Fingerprint>,
+    ) {
+        match mapping {
+            ValueMapping::Constant(_) => {}
+            ValueMapping::Field(field) => self.add_field_deps(field, scope_name, deps),
+            ValueMapping::Struct(mapping) => {
+                for field in mapping.fields.iter() {
+                    self.add_value_mapping_deps(&field.spec, scope_name, deps);
+                }
+            }
+            ValueMapping::Collection(mapping) => {
+                let mut row_deps = vec![];
+                self.add_field_deps(&mapping.field, scope_name, &mut row_deps);
+                deps.extend(row_deps.iter().copied());
+                self.scope_deps.insert(mapping.scope_name.clone(), row_deps);
+                self.add_value_mapping_deps(&mapping.value, &mapping.scope_name, deps);
+                if let Some(filter) = &mapping.filter {
+                    self.add_value_mapping_deps(filter, &mapping.scope_name, deps);
+                }
+            }
+            ValueMapping::Collector(mapping) => {
+                let scope_name = mapping.scope.as_deref().unwrap_or(scope_name);
+                deps.extend(
+                    self.collector_deps
+                        .get(&(scope_name.to_string(), mapping.collector_name.clone()))
+                        .into_iter()
+                        .flatten(),
+                );
+            }
+        }
+    }
+
+    /// `deps` are fingerprints of enclosing ops deciding whether ops in `ops` are evaluated.
+    fn collect_ops(
+        &mut self,
+        ops: &[NamedSpec<ReactiveOpSpec>],
+        scope_name: &str,
+        key_prefix: &str,
+        deps: &[Fingerprint],
+    ) -> Result<()> {
+        let mut scope_op_deps = deps.to_vec();
+        for op in ops.iter() {
+            let mut deps = scope_op_deps.clone();
+            match &op.spec {
+                ReactiveOpSpec::Transform(op_spec) => {
+                    for input in op_spec.inputs.iter() {
+                        self.add_value_mapping_deps(&input.value, scope_name, &mut deps);
+                    }
+                    let fingerprint = Fingerprinter::default()
+                        .with(&op_spec.inputs)?
+                        .with(&logic_op_spec(&op_spec.op, self.registry))?
+                        .with(&deps)?
+                        .into_fingerprint();
+                    self.result
+                        .insert(format!("{key_prefix}transform:{}", op.name), fingerprint);
+                    self.field_deps
+                        .entry((scope_name.to_string(), op.name.clone()))
+                        .or_default()
+                        .push(fingerprint);
+                }
+                ReactiveOpSpec::ForEach(op_spec) => {
+                    let key = format!("{key_prefix}foreach:{}", op.name);
+                    let field_name = op_spec.field_path.first().ok_or_else(|| {
+                        api_error!("ForEach op `{}` has empty field path", op.name)
+                    })?;
+                    self.add_field_deps(
+                        &FieldMapping {
+                            scope: None,
+                            field_path: op_spec.field_path.clone(),
+                        },
+                        scope_name,
+                        &mut deps,
+                    );
+                    let fingerprint = Fingerprinter::default()
+                        .with(&op_spec.field_path)?
+                        .with(&op_spec.op_scope.name)?
+                        .with(&deps)?
+                        .into_fingerprint();
+                    self.scope_deps
+                        .insert(op_spec.op_scope.name.clone(), vec![fingerprint]);
+                    let row_ops_prefix = format!("{key}/");
+                    self.collect_ops(
+                        &op_spec.op_scope.ops,
+                        &op_spec.op_scope.name,
+                        &row_ops_prefix,
+                        &[fingerprint],
+                    )?;
+                    // The collection field is updated by ops on its rows.
+                    let row_op_fingerprints = self
+                        .result
+                        .range(row_ops_prefix.clone()..)
+                        .take_while(|(k, _)| k.starts_with(&row_ops_prefix))
+                        .map(|(_, fp)| *fp)
+                        .collect::<Vec<_>>();
+                    let field_deps = self
+                        .field_deps
+                        .entry((scope_name.to_string(), field_name.clone()))
+                        .or_default();
+                    field_deps.push(fingerprint);
+                    field_deps.extend(row_op_fingerprints);
+                    self.result.insert(key, fingerprint);
+                }
+                ReactiveOpSpec::Collect(op_spec) => {
+                    for field in op_spec.input.fields.iter() {
+                        self.add_value_mapping_deps(&field.spec, scope_name, &mut deps);
+                    }
+                    let fingerprint = Fingerprinter::default()
+                        .with(op_spec)?
+                        .with(&deps)?
+                        .into_fingerprint();
+                    self.result
+                        .insert(format!("{key_prefix}collect:{}", op.name), fingerprint);
+                    self.collector_deps
+                        .entry((op_spec.scope_name.clone(), op_spec.collector_name.clone()))
+                        .or_default()
+                        .push(fingerprint);
+                }
+                ReactiveOpSpec::Filter(op_spec) => {
+                    self.add_value_mapping_deps(&op_spec.condition, scope_name, &mut deps);
+                    let fingerprint = Fingerprinter::default()
+                        .with(op_spec)?
+                        .with(&deps)?
+                        .into_fingerprint();
+                    self.result
+                        .insert(format!("{key_prefix}filter:{}", op.name), fingerprint);
+                    // Remaining ops of the scope are only evaluated if the condition is true.
+                    scope_op_deps.push(fingerprint);
+                }
+                ReactiveOpSpec::Switch(op_spec) => {
+                    let key = format!("{key_prefix}switch:{}", op.name);
+                    self.add_value_mapping_deps(&op_spec.input, scope_name, &mut deps);
+                    let mut fingerprinter = Fingerprinter::default().with(&op_spec.input)?;
+                    for case in op_spec.cases.iter() {
+                        fingerprinter = fingerprinter.with(&case.value)?;
+                    }
+                    let fingerprint = fingerprinter.with(&deps)?.into_fingerprint();
+                    for (i, case) in op_spec.cases.iter().enumerate() {
+                        self.collect_ops(
+                            &case.ops,
+                            scope_name,
+                            &format!("{key}/case:{i}/"),
+                            &[fingerprint],
+                        )?;
+                    }
+                    self.collect_ops(
+                        &op_spec.default_ops,
+                        scope_name,
+                        &format!("{key}/default/"),
+                        &[fingerprint],
+                    )?;
+                    self.result.insert(key, fingerprint);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fingerprints of logic of all reactive ops and export ops of the flow. See `ExecutionPlan::op_logic_fingerprints`.
+/// Each collector exported has its own entry as well, covering ops collecting into it, so the fingerprint of the export
+/// op itself only changes with its own logic.
+fn analyze_op_logic_fingerprints(
+    flow_inst: &FlowInstanceSpec,
+    registry: &ExecutorFactoryRegistry,
+) -> Result<BTreeMap<String, Fingerprint>> {
+    let mut collector = OpLogicFingerprintsCollector::new(registry);
+    collector.collect_ops(&flow_inst.reactive_ops, ROOT_SCOPE_NAME, "", &[])?;
+    let mut result = std::mem::take(&mut collector.result);
+    for export_op in flow_inst.export_ops.iter() {
+        // Vector indexes and setup options don't affect exported rows.
+        let mut fingerprinter = Fingerprinter::default()
+            .with(&export_op.spec.collector_name)?
+            .with(&export_op.spec.target)?
+            .with(&export_op.spec.index_options.primary_key_fields)?;
+        if let Some(aggregation) = &export_op.spec.aggregation {
+            fingerprinter = fingerprinter.with(aggregation)?;
+        }
+        let fingerprint = fingerprinter.into_fingerprint();
+        result.insert(export_op_logic_key(&export_op.name), fingerprint);
+
+        let collect_op_fingerprints = collector
+            .collector_deps
+            .get(&(
+                ROOT_SCOPE_NAME.to_string(),
+                export_op.spec.collector_name.clone(),
+            ))
+            .map(|fps| fps.as_slice())
+            .unwrap_or_default();
+        result.insert(
+            format!("collector:{}", export_op.spec.collector_name),
+            Fingerprinter::default()
+                .with(&collect_op_fingerprints)?
+                .into_fingerprint(),
+        );
+    }
+    Ok(result)
+}
+
 pub fn build_flow_instance_context(
     flow_inst_name: &str,
     py_exec_ctx: Option<crate::py::PythonExecutionContext>,
@@ -1139,7 +1930,16 @@ pub fn analyze_flow(
         targets: IndexMap::new(),
     };
 
-    let analyzer_ctx = AnalyzerContext { registry, flow_ctx };
+    let foreach_concurrency_controller = match flow_inst.max_foreach_concurrency {
+        Some(0) => api_bail!("`max_foreach_concurrency` must be positive"),
+        Some(n) => Some(Arc::new(tokio::sync::Semaphore::new(n))),
+        None => None,
+    };
+    let analyzer_ctx = AnalyzerContext {
+        registry,
+        flow_ctx,
+        foreach_concurrency_controller,
+    };
     let mut root_exec_scope = ExecutionScope {
         name: ROOT_SCOPE_NAME,
         data: &mut root_data_scope,
@@ -1210,10 +2010,8 @@ pub fn analyze_flow(
 
     let tracking_table_setup = setup_state.tracking_table.clone();
     let data_schema = root_data_scope.into_data_schema()?;
-    let logic_fingerprint = Fingerprinter::default()
-        .with(&flow_inst)?
-        .with(&data_schema)?
-        .into_fingerprint();
+    let op_logic_fingerprints = analyze_op_logic_fingerprints(flow_inst, registry)?;
+    let flow_name = flow_inst.name.clone();
     let plan_fut = async move {
         let (import_ops, op_scope, export_ops) = try_join3(
             try_join_all(import_ops_futs),
@@ -1223,8 +2021,9 @@ pub fn analyze_flow(
         .await?;
 
         Ok(ExecutionPlan {
+            flow_name,
             tracking_table_setup,
-            logic_fingerprint,
+            op_logic_fingerprints,
             import_ops,
             op_scope,
             export_ops,
@@ -1245,7 +2044,11 @@ pub fn analyze_transient_flow<'a>(
     impl Future<Output = Result<TransientExecutionPlan>> + Send + 'a,
 )> {
     let mut root_data_scope = DataScopeBuilder::new();
-    let analyzer_ctx = AnalyzerContext { registry, flow_ctx };
+    let analyzer_ctx = AnalyzerContext {
+        registry,
+        flow_ctx,
+        foreach_concurrency_controller: None,
+    };
     let mut input_fields = vec![];
     for field in flow_inst.input_fields.iter() {
         let analyzed_field = root_data_scope.add_field(field.name.clone(), &field.value_type)?;
@@ -1274,3 +2077,506 @@ pub fn analyze_transient_flow<'a>(
     };
     Ok((output_type, root_data_scope.into_data_schema()?, plan_fut))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value_type(typ: ValueType, nullable: bool) -> EnrichedValueType {
+        EnrichedValueType {
+            typ,
+            nullable,
+            attrs: Default::default(),
+        }
+    }
+
+    fn basic_field(name: &str, typ: BasicValueType) -> FieldSchema {
+        FieldSchema::new(name, value_type(ValueType::Basic(typ), false))
+    }
+
+    fn struct_schema(fields: Vec<FieldSchema>) -> StructSchema {
+        StructSchema {
+            fields: Arc::new(fields),
+            description: None,
+        }
+    }
+
+    fn tags_schema() -> StructSchema {
+        struct_schema(vec![basic_field("tag", BasicValueType::Str)])
+    }
+
+    /// Root fields: title, docs (table of `id`, `text`, `score` and `tags`), and a nullable list of tags.
+    fn root_data_scope() -> DataScopeBuilder {
+        let docs_schema = CollectionSchema::new(
+            CollectionKind::Table,
+            struct_schema(vec![
+                basic_field("id", BasicValueType::Str),
+                basic_field("text", BasicValueType::Str),
+                basic_field("score", BasicValueType::Float64),
+                FieldSchema::new(
+                    "tags",
+                    value_type(
+                        ValueType::Collection(CollectionSchema::new(
+                            CollectionKind::List,
+                            tags_schema(),
+                        )),
+                        false,
+                    ),
+                ),
+            ]),
+        );
+        let mut scope = DataScopeBuilder::new();
+        scope
+            .add_field(
+                "title".to_string(),
+                &value_type(ValueType::Basic(BasicValueType::Str), false),
+            )
+            .unwrap();
+        scope
+            .add_field(
+                "docs".to_string(),
+                &value_type(ValueType::Collection(docs_schema), false),
+            )
+            .unwrap();
+        scope
+            .add_field(
+                "tags".to_string(),
+                &value_type(
+                    ValueType::Collection(CollectionSchema::new(
+                        CollectionKind::List,
+                        tags_schema(),
+                    )),
+                    true,
+                ),
+            )
+            .unwrap();
+        scope
+    }
+
+    fn field(scope: Option<&str>, path: &[&str]) -> ValueMapping {
+        ValueMapping::Field(FieldMapping {
+            scope: scope.map(|s| s.to_string()),
+            field_path: FieldPath(path.iter().map(|f| f.to_string()).collect()),
+        })
+    }
+
+    fn collection(path: &str, value: ValueMapping, filter: Option<ValueMapping>) -> ValueMapping {
+        ValueMapping::Collection(CollectionMapping {
+            field: FieldMapping {
+                scope: None,
+                field_path: FieldPath(vec![path.to_string()]),
+            },
+            scope_name: "_row".to_string(),
+            value: Box::new(value),
+            filter: filter.map(Box::new),
+        })
+    }
+
+    fn analyze(mapping: &ValueMapping) -> Result<(AnalyzedCollectionMapping, EnrichedValueType)> {
+        let mut root_data_scope = root_data_scope();
+        let root_scope = ExecutionScope {
+            name: ROOT_SCOPE_NAME,
+            data: &mut root_data_scope,
+        };
+        match analyze_value_mapping(mapping, RefList::Nil.prepend(&root_scope))? {
+            (AnalyzedValueMapping::Collection(mapping), value_type) => Ok((mapping, value_type)),
+            (mapping, _) => bail!("Unexpected mapping: {mapping:?}"),
+        }
+    }
+
+    #[test]
+    fn collection_mapping_of_basic_values_outputs_vector() {
+        let (mapping, output_type) =
+            analyze(&collection("docs", field(None, &["score"]), None)).unwrap();
+        assert!(matches!(
+            mapping.output,
+            AnalyzedCollectionMappingOutput::Vector
+        ));
+        assert_eq!(mapping.field.scope_up_level, 0);
+        assert_eq!(mapping.field.local.fields_idx, vec![1]);
+        assert!(mapping.filter.is_none());
+        assert_eq!(
+            output_type,
+            value_type(
+                ValueType::Basic(BasicValueType::Vector(VectorTypeSchema {
+                    element_type: Box::new(BasicValueType::Float64),
+                    dimension: None,
+                })),
+                false
+            )
+        );
+    }
+
+    #[test]
+    fn collection_mapping_of_structs_outputs_list() {
+        let mapping = collection(
+            "tags",
+            ValueMapping::Struct(StructMapping {
+                fields: vec![
+                    NamedSpec {
+                        name: "tag".to_string(),
+                        spec: field(None, &["tag"]),
+                    },
+                    NamedSpec {
+                        name: "title".to_string(),
+                        spec: field(Some(ROOT_SCOPE_NAME), &["title"]),
+                    },
+                ],
+            }),
+            None,
+        );
+        let (mapping, output_type) = analyze(&mapping).unwrap();
+        assert!(matches!(
+            mapping.output,
+            AnalyzedCollectionMappingOutput::List
+        ));
+        match mapping.value.as_ref() {
+            AnalyzedValueMapping::Struct(value) => {
+                assert!(matches!(
+                    value.fields.as_slice(),
+                    [
+                        AnalyzedValueMapping::Field(AnalyzedFieldReference {
+                            scope_up_level: 0,
+                            ..
+                        }),
+                        AnalyzedValueMapping::Field(AnalyzedFieldReference {
+                            scope_up_level: 1,
+                            ..
+                        }),
+                    ]
+                ));
+            }
+            value => panic!("Unexpected value mapping: {value:?}"),
+        }
+        // Nullability follows the mapped collection.
+        assert_eq!(
+            output_type,
+            value_type(
+                ValueType::Collection(CollectionSchema::new(
+                    CollectionKind::List,
+                    struct_schema(vec![
+                        basic_field("tag", BasicValueType::Str),
+                        basic_field("title", BasicValueType::Str),
+                    ]),
+                )),
+                true
+            )
+        );
+    }
+
+    #[test]
+    fn collection_mapping_with_filter() {
+        let filter = ValueMapping::Constant(ConstantMapping {
+            schema: value_type(ValueType::Basic(BasicValueType::Bool), false),
+            value: serde_json::Value::Bool(true),
+        });
+        let (mapping, _) =
+            analyze(&collection("docs", field(None, &["text"]), Some(filter))).unwrap();
+        assert!(mapping.filter.is_some());
+
+        let err = analyze(&collection(
+            "docs",
+            field(None, &["text"]),
+            Some(field(None, &["score"])),
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("must be a boolean"), "{err}");
+    }
+
+    #[test]
+    fn collection_mapping_errors() {
+        let err = analyze(&collection("title", field(None, &["text"]), None)).unwrap_err();
+        assert!(
+            err.to_string().contains("only works on collection"),
+            "{err}"
+        );
+
+        let err = analyze(&collection("docs", field(None, &["tags"]), None)).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("cannot be mapped into collections"),
+            "{err}"
+        );
+    }
+
+    fn add_field_in_switch_case(
+        scope: &mut DataScopeBuilder,
+        switch_cases: &[(&str, Option<usize>)],
+        name: &str,
+        value_type: &EnrichedValueType,
+    ) -> Result<u32> {
+        scope.switch_cases = switch_cases
+            .iter()
+            .map(|(op_name, case_idx)| (op_name.to_string(), *case_idx))
+            .collect();
+        scope.conditional_depth = switch_cases.len();
+        let output = scope.add_field(name.to_string(), value_type);
+        scope.switch_cases.clear();
+        scope.conditional_depth = 0;
+        Ok(output?.field_idx)
+    }
+
+    #[test]
+    fn field_shared_across_switch_cases() {
+        let str_type = value_type(ValueType::Basic(BasicValueType::Str), false);
+        let mut scope = root_data_scope();
+        let field_idx =
+            add_field_in_switch_case(&mut scope, &[("switch", Some(0))], "out", &str_type).unwrap();
+        assert_eq!(
+            add_field_in_switch_case(&mut scope, &[("switch", Some(1))], "out", &str_type).unwrap(),
+            field_idx
+        );
+        assert_eq!(
+            add_field_in_switch_case(
+                &mut scope,
+                &[("switch", None), ("inner", Some(0))],
+                "out",
+                &str_type
+            )
+            .unwrap(),
+            field_idx
+        );
+        let (_, field) = scope.data.find_field("out").unwrap();
+        assert!(field.value_type.nullable);
+        assert_eq!(scope.data.fields.len(), 4);
+    }
+
+    #[test]
+    fn field_shared_across_switch_cases_keeps_common_attrs() {
+        let mut scope = root_data_scope();
+        let mut str_type = value_type(ValueType::Basic(BasicValueType::Str), false);
+        str_type.attrs = Arc::new(BTreeMap::from([
+            ("a".to_string(), serde_json::json!(1)),
+            ("b".to_string(), serde_json::json!(2)),
+        ]));
+        add_field_in_switch_case(&mut scope, &[("switch", Some(0))], "out", &str_type).unwrap();
+        str_type.attrs = Arc::new(BTreeMap::from([
+            ("a".to_string(), serde_json::json!(1)),
+            ("b".to_string(), serde_json::json!(3)),
+        ]));
+        add_field_in_switch_case(&mut scope, &[("switch", Some(1))], "out", &str_type).unwrap();
+        let (_, field) = scope.data.find_field("out").unwrap();
+        assert_eq!(field.value_type.attrs.keys().collect::<Vec<_>>(), vec!["a"]);
+    }
+
+    #[test]
+    fn field_not_shared_unless_switch_cases_exclusive() {
+        let str_type = value_type(ValueType::Basic(BasicValueType::Str), false);
+        let mut scope = root_data_scope();
+        add_field_in_switch_case(&mut scope, &[("switch", Some(0))], "out", &str_type).unwrap();
+
+        // Same case.
+        let err = add_field_in_switch_case(&mut scope, &[("switch", Some(0))], "out", &str_type)
+            .unwrap_err();
+        assert!(err.to_string().contains("already exists"), "{err}");
+        // Another switch op, evaluated together.
+        let err = add_field_in_switch_case(&mut scope, &[("other", Some(1))], "out", &str_type)
+            .unwrap_err();
+        assert!(err.to_string().contains("already exists"), "{err}");
+        // Outside of switch cases.
+        let err = add_field_in_switch_case(&mut scope, &[], "out", &str_type).unwrap_err();
+        assert!(err.to_string().contains("already exists"), "{err}");
+        // Fields not defined in switch cases are never shared.
+        let err = add_field_in_switch_case(&mut scope, &[("switch", Some(1))], "title", &str_type)
+            .unwrap_err();
+        assert!(err.to_string().contains("already exists"), "{err}");
+    }
+
+    #[test]
+    fn field_shared_across_switch_cases_with_incompatible_types() {
+        let mut scope = root_data_scope();
+        add_field_in_switch_case(
+            &mut scope,
+            &[("switch", Some(0))],
+            "out",
+            &value_type(ValueType::Basic(BasicValueType::Str), false),
+        )
+        .unwrap();
+        let err = add_field_in_switch_case(
+            &mut scope,
+            &[("switch", Some(1))],
+            "out",
+            &value_type(ValueType::Basic(BasicValueType::Int64), false),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("incompatible types"), "{err}");
+    }
+
+    fn transform_op(name: &str, input_field: &str, kind: &str) -> serde_json::Value {
+        serde_json::json!({
+            "name": name,
+            "action": "Transform",
+            "inputs": [{"kind": "Field", "field_path": [input_field]}],
+            "op": {"kind": kind},
+        })
+    }
+
+    fn collect_op(name: &str, collector_name: &str, fields: &[&str]) -> serde_json::Value {
+        serde_json::json!({
+            "name": name,
+            "action": "Collect",
+            "input": {"fields": fields.iter().map(|f| serde_json::json!({
+                "name": f, "kind": "Field", "field_path": [f],
+            })).collect::<Vec<_>>()},
+            "scope_name": ROOT_SCOPE_NAME,
+            "collector_name": collector_name,
+            "auto_uuid_field": null,
+        })
+    }
+
+    /// A flow splitting `content` from the source into chunks, embedding and exporting them, and exporting its summary.
+    /// `embed_ops` are ops on each chunk after embedding, and `root_ops` are additional ops on the root scope.
+    fn chunking_flow(
+        embed_kind: &str,
+        embed_ops: Vec<serde_json::Value>,
+        root_ops: Vec<serde_json::Value>,
+    ) -> FlowInstanceSpec {
+        let mut chunk_ops = vec![transform_op("embedding", "text", embed_kind)];
+        chunk_ops.extend(embed_ops);
+        chunk_ops.push(collect_op(
+            "collect_chunk",
+            "chunks_out",
+            &["text", "embedding"],
+        ));
+        let mut reactive_ops = vec![
+            transform_op("chunks", "content", "SplitText"),
+            serde_json::json!({
+                "name": "for_chunks",
+                "action": "ForEach",
+                "field_path": ["chunks"],
+                "op_scope": {"name": "_chunk", "ops": chunk_ops},
+            }),
+            transform_op("summary", "content", "Summarize"),
+        ];
+        reactive_ops.extend(root_ops);
+        let export_op = |name: &str, collector_name: &str| {
+            serde_json::json!({
+                "name": name,
+                "collector_name": collector_name,
+                "target": {"kind": "Postgres"},
+                "index_options": {},
+                "setup_by_user": false,
+            })
+        };
+        serde_json::from_value(serde_json::json!({
+            "name": "test_flow",
+            "reactive_ops": reactive_ops,
+            "export_ops": [
+                export_op("export_chunks", "chunks_out"),
+                export_op("export_summaries", "summaries_out"),
+            ],
+        }))
+        .unwrap()
+    }
+
+    fn collect_summary_op() -> serde_json::Value {
+        collect_op("collect_summary", "summaries_out", &["summary"])
+    }
+
+    fn changed_ops(old_flow: &FlowInstanceSpec, new_flow: &FlowInstanceSpec) -> Vec<String> {
+        let registry = ExecutorFactoryRegistry::new();
+        let old_fps = analyze_op_logic_fingerprints(old_flow, &registry).unwrap();
+        let new_fps = analyze_op_logic_fingerprints(new_flow, &registry).unwrap();
+        crate::execution::row_indexer::changed_op_logic(&old_fps, &new_fps)
+            .map(|op| op.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn op_logic_fingerprints_are_stable() {
+        let flow = chunking_flow("Embed", vec![], vec![collect_summary_op()]);
+        assert!(changed_ops(&flow, &flow).is_empty());
+        let registry = ExecutorFactoryRegistry::new();
+        let fps = analyze_op_logic_fingerprints(&flow, &registry).unwrap();
+        assert_eq!(
+            fps.keys().collect::<Vec<_>>(),
+            vec![
+                "collect:collect_summary",
+                "collector:chunks_out",
+                "collector:summaries_out",
+                "export:export_chunks",
+                "export:export_summaries",
+                "foreach:for_chunks",
+                "foreach:for_chunks/collect:collect_chunk",
+                "foreach:for_chunks/transform:embedding",
+                "transform:chunks",
+                "transform:summary",
+            ]
+        );
+    }
+
+    #[test]
+    fn op_logic_changes_cover_downstream_ops() {
+        let flow = chunking_flow("Embed", vec![], vec![collect_summary_op()]);
+        assert_eq!(
+            changed_ops(
+                &flow,
+                &chunking_flow("EmbedV2", vec![], vec![collect_summary_op()])
+            ),
+            vec![
+                "collector:chunks_out",
+                "foreach:for_chunks/collect:collect_chunk",
+                "foreach:for_chunks/transform:embedding",
+            ]
+        );
+
+        let mut split_changed = flow.clone();
+        split_changed.reactive_ops[0] =
+            serde_json::from_value(transform_op("chunks", "content", "SplitTextV2")).unwrap();
+        assert_eq!(
+            changed_ops(&flow, &split_changed),
+            vec![
+                "collector:chunks_out",
+                "foreach:for_chunks",
+                "foreach:for_chunks/collect:collect_chunk",
+                "foreach:for_chunks/transform:embedding",
+                "transform:chunks",
+            ]
+        );
+    }
+
+    #[test]
+    fn op_logic_changes_by_filter() {
+        let flow = chunking_flow("Embed", vec![], vec![collect_summary_op()]);
+        let filter_op = serde_json::json!({
+            "name": "filter",
+            "action": "Filter",
+            "condition": {"kind": "Field", "field_path": ["embedding"]},
+        });
+        // Ops before the filter are unaffected, while ones after it are only evaluated if the condition holds.
+        assert_eq!(
+            changed_ops(
+                &flow,
+                &chunking_flow("Embed", vec![filter_op], vec![collect_summary_op()])
+            ),
+            vec![
+                "collector:chunks_out",
+                "foreach:for_chunks/collect:collect_chunk",
+                "foreach:for_chunks/filter:filter",
+            ]
+        );
+    }
+
+    #[test]
+    fn op_logic_changes_by_removed_ops() {
+        let flow = chunking_flow(
+            "Embed",
+            vec![],
+            vec![
+                collect_summary_op(),
+                transform_op("unused", "summary", "Translate"),
+            ],
+        );
+        // Nothing depended on the removed op.
+        assert!(changed_ops(
+            &flow,
+            &chunking_flow("Embed", vec![], vec![collect_summary_op()])
+        )
+        .is_empty());
+        // Exported rows collected by the removed op are gone.
+        assert_eq!(
+            changed_ops(&flow, &chunking_flow("Embed", vec![], vec![])),
+            vec!["collector:summaries_out"]
+        );
+    }
+}
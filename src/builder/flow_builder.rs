@@ -23,12 +23,24 @@ use crate::{
 };
 use crate::{lib_context::FlowContext, py};
 
+/// A case of a Switch op being built on a scope. Ops added to the scope go into it.
+#[derive(Debug, Clone)]
+struct ActiveSwitchCase {
+    op_name: String,
+    /// None for default ops.
+    case_idx: Option<usize>,
+}
+
 #[derive(Debug)]
 pub struct DataScopeRefInfo {
     scope_name: String,
     parent: Option<(DataScopeRef, spec::FieldPath)>,
     scope_builder: Arc<Mutex<DataScopeBuilder>>,
     children: Mutex<HashMap<spec::FieldPath, Weak<DataScopeRefInfo>>>,
+    /// Max number of rows to evaluate concurrently for the ForEach op of this scope.
+    max_concurrency: Mutex<Option<usize>>,
+    /// Cases of (nested) Switch ops being built on this scope, outermost first.
+    active_switch_cases: Mutex<Vec<ActiveSwitchCase>>,
 }
 
 #[pyclass]
@@ -138,6 +150,8 @@ impl DataScopeRef {
             parent: Some((self.clone(), field_path.clone())),
             scope_builder,
             children: Mutex::new(HashMap::new()),
+            max_concurrency: Mutex::new(None),
+            active_switch_cases: Mutex::new(vec![]),
         }));
         Ok(new_scope)
     }
@@ -235,6 +249,12 @@ impl DataSlice {
                     "field access not supported for literal",
                 ))
             }
+
+            spec::ValueMapping::Collection(_) | spec::ValueMapping::Collector(_) => {
+                return Err(PyException::new_err(
+                    "field access not supported for collection",
+                ))
+            }
         };
         Ok(Some(DataSlice {
             scope: self.scope.clone(),
@@ -243,14 +263,76 @@ impl DataSlice {
         }))
     }
 
-    pub fn collection_entry_scope(&self) -> PyResult<DataScopeRef> {
+    #[pyo3(signature = (max_concurrency=None))]
+    pub fn collection_entry_scope(&self, max_concurrency: Option<usize>) -> PyResult<DataScopeRef> {
         let field_path = match self.value.as_ref() {
             spec::ValueMapping::Field(v) => &v.field_path,
             _ => return Err(PyException::new_err("expect field path")),
         };
-        self.scope
+        let scope = self
+            .scope
             .get_child_scope(field_path.clone())
-            .into_py_result()
+            .into_py_result()?;
+        if max_concurrency.is_some() {
+            *scope.max_concurrency.lock().unwrap() = max_concurrency;
+        }
+        Ok(scope)
+    }
+
+    /// Map each row of the collection into `value`, keeping only rows where `filter` is true.
+    #[pyo3(signature = (value, filter=None))]
+    pub fn collection_mapping(
+        &self,
+        value: &DataSlice,
+        filter: Option<&DataSlice>,
+    ) -> PyResult<DataSlice> {
+        let field_mapping = match self.value.as_ref() {
+            spec::ValueMapping::Field(v) => v,
+            _ => return Err(PyException::new_err("expect field path")),
+        };
+        let row_scope = self
+            .scope
+            .get_child_scope(field_mapping.field_path.clone())
+            .into_py_result()?;
+        for slice in std::iter::once(value).chain(filter) {
+            if !row_scope.is_ds_scope_descendant(&slice.scope) {
+                return Err(PyException::new_err(format!(
+                    "data slice {slice} is not available in rows of {self}"
+                )));
+            }
+        }
+        if let Some(filter) = filter {
+            if !matches!(
+                filter.data_type.schema.typ,
+                schema::ValueType::Basic(schema::BasicValueType::Bool)
+            ) {
+                return Err(PyException::new_err(format!(
+                    "filter must be of bool type, got {}",
+                    filter.data_type.schema
+                )));
+            }
+        }
+        let (_, mut data_type) =
+            super::analyzer::collection_mapping_output_type(&value.data_type.schema)
+                .into_py_result()?;
+        data_type.nullable = self.data_type.schema.nullable;
+        let value_mapping = spec::ValueMapping::Collection(spec::CollectionMapping {
+            field: spec::FieldMapping {
+                scope: field_mapping
+                    .scope
+                    .clone()
+                    .or_else(|| Some(self.scope.scope_name.clone())),
+                field_path: field_mapping.field_path.clone(),
+            },
+            scope_name: row_scope.scope_name.clone(),
+            value: Box::new(value.extract_value_mapping()),
+            filter: filter.map(|f| Box::new(f.extract_value_mapping())),
+        });
+        Ok(DataSlice {
+            scope: self.scope.clone(),
+            value: Arc::new(value_mapping),
+            data_type: data_type.into(),
+        })
     }
 }
 
@@ -296,6 +378,24 @@ impl DataCollector {
     fn __repr__(&self) -> String {
         self.__str__()
     }
+
+    /// Rows collected into the collector, as a collection within the collector's scope.
+    /// No more rows can be collected into the collector afterwards.
+    pub fn collected_rows(&self) -> PyResult<DataSlice> {
+        let mut collector = self.collector.lock().unwrap();
+        let collector = collector.as_mut().ok_or_else(|| {
+            PyException::new_err(format!("nothing is collected into collector {}", self.name))
+        })?;
+        let collector_schema = collector.use_schema();
+        Ok(DataSlice {
+            scope: self.scope.clone(),
+            value: Arc::new(spec::ValueMapping::Collector(spec::CollectorMapping {
+                scope: Some(self.scope.scope_name.clone()),
+                collector_name: self.name.clone(),
+            })),
+            data_type: super::analyzer::collector_value_type(&collector_schema).into(),
+        })
+    }
 }
 
 impl std::fmt::Display for DataCollector {
@@ -332,6 +432,7 @@ pub struct FlowBuilder {
     export_ops: Vec<NamedSpec<spec::ExportOpSpec>>,
 
     declarations: Vec<spec::OpSpec>,
+    max_foreach_concurrency: Option<usize>,
 
     next_generated_op_id: usize,
 }
@@ -360,6 +461,8 @@ impl FlowBuilder {
                 parent: None,
                 scope_builder: root_data_scope.clone(),
                 children: Mutex::new(HashMap::new()),
+                max_concurrency: Mutex::new(None),
+                active_switch_cases: Mutex::new(vec![]),
             })),
             root_data_scope,
             flow_instance_name: name.to_string(),
@@ -373,6 +476,7 @@ impl FlowBuilder {
             direct_output_value: None,
 
             declarations: vec![],
+            max_foreach_concurrency: None,
 
             next_generated_op_id: 0,
         };
@@ -383,6 +487,11 @@ impl FlowBuilder {
         self.root_data_scope_ref.clone()
     }
 
+    #[pyo3(signature = (max_concurrency=None))]
+    pub fn set_max_foreach_concurrency(&mut self, max_concurrency: Option<usize>) {
+        self.max_foreach_concurrency = max_concurrency;
+    }
+
     #[pyo3(signature = (kind, op_spec, target_scope, name, refresh_options=None))]
     pub fn add_source(
         &mut self,
@@ -412,6 +521,7 @@ impl FlowBuilder {
         let analyzer_ctx = AnalyzerContext {
             registry: &crate::ops::executor_factory_registry(),
             flow_ctx: &self.flow_inst_context,
+            foreach_concurrency_controller: None,
         };
         let mut root_data_scope = self.root_data_scope.lock().unwrap();
 
@@ -474,7 +584,7 @@ impl FlowBuilder {
         Ok(())
     }
 
-    #[pyo3(signature = (kind, op_spec, args, target_scope, name))]
+    #[pyo3(signature = (kind, op_spec, args, target_scope, name, cache_options=None))]
     pub fn transform(
         &mut self,
         kind: String,
@@ -482,6 +592,7 @@ impl FlowBuilder {
         args: Vec<(DataSlice, Option<String>)>,
         target_scope: Option<DataScopeRef>,
         name: String,
+        cache_options: Option<py::Pythonized<spec::FunctionCacheOptions>>,
     ) -> PyResult<DataSlice> {
         let spec = spec::OpSpec {
             kind,
@@ -504,6 +615,7 @@ impl FlowBuilder {
                             })
                             .collect(),
                         op: spec,
+                        cache: cache_options.map(|options| options.into_inner()),
                     }),
                 };
 
@@ -511,15 +623,110 @@ impl FlowBuilder {
                     analyzer_ctx.analyze_reactive_op(scope, &reactive_op, parent_scopes)?;
                 std::mem::drop(analyzed);
 
+                // The output field may be shared with the same-named op in another switch case.
+                let result =
+                    Self::field_to_data_slice(scope.data, &reactive_op.name, common_scope.clone())
+                        .into_py_result()?;
                 reactive_ops.push(reactive_op);
-                let result = Self::last_field_to_data_slice(scope.data, common_scope.clone())
-                    .into_py_result()?;
                 Ok(result)
             },
         )
         .into_py_result()
     }
 
+    pub fn filter(&mut self, condition: DataSlice, target_scope: DataScopeRef) -> PyResult<()> {
+        let common_scope =
+            Self::minimum_common_scope(std::iter::once(&condition.scope), Some(&target_scope))
+                .into_py_result()?;
+        let name = format!(".filter.{}", self.next_generated_op_id);
+        self.next_generated_op_id += 1;
+        self.do_in_scope(
+            common_scope,
+            |reactive_ops, scope, parent_scopes, analyzer_ctx| {
+                let reactive_op = spec::NamedSpec {
+                    name,
+                    spec: spec::ReactiveOpSpec::Filter(spec::FilterOpSpec {
+                        condition: condition.extract_value_mapping(),
+                    }),
+                };
+
+                let analyzed =
+                    analyzer_ctx.analyze_reactive_op(scope, &reactive_op, parent_scopes)?;
+                std::mem::drop(analyzed);
+
+                reactive_ops.push(reactive_op);
+                Ok(())
+            },
+        )
+        .into_py_result()
+    }
+
+    /// Add a Switch op without cases, and return its name to add cases to it.
+    pub fn add_switch(&mut self, input: DataSlice, target_scope: DataScopeRef) -> PyResult<String> {
+        let common_scope =
+            Self::minimum_common_scope(std::iter::once(&input.scope), Some(&target_scope))
+                .into_py_result()?;
+        let name = format!(".switch.{}", self.next_generated_op_id);
+        self.next_generated_op_id += 1;
+        self.do_in_scope(
+            common_scope,
+            |reactive_ops, scope, parent_scopes, analyzer_ctx| {
+                let reactive_op = spec::NamedSpec {
+                    name: name.clone(),
+                    spec: spec::ReactiveOpSpec::Switch(spec::SwitchOpSpec {
+                        input: input.extract_value_mapping(),
+                        cases: vec![],
+                        default_ops: vec![],
+                    }),
+                };
+
+                let analyzed =
+                    analyzer_ctx.analyze_reactive_op(scope, &reactive_op, parent_scopes)?;
+                std::mem::drop(analyzed);
+
+                reactive_ops.push(reactive_op);
+                Ok(())
+            },
+        )
+        .into_py_result()?;
+        Ok(name)
+    }
+
+    /// Start adding ops on the scope into a case of the Switch op.
+    pub fn enter_switch_case(
+        &mut self,
+        target_scope: DataScopeRef,
+        op_name: String,
+        value: py::Pythonized<serde_json::Value>,
+    ) -> PyResult<()> {
+        let value = value.into_inner();
+        if value.is_null() {
+            return Err(PyException::new_err(format!(
+                "Case value of switch op `{op_name}` cannot be null, use the default case instead"
+            )));
+        }
+        self.enter_switch(target_scope, op_name, Some(value))
+    }
+
+    /// Start adding ops on the scope into the default ops of the Switch op.
+    pub fn enter_switch_default(
+        &mut self,
+        target_scope: DataScopeRef,
+        op_name: String,
+    ) -> PyResult<()> {
+        self.enter_switch(target_scope, op_name, None)
+    }
+
+    pub fn exit_switch_case(&mut self, target_scope: DataScopeRef) -> PyResult<()> {
+        target_scope
+            .active_switch_cases
+            .lock()
+            .unwrap()
+            .pop()
+            .ok_or_else(|| PyException::new_err("no active switch case"))?;
+        Ok(())
+    }
+
     #[pyo3(signature = (collector, fields, auto_uuid_field=None))]
     pub fn collect(
         &mut self,
@@ -584,7 +791,7 @@ impl FlowBuilder {
         Ok(())
     }
 
-    #[pyo3(signature = (name, kind, op_spec, index_options, input, setup_by_user=false))]
+    #[pyo3(signature = (name, kind, op_spec, index_options, input, setup_by_user=false, aggregation=None))]
     pub fn export(
         &mut self,
         name: String,
@@ -593,6 +800,7 @@ impl FlowBuilder {
         index_options: py::Pythonized<spec::IndexOptions>,
         input: &DataCollector,
         setup_by_user: bool,
+        aggregation: Option<py::Pythonized<spec::AggregationSpec>>,
     ) -> PyResult<()> {
         let spec = spec::OpSpec {
             kind,
@@ -611,6 +819,7 @@ impl FlowBuilder {
                 target: spec,
                 index_options: index_options.into_inner(),
                 setup_by_user,
+                aggregation: aggregation.map(|a| a.into_inner()),
             },
         });
         Ok(())
@@ -652,6 +861,7 @@ impl FlowBuilder {
             reactive_ops: self.reactive_ops.clone(),
             export_ops: self.export_ops.clone(),
             declarations: self.declarations.clone(),
+            max_foreach_concurrency: self.max_foreach_concurrency,
         };
         let flow_instance_ctx = build_flow_instance_context(
             &self.flow_instance_name,
@@ -771,13 +981,32 @@ impl FlowBuilder {
         scope: DataScopeRef,
     ) -> Result<DataSlice> {
         let last_field = data_builder.last_field().unwrap();
+        Self::field_schema_to_data_slice(last_field, scope)
+    }
+
+    fn field_to_data_slice(
+        data_builder: &DataScopeBuilder,
+        field_name: &str,
+        scope: DataScopeRef,
+    ) -> Result<DataSlice> {
+        let (_, field) = data_builder
+            .data
+            .find_field(field_name)
+            .ok_or_else(|| anyhow!("field `{field_name}` not found"))?;
+        Self::field_schema_to_data_slice(field, scope)
+    }
+
+    fn field_schema_to_data_slice(
+        field: &FieldSchema<ValueTypeBuilder>,
+        scope: DataScopeRef,
+    ) -> Result<DataSlice> {
         let result = DataSlice {
             scope,
             value: Arc::new(spec::ValueMapping::Field(spec::FieldMapping {
                 scope: None,
-                field_path: spec::FieldPath(vec![last_field.name.clone()]),
+                field_path: spec::FieldPath(vec![field.name.clone()]),
             })),
-            data_type: schema::EnrichedValueType::from_alternative(&last_field.value_type)?.into(),
+            data_type: schema::EnrichedValueType::from_alternative(&field.value_type)?.into(),
         };
         Ok(result)
     }
@@ -812,6 +1041,42 @@ impl FlowBuilder {
         Ok(common_scope)
     }
 
+    /// Enter a case of the Switch op, or its default ops if `value` is None.
+    fn enter_switch(
+        &mut self,
+        target_scope: DataScopeRef,
+        op_name: String,
+        value: Option<serde_json::Value>,
+    ) -> PyResult<()> {
+        let case_idx = self
+            .do_in_scope(&target_scope, |reactive_ops, _, _, _| {
+                let switch_spec = match reactive_ops.last_mut() {
+                    Some(spec::NamedSpec {
+                        name,
+                        spec: spec::ReactiveOpSpec::Switch(switch_spec),
+                    }) if name == &op_name => switch_spec,
+                    _ => api_bail!(
+                        "Switch op `{op_name}` is not the last op of scope {target_scope}, \
+                         cases must be added right after it"
+                    ),
+                };
+                let case_idx = value.map(|value| {
+                    switch_spec
+                        .cases
+                        .push(spec::SwitchCaseSpec { value, ops: vec![] });
+                    switch_spec.cases.len() - 1
+                });
+                Ok(case_idx)
+            })
+            .into_py_result()?;
+        target_scope
+            .active_switch_cases
+            .lock()
+            .unwrap()
+            .push(ActiveSwitchCase { op_name, case_idx });
+        Ok(())
+    }
+
     fn do_in_scope<T>(
         &mut self,
         data_slice_scope: &DataScopeRef,
@@ -828,6 +1093,7 @@ impl FlowBuilder {
             data_slice_scopes.push(next_ds_scope);
             next_ds_scope = parent;
         }
+        data_slice_scopes.push(next_ds_scope);
 
         Self::do_in_sub_scope(
             &mut ExecutionScope {
@@ -841,11 +1107,36 @@ impl FlowBuilder {
             &AnalyzerContext {
                 registry: &crate::ops::executor_factory_registry(),
                 flow_ctx: &self.flow_inst_context,
+                foreach_concurrency_controller: None,
             },
             f,
         )
     }
 
+    /// Ops of the innermost active Switch case, if any.
+    fn switch_case_ops<'a>(
+        mut reactive_ops: &'a mut Vec<spec::NamedSpec<spec::ReactiveOpSpec>>,
+        active_switch_cases: &[ActiveSwitchCase],
+    ) -> Result<&'a mut Vec<spec::NamedSpec<spec::ReactiveOpSpec>>> {
+        for active_case in active_switch_cases {
+            reactive_ops = match reactive_ops.last_mut() {
+                Some(spec::NamedSpec {
+                    name,
+                    spec: spec::ReactiveOpSpec::Switch(switch_spec),
+                }) if name == &active_case.op_name => match active_case.case_idx {
+                    Some(case_idx) => &mut switch_spec.cases[case_idx].ops,
+                    None => &mut switch_spec.default_ops,
+                },
+                _ => api_bail!(
+                    "Ops are added out of the active case of switch op `{}`",
+                    active_case.op_name
+                ),
+            };
+        }
+        Ok(reactive_ops)
+    }
+
+    /// `data_slice_scopes` are from the target scope to the current one.
     fn do_in_sub_scope<T>(
         scope: &mut ExecutionScope<'_>,
         parent_scopes: RefList<'_, &'_ ExecutionScope<'_>>,
@@ -860,11 +1151,61 @@ impl FlowBuilder {
             &AnalyzerContext<'_>,
         ) -> Result<T>,
     ) -> Result<T> {
-        let curr_ds_scope = if let Some(&ds_scope) = data_slice_scopes.last() {
-            ds_scope
+        let (curr_ds_scope, data_slice_scopes) = data_slice_scopes
+            .split_last()
+            .ok_or_else(|| anyhow!("expect at least one data scope"))?;
+        let active_switch_cases = curr_ds_scope.active_switch_cases.lock().unwrap().clone();
+        let reactive_ops = Self::switch_case_ops(reactive_ops, &active_switch_cases)?;
+        let in_switch_case = !active_switch_cases.is_empty();
+        let outer_switch_cases = if in_switch_case {
+            scope.data.conditional_depth += 1;
+            let switch_cases = active_switch_cases
+                .iter()
+                .map(|case| (case.op_name.clone(), case.case_idx))
+                .collect();
+            Some(std::mem::replace(
+                &mut scope.data.switch_cases,
+                switch_cases,
+            ))
         } else {
-            return f(reactive_ops, scope, parent_scopes, analyzer_ctx);
+            None
         };
+        let result = if data_slice_scopes.is_empty() {
+            f(reactive_ops, scope, parent_scopes, analyzer_ctx)
+        } else {
+            Self::do_in_child_scope(
+                scope,
+                parent_scopes,
+                data_slice_scopes,
+                reactive_ops,
+                next_generated_op_id,
+                analyzer_ctx,
+                f,
+            )
+        };
+        if let Some(outer_switch_cases) = outer_switch_cases {
+            scope.data.conditional_depth -= 1;
+            scope.data.switch_cases = outer_switch_cases;
+        }
+        result
+    }
+
+    /// `data_slice_scopes` are from the target scope to the child scope to enter.
+    fn do_in_child_scope<T>(
+        scope: &mut ExecutionScope<'_>,
+        parent_scopes: RefList<'_, &'_ ExecutionScope<'_>>,
+        data_slice_scopes: &[&DataScopeRef],
+        reactive_ops: &mut Vec<spec::NamedSpec<spec::ReactiveOpSpec>>,
+        next_generated_op_id: &mut usize,
+        analyzer_ctx: &AnalyzerContext<'_>,
+        f: impl FnOnce(
+            &mut Vec<spec::NamedSpec<spec::ReactiveOpSpec>>,
+            &mut ExecutionScope<'_>,
+            RefList<'_, &'_ ExecutionScope<'_>>,
+            &AnalyzerContext<'_>,
+        ) -> Result<T>,
+    ) -> Result<T> {
+        let curr_ds_scope = *data_slice_scopes.last().unwrap();
         let field_path = if let Some((_, field_path)) = &curr_ds_scope.parent {
             field_path
         } else {
@@ -879,6 +1220,9 @@ impl FlowBuilder {
             }) if &foreach_spec.field_path == field_path
                 && foreach_spec.op_scope.name == curr_ds_scope.scope_name =>
             {
+                if let Some(max_concurrency) = *curr_ds_scope.max_concurrency.lock().unwrap() {
+                    foreach_spec.max_concurrency = Some(max_concurrency);
+                }
                 &mut foreach_spec.op_scope.ops
             }
             _ => {
@@ -890,6 +1234,7 @@ impl FlowBuilder {
                             name: curr_ds_scope.scope_name.clone(),
                             ops: vec![],
                         },
+                        max_concurrency: *curr_ds_scope.max_concurrency.lock().unwrap(),
                     }),
                 });
                 *next_generated_op_id += 1;
@@ -900,6 +1245,8 @@ impl FlowBuilder {
             }
         };
 
+        // Rows of the collection are conditionally evaluated as well, if the parent scope is.
+        let conditional = scope.data.is_conditional();
         let (_, field_type) = scope.data.analyze_field_path(field_path)?;
         let sub_scope = match &field_type.typ {
             ValueTypeBuilder::Collection(collection_type) => &collection_type.sub_scope,
@@ -909,17 +1256,24 @@ impl FlowBuilder {
             ),
         };
         let mut sub_scope = sub_scope.lock().unwrap();
-        Self::do_in_sub_scope(
+        if conditional {
+            sub_scope.conditional_depth += 1;
+        }
+        let result = Self::do_in_sub_scope(
             &mut ExecutionScope {
                 name: curr_ds_scope.scope_name.as_str(),
                 data: &mut sub_scope,
             },
             parent_scopes.prepend(scope),
-            &data_slice_scopes[0..data_slice_scopes.len() - 1],
+            data_slice_scopes,
             reactive_ops,
             next_generated_op_id,
             analyzer_ctx,
             f,
-        )
+        );
+        if conditional {
+            sub_scope.conditional_depth -= 1;
+        }
+        result
     }
 }
@@ -1,6 +1,6 @@
 use crate::prelude::*;
 
-use crate::execution::db_tracking_setup;
+use crate::execution::{aggregation, db_tracking_setup, memoization};
 use crate::ops::interface::*;
 use crate::utils::fingerprint::{Fingerprint, Fingerprinter};
 
@@ -38,12 +38,32 @@ pub struct AnalyzedStructMapping {
     pub fields: Vec<AnalyzedValueMapping>,
 }
 
+#[derive(Debug, Clone, Copy, Serialize)]
+pub enum AnalyzedCollectionMappingOutput {
+    /// Mapped values are basic values, collected into a vector.
+    Vector,
+    /// Mapped values are structs, collected into a list.
+    List,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AnalyzedCollectionMapping {
+    pub field: AnalyzedFieldReference,
+    /// Evaluated with the row scope prepended to scopes of the mapping.
+    pub value: Box<AnalyzedValueMapping>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<Box<AnalyzedValueMapping>>,
+    pub output: AnalyzedCollectionMappingOutput,
+}
+
 #[derive(Debug, Clone, Serialize)]
 #[serde(tag = "kind")]
 pub enum AnalyzedValueMapping {
     Constant { value: value::Value },
     Field(AnalyzedFieldReference),
     Struct(AnalyzedStructMapping),
+    Collection(AnalyzedCollectionMapping),
+    Collector(AnalyzedLocalCollectorReference),
 }
 
 #[derive(Debug, Clone)]
@@ -58,6 +78,9 @@ pub struct AnalyzedImportOp {
     pub output: AnalyzedOpOutput,
     pub primary_key_type: schema::ValueType,
     pub refresh_options: spec::SourceRefreshOptions,
+    /// Fingerprint of the source's own logic, excluding reactive ops and export ops, which are tracked
+    /// separately in `ExecutionPlan::op_logic_fingerprints`. Rows processed with a different one need reprocessing.
+    pub logic_fingerprint: Fingerprint,
 }
 
 pub struct AnalyzedFunctionExecInfo {
@@ -67,6 +90,8 @@ pub struct AnalyzedFunctionExecInfo {
     /// Fingerprinter of the function's behavior.
     pub fingerprinter: Fingerprinter,
     pub output_type: schema::ValueType,
+
+    pub cache_options: memoization::CacheEntryOptions,
 }
 
 pub struct AnalyzedTransformOp {
@@ -81,6 +106,7 @@ pub struct AnalyzedForEachOp {
     pub name: String,
     pub local_field_ref: AnalyzedLocalFieldReference,
     pub op_scope: AnalyzedOpScope,
+    pub max_concurrency: Option<usize>,
 }
 
 pub struct AnalyzedCollectOp {
@@ -92,6 +118,23 @@ pub struct AnalyzedCollectOp {
     pub fingerprinter: Fingerprinter,
 }
 
+pub struct AnalyzedFilterOp {
+    pub name: String,
+    pub condition: AnalyzedValueMapping,
+}
+
+pub struct AnalyzedSwitchCase {
+    pub value: value::KeyValue,
+    pub op_scope: AnalyzedOpScope,
+}
+
+pub struct AnalyzedSwitchOp {
+    pub name: String,
+    pub input: AnalyzedValueMapping,
+    pub cases: Vec<AnalyzedSwitchCase>,
+    pub default_op_scope: AnalyzedOpScope,
+}
+
 pub enum AnalyzedPrimaryKeyDef {
     Fields(Vec<u32>),
 }
@@ -109,6 +152,24 @@ pub struct AnalyzedExportOp {
     /// If true, value is never changed on the same primary key.
     /// This is guaranteed if the primary key contains auto-generated UUIDs.
     pub value_stable: bool,
+    /// If present, collected rows are aggregated across source rows by the primary key (i.e. group-by fields),
+    /// and `value_fields` is empty.
+    pub aggregation: Option<AnalyzedAggregation>,
+}
+
+pub struct AnalyzedReducer {
+    pub kind: spec::ReducerKind,
+    /// idx for the input field in collected rows. Only absent for `Count` counting all rows.
+    pub input_field_idx: Option<u32>,
+    /// Type of values kept in reducer states: the input field's type, or the element type for `CollectSet`.
+    pub value_type: Option<schema::ValueType>,
+}
+
+pub struct AnalyzedAggregation {
+    /// One for each value field of the target.
+    pub reducers: Vec<AnalyzedReducer>,
+    /// Serialize recomputing aggregated rows of the same group within the process.
+    pub group_locks: aggregation::GroupLocks,
 }
 
 pub struct AnalyzedExportTargetOpGroup {
@@ -120,15 +181,28 @@ pub enum AnalyzedReactiveOp {
     Transform(AnalyzedTransformOp),
     ForEach(AnalyzedForEachOp),
     Collect(AnalyzedCollectOp),
+    Filter(AnalyzedFilterOp),
+    Switch(AnalyzedSwitchOp),
 }
 
 pub struct AnalyzedOpScope {
     pub reactive_ops: Vec<AnalyzedReactiveOp>,
+    /// Shared by transform ops in the scope to limit their concurrent evaluations.
+    pub concurrency_controller: Option<Arc<tokio::sync::Semaphore>>,
+}
+
+/// Key of an export op in `ExecutionPlan::op_logic_fingerprints`.
+pub fn export_op_logic_key(export_op_name: &str) -> String {
+    format!("export:{export_op_name}")
 }
 
 pub struct ExecutionPlan {
+    pub flow_name: String,
     pub tracking_table_setup: db_tracking_setup::TrackingTableSetupState,
-    pub logic_fingerprint: Fingerprint,
+    /// Fingerprints of logic of each reactive op, export op and exported collector, keyed by op kind and name,
+    /// e.g. `transform:summary`. Except for export ops, each also covers the logic of ops it depends on.
+    /// They're kept for each source row, to tell which ops are changed since the row was processed.
+    pub op_logic_fingerprints: BTreeMap<String, Fingerprint>,
 
     pub import_ops: Vec<AnalyzedImportOp>,
     pub op_scope: AnalyzedOpScope,
@@ -0,0 +1,568 @@
+//! Aggregation of collected rows across source rows.
+//!
+//! Each source row keeps its partial aggregates ("contributions") for each group it collects rows into.
+//! When a source row changes, its contributions are replaced, and rows of affected groups are recomputed
+//! by merging contributions from all source rows in the group.
+
+use crate::prelude::*;
+
+use std::cmp::Ordering;
+use std::hash::{DefaultHasher, Hasher};
+
+use super::row_indexer::extract_primary_key;
+use crate::base::spec::ReducerKind;
+use crate::base::value::{BasicValue, FieldValues, KeyValue, Value};
+use crate::builder::plan::{AnalyzedAggregation, AnalyzedPrimaryKeyDef, AnalyzedReducer};
+
+const NUM_GROUP_LOCKS: usize = 64;
+
+/// In-process locks for groups of an aggregated target, striped by hash of group keys.
+/// Locks across processes are taken through the state store.
+pub struct GroupLocks(Vec<tokio::sync::Mutex<()>>);
+
+impl Default for GroupLocks {
+    fn default() -> Self {
+        Self((0..NUM_GROUP_LOCKS).map(|_| Default::default()).collect())
+    }
+}
+
+impl GroupLocks {
+    /// Lock all given groups. Locks are always acquired in the same order, so there's no deadlock.
+    pub async fn lock<'a>(
+        &self,
+        group_keys: impl IntoIterator<Item = &'a KeyValue>,
+    ) -> Vec<tokio::sync::MutexGuard<'_, ()>> {
+        let lock_indices: BTreeSet<usize> = group_keys
+            .into_iter()
+            .map(|key| {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                hasher.finish() as usize % NUM_GROUP_LOCKS
+            })
+            .collect();
+        let mut guards = Vec::with_capacity(lock_indices.len());
+        for idx in lock_indices {
+            guards.push(self.0[idx].lock().await);
+        }
+        guards
+    }
+}
+
+enum ReducerState {
+    Count(i64),
+    Sum(Value),
+    Min(Value),
+    Max(Value),
+    CollectSet(BTreeSet<KeyValue>),
+    LastByOrdinal(Option<(i64, Value)>),
+}
+
+fn compare_values(v1: &Value, v2: &Value) -> Result<Ordering> {
+    let result = match (v1, v2) {
+        (Value::Basic(v1), Value::Basic(v2)) => match (v1, v2) {
+            (BasicValue::Float32(v1), BasicValue::Float32(v2)) => v1.total_cmp(v2),
+            (BasicValue::Float64(v1), BasicValue::Float64(v2)) => v1.total_cmp(v2),
+            (BasicValue::Time(v1), BasicValue::Time(v2)) => v1.cmp(v2),
+            (BasicValue::LocalDateTime(v1), BasicValue::LocalDateTime(v2)) => v1.cmp(v2),
+            (BasicValue::OffsetDateTime(v1), BasicValue::OffsetDateTime(v2)) => v1.cmp(v2),
+            (BasicValue::TimeDelta(v1), BasicValue::TimeDelta(v2)) => v1.cmp(v2),
+            (v1, v2) => v1.as_key()?.cmp(&v2.as_key()?),
+        },
+        _ => bail!("values are not comparable: {v1:?} vs {v2:?}"),
+    };
+    Ok(result)
+}
+
+fn add_values(v1: &Value, v2: &Value) -> Result<Value> {
+    let result = match (v1, v2) {
+        (Value::Basic(BasicValue::Int64(v1)), Value::Basic(BasicValue::Int64(v2))) => {
+            Value::Basic(BasicValue::Int64(v1 + v2))
+        }
+        (Value::Basic(BasicValue::Float32(v1)), Value::Basic(BasicValue::Float32(v2))) => {
+            Value::Basic(BasicValue::Float32(v1 + v2))
+        }
+        (Value::Basic(BasicValue::Float64(v1)), Value::Basic(BasicValue::Float64(v2))) => {
+            Value::Basic(BasicValue::Float64(v1 + v2))
+        }
+        _ => bail!("values cannot be summed: {v1:?} and {v2:?}"),
+    };
+    Ok(result)
+}
+
+impl ReducerState {
+    fn from_row(reducer: &AnalyzedReducer, row: &FieldValues, ordinal: i64) -> Result<Self> {
+        let input = reducer.input_field_idx.map(|idx| &row.fields[idx as usize]);
+        let result = match reducer.kind {
+            ReducerKind::Count => ReducerState::Count(if input.is_some_and(|v| v.is_null()) {
+                0
+            } else {
+                1
+            }),
+            ReducerKind::Sum => ReducerState::Sum(input.cloned().unwrap_or_default()),
+            ReducerKind::Min => ReducerState::Min(input.cloned().unwrap_or_default()),
+            ReducerKind::Max => ReducerState::Max(input.cloned().unwrap_or_default()),
+            ReducerKind::CollectSet => {
+                let mut set = BTreeSet::new();
+                match input {
+                    None | Some(Value::Null) => {}
+                    // Union of elements for vector and list inputs.
+                    Some(Value::Basic(BasicValue::Vector(elements))) => {
+                        for element in elements.iter() {
+                            set.insert(element.as_key()?);
+                        }
+                    }
+                    Some(Value::List(rows)) => {
+                        for row in rows.iter() {
+                            match row.fields.first() {
+                                None | Some(Value::Null) => {}
+                                Some(v) => {
+                                    set.insert(v.as_key()?);
+                                }
+                            }
+                        }
+                    }
+                    Some(v) => {
+                        set.insert(v.as_key()?);
+                    }
+                }
+                ReducerState::CollectSet(set)
+            }
+            ReducerKind::LastByOrdinal => {
+                ReducerState::LastByOrdinal(Some((ordinal, input.cloned().unwrap_or_default())))
+            }
+        };
+        Ok(result)
+    }
+
+    /// Merge another partial aggregate into this one. For last-by-ordinal, `other` wins on ties.
+    fn merge(&mut self, other: ReducerState) -> Result<()> {
+        match (self, other) {
+            (ReducerState::Count(v), ReducerState::Count(other)) => *v += other,
+            (ReducerState::Sum(v), ReducerState::Sum(other)) => {
+                if v.is_null() {
+                    *v = other;
+                } else if !other.is_null() {
+                    *v = add_values(v, &other)?;
+                }
+            }
+            (ReducerState::Min(v), ReducerState::Min(other)) => {
+                if v.is_null() || (!other.is_null() && compare_values(&other, v)? == Ordering::Less)
+                {
+                    *v = other;
+                }
+            }
+            (ReducerState::Max(v), ReducerState::Max(other)) => {
+                if v.is_null()
+                    || (!other.is_null() && compare_values(&other, v)? == Ordering::Greater)
+                {
+                    *v = other;
+                }
+            }
+            (ReducerState::CollectSet(v), ReducerState::CollectSet(other)) => v.extend(other),
+            (ReducerState::LastByOrdinal(v), ReducerState::LastByOrdinal(other)) => {
+                if let Some(other) = other {
+                    if v.as_ref().is_none_or(|(ordinal, _)| *ordinal <= other.0) {
+                        *v = Some(other);
+                    }
+                }
+            }
+            _ => bail!("mismatched reducer states"),
+        }
+        Ok(())
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value> {
+        let result = match self {
+            ReducerState::Count(v) => serde_json::to_value(v)?,
+            ReducerState::Sum(v) | ReducerState::Min(v) | ReducerState::Max(v) => {
+                serde_json::to_value(v)?
+            }
+            ReducerState::CollectSet(v) => serde_json::to_value(v)?,
+            ReducerState::LastByOrdinal(v) => match v {
+                Some((ordinal, value)) => serde_json::json!([ordinal, value]),
+                None => serde_json::Value::Null,
+            },
+        };
+        Ok(result)
+    }
+
+    fn from_json(reducer: &AnalyzedReducer, json: serde_json::Value) -> Result<Self> {
+        let input_value = |json: serde_json::Value| -> Result<Value> {
+            let value_type = reducer
+                .value_type
+                .as_ref()
+                .ok_or_else(|| anyhow!("value type is missing for reducer {}", reducer.kind))?;
+            Value::<value::ScopeValue>::from_json(json, value_type)
+        };
+        let result = match reducer.kind {
+            ReducerKind::Count => ReducerState::Count(serde_json::from_value(json)?),
+            ReducerKind::Sum => ReducerState::Sum(input_value(json)?),
+            ReducerKind::Min => ReducerState::Min(input_value(json)?),
+            ReducerKind::Max => ReducerState::Max(input_value(json)?),
+            ReducerKind::CollectSet => ReducerState::CollectSet(
+                serde_json::from_value::<Vec<serde_json::Value>>(json)?
+                    .into_iter()
+                    .map(|v| input_value(v)?.into_key())
+                    .collect::<Result<_>>()?,
+            ),
+            ReducerKind::LastByOrdinal => match json {
+                serde_json::Value::Null => ReducerState::LastByOrdinal(None),
+                json => {
+                    let (ordinal, value): (i64, serde_json::Value) = serde_json::from_value(json)?;
+                    ReducerState::LastByOrdinal(Some((ordinal, input_value(value)?)))
+                }
+            },
+        };
+        Ok(result)
+    }
+
+    fn into_value(self) -> Result<Value> {
+        let result = match self {
+            ReducerState::Count(v) => Value::Basic(BasicValue::Int64(v)),
+            ReducerState::Sum(v) | ReducerState::Min(v) | ReducerState::Max(v) => v,
+            ReducerState::CollectSet(v) => Value::Basic(BasicValue::Vector(Arc::from(
+                v.into_iter()
+                    .map(|key| match Value::from(key) {
+                        Value::Basic(v) => Ok(v),
+                        v => bail!("unexpected value in a set: {v:?}"),
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+            ))),
+            ReducerState::LastByOrdinal(v) => v.map(|(_, value)| value).unwrap_or_default(),
+        };
+        Ok(result)
+    }
+}
+
+fn merge_group_state(state: &mut [ReducerState], other: Vec<ReducerState>) -> Result<()> {
+    for (reducer_state, other) in state.iter_mut().zip(other) {
+        reducer_state.merge(other)?;
+    }
+    Ok(())
+}
+
+/// Aggregate rows collected by a source row into partial aggregates keyed by group keys.
+/// `ordinal` orders rows for last-by-ordinal reducers.
+pub fn collect_contributions(
+    aggregation: &AnalyzedAggregation,
+    primary_key_def: &AnalyzedPrimaryKeyDef,
+    rows: &[FieldValues],
+    ordinal: i64,
+) -> Result<BTreeMap<KeyValue, serde_json::Value>> {
+    let mut group_states = BTreeMap::<KeyValue, Vec<ReducerState>>::new();
+    for row in rows.iter() {
+        let group_key = extract_primary_key(primary_key_def, row)?;
+        let row_state = aggregation
+            .reducers
+            .iter()
+            .map(|reducer| ReducerState::from_row(reducer, row, ordinal))
+            .collect::<Result<Vec<_>>>()?;
+        match group_states.entry(group_key) {
+            std::collections::btree_map::Entry::Occupied(mut entry) => {
+                merge_group_state(entry.get_mut(), row_state)?;
+            }
+            std::collections::btree_map::Entry::Vacant(entry) => {
+                entry.insert(row_state);
+            }
+        }
+    }
+    group_states
+        .into_iter()
+        .map(|(group_key, state)| {
+            let json = state
+                .iter()
+                .map(|s| s.to_json())
+                .collect::<Result<Vec<_>>>()?;
+            Ok((group_key, serde_json::Value::Array(json)))
+        })
+        .collect()
+}
+
+/// Merge contributions from all source rows in a group into values of the aggregated row.
+/// Returns None if there's no contribution, i.e. the group is gone.
+pub fn aggregate_contributions(
+    aggregation: &AnalyzedAggregation,
+    contributions: Vec<serde_json::Value>,
+) -> Result<Option<FieldValues>> {
+    let mut group_state: Option<Vec<ReducerState>> = None;
+    for contribution in contributions {
+        let jsons: Vec<serde_json::Value> = serde_json::from_value(contribution)?;
+        if jsons.len() != aggregation.reducers.len() {
+            bail!(
+                "contribution has {} reducer states, expect {}",
+                jsons.len(),
+                aggregation.reducers.len()
+            );
+        }
+        let state = aggregation
+            .reducers
+            .iter()
+            .zip(jsons)
+            .map(|(reducer, json)| ReducerState::from_json(reducer, json))
+            .collect::<Result<Vec<_>>>()?;
+        match &mut group_state {
+            Some(group_state) => merge_group_state(group_state, state)?,
+            None => group_state = Some(state),
+        }
+    }
+    group_state
+        .map(|state| {
+            Ok(FieldValues {
+                fields: state
+                    .into_iter()
+                    .map(|s| s.into_value())
+                    .collect::<Result<Vec<_>>>()?,
+            })
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::schema::{BasicValueType, ValueType};
+    use crate::base::value::ScopeValue;
+    use serde_json::json;
+
+    // Fields of collected rows: group key, amount, score, tag, note.
+    const AMOUNT: u32 = 1;
+    const SCORE: u32 = 2;
+    const TAG: u32 = 3;
+    const NOTE: u32 = 4;
+
+    fn reducer(
+        kind: ReducerKind,
+        field: Option<u32>,
+        typ: Option<BasicValueType>,
+    ) -> AnalyzedReducer {
+        AnalyzedReducer {
+            kind,
+            input_field_idx: field,
+            value_type: typ.map(ValueType::Basic),
+        }
+    }
+
+    fn aggregation() -> AnalyzedAggregation {
+        AnalyzedAggregation {
+            reducers: vec![
+                reducer(ReducerKind::Count, None, None),
+                reducer(ReducerKind::Sum, Some(AMOUNT), Some(BasicValueType::Int64)),
+                reducer(ReducerKind::Min, Some(SCORE), Some(BasicValueType::Float64)),
+                reducer(ReducerKind::Max, Some(SCORE), Some(BasicValueType::Float64)),
+                reducer(
+                    ReducerKind::CollectSet,
+                    Some(TAG),
+                    Some(BasicValueType::Str),
+                ),
+                reducer(
+                    ReducerKind::LastByOrdinal,
+                    Some(NOTE),
+                    Some(BasicValueType::Str),
+                ),
+            ],
+            group_locks: GroupLocks::default(),
+        }
+    }
+
+    fn primary_key_def() -> AnalyzedPrimaryKeyDef {
+        AnalyzedPrimaryKeyDef::Fields(vec![0])
+    }
+
+    fn row(group: &str, amount: Option<i64>, score: f64, tag: &str, note: &str) -> FieldValues {
+        FieldValues {
+            fields: vec![
+                Value::from(group.to_string()),
+                amount.map(Value::from).unwrap_or_default(),
+                Value::from(score),
+                Value::from(tag.to_string()),
+                Value::from(note.to_string()),
+            ],
+        }
+    }
+
+    fn to_json(values: &FieldValues) -> serde_json::Value {
+        serde_json::to_value(
+            values
+                .fields
+                .iter()
+                .map(|v| serde_json::to_value(v).unwrap())
+                .collect::<Vec<_>>(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn merge_contributions_of_source_rows() {
+        let aggregation = aggregation();
+        let source1 = collect_contributions(
+            &aggregation,
+            &primary_key_def(),
+            &[
+                row("g1", Some(1), 0.5, "a", "first"),
+                row("g1", Some(2), -1.5, "b", "second"),
+                row("g2", Some(10), 3.0, "c", "other"),
+            ],
+            1,
+        )
+        .unwrap();
+        assert_eq!(
+            source1.keys().cloned().collect::<Vec<_>>(),
+            vec![
+                KeyValue::from("g1".to_string()),
+                KeyValue::from("g2".to_string())
+            ]
+        );
+        let source2 = collect_contributions(
+            &aggregation,
+            &primary_key_def(),
+            &[row("g1", None, 2.5, "a", "third")],
+            2,
+        )
+        .unwrap();
+
+        let group_key = KeyValue::from("g1".to_string());
+        let contributions = vec![source2[&group_key].clone(), source1[&group_key].clone()];
+        let aggregated = aggregate_contributions(&aggregation, contributions)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            to_json(&aggregated),
+            json!([3, 3, -1.5, 2.5, ["a", "b"], "third"])
+        );
+    }
+
+    #[test]
+    fn no_contribution_means_no_group() {
+        assert!(aggregate_contributions(&aggregation(), vec![])
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn nulls_are_skipped() {
+        let reducers = [
+            reducer(
+                ReducerKind::Count,
+                Some(AMOUNT),
+                Some(BasicValueType::Int64),
+            ),
+            reducer(ReducerKind::Sum, Some(AMOUNT), Some(BasicValueType::Int64)),
+            reducer(ReducerKind::Min, Some(AMOUNT), Some(BasicValueType::Int64)),
+        ];
+        for (reducer, expected) in reducers.iter().zip([json!(1), json!(5), json!(5)]) {
+            let mut state =
+                ReducerState::from_row(reducer, &row("g", None, 0.0, "", ""), 0).unwrap();
+            for amount in [Some(5), None] {
+                let other =
+                    ReducerState::from_row(reducer, &row("g", amount, 0.0, "", ""), 0).unwrap();
+                state.merge(other).unwrap();
+            }
+            assert_eq!(
+                serde_json::to_value(state.into_value().unwrap()).unwrap(),
+                expected,
+                "reducer {}",
+                reducer.kind
+            );
+        }
+    }
+
+    #[test]
+    fn last_by_ordinal_prefers_later_on_ties() {
+        let reducer = reducer(
+            ReducerKind::LastByOrdinal,
+            Some(NOTE),
+            Some(BasicValueType::Str),
+        );
+        let state = |note: &str, ordinal| {
+            ReducerState::from_row(&reducer, &row("g", None, 0.0, "", note), ordinal).unwrap()
+        };
+        let mut merged = state("newer", 3);
+        merged.merge(state("older", 2)).unwrap();
+        merged.merge(state("tie", 3)).unwrap();
+        assert_eq!(
+            serde_json::to_value(merged.into_value().unwrap()).unwrap(),
+            json!("tie")
+        );
+    }
+
+    #[test]
+    fn collect_set_of_vectors_and_lists() {
+        let reducer = reducer(ReducerKind::CollectSet, Some(0), Some(BasicValueType::Str));
+        let str_value = |s: &str| BasicValue::from(s.to_string());
+        let vector = Value::Basic(BasicValue::Vector(Arc::from(vec![
+            str_value("x"),
+            str_value("y"),
+        ])));
+        let list = Value::List(
+            ["y", "z"]
+                .iter()
+                .map(|s| {
+                    ScopeValue(FieldValues {
+                        fields: vec![Value::Basic(str_value(s))],
+                    })
+                })
+                .chain(std::iter::once(ScopeValue(FieldValues {
+                    fields: vec![Value::Null],
+                })))
+                .collect(),
+        );
+        let mut state = ReducerState::from_row(
+            &reducer,
+            &FieldValues {
+                fields: vec![vector],
+            },
+            0,
+        )
+        .unwrap();
+        state
+            .merge(
+                ReducerState::from_row(&reducer, &FieldValues { fields: vec![list] }, 0).unwrap(),
+            )
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(state.into_value().unwrap()).unwrap(),
+            json!(["x", "y", "z"])
+        );
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let aggregation = aggregation();
+        let rows = [
+            row("g", Some(7), 1.25, "t", "n"),
+            row("g", None, f64::MIN, "", ""),
+        ];
+        let states = rows
+            .iter()
+            .flat_map(|row| {
+                aggregation
+                    .reducers
+                    .iter()
+                    .map(|reducer| (reducer, ReducerState::from_row(reducer, row, 42).unwrap()))
+                    .collect::<Vec<_>>()
+            })
+            .chain(std::iter::once((
+                &aggregation.reducers[5],
+                ReducerState::LastByOrdinal(None),
+            )));
+        for (reducer, state) in states {
+            let json = state.to_json().unwrap();
+            let restored = ReducerState::from_json(reducer, json.clone()).unwrap();
+            assert_eq!(
+                restored.to_json().unwrap(),
+                json,
+                "reducer {}",
+                reducer.kind
+            );
+        }
+    }
+
+    #[test]
+    fn mismatched_contribution_is_rejected() {
+        let err = aggregate_contributions(&aggregation(), vec![json!([1, 2])]).unwrap_err();
+        assert!(
+            err.to_string().contains("has 2 reducer states, expect 6"),
+            "got: {err}"
+        );
+    }
+}
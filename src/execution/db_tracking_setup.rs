@@ -2,7 +2,6 @@ use crate::prelude::*;
 
 use crate::setup::{CombinedState, ResourceSetupInfo, ResourceSetupStatusCheck, SetupChangeType};
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
 
 pub fn default_tracking_table_name(flow_name: &str) -> String {
     let sanitized_name = flow_name
@@ -12,40 +11,39 @@ pub fn default_tracking_table_name(flow_name: &str) -> String {
     format!("{}__cocoindex_tracking", sanitized_name)
 }
 
-pub const CURRENT_TRACKING_TABLE_VERSION: i32 = 1;
-
-async fn upgrade_tracking_table(
-    pool: &PgPool,
-    table_name: &str,
-    existing_version_id: i32,
-    target_version_id: i32,
-) -> Result<()> {
-    if existing_version_id < 1 && target_version_id >= 1 {
-        let query = format!(
-            "CREATE TABLE IF NOT EXISTS {table_name} (
-                source_id INTEGER NOT NULL,
-                source_key JSONB NOT NULL,
-            
-                -- Update in the precommit phase: after evaluation done, before really applying the changes to the target storage.
-                max_process_ordinal BIGINT NOT NULL,
-                staging_target_keys JSONB NOT NULL,
-                memoization_info JSONB,
-            
-                -- Update after applying the changes to the target storage.
-                processed_source_ordinal BIGINT,
-                process_logic_fingerprint BYTEA,
-                process_ordinal BIGINT,
-                process_time_micros BIGINT,
-                target_keys JSONB,
-            
-                PRIMARY KEY (source_id, source_key)
-            );",
-        );
-        sqlx::query(&query).execute(pool).await?;
-    }
-    Ok(())
+/// Name of the table keeping source rows that failed to process, next to the tracking table.
+pub fn error_table_name(tracking_table_name: &str) -> String {
+    format!("{}__errors", tracking_table_name)
+}
+
+/// Name of the table keeping function results shared by all source rows, next to the tracking table.
+pub fn shared_cache_table_name(tracking_table_name: &str) -> String {
+    format!("{}__function_cache", tracking_table_name)
+}
+
+/// Name of the table keeping leases of source keys being processed by workers, next to the tracking table.
+pub fn lease_table_name(tracking_table_name: &str) -> String {
+    format!("{}__leases", tracking_table_name)
+}
+
+/// Name of the table keeping partial aggregates contributed by each source row to groups of aggregated targets,
+/// next to the tracking table.
+pub fn aggregation_table_name(tracking_table_name: &str) -> String {
+    format!("{}__aggregation", tracking_table_name)
+}
+
+/// Tables living next to the tracking table, which are renamed and dropped together with it.
+fn auxiliary_table_names(tracking_table_name: &str) -> [String; 4] {
+    [
+        error_table_name(tracking_table_name),
+        shared_cache_table_name(tracking_table_name),
+        lease_table_name(tracking_table_name),
+        aggregation_table_name(tracking_table_name),
+    ]
 }
 
+pub const CURRENT_TRACKING_TABLE_VERSION: i32 = 6;
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct TrackingTableSetupState {
     pub table_name: String,
@@ -151,29 +149,37 @@ impl ResourceSetupStatusCheck for TrackingTableSetupStatusCheck {
     }
 
     async fn apply_change(&self) -> Result<()> {
-        let pool = &get_lib_context()?.pool;
+        let state_store = &get_lib_context()?.state_store;
         if let Some(desired) = &self.desired_state {
             for lagacy_name in self.legacy_table_names.iter() {
-                let query = format!(
-                    "ALTER TABLE IF EXISTS {} RENAME TO {}",
-                    lagacy_name, desired.table_name
-                );
-                sqlx::query(&query).execute(pool).await?;
+                state_store
+                    .rename_table_if_exists(lagacy_name, &desired.table_name)
+                    .await?;
+                for (legacy_aux_name, aux_name) in auxiliary_table_names(lagacy_name)
+                    .into_iter()
+                    .zip(auxiliary_table_names(&desired.table_name))
+                {
+                    state_store
+                        .rename_table_if_exists(&legacy_aux_name, &aux_name)
+                        .await?;
+                }
             }
 
             if self.min_existing_version_id != Some(desired.version_id) {
-                upgrade_tracking_table(
-                    pool,
-                    &desired.table_name,
-                    self.min_existing_version_id.unwrap_or(0),
-                    desired.version_id,
-                )
-                .await?;
+                state_store
+                    .upgrade_tracking_table(
+                        &desired.table_name,
+                        self.min_existing_version_id.unwrap_or(0),
+                        desired.version_id,
+                    )
+                    .await?;
             }
         } else {
             for lagacy_name in self.legacy_table_names.iter() {
-                let query = format!("DROP TABLE IF EXISTS {}", lagacy_name);
-                sqlx::query(&query).execute(pool).await?;
+                state_store.drop_table_if_exists(lagacy_name).await?;
+                for aux_name in auxiliary_table_names(lagacy_name) {
+                    state_store.drop_table_if_exists(&aux_name).await?;
+                }
             }
             return Ok(());
         }
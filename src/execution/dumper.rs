@@ -1,3 +1,4 @@
+use crate::state_store::SharedStateStore;
 use anyhow::Result;
 use futures::future::try_join_all;
 use futures::StreamExt;
@@ -5,7 +6,6 @@ use indexmap::IndexMap;
 use itertools::Itertools;
 use serde::ser::SerializeSeq;
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
 use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
@@ -62,7 +62,7 @@ struct SourceOutputData<'a> {
 struct Dumper<'a> {
     plan: &'a ExecutionPlan,
     schema: &'a schema::DataSchema,
-    pool: &'a PgPool,
+    state_store: &'a SharedStateStore,
     options: EvaluateAndDumpOptions,
 }
 
@@ -85,7 +85,7 @@ impl<'a> Dumper<'a> {
                 enable_cache: self.options.use_cache,
                 evaluation_only: true,
             },
-            self.pool,
+            self.state_store,
         )
         .await?;
 
@@ -227,7 +227,7 @@ pub async fn evaluate_and_dump(
     plan: &ExecutionPlan,
     schema: &schema::DataSchema,
     options: EvaluateAndDumpOptions,
-    pool: &PgPool,
+    state_store: &SharedStateStore,
 ) -> Result<()> {
     let output_dir = Path::new(&options.output_dir);
     if output_dir.exists() {
@@ -241,7 +241,7 @@ pub async fn evaluate_and_dump(
     let dumper = Dumper {
         plan,
         schema,
-        pool,
+        state_store,
         options,
     };
     dumper.evaluate_and_dump().await
@@ -1,17 +1,39 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Mutex, OnceLock};
 use std::{borrow::Cow, collections::BTreeMap};
 
 use anyhow::{bail, Context, Ok, Result};
 use futures::future::try_join_all;
+use futures::{StreamExt, TryStreamExt};
+use tracing::Instrument;
 
 use crate::builder::{plan::*, AnalyzedTransientFlow};
 use crate::py::IntoPyResult;
 use crate::{
     base::{schema, value},
-    utils::immutable::RefList,
+    utils::{fingerprint::Fingerprinter, immutable::RefList},
 };
 
 use super::memoization::{evaluate_with_cell, EvaluationMemory, EvaluationMemoryOptions};
+use super::stats;
+
+/// Context of errors from evaluating a transform op, to identify the failed op from the error.
+#[derive(Debug)]
+pub struct TransformOpEvaluationContext {
+    pub op_name: String,
+}
+
+impl std::fmt::Display for TransformOpEvaluationContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Evaluating Transform op `{}`", self.op_name)
+    }
+}
+
+/// Name of the transform op that caused the error, if any.
+pub fn failed_transform_op_name(err: &anyhow::Error) -> Option<&str> {
+    err.downcast_ref::<TransformOpEvaluationContext>()
+        .map(|ctx| ctx.op_name.as_str())
+}
 
 #[derive(Debug)]
 pub struct ScopeValueBuilder {
@@ -19,6 +41,14 @@ pub struct ScopeValueBuilder {
     pub fields: Vec<OnceLock<value::Value<ScopeValueBuilder>>>,
 
     pub collected_values: Vec<Mutex<Vec<value::FieldValues>>>,
+
+    /// Values collected by the row into collectors of ancestor scopes, as (scope up level, collector index, values).
+    /// They're moved to the parent scope after all rows of the enclosing ForEach op finish, in row order,
+    /// so collected values are in a deterministic order even if rows are evaluated concurrently.
+    collected_values_for_ancestors: Mutex<Vec<(u32, u32, value::FieldValues)>>,
+
+    /// Set when a Filter op on the scope evaluates to false, so remaining ops on the scope are skipped.
+    pub filtered_out: AtomicBool,
 }
 
 impl From<&ScopeValueBuilder> for value::ScopeValue {
@@ -55,6 +85,8 @@ impl ScopeValueBuilder {
         Self {
             fields,
             collected_values,
+            collected_values_for_ancestors: Mutex::new(Vec::new()),
+            filtered_out: AtomicBool::new(false),
         }
     }
 
@@ -248,6 +280,32 @@ impl ScopeEntry<'_> {
             .expect("Field is already set, violating single-definition rule");
     }
 
+    fn define_field_if_unset(
+        &self,
+        output_field: &AnalyzedOpOutput,
+        val: value::Value<ScopeValueBuilder>,
+    ) {
+        let field_index = output_field.field_idx as usize;
+        let index_base = self.key.value_field_index_base() as usize;
+        let _ = self.value.fields[field_index - index_base].set(val);
+    }
+
+    /// Collect values into a collector of this scope (`scope_up_level` is 0) or an ancestor scope.
+    fn collect(&self, scope_up_level: u32, collector_idx: u32, values: value::FieldValues) {
+        if scope_up_level == 0 {
+            self.value.collected_values[collector_idx as usize]
+                .lock()
+                .unwrap()
+                .push(values);
+        } else {
+            self.value
+                .collected_values_for_ancestors
+                .lock()
+                .unwrap()
+                .push((scope_up_level, collector_idx, values));
+        }
+    }
+
     fn define_field(&self, output_field: &AnalyzedOpOutput, val: &value::Value) -> Result<()> {
         let field_index = output_field.field_idx as usize;
         let field_schema = &self.schema.fields[field_index];
@@ -260,8 +318,8 @@ impl ScopeEntry<'_> {
 fn assemble_value(
     value_mapping: &AnalyzedValueMapping,
     scoped_entries: RefList<'_, &ScopeEntry<'_>>,
-) -> value::Value {
-    match value_mapping {
+) -> Result<value::Value> {
+    let result = match value_mapping {
         AnalyzedValueMapping::Constant { value } => value.clone(),
         AnalyzedValueMapping::Field(field_ref) => scoped_entries
             .headn(field_ref.scope_up_level as usize)
@@ -272,70 +330,334 @@ fn assemble_value(
                 .fields
                 .iter()
                 .map(|f| assemble_value(f, scoped_entries))
-                .collect();
+                .collect::<Result<Vec<_>>>()?;
             value::Value::Struct(value::FieldValues { fields })
         }
+        AnalyzedValueMapping::Collection(mapping) => {
+            assemble_collection_value(mapping, scoped_entries)?
+        }
+        AnalyzedValueMapping::Collector(collector_ref) => {
+            let collected_values = scoped_entries.head().unwrap().value.collected_values
+                [collector_ref.collector_idx as usize]
+                .lock()
+                .unwrap();
+            value::Value::Collection(
+                collected_values
+                    .iter()
+                    .map(|v| value::ScopeValue(v.clone()))
+                    .collect(),
+            )
+        }
+    };
+    Ok(result)
+}
+
+/// Scope entries for rows of a collection value. Null collections have no rows.
+fn collection_row_entries<'a>(
+    collection: &'a value::Value<ScopeValueBuilder>,
+    collection_schema: &'a schema::CollectionSchema,
+) -> Result<Vec<ScopeEntry<'a>>> {
+    let row_entries = match collection {
+        value::Value::Null => vec![],
+        value::Value::Collection(v) => v
+            .iter()
+            .map(|item| ScopeEntry {
+                key: ScopeKey::None,
+                value: item,
+                schema: &collection_schema.row,
+            })
+            .collect(),
+        value::Value::Table(v) => v
+            .iter()
+            .map(|(k, v)| ScopeEntry {
+                key: ScopeKey::MapKey(k),
+                value: v,
+                schema: &collection_schema.row,
+            })
+            .collect(),
+        value::Value::List(v) => v
+            .iter()
+            .enumerate()
+            .map(|(i, item)| ScopeEntry {
+                key: ScopeKey::ListIndex(i),
+                value: item,
+                schema: &collection_schema.row,
+            })
+            .collect(),
+        _ => bail!("Value is expected to be a collection"),
+    };
+    Ok(row_entries)
+}
+
+fn assemble_collection_value(
+    mapping: &AnalyzedCollectionMapping,
+    scoped_entries: RefList<'_, &ScopeEntry<'_>>,
+) -> Result<value::Value> {
+    let scope_entry = scoped_entries
+        .headn(mapping.field.scope_up_level as usize)
+        .unwrap();
+    let collection_schema = match &scope_entry
+        .get_field_schema(&mapping.field.local)?
+        .value_type
+        .typ
+    {
+        schema::ValueType::Collection(cs) => cs,
+        _ => bail!("Expect mapped field to be a collection"),
+    };
+    let collection = scope_entry.get_value_field_builder(&mapping.field.local);
+    if collection.is_null() {
+        return Ok(value::Value::Null);
+    }
+    let row_entries = collection_row_entries(collection, collection_schema)?;
+
+    let mut row_values = Vec::with_capacity(row_entries.len());
+    for row_entry in row_entries.iter() {
+        let row_scoped_entries = scoped_entries.prepend(row_entry);
+        if let Some(filter) = &mapping.filter {
+            match assemble_value(filter, row_scoped_entries)? {
+                value::Value::Basic(value::BasicValue::Bool(true)) => {}
+                _ => continue,
+            }
+        }
+        match assemble_value(&mapping.value, row_scoped_entries)? {
+            value::Value::Null => {}
+            v => row_values.push(v),
+        }
     }
+    let result = match mapping.output {
+        AnalyzedCollectionMappingOutput::Vector => value::Value::Basic(value::BasicValue::Vector(
+            row_values
+                .into_iter()
+                .map(|v| match v {
+                    value::Value::Basic(v) => Ok(v),
+                    v => bail!("Expect basic value for vector element, got {v:?}"),
+                })
+                .collect::<Result<Vec<_>>>()?
+                .into(),
+        )),
+        AnalyzedCollectionMappingOutput::List => value::Value::List(
+            row_values
+                .into_iter()
+                .map(|v| match v {
+                    value::Value::Struct(fields) => Ok(value::ScopeValue(fields)),
+                    v => bail!("Expect struct value for list row, got {v:?}"),
+                })
+                .collect::<Result<Vec<_>>>()?,
+        ),
+    };
+    Ok(result)
 }
 
 fn assemble_input_values<'a>(
     value_mappings: &'a [AnalyzedValueMapping],
     scoped_entries: RefList<'a, &ScopeEntry<'a>>,
-) -> impl Iterator<Item = value::Value> + 'a {
+) -> impl Iterator<Item = Result<value::Value>> + 'a {
     value_mappings
         .iter()
         .map(move |value_mapping| assemble_value(value_mapping, scoped_entries))
 }
 
+/// Identifies the source row being evaluated, attached to tracing spans.
+/// Created once per source row, as computing the key fingerprint isn't free.
+pub struct SourceRowTraceInfo<'a> {
+    pub flow_name: &'a str,
+    pub source_name: &'a str,
+    pub key: &'a value::KeyValue,
+    pub key_fingerprint: String,
+}
+
+impl<'a> SourceRowTraceInfo<'a> {
+    pub fn new(
+        plan: &'a ExecutionPlan,
+        import_op: &'a AnalyzedImportOp,
+        key: &'a value::KeyValue,
+    ) -> Result<Self> {
+        Ok(Self {
+            flow_name: &plan.flow_name,
+            source_name: &import_op.name,
+            key,
+            key_fingerprint: Fingerprinter::default()
+                .with(key)?
+                .into_fingerprint()
+                .to_base64(),
+        })
+    }
+}
+
 async fn evaluate_child_op_scope(
     op_scope: &AnalyzedOpScope,
     scoped_entries: RefList<'_, &ScopeEntry<'_>>,
     child_scope_entry: ScopeEntry<'_>,
     memory: &EvaluationMemory,
+    update_stats: Option<&stats::UpdateStats>,
+    trace_info: Option<&SourceRowTraceInfo<'_>>,
 ) -> Result<()> {
-    evaluate_op_scope(op_scope, scoped_entries.prepend(&child_scope_entry), memory)
-        .await
-        .with_context(|| {
-            format!(
-                "Evaluating in scope with key {}",
-                match child_scope_entry.key.key() {
-                    Some(k) => k.to_string(),
-                    None => "()".to_string(),
-                }
+    evaluate_op_scope(
+        op_scope,
+        scoped_entries.prepend(&child_scope_entry),
+        memory,
+        update_stats,
+        trace_info,
+    )
+    .await
+    .with_context(|| {
+        format!(
+            "Evaluating in scope with key {}",
+            match child_scope_entry.key.key() {
+                Some(k) => k.to_string(),
+                None => "()".to_string(),
+            }
+        )
+    })
+}
+
+async fn evaluate_transform_op(
+    op: &AnalyzedTransformOp,
+    op_scope: &AnalyzedOpScope,
+    scoped_entries: RefList<'_, &ScopeEntry<'_>>,
+    memory: &EvaluationMemory,
+    update_stats: Option<&stats::UpdateStats>,
+) -> Result<()> {
+    let head_scope = *scoped_entries.head().unwrap();
+    let mut input_values = Vec::with_capacity(op.inputs.len());
+    input_values
+        .extend(assemble_input_values(&op.inputs, scoped_entries).collect::<Result<Vec<_>>>()?);
+    let cache_lookup = memory.get_cache_entry(
+        || {
+            Ok(op
+                .function_exec_info
+                .fingerprinter
+                .clone()
+                .with(&input_values)?
+                .into_fingerprint())
+        },
+        &op.function_exec_info.output_type,
+        op.function_exec_info.cache_options,
+    )?;
+    if let Some(cache_lookup) = &cache_lookup {
+        memory
+            .fill_from_shared_cache(
+                cache_lookup,
+                &op.function_exec_info.output_type,
+                &op.function_exec_info.cache_options,
             )
-        })
+            .await?;
+    }
+    let op_stats = update_stats.map(|update_stats| update_stats.ops.get(&op.name));
+    let mut evaluated = false;
+    let output_value_cell = cache_lookup.as_ref().map(|lookup| &lookup.cell);
+    let output_value = evaluate_with_cell(output_value_cell, || {
+        evaluated = true;
+        let op_stats = op_stats.clone();
+        async move {
+            let _permit = match &op_scope.concurrency_controller {
+                Some(controller) => Some(controller.acquire().await?),
+                None => None,
+            };
+            let start_time = std::time::Instant::now();
+            let result =
+                stats::with_op_stats(op_stats.clone(), op.executor.evaluate(input_values)).await;
+            if let Some(op_stats) = &op_stats {
+                op_stats.latency.observe(start_time.elapsed().as_secs_f64());
+            }
+            result
+        }
+    })
+    .await
+    .with_context(|| TransformOpEvaluationContext {
+        op_name: op.name.clone(),
+    })?;
+    match &cache_lookup {
+        Some(cache_lookup) if evaluated => {
+            memory
+                .save_to_shared_cache(
+                    cache_lookup.key,
+                    &output_value,
+                    &op.function_exec_info.cache_options,
+                )
+                .await?
+        }
+        _ => {}
+    }
+    if cache_lookup.is_some() {
+        tracing::Span::current().record("cache_hit", !evaluated);
+    }
+    if let (Some(cache_lookup), Some(op_stats)) = (&cache_lookup, &op_stats) {
+        if !evaluated {
+            op_stats.num_cache_hits.inc(1);
+        } else {
+            op_stats.num_cache_misses.inc(1);
+            if cache_lookup.expired {
+                op_stats.num_cache_expirations.inc(1);
+            }
+        }
+    }
+    head_scope.define_field(&op.output, &output_value)?;
+    Ok(())
+}
+
+/// Define fields of ops skipped on the scope (and rows of collections they iterate) as null.
+fn skip_ops(reactive_ops: &[AnalyzedReactiveOp], scope_entry: &ScopeEntry<'_>) -> Result<()> {
+    for reactive_op in reactive_ops.iter() {
+        match reactive_op {
+            AnalyzedReactiveOp::Transform(op) => {
+                // The field may be shared with an op in the matched Switch case, which already set it.
+                scope_entry.define_field_if_unset(&op.output, value::Value::Null);
+            }
+            AnalyzedReactiveOp::ForEach(op) => {
+                let collection_schema = match &scope_entry
+                    .get_field_schema(&op.local_field_ref)?
+                    .value_type
+                    .typ
+                {
+                    schema::ValueType::Collection(cs) => cs,
+                    _ => bail!("Expect target field to be a collection"),
+                };
+                let row_entries = collection_row_entries(
+                    scope_entry.get_value_field_builder(&op.local_field_ref),
+                    collection_schema,
+                )?;
+                for row_entry in row_entries.iter() {
+                    skip_ops(&op.op_scope.reactive_ops, row_entry)?;
+                }
+            }
+            AnalyzedReactiveOp::Collect(_) | AnalyzedReactiveOp::Filter(_) => {}
+            AnalyzedReactiveOp::Switch(op) => {
+                for case in op.cases.iter() {
+                    skip_ops(&case.op_scope.reactive_ops, scope_entry)?;
+                }
+                skip_ops(&op.default_op_scope.reactive_ops, scope_entry)?;
+            }
+        }
+    }
+    Ok(())
 }
 
 async fn evaluate_op_scope(
     op_scope: &AnalyzedOpScope,
     scoped_entries: RefList<'_, &ScopeEntry<'_>>,
     memory: &EvaluationMemory,
+    update_stats: Option<&stats::UpdateStats>,
+    trace_info: Option<&SourceRowTraceInfo<'_>>,
 ) -> Result<()> {
     let head_scope = *scoped_entries.head().unwrap();
-    for reactive_op in op_scope.reactive_ops.iter() {
+    for (op_idx, reactive_op) in op_scope.reactive_ops.iter().enumerate() {
+        if head_scope.value.filtered_out.load(Ordering::Acquire) {
+            return skip_ops(&op_scope.reactive_ops[op_idx..], head_scope);
+        }
         match reactive_op {
             AnalyzedReactiveOp::Transform(op) => {
-                let mut input_values = Vec::with_capacity(op.inputs.len());
-                input_values
-                    .extend(assemble_input_values(&op.inputs, scoped_entries).collect::<Vec<_>>());
-                let output_value_cell = memory.get_cache_entry(
-                    || {
-                        Ok(op
-                            .function_exec_info
-                            .fingerprinter
-                            .clone()
-                            .with(&input_values)?
-                            .into_fingerprint())
-                    },
-                    &op.function_exec_info.output_type,
-                    /*ttl=*/ None,
-                )?;
-                let output_value = evaluate_with_cell(output_value_cell.as_ref(), move || {
-                    op.executor.evaluate(input_values)
-                })
-                .await
-                .with_context(|| format!("Evaluating Transform op `{}`", op.name,))?;
-                head_scope.define_field(&op.output, &output_value)?;
+                let span = tracing::info_span!(
+                    "evaluate_op",
+                    flow = trace_info.map(|info| info.flow_name),
+                    source = trace_info.map(|info| info.source_name),
+                    key_fingerprint = trace_info.map(|info| info.key_fingerprint.as_str()),
+                    op = op.name.as_str(),
+                    cache_hit = tracing::field::Empty,
+                );
+                evaluate_transform_op(op, op_scope, scoped_entries, memory, update_stats)
+                    .instrument(span)
+                    .await?;
             }
 
             AnalyzedReactiveOp::ForEach(op) => {
@@ -359,6 +681,8 @@ async fn evaluate_op_scope(
                                     schema: &collection_schema.row,
                                 },
                                 memory,
+                                update_stats,
+                                trace_info,
                             )
                         })
                         .collect::<Vec<_>>(),
@@ -374,6 +698,8 @@ async fn evaluate_op_scope(
                                     schema: &collection_schema.row,
                                 },
                                 memory,
+                                update_stats,
+                                trace_info,
                             )
                         })
                         .collect::<Vec<_>>(),
@@ -390,23 +716,47 @@ async fn evaluate_op_scope(
                                     schema: &collection_schema.row,
                                 },
                                 memory,
+                                update_stats,
+                                trace_info,
                             )
                         })
                         .collect::<Vec<_>>(),
+                    value::Value::Null => vec![],
                     _ => {
                         bail!("Target field type is expected to be a collection");
                     }
                 };
-                try_join_all(task_futs)
-                    .await
-                    .with_context(|| format!("Evaluating ForEach op `{}`", op.name,))?;
+                match op.max_concurrency {
+                    Some(max_concurrency) => {
+                        futures::stream::iter(task_futs)
+                            .buffer_unordered(max_concurrency)
+                            .try_collect::<Vec<_>>()
+                            .await
+                    }
+                    None => try_join_all(task_futs).await,
+                }
+                .with_context(|| format!("Evaluating ForEach op `{}`", op.name,))?;
+
+                for row_entry in collection_row_entries(target_field, collection_schema)? {
+                    let collected = std::mem::take(
+                        &mut *row_entry
+                            .value
+                            .collected_values_for_ancestors
+                            .lock()
+                            .unwrap(),
+                    );
+                    for (scope_up_level, collector_idx, values) in collected {
+                        head_scope.collect(scope_up_level - 1, collector_idx, values);
+                    }
+                }
             }
 
             AnalyzedReactiveOp::Collect(op) => {
                 let mut field_values = Vec::with_capacity(
                     op.input.fields.len() + if op.has_auto_uuid_field { 1 } else { 0 },
                 );
-                let field_values_iter = assemble_input_values(&op.input.fields, scoped_entries);
+                let field_values_iter = assemble_input_values(&op.input.fields, scoped_entries)
+                    .collect::<Result<Vec<_>>>()?;
                 if op.has_auto_uuid_field {
                     field_values.push(value::Value::Null);
                     field_values.extend(field_values_iter);
@@ -420,17 +770,54 @@ async fn evaluate_op_scope(
                 } else {
                     field_values.extend(field_values_iter);
                 };
-                let collector_entry = scoped_entries
-                    .headn(op.collector_ref.scope_up_level as usize)
-                    .unwrap();
-                {
-                    let mut collected_records = collector_entry.value.collected_values
-                        [op.collector_ref.local.collector_idx as usize]
-                        .lock()
-                        .unwrap();
-                    collected_records.push(value::FieldValues {
+                head_scope.collect(
+                    op.collector_ref.scope_up_level,
+                    op.collector_ref.local.collector_idx,
+                    value::FieldValues {
                         fields: field_values,
-                    });
+                    },
+                );
+            }
+
+            AnalyzedReactiveOp::Filter(op) => {
+                match assemble_value(&op.condition, scoped_entries)? {
+                    value::Value::Basic(value::BasicValue::Bool(true)) => {}
+                    _ => head_scope.value.filtered_out.store(true, Ordering::Release),
+                }
+            }
+
+            AnalyzedReactiveOp::Switch(op) => {
+                let input = assemble_value(&op.input, scoped_entries)?;
+                let input_key = if input.is_null() {
+                    None
+                } else {
+                    Some(input.into_key()?)
+                };
+                let matched_op_scope = op
+                    .cases
+                    .iter()
+                    .find(|case| input_key.as_ref() == Some(&case.value))
+                    .map(|case| &case.op_scope)
+                    .unwrap_or(&op.default_op_scope);
+                Box::pin(evaluate_op_scope(
+                    matched_op_scope,
+                    scoped_entries,
+                    memory,
+                    update_stats,
+                    trace_info,
+                ))
+                .await
+                .with_context(|| format!("Evaluating Switch op `{}`", op.name))?;
+                // Skipped after the matched case is evaluated, as fields may be shared across cases.
+                for op_scope in op
+                    .cases
+                    .iter()
+                    .map(|case| &case.op_scope)
+                    .chain(std::iter::once(&op.default_op_scope))
+                {
+                    if !std::ptr::eq(op_scope, matched_op_scope) {
+                        skip_ops(&op_scope.reactive_ops, head_scope)?;
+                    }
                 }
             }
         }
@@ -442,9 +829,10 @@ pub async fn evaluate_source_entry(
     plan: &ExecutionPlan,
     import_op: &AnalyzedImportOp,
     schema: &schema::DataSchema,
-    key: &value::KeyValue,
+    trace_info: &SourceRowTraceInfo<'_>,
     source_value: value::FieldValues,
     memory: &EvaluationMemory,
+    update_stats: Option<&stats::UpdateStats>,
 ) -> Result<ScopeValueBuilder> {
     let root_schema = &schema.schema;
     let root_scope_value =
@@ -469,13 +857,15 @@ pub async fn evaluate_source_entry(
         ScopeValueBuilder::augmented_from(&value::ScopeValue(source_value), collection_schema)?;
     root_scope_entry.define_field_w_builder(
         &import_op.output,
-        value::Value::Table(BTreeMap::from([(key.clone(), scope_value)])),
+        value::Value::Table(BTreeMap::from([(trace_info.key.clone(), scope_value)])),
     );
 
     evaluate_op_scope(
         &plan.op_scope,
         RefList::Nil.prepend(&root_scope_entry),
         memory,
+        update_stats,
+        Some(trace_info),
     )
     .await?;
     Ok(root_scope_value)
@@ -507,6 +897,7 @@ pub async fn evaluate_transient_flow(
     let eval_memory = EvaluationMemory::new(
         chrono::Utc::now(),
         None,
+        None,
         EvaluationMemoryOptions {
             enable_cache: false,
             evaluation_only: true,
@@ -516,11 +907,571 @@ pub async fn evaluate_transient_flow(
         &flow.execution_plan.op_scope,
         RefList::Nil.prepend(&root_scope_entry),
         &eval_memory,
+        None,
+        None,
     )
     .await?;
     let output_value = assemble_value(
         &flow.execution_plan.output_value,
         RefList::Nil.prepend(&root_scope_entry),
-    );
+    )?;
     Ok(output_value)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::interface::SimpleFunctionExecutor;
+    use async_trait::async_trait;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    struct UnreachableExecutor;
+
+    #[async_trait]
+    impl SimpleFunctionExecutor for UnreachableExecutor {
+        async fn evaluate(&self, _args: Vec<value::Value>) -> Result<value::Value> {
+            unreachable!("skipped ops are never evaluated")
+        }
+    }
+
+    fn str_field(name: &str) -> schema::FieldSchema {
+        schema::FieldSchema::new(
+            name,
+            schema::EnrichedValueType {
+                typ: schema::ValueType::Basic(schema::BasicValueType::Str),
+                nullable: true,
+                attrs: Default::default(),
+            },
+        )
+    }
+
+    fn struct_schema(fields: Vec<schema::FieldSchema>) -> schema::StructSchema {
+        schema::StructSchema {
+            fields: Arc::new(fields),
+            description: None,
+        }
+    }
+
+    fn op_scope(reactive_ops: Vec<AnalyzedReactiveOp>) -> AnalyzedOpScope {
+        AnalyzedOpScope {
+            reactive_ops,
+            concurrency_controller: None,
+        }
+    }
+
+    /// Echoes its input, a row index, finishing later for earlier rows.
+    struct ReverseDelayExecutor {
+        num_rows: u64,
+    }
+
+    #[async_trait]
+    impl SimpleFunctionExecutor for ReverseDelayExecutor {
+        async fn evaluate(&self, args: Vec<value::Value>) -> Result<value::Value> {
+            let row_idx: u64 = args[0].as_str()?.parse()?;
+            tokio::time::sleep(std::time::Duration::from_millis(
+                (self.num_rows - row_idx) * 10,
+            ))
+            .await;
+            Ok(args[0].clone())
+        }
+    }
+
+    fn transform(name: &str, field_idx: u32) -> AnalyzedReactiveOp {
+        transform_with(name, vec![], Box::new(UnreachableExecutor), field_idx)
+    }
+
+    fn transform_with(
+        name: &str,
+        inputs: Vec<AnalyzedValueMapping>,
+        executor: Box<dyn SimpleFunctionExecutor>,
+        field_idx: u32,
+    ) -> AnalyzedReactiveOp {
+        AnalyzedReactiveOp::Transform(AnalyzedTransformOp {
+            name: name.to_string(),
+            inputs,
+            function_exec_info: AnalyzedFunctionExecInfo {
+                enable_cache: false,
+                behavior_version: None,
+                fingerprinter: Fingerprinter::default(),
+                output_type: schema::ValueType::Basic(schema::BasicValueType::Str),
+                cache_options: Default::default(),
+            },
+            executor,
+            output: AnalyzedOpOutput { field_idx },
+        })
+    }
+
+    fn field_ref(scope_up_level: u32, field_idx: u32) -> AnalyzedValueMapping {
+        AnalyzedValueMapping::Field(AnalyzedFieldReference {
+            local: AnalyzedLocalFieldReference {
+                fields_idx: vec![field_idx],
+            },
+            scope_up_level,
+        })
+    }
+
+    fn str_value(s: &str) -> value::Value<ScopeValueBuilder> {
+        value::Value::Basic(value::BasicValue::Str(s.into()))
+    }
+
+    fn is_null(field: &OnceLock<value::Value<ScopeValueBuilder>>) -> bool {
+        matches!(field.get(), Some(value::Value::Null))
+    }
+
+    // Root fields: rows (collection of `x` and `y`), a, b, c.
+    fn root_schema() -> schema::StructSchema {
+        let rows_schema = schema::CollectionSchema::new(
+            schema::CollectionKind::Collection,
+            struct_schema(vec![str_field("x"), str_field("y")]),
+        );
+        struct_schema(vec![
+            schema::FieldSchema::new(
+                "rows",
+                schema::EnrichedValueType {
+                    typ: schema::ValueType::Collection(rows_schema),
+                    nullable: true,
+                    attrs: Default::default(),
+                },
+            ),
+            str_field("a"),
+            str_field("b"),
+            str_field("c"),
+        ])
+    }
+
+    /// Ops defining all fields except `rows` and `x`, nested in a switch and a for-each.
+    fn reactive_ops() -> Vec<AnalyzedReactiveOp> {
+        vec![
+            AnalyzedReactiveOp::Filter(AnalyzedFilterOp {
+                name: "filter".to_string(),
+                condition: AnalyzedValueMapping::Constant {
+                    value: value::Value::Basic(value::BasicValue::Bool(true)),
+                },
+            }),
+            AnalyzedReactiveOp::ForEach(AnalyzedForEachOp {
+                name: "for_each".to_string(),
+                local_field_ref: AnalyzedLocalFieldReference {
+                    fields_idx: vec![0],
+                },
+                op_scope: op_scope(vec![transform("y", 1)]),
+                max_concurrency: None,
+            }),
+            AnalyzedReactiveOp::Switch(AnalyzedSwitchOp {
+                name: "switch".to_string(),
+                input: AnalyzedValueMapping::Constant {
+                    value: value::Value::Null,
+                },
+                cases: vec![AnalyzedSwitchCase {
+                    value: value::KeyValue::Str("case".into()),
+                    op_scope: op_scope(vec![transform("a", 1)]),
+                }],
+                default_op_scope: op_scope(vec![transform("b", 2)]),
+            }),
+            transform("c", 3),
+        ]
+    }
+
+    #[test]
+    fn skipped_ops_define_null_fields() {
+        let schema = root_schema();
+        let rows = (0..2)
+            .map(|_| {
+                let row = ScopeValueBuilder::new(2, 0);
+                row.fields[0].set(str_value("x")).unwrap();
+                row
+            })
+            .collect::<Vec<_>>();
+        let root = ScopeValueBuilder::new(4, 0);
+        root.fields[0].set(value::Value::Collection(rows)).unwrap();
+        let root_entry = ScopeEntry {
+            key: ScopeKey::None,
+            value: &root,
+            schema: &schema,
+        };
+
+        skip_ops(&reactive_ops(), &root_entry).unwrap();
+
+        assert!(root.fields[1..].iter().all(is_null));
+        let rows = match root.fields[0].get() {
+            Some(value::Value::Collection(rows)) => rows,
+            v => panic!("unexpected rows: {v:?}"),
+        };
+        for row in rows.iter() {
+            assert!(matches!(row.fields[0].get(), Some(value::Value::Basic(_))));
+            assert!(is_null(&row.fields[1]));
+        }
+    }
+
+    #[test]
+    fn skipped_for_each_on_null_collection() {
+        let schema = root_schema();
+        let root = ScopeValueBuilder::new(4, 0);
+        root.fields[0].set(value::Value::Null).unwrap();
+        let root_entry = ScopeEntry {
+            key: ScopeKey::None,
+            value: &root,
+            schema: &schema,
+        };
+
+        skip_ops(&reactive_ops(), &root_entry).unwrap();
+        assert!(root.fields.iter().all(is_null));
+    }
+
+    #[tokio::test]
+    async fn collected_values_in_row_order() {
+        let schema = root_schema();
+        let num_rows = 5;
+        let rows = (0..num_rows)
+            .map(|i| {
+                let row = ScopeValueBuilder::new(2, 0);
+                row.fields[0].set(str_value(&i.to_string())).unwrap();
+                row
+            })
+            .collect::<Vec<_>>();
+        let root = ScopeValueBuilder::new(4, 1);
+        root.fields[0].set(value::Value::Collection(rows)).unwrap();
+        let root_entry = ScopeEntry {
+            key: ScopeKey::None,
+            value: &root,
+            schema: &schema,
+        };
+        // Rows finish in reverse order, and each collects its `y` into the root collector.
+        let root_op_scope = op_scope(vec![AnalyzedReactiveOp::ForEach(AnalyzedForEachOp {
+            name: "for_each".to_string(),
+            local_field_ref: AnalyzedLocalFieldReference {
+                fields_idx: vec![0],
+            },
+            op_scope: op_scope(vec![
+                transform_with(
+                    "y",
+                    vec![field_ref(0, 0)],
+                    Box::new(ReverseDelayExecutor { num_rows }),
+                    1,
+                ),
+                AnalyzedReactiveOp::Collect(AnalyzedCollectOp {
+                    name: "collect".to_string(),
+                    has_auto_uuid_field: false,
+                    input: AnalyzedStructMapping {
+                        fields: vec![field_ref(0, 1)],
+                    },
+                    collector_ref: AnalyzedCollectorReference {
+                        local: AnalyzedLocalCollectorReference { collector_idx: 0 },
+                        scope_up_level: 1,
+                    },
+                    fingerprinter: Fingerprinter::default(),
+                }),
+            ]),
+            max_concurrency: Some(num_rows as usize),
+        })]);
+        let memory = EvaluationMemory::new(
+            chrono::Utc::now(),
+            None,
+            None,
+            EvaluationMemoryOptions {
+                enable_cache: false,
+                evaluation_only: true,
+            },
+        );
+
+        evaluate_op_scope(
+            &root_op_scope,
+            RefList::Nil.prepend(&root_entry),
+            &memory,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        let collected = root.collected_values[0].lock().unwrap();
+        let collected = collected
+            .iter()
+            .map(|values| values.fields[0].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(collected, vec!["0", "1", "2", "3", "4"]);
+    }
+
+    struct EchoExecutor;
+
+    #[async_trait]
+    impl SimpleFunctionExecutor for EchoExecutor {
+        async fn evaluate(&self, args: Vec<value::Value>) -> Result<value::Value> {
+            Ok(args[0].clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn switch_cases_sharing_output_field() {
+        let schema = root_schema();
+        let root = ScopeValueBuilder::new(4, 0);
+        root.fields[0].set(value::Value::Null).unwrap();
+        let root_entry = ScopeEntry {
+            key: ScopeKey::None,
+            value: &root,
+            schema: &schema,
+        };
+        let str_constant = |s: &str| AnalyzedValueMapping::Constant {
+            value: value::Value::Basic(value::BasicValue::Str(s.into())),
+        };
+        // All cases define `a`; the matched one is not the first.
+        let root_op_scope = op_scope(vec![AnalyzedReactiveOp::Switch(AnalyzedSwitchOp {
+            name: "switch".to_string(),
+            input: str_constant("second"),
+            cases: vec![
+                AnalyzedSwitchCase {
+                    value: value::KeyValue::Str("first".into()),
+                    op_scope: op_scope(vec![transform("a", 1)]),
+                },
+                AnalyzedSwitchCase {
+                    value: value::KeyValue::Str("second".into()),
+                    op_scope: op_scope(vec![transform_with(
+                        "a",
+                        vec![str_constant("matched")],
+                        Box::new(EchoExecutor),
+                        1,
+                    )]),
+                },
+            ],
+            default_op_scope: op_scope(vec![transform("a", 1), transform("b", 2)]),
+        })]);
+        let memory = EvaluationMemory::new(
+            chrono::Utc::now(),
+            None,
+            None,
+            EvaluationMemoryOptions {
+                enable_cache: false,
+                evaluation_only: true,
+            },
+        );
+
+        evaluate_op_scope(
+            &root_op_scope,
+            RefList::Nil.prepend(&root_entry),
+            &memory,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            root.fields[1].get(),
+            Some(value::Value::Basic(value::BasicValue::Str(s))) if s.as_ref() == "matched"
+        ));
+        assert!(is_null(&root.fields[2]));
+        assert!(root.fields[3].get().is_none());
+    }
+
+    fn bool_value(b: bool) -> value::Value<ScopeValueBuilder> {
+        value::Value::Basic(value::BasicValue::Bool(b))
+    }
+
+    // Root fields: title, docs (table of `id`, `text` and `keep`), items (list of `name`).
+    fn collections_root_schema() -> schema::StructSchema {
+        let collection_field = |name: &str, kind, fields| {
+            schema::FieldSchema::new(
+                name,
+                schema::EnrichedValueType {
+                    typ: schema::ValueType::Collection(schema::CollectionSchema::new(
+                        kind,
+                        struct_schema(fields),
+                    )),
+                    nullable: true,
+                    attrs: Default::default(),
+                },
+            )
+        };
+        struct_schema(vec![
+            str_field("title"),
+            collection_field(
+                "docs",
+                schema::CollectionKind::Table,
+                vec![
+                    str_field("id"),
+                    str_field("text"),
+                    schema::FieldSchema::new(
+                        "keep",
+                        schema::EnrichedValueType {
+                            typ: schema::ValueType::Basic(schema::BasicValueType::Bool),
+                            nullable: true,
+                            attrs: Default::default(),
+                        },
+                    ),
+                ],
+            ),
+            collection_field(
+                "items",
+                schema::CollectionKind::List,
+                vec![str_field("name")],
+            ),
+        ])
+    }
+
+    /// Root value with docs `(id, text, keep)` and item names.
+    fn collections_root(
+        docs: Option<Vec<(&str, Option<&str>, Option<bool>)>>,
+        items: Vec<Option<&str>>,
+    ) -> ScopeValueBuilder {
+        let root = ScopeValueBuilder::new(3, 0);
+        root.fields[0].set(str_value("title")).unwrap();
+        let docs = match docs {
+            Some(docs) => value::Value::Table(
+                docs.into_iter()
+                    .map(|(id, text, keep)| {
+                        let row = ScopeValueBuilder::new(2, 0);
+                        row.fields[0]
+                            .set(text.map(str_value).unwrap_or(value::Value::Null))
+                            .unwrap();
+                        row.fields[1]
+                            .set(keep.map(bool_value).unwrap_or(value::Value::Null))
+                            .unwrap();
+                        (value::KeyValue::Str(id.into()), row)
+                    })
+                    .collect(),
+            ),
+            None => value::Value::Null,
+        };
+        root.fields[1].set(docs).unwrap();
+        let items = items
+            .into_iter()
+            .map(|name| {
+                let row = ScopeValueBuilder::new(1, 0);
+                row.fields[0]
+                    .set(name.map(str_value).unwrap_or(value::Value::Null))
+                    .unwrap();
+                row
+            })
+            .collect();
+        root.fields[2].set(value::Value::List(items)).unwrap();
+        root
+    }
+
+    fn assemble_collection(
+        root: &ScopeValueBuilder,
+        mapping: &AnalyzedCollectionMapping,
+    ) -> Result<value::Value> {
+        let schema = collections_root_schema();
+        let root_entry = ScopeEntry {
+            key: ScopeKey::None,
+            value: root,
+            schema: &schema,
+        };
+        assemble_collection_value(mapping, RefList::Nil.prepend(&root_entry))
+    }
+
+    fn collection_mapping(
+        field_idx: u32,
+        value: AnalyzedValueMapping,
+        filter: Option<AnalyzedValueMapping>,
+        output: AnalyzedCollectionMappingOutput,
+    ) -> AnalyzedCollectionMapping {
+        AnalyzedCollectionMapping {
+            field: AnalyzedFieldReference {
+                local: AnalyzedLocalFieldReference {
+                    fields_idx: vec![field_idx],
+                },
+                scope_up_level: 0,
+            },
+            value: Box::new(value),
+            filter: filter.map(Box::new),
+            output,
+        }
+    }
+
+    /// Strings in a vector, as JSON.
+    fn str_vector(value: value::Value) -> serde_json::Value {
+        assert!(
+            matches!(value, value::Value::Basic(value::BasicValue::Vector(_))),
+            "expected a vector, got {value:?}"
+        );
+        serde_json::to_value(value).unwrap()
+    }
+
+    #[test]
+    fn collection_mapping_filters_rows_and_skips_null_values() {
+        let root = collections_root(
+            Some(vec![
+                ("k1", Some("a"), Some(true)),
+                ("k2", None, Some(true)),
+                ("k3", Some("c"), Some(false)),
+                ("k4", Some("d"), None),
+                ("k5", Some("e"), Some(true)),
+            ]),
+            vec![],
+        );
+        let texts = collection_mapping(
+            1,
+            field_ref(0, 1),
+            Some(field_ref(0, 2)),
+            AnalyzedCollectionMappingOutput::Vector,
+        );
+        assert_eq!(
+            str_vector(assemble_collection(&root, &texts).unwrap()),
+            json!(["a", "e"])
+        );
+
+        // Keys of table rows can be mapped too.
+        let ids = collection_mapping(
+            1,
+            field_ref(0, 0),
+            Some(field_ref(0, 2)),
+            AnalyzedCollectionMappingOutput::Vector,
+        );
+        assert_eq!(
+            str_vector(assemble_collection(&root, &ids).unwrap()),
+            json!(["k1", "k2", "k5"])
+        );
+
+        let all_texts = collection_mapping(
+            1,
+            field_ref(0, 1),
+            None,
+            AnalyzedCollectionMappingOutput::Vector,
+        );
+        assert_eq!(
+            str_vector(assemble_collection(&root, &all_texts).unwrap()),
+            json!(["a", "c", "d", "e"])
+        );
+    }
+
+    #[test]
+    fn collection_mapping_of_structs_outputs_list() {
+        let root = collections_root(None, vec![Some("x"), None]);
+        let mapping = collection_mapping(
+            2,
+            AnalyzedValueMapping::Struct(AnalyzedStructMapping {
+                fields: vec![field_ref(0, 0), field_ref(1, 0)],
+            }),
+            None,
+            AnalyzedCollectionMappingOutput::List,
+        );
+        let value = assemble_collection(&root, &mapping).unwrap();
+        assert!(matches!(value, value::Value::List(_)), "{value:?}");
+        // Structs with null fields are still kept.
+        assert_eq!(
+            serde_json::to_value(value).unwrap(),
+            json!([["x", "title"], [null, "title"]])
+        );
+    }
+
+    #[test]
+    fn collection_mapping_on_null_or_mismatched_output() {
+        let root = collections_root(None, vec![Some("x")]);
+        let texts = collection_mapping(
+            1,
+            field_ref(0, 1),
+            None,
+            AnalyzedCollectionMappingOutput::Vector,
+        );
+        assert!(assemble_collection(&root, &texts).unwrap().is_null());
+
+        let names_as_list = collection_mapping(
+            2,
+            field_ref(0, 0),
+            None,
+            AnalyzedCollectionMappingOutput::List,
+        );
+        assert!(assemble_collection(&root, &names_as_list).is_err());
+    }
+}
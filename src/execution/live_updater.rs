@@ -3,14 +3,83 @@ use std::time::Instant;
 use crate::prelude::*;
 
 use super::stats;
+use crate::state_store::SharedStateStore;
 use futures::future::try_join_all;
-use sqlx::PgPool;
 use tokio::{task::JoinSet, time::MissedTickBehavior};
 
 pub struct FlowLiveUpdater {
     flow_ctx: Arc<FlowContext>,
     tasks: JoinSet<Result<()>>,
     sources_update_stats: Vec<Arc<stats::UpdateStats>>,
+    control: Arc<LiveUpdaterControl>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum LiveUpdaterStatus {
+    Running,
+    /// No new source rows are started. Rows in progress are still finished.
+    Paused,
+    /// No new source rows are started, and the updater stops once rows in progress are finished.
+    Draining,
+}
+
+/// Controls a live updater while it's running, shared with row processing of its sources.
+pub struct LiveUpdaterControl {
+    status: tokio::sync::watch::Sender<LiveUpdaterStatus>,
+}
+
+impl Default for LiveUpdaterControl {
+    fn default() -> Self {
+        Self {
+            status: tokio::sync::watch::Sender::new(LiveUpdaterStatus::Running),
+        }
+    }
+}
+
+impl LiveUpdaterControl {
+    pub fn status(&self) -> LiveUpdaterStatus {
+        *self.status.borrow()
+    }
+
+    pub fn pause(&self) {
+        self.status.send_if_modified(|status| {
+            let modified = *status == LiveUpdaterStatus::Running;
+            if modified {
+                *status = LiveUpdaterStatus::Paused;
+            }
+            modified
+        });
+    }
+
+    pub fn resume(&self) {
+        self.status.send_if_modified(|status| {
+            let modified = *status == LiveUpdaterStatus::Paused;
+            if modified {
+                *status = LiveUpdaterStatus::Running;
+            }
+            modified
+        });
+    }
+
+    pub fn drain(&self) {
+        self.status.send_replace(LiveUpdaterStatus::Draining);
+    }
+
+    /// Wait while paused. Returns false if draining, i.e. nothing new should be started.
+    pub async fn wait_running(&self) -> bool {
+        let mut receiver = self.status.subscribe();
+        receiver
+            .wait_for(|status| *status != LiveUpdaterStatus::Paused)
+            .await
+            .is_ok_and(|status| *status == LiveUpdaterStatus::Running)
+    }
+
+    pub async fn wait_draining(&self) {
+        let mut receiver = self.status.subscribe();
+        let _ = receiver
+            .wait_for(|status| *status == LiveUpdaterStatus::Draining)
+            .await;
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -21,6 +90,16 @@ pub struct FlowLiveUpdaterOptions {
 
     /// If true, stats will be printed to the console.
     pub print_stats: bool,
+
+    /// If true, the updater only reports changes to apply from the source, without evaluating or writing anything.
+    /// `live_mode` is ignored in this mode.
+    pub dry_run: bool,
+
+    /// In dry-run mode, number of source rows to be changed to evaluate, for reporting changes on targets.
+    pub dry_run_sample_size: usize,
+
+    /// If true, rows gone from the source are deleted even if it looks like a mass deletion.
+    pub allow_mass_deletion: bool,
 }
 
 struct StatsReportState {
@@ -36,11 +115,12 @@ async fn update_source(
     plan: Arc<plan::ExecutionPlan>,
     source_update_stats: Arc<stats::UpdateStats>,
     source_idx: usize,
-    pool: PgPool,
+    state_store: SharedStateStore,
     options: FlowLiveUpdaterOptions,
+    control: Arc<LiveUpdaterControl>,
 ) -> Result<()> {
     let source_context = flow_ctx
-        .get_source_indexing_context(source_idx, &pool)
+        .get_source_indexing_context(source_idx, &state_store)
         .await?;
 
     let import_op = &plan.import_ops[source_idx];
@@ -49,13 +129,21 @@ async fn update_source(
         last_report_time: None,
         last_stats: source_update_stats.as_ref().clone(),
     });
-    let report_stats = || {
+    // Reports are throttled unless `force` is true.
+    let report_stats = |force: bool| {
+        source_update_stats
+            .throughput
+            .sample(source_update_stats.num_done_rows());
         let new_stats = source_update_stats.as_ref().clone();
+        let progress = new_stats
+            .progress()
+            .map(|progress| format!(" ({progress})"))
+            .unwrap_or_default();
         let now = Instant::now();
         let delta = {
             let mut state = stats_report_state.lock().unwrap();
             if let Some(last_report_time) = state.last_report_time {
-                if now.duration_since(last_report_time) < MIN_REPORT_INTERVAL {
+                if !force && now.duration_since(last_report_time) < MIN_REPORT_INTERVAL {
                     return;
                 }
             }
@@ -67,49 +155,71 @@ async fn update_source(
             state.last_report_time = Some(now);
             delta
         };
+        let mode_label = if options.dry_run { " (dry run)" } else { "" };
         if options.print_stats {
             println!(
-                "{}.{}: {}",
-                flow_ctx.flow.flow_instance.name, import_op.name, delta
+                "{}.{}{}: {}{}",
+                flow_ctx.flow.flow_instance.name, import_op.name, mode_label, delta, progress
             );
         } else {
             trace!(
-                "{}.{}: {}",
+                "{}.{}{}: {}{}",
                 flow_ctx.flow.flow_instance.name,
                 import_op.name,
-                delta
+                mode_label,
+                delta,
+                progress
             );
         }
     };
 
+    if options.dry_run {
+        source_context
+            .dry_run(
+                &state_store,
+                &source_update_stats,
+                options.dry_run_sample_size,
+                options.allow_mass_deletion,
+            )
+            .await?;
+        report_stats(true);
+        return Ok(());
+    }
+
     let mut futs: Vec<BoxFuture<'_, Result<()>>> = Vec::new();
 
     // Deal with change streams.
     if options.live_mode {
         if let Some(change_stream) = import_op.executor.change_stream().await? {
-            let pool = pool.clone();
+            let state_store = state_store.clone();
             let source_update_stats = source_update_stats.clone();
+            let control = control.clone();
             futs.push(
                 async move {
                     let mut change_stream = change_stream;
-                    while let Some(change) = change_stream.next().await {
-                        source_context
-                            .process_change(change, &pool, &source_update_stats)
-                            .map(tokio::spawn);
-                    }
-                    Ok(())
-                }
-                .boxed(),
-            );
-            futs.push(
-                async move {
-                    let mut interval = tokio::time::interval(REPORT_INTERVAL);
-                    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
-                    interval.tick().await;
+                    let mut join_set = JoinSet::new();
                     loop {
-                        interval.tick().await;
-                        report_stats();
+                        let change = tokio::select! {
+                            change = change_stream.next() => change,
+                            _ = control.wait_draining() => None,
+                        };
+                        let change = match change {
+                            Some(change) => change,
+                            None => break,
+                        };
+                        if let Some(fut) = source_context.process_change(
+                            change,
+                            &state_store,
+                            &source_update_stats,
+                            &control,
+                        ) {
+                            join_set.spawn(fut);
+                        }
+                        while join_set.try_join_next().is_some() {}
                     }
+                    // Finish rows in progress before stopping.
+                    while join_set.join_next().await.is_some() {}
+                    Ok(())
                 }
                 .boxed(),
             );
@@ -120,8 +230,15 @@ async fn update_source(
     let source_update_stats = source_update_stats.clone();
     futs.push(
         async move {
-            source_context.update(&pool, &source_update_stats).await?;
-            report_stats();
+            source_context
+                .update(
+                    &state_store,
+                    &source_update_stats,
+                    options.allow_mass_deletion,
+                    &control,
+                )
+                .await?;
+            report_stats(true);
 
             if let (true, Some(refresh_interval)) = (
                 options.live_mode,
@@ -131,9 +248,22 @@ async fn update_source(
                 interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
                 interval.tick().await;
                 loop {
-                    interval.tick().await;
-                    source_context.update(&pool, &source_update_stats).await?;
-                    report_stats();
+                    tokio::select! {
+                        _ = interval.tick() => {},
+                        _ = control.wait_draining() => break,
+                    }
+                    if !control.wait_running().await {
+                        break;
+                    }
+                    source_context
+                        .update(
+                            &state_store,
+                            &source_update_stats,
+                            options.allow_mass_deletion,
+                            &control,
+                        )
+                        .await?;
+                    report_stats(true);
                 }
             }
             Ok(())
@@ -141,17 +271,33 @@ async fn update_source(
         .boxed(),
     );
 
-    try_join_all(futs).await?;
+    // Report stats periodically, e.g. progress of a long scan, until all above are finished.
+    let report_periodically = async {
+        let mut interval = tokio::time::interval(REPORT_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            report_stats(false);
+        }
+    };
+    tokio::select! {
+        result = try_join_all(futs) => {
+            result?;
+        }
+        _ = report_periodically => {}
+    }
     Ok(())
 }
 
 impl FlowLiveUpdater {
     pub async fn start(
         flow_ctx: Arc<FlowContext>,
-        pool: &PgPool,
+        state_store: &SharedStateStore,
         options: FlowLiveUpdaterOptions,
     ) -> Result<Self> {
         let plan = flow_ctx.flow.get_execution_plan().await?;
+        let control = Arc::new(LiveUpdaterControl::default());
 
         let mut tasks = JoinSet::new();
         let sources_update_stats = (0..plan.import_ops.len())
@@ -162,16 +308,19 @@ impl FlowLiveUpdater {
                     plan.clone(),
                     source_update_stats.clone(),
                     source_idx,
-                    pool.clone(),
+                    state_store.clone(),
                     options.clone(),
+                    control.clone(),
                 ));
                 source_update_stats
             })
-            .collect();
+            .collect::<Vec<_>>();
+        flow_ctx.register_live_updater(&control, &sources_update_stats);
         Ok(Self {
             flow_ctx,
             tasks,
             sources_update_stats,
+            control,
         })
     }
 
@@ -194,15 +343,19 @@ impl FlowLiveUpdater {
         self.tasks.abort_all();
     }
 
+    /// Shared control of the updater, usable while another caller is waiting for it.
+    pub fn control(&self) -> &Arc<LiveUpdaterControl> {
+        &self.control
+    }
+
     pub fn index_update_info(&self) -> stats::IndexUpdateInfo {
         stats::IndexUpdateInfo {
             sources: std::iter::zip(
                 self.flow_ctx.flow.flow_instance.import_ops.iter(),
                 self.sources_update_stats.iter(),
             )
-            .map(|(import_op, stats)| stats::SourceUpdateInfo {
-                source_name: import_op.name.clone(),
-                stats: (**stats).clone(),
+            .map(|(import_op, stats)| {
+                stats::SourceUpdateInfo::new(import_op.name.clone(), (**stats).clone())
             })
             .collect(),
         }
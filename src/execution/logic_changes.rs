@@ -0,0 +1,95 @@
+use crate::prelude::*;
+
+use super::row_indexer::{SourceVersion, SourceVersionKind};
+use crate::state_store::SharedStateStore;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum OpLogicChangeKind {
+    Added,
+    Changed,
+    Removed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OpLogicChange {
+    /// Op kind and name, e.g. `transform:summary` or `export:doc_embeddings`.
+    /// Ops downstream of a changed op are reported as changed as well.
+    pub op: String,
+    pub kind: OpLogicChangeKind,
+    /// Number of source rows processed before the change. They'll be reprocessed for it, unless it's a removed op
+    /// nothing depended on.
+    pub num_rows: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SourceLogicChanges {
+    pub source_name: String,
+    pub num_rows: i64,
+    /// Number of rows processed with a different logic, which will be reprocessed in the next update.
+    pub num_stale_rows: i64,
+    /// Rows processed before op fingerprints were tracked are only counted in `num_stale_rows`, as which ops changed
+    /// for them is unknown. They're fully reprocessed once in the next update.
+    pub op_changes: Vec<OpLogicChange>,
+}
+
+/// Compare the current logic of the flow with the logic source rows were processed with,
+/// and report how many rows each changed op touches.
+pub async fn analyze_logic_changes(
+    flow_ctx: &FlowContext,
+    state_store: &SharedStateStore,
+) -> Result<Vec<SourceLogicChanges>> {
+    let plan = flow_ctx.flow.get_execution_plan().await?;
+    let mut result = Vec::with_capacity(plan.import_ops.len());
+    for import_op in plan.import_ops.iter() {
+        let groups = state_store
+            .count_source_rows_by_logic(import_op.source_id, &plan.tracking_table_setup)
+            .await?;
+        let mut num_rows = 0;
+        let mut num_stale_rows = 0;
+        let mut op_changes = BTreeMap::<(String, OpLogicChangeKind), i64>::new();
+        for group in groups.into_iter() {
+            num_rows += group.num_rows;
+            let source_version = SourceVersion::from_stored(
+                None,
+                &group.process_logic_fingerprint,
+                group.process_op_fingerprints.as_deref(),
+                import_op.logic_fingerprint,
+                &plan.op_logic_fingerprints,
+            );
+            if source_version.kind == SourceVersionKind::DifferentLogic {
+                num_stale_rows += group.num_rows;
+            }
+            let sqlx::types::Json(existing_op_fingerprints) = match group.process_op_fingerprints {
+                Some(fingerprints) => fingerprints,
+                None => continue,
+            };
+            for (op, fingerprint) in plan.op_logic_fingerprints.iter() {
+                let kind = match existing_op_fingerprints.get(op) {
+                    None => OpLogicChangeKind::Added,
+                    Some(existing_fingerprint) if existing_fingerprint != fingerprint => {
+                        OpLogicChangeKind::Changed
+                    }
+                    Some(_) => continue,
+                };
+                *op_changes.entry((op.clone(), kind)).or_default() += group.num_rows;
+            }
+            for op in existing_op_fingerprints.keys() {
+                if !plan.op_logic_fingerprints.contains_key(op) {
+                    *op_changes
+                        .entry((op.clone(), OpLogicChangeKind::Removed))
+                        .or_default() += group.num_rows;
+                }
+            }
+        }
+        result.push(SourceLogicChanges {
+            source_name: import_op.name.clone(),
+            num_rows,
+            num_stale_rows,
+            op_changes: op_changes
+                .into_iter()
+                .map(|((op, kind), num_rows)| OpLogicChange { op, kind, num_rows })
+                .collect(),
+        });
+    }
+    Ok(result)
+}
@@ -1,3 +1,4 @@
+use crate::state_store::SharedStateStore;
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 use std::{
@@ -7,6 +8,7 @@ use std::{
     sync::{Arc, Mutex},
 };
 
+use super::db_tracking_setup::TrackingTableSetupState;
 use crate::{
     base::{schema, value},
     service::error::{SharedError, SharedResultExtRef},
@@ -25,6 +27,10 @@ pub struct StoredMemoizationInfo {
 
     #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     pub uuids: HashMap<Fingerprint, Vec<uuid::Uuid>>,
+
+    /// Keys of entries in the shared function cache used by the row.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shared_cache: Vec<Fingerprint>,
 }
 
 pub type CacheEntryCell = Arc<tokio::sync::OnceCell<Result<value::Value, SharedError>>>;
@@ -35,9 +41,57 @@ enum CacheData {
     Current(CacheEntryCell),
 }
 
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheEntryOptions {
+    /// Entries older than this are recomputed.
+    pub ttl: Option<chrono::Duration>,
+    /// Max size of the value's JSON representation to keep it in stored cache.
+    pub max_value_size: Option<usize>,
+    /// If true, the value is kept in the cache shared by all source rows, instead of the row's own.
+    pub shared: bool,
+}
+
 struct CacheEntry {
     time: chrono::DateTime<chrono::Utc>,
     data: CacheData,
+    options: CacheEntryOptions,
+}
+
+pub struct CacheEntryLookup {
+    pub key: Fingerprint,
+    pub cell: CacheEntryCell,
+    /// True if there was a previous entry, but it's expired.
+    pub expired: bool,
+}
+
+/// Function results shared by all source rows of a flow, kept in a table next to the tracking table.
+#[derive(Clone)]
+pub struct SharedFunctionCache {
+    db_setup: TrackingTableSetupState,
+    state_store: SharedStateStore,
+    /// If false, existing entries are ignored, and only newly evaluated values are saved.
+    read_enabled: bool,
+}
+
+impl SharedFunctionCache {
+    pub fn new(db_setup: TrackingTableSetupState, state_store: SharedStateStore) -> Self {
+        Self {
+            db_setup,
+            state_store,
+            read_enabled: true,
+        }
+    }
+
+    pub fn new_write_only(
+        db_setup: TrackingTableSetupState,
+        state_store: SharedStateStore,
+    ) -> Self {
+        Self {
+            db_setup,
+            state_store,
+            read_enabled: false,
+        }
+    }
 }
 
 #[derive(Default)]
@@ -78,6 +132,7 @@ pub struct EvaluationMemory {
     current_time: chrono::DateTime<chrono::Utc>,
     cache: Option<Mutex<HashMap<Fingerprint, CacheEntry>>>,
     uuids: Mutex<HashMap<Fingerprint, UuidEntry>>,
+    shared_cache: Option<SharedFunctionCache>,
     evaluation_only: bool,
 }
 
@@ -85,6 +140,7 @@ impl EvaluationMemory {
     pub fn new(
         current_time: chrono::DateTime<chrono::Utc>,
         stored_info: Option<StoredMemoizationInfo>,
+        shared_cache: Option<SharedFunctionCache>,
         options: EvaluationMemoryOptions,
     ) -> Self {
         let (stored_cache, stored_uuids) = stored_info
@@ -104,6 +160,7 @@ impl EvaluationMemory {
                                     time: chrono::DateTime::from_timestamp(e.time_sec, 0)
                                         .unwrap_or(chrono::DateTime::<chrono::Utc>::MIN_UTC),
                                     data: CacheData::Previous(e.value),
+                                    options: CacheEntryOptions::default(),
                                 },
                             )
                         })
@@ -119,6 +176,7 @@ impl EvaluationMemory {
                     .map(|(k, v)| (k, UuidEntry::new(v)))
                     .collect(),
             ),
+            shared_cache,
             evaluation_only: options.evaluation_only,
         }
     }
@@ -127,26 +185,37 @@ impl EvaluationMemory {
         if self.evaluation_only {
             bail!("For evaluation only, cannot convert to stored MemoizationInfo");
         }
+        let mut shared_cache = Vec::new();
         let cache = if let Some(cache) = self.cache {
-            cache
-                .into_inner()?
-                .into_iter()
-                .filter_map(|(k, e)| match e.data {
-                    CacheData::Previous(_) => None,
+            let mut stored_cache = HashMap::new();
+            for (k, e) in cache.into_inner()? {
+                // Entries not used in the current run are dropped.
+                let value = match &e.data {
+                    CacheData::Previous(_) => continue,
                     CacheData::Current(entry) => match entry.get() {
-                        Some(Ok(v)) => Some(serde_json::to_value(v).map(|value| {
-                            (
-                                k,
-                                StoredCacheEntry {
-                                    time_sec: e.time.timestamp(),
-                                    value,
-                                },
-                            )
-                        })),
-                        _ => None,
+                        Some(Ok(v)) => v,
+                        _ => continue,
+                    },
+                };
+                if e.options.shared {
+                    shared_cache.push(k);
+                    continue;
+                }
+                let value = serde_json::to_value(value)?;
+                if let Some(max_value_size) = e.options.max_value_size {
+                    if serde_json::to_string(&value)?.len() > max_value_size {
+                        continue;
+                    }
+                }
+                stored_cache.insert(
+                    k,
+                    StoredCacheEntry {
+                        time_sec: e.time.timestamp(),
+                        value,
                     },
-                })
-                .collect::<Result<_, _>>()?
+                );
+            }
+            stored_cache
         } else {
             bail!("Cache is disabled, cannot convert to stored MemoizationInfo");
         };
@@ -156,28 +225,34 @@ impl EvaluationMemory {
             .into_iter()
             .filter_map(|(k, v)| v.into_stored().map(|uuids| (k, uuids)))
             .collect();
-        Ok(StoredMemoizationInfo { cache, uuids })
+        Ok(StoredMemoizationInfo {
+            cache,
+            uuids,
+            shared_cache,
+        })
     }
 
     pub fn get_cache_entry(
         &self,
         key: impl FnOnce() -> Result<Fingerprint>,
         typ: &schema::ValueType,
-        ttl: Option<chrono::Duration>,
-    ) -> Result<Option<CacheEntryCell>> {
+        options: CacheEntryOptions,
+    ) -> Result<Option<CacheEntryLookup>> {
         let mut cache = if let Some(cache) = &self.cache {
             cache.lock().unwrap()
         } else {
             return Ok(None);
         };
-        let result = match cache.entry(key()?) {
+        let key = key()?;
+        let result = match cache.entry(key) {
             std::collections::hash_map::Entry::Occupied(mut entry)
-                if !ttl
+                if !options
+                    .ttl
                     .map(|ttl| entry.get().time + ttl < self.current_time)
                     .unwrap_or(false) =>
             {
                 let entry_mut = &mut entry.get_mut();
-                match &mut entry_mut.data {
+                let cell = match &mut entry_mut.data {
                     CacheData::Previous(value) => {
                         let value = value::Value::from_json(std::mem::take(value), typ)?;
                         let cell = Arc::new(tokio::sync::OnceCell::from(Ok(value)));
@@ -185,24 +260,95 @@ impl EvaluationMemory {
                         entry.insert(CacheEntry {
                             time,
                             data: CacheData::Current(cell.clone()),
+                            options,
                         });
                         cell
                     }
                     CacheData::Current(cell) => cell.clone(),
+                };
+                CacheEntryLookup {
+                    key,
+                    cell,
+                    expired: false,
                 }
             }
             entry => {
+                let expired = matches!(entry, std::collections::hash_map::Entry::Occupied(_));
                 let cell = Arc::new(tokio::sync::OnceCell::new());
                 entry.insert_entry(CacheEntry {
                     time: self.current_time,
                     data: CacheData::Current(cell.clone()),
+                    options,
                 });
-                cell
+                CacheEntryLookup { key, cell, expired }
             }
         };
         Ok(Some(result))
     }
 
+    /// Fill the cell of the entry with the value in the shared cache, if it's not filled yet and an unexpired value exists.
+    /// Returns true if the cell is filled by this call.
+    pub async fn fill_from_shared_cache(
+        &self,
+        lookup: &CacheEntryLookup,
+        typ: &schema::ValueType,
+        options: &CacheEntryOptions,
+    ) -> Result<bool> {
+        let shared_cache = match &self.shared_cache {
+            Some(shared_cache)
+                if shared_cache.read_enabled && options.shared && !lookup.cell.initialized() =>
+            {
+                shared_cache
+            }
+            _ => return Ok(false),
+        };
+        let entry = shared_cache
+            .state_store
+            .read_shared_cache_entry(&lookup.key.to_base64(), &shared_cache.db_setup)
+            .await?;
+        let entry = match entry {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        if let Some(ttl) = options.ttl {
+            let time = chrono::DateTime::from_timestamp_micros(entry.time_micros)
+                .unwrap_or(chrono::DateTime::<chrono::Utc>::MIN_UTC);
+            if time + ttl < self.current_time {
+                return Ok(false);
+            }
+        }
+        let value = value::Value::from_json(entry.value, typ)?;
+        Ok(lookup.cell.set(Ok(value)).is_ok())
+    }
+
+    /// Keep a newly evaluated value in the shared cache.
+    pub async fn save_to_shared_cache(
+        &self,
+        key: Fingerprint,
+        value: &value::Value,
+        options: &CacheEntryOptions,
+    ) -> Result<()> {
+        let shared_cache = match &self.shared_cache {
+            Some(shared_cache) if options.shared && !self.evaluation_only => shared_cache,
+            _ => return Ok(()),
+        };
+        let value = serde_json::to_value(value)?;
+        if let Some(max_value_size) = options.max_value_size {
+            if serde_json::to_string(&value)?.len() > max_value_size {
+                return Ok(());
+            }
+        }
+        shared_cache
+            .state_store
+            .upsert_shared_cache_entry(
+                &key.to_base64(),
+                &value,
+                self.current_time.timestamp_micros(),
+                &shared_cache.db_setup,
+            )
+            .await
+    }
+
     pub fn next_uuid(&self, key: Fingerprint) -> Result<uuid::Uuid> {
         let mut uuids = self.uuids.lock().unwrap();
 
@@ -1,13 +1,16 @@
+pub(crate) mod aggregation;
+pub(crate) mod db_tracking;
 pub(crate) mod db_tracking_setup;
 pub(crate) mod dumper;
 pub(crate) mod evaluator;
+pub(crate) mod logic_changes;
 pub(crate) mod memoization;
 pub(crate) mod query;
+pub(crate) mod reprocess;
+pub(crate) mod row_errors;
 pub(crate) mod row_indexer;
 pub(crate) mod source_indexer;
 pub(crate) mod stats;
 
 mod live_updater;
 pub(crate) use live_updater::*;
-
-mod db_tracking;
@@ -0,0 +1,68 @@
+use crate::prelude::*;
+
+use crate::state_store::SharedStateStore;
+
+use super::stats;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ReprocessOptions {
+    /// Keys of source rows to reprocess, in the same JSON representation as stored in the tracking table.
+    #[serde(default)]
+    pub keys: Vec<serde_json::Value>,
+
+    /// If set, also reprocess all tracked rows with string keys starting with it.
+    #[serde(default)]
+    pub key_prefix: Option<String>,
+
+    /// If true, memoized function results are ignored and evaluated again.
+    #[serde(default)]
+    pub bypass_cache: bool,
+}
+
+/// Process specific rows of a source again, regardless of whether they changed.
+pub async fn reprocess_source_rows(
+    flow_ctx: &FlowContext,
+    source_name: &str,
+    options: ReprocessOptions,
+    state_store: &SharedStateStore,
+) -> Result<stats::IndexUpdateInfo> {
+    let source_idx = flow_ctx
+        .flow
+        .flow_instance
+        .import_ops
+        .iter()
+        .position(|op| op.name == source_name)
+        .ok_or_else(|| api_error!("source not found: {source_name}"))?;
+    let plan = flow_ctx.flow.get_execution_plan().await?;
+    let import_op = &plan.import_ops[source_idx];
+    let source_context = flow_ctx
+        .get_source_indexing_context(source_idx, state_store)
+        .await?;
+
+    let mut keys = options
+        .keys
+        .into_iter()
+        .map(|key| {
+            value::Value::<value::ScopeValue>::from_json(key, &import_op.primary_key_type)?
+                .into_key()
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(key_prefix) = &options.key_prefix {
+        keys.extend(source_context.tracked_keys_with_prefix(key_prefix));
+    }
+    keys.sort();
+    keys.dedup();
+
+    let update_stats = Arc::new(stats::UpdateStats::default());
+    let result = source_context
+        .reprocess(keys, options.bypass_cache, state_store, &update_stats)
+        .await;
+    flow_ctx.record_update_stats(source_idx, &update_stats);
+    result?;
+    Ok(stats::IndexUpdateInfo {
+        sources: vec![stats::SourceUpdateInfo::new(
+            source_name.to_string(),
+            update_stats.as_ref().clone(),
+        )],
+    })
+}
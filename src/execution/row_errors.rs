@@ -0,0 +1,53 @@
+use crate::prelude::*;
+
+use crate::state_store::SharedStateStore;
+
+use super::{db_tracking::SourceRowError, stats};
+
+#[derive(Debug, Serialize)]
+pub struct SourceRowErrors {
+    pub source_name: String,
+    pub errors: Vec<SourceRowError>,
+}
+
+/// List rows that kept failing after all retries, for each source of the flow.
+pub async fn list_row_errors(
+    flow_ctx: &FlowContext,
+    state_store: &SharedStateStore,
+) -> Result<Vec<SourceRowErrors>> {
+    let mut result = Vec::new();
+    for (source_idx, import_op) in flow_ctx.flow.flow_instance.import_ops.iter().enumerate() {
+        let source_context = flow_ctx
+            .get_source_indexing_context(source_idx, state_store)
+            .await?;
+        result.push(SourceRowErrors {
+            source_name: import_op.name.clone(),
+            errors: source_context.list_errors(state_store).await?,
+        });
+    }
+    Ok(result)
+}
+
+/// Process rows that kept failing again, for each source of the flow.
+pub async fn redrive_row_errors(
+    flow_ctx: &FlowContext,
+    state_store: &SharedStateStore,
+) -> Result<stats::IndexUpdateInfo> {
+    let mut sources = Vec::new();
+    for (source_idx, import_op) in flow_ctx.flow.flow_instance.import_ops.iter().enumerate() {
+        let source_context = flow_ctx
+            .get_source_indexing_context(source_idx, state_store)
+            .await?;
+        let update_stats = Arc::new(stats::UpdateStats::default());
+        let result = source_context
+            .redrive_errors(state_store, &update_stats)
+            .await;
+        flow_ctx.record_update_stats(source_idx, &update_stats);
+        result?;
+        sources.push(stats::SourceUpdateInfo::new(
+            import_op.name.clone(),
+            update_stats.as_ref().clone(),
+        ));
+    }
+    Ok(stats::IndexUpdateInfo { sources })
+}
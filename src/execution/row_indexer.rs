@@ -1,13 +1,17 @@
 use crate::prelude::*;
 
+use crate::state_store::{SharedStateStore, StateStoreTxn};
 use futures::future::try_join_all;
-use sqlx::PgPool;
 use std::collections::{HashMap, HashSet};
+use tracing::Instrument;
 
-use super::db_tracking::{self, read_source_tracking_info_for_processing, TrackedTargetKey};
+use super::aggregation;
+use super::db_tracking::{self, TrackedTargetKey};
 use super::db_tracking_setup;
-use super::evaluator::{evaluate_source_entry, ScopeValueBuilder};
-use super::memoization::{EvaluationMemory, EvaluationMemoryOptions, StoredMemoizationInfo};
+use super::evaluator::{evaluate_source_entry, ScopeValueBuilder, SourceRowTraceInfo};
+use super::memoization::{
+    EvaluationMemory, EvaluationMemoryOptions, SharedFunctionCache, StoredMemoizationInfo,
+};
 use super::stats;
 
 use crate::base::schema;
@@ -43,6 +47,8 @@ pub enum SourceVersionKind {
     NonExistent,
     DifferentLogic,
     CurrentLogic,
+    /// Processed again with the current logic, even if the same ordinal is already processed.
+    Reprocessing,
     Deleted,
 }
 
@@ -53,19 +59,32 @@ pub struct SourceVersion {
 }
 
 impl SourceVersion {
+    /// The stored version is processed with the current logic only if the source's logic fingerprint is unchanged and
+    /// no op has logic changed since then (see `changed_op_logic()`).
+    /// Rows processed before op fingerprints were tracked are processed with a different logic. They're fully
+    /// reprocessed once, after which their op fingerprints are tracked.
     pub fn from_stored(
         stored_ordinal: Option<i64>,
         stored_fp: &Option<Vec<u8>>,
+        stored_op_fps: Option<&db_tracking::OpLogicFingerprints>,
         curr_fp: Fingerprint,
+        curr_op_fps: &db_tracking::OpLogicFingerprints,
     ) -> Self {
         Self {
             ordinal: stored_ordinal.map(Ordinal),
             kind: match &stored_fp {
                 Some(stored_fp) => {
-                    if stored_fp.as_slice() == curr_fp.0.as_slice() {
-                        SourceVersionKind::CurrentLogic
-                    } else {
+                    let logic_changed = stored_fp.as_slice() != curr_fp.0.as_slice()
+                        || match stored_op_fps {
+                            Some(stored_op_fps) => changed_op_logic(stored_op_fps, curr_op_fps)
+                                .next()
+                                .is_some(),
+                            None => true,
+                        };
+                    if logic_changed {
                         SourceVersionKind::DifferentLogic
+                    } else {
+                        SourceVersionKind::CurrentLogic
                     }
                 }
                 None => SourceVersionKind::NonExistent,
@@ -87,6 +106,13 @@ impl SourceVersion {
         }
     }
 
+    pub fn for_reprocessing(&self) -> Self {
+        Self {
+            ordinal: self.ordinal,
+            kind: SourceVersionKind::Reprocessing,
+        }
+    }
+
     pub fn should_skip(
         &self,
         target: &SourceVersion,
@@ -107,6 +133,21 @@ impl SourceVersion {
     }
 }
 
+/// Keys of ops (including export ops and exported collectors) in the current flow with logic changed since a source row
+/// was processed with `stored_op_fps`. As op fingerprints cover ops they depend on, these are changed ops and all ops
+/// downstream of them. When the row is reprocessed, only they are re-run: other ops get the same inputs, so their
+/// outputs memoized for the row are reused. Ops removed from the flow aren't included, so a row is not reprocessed if
+/// ops are only removed and nothing depended on them.
+pub fn changed_op_logic<'a>(
+    stored_op_fps: &'a db_tracking::OpLogicFingerprints,
+    curr_op_fps: &'a db_tracking::OpLogicFingerprints,
+) -> impl Iterator<Item = &'a str> + 'a {
+    curr_op_fps
+        .iter()
+        .filter(|(op, fp)| stored_op_fps.get(*op) != Some(*fp))
+        .map(|(op, _)| op.as_str())
+}
+
 pub enum SkippedOr<T> {
     Normal(T),
     Skipped(SourceVersion),
@@ -143,47 +184,42 @@ struct PrecommitMetadata {
 struct PrecommitOutput {
     metadata: PrecommitMetadata,
     target_mutations: HashMap<i32, ExportTargetMutation>,
+    /// Groups of aggregated targets whose rows need to be recomputed, keyed by target ID.
+    aggregated_groups: HashMap<i32, BTreeSet<KeyValue>>,
 }
 
-async fn precommit_source_tracking_info(
-    source_id: i32,
-    source_key_json: &serde_json::Value,
-    source_version: &SourceVersion,
-    logic_fp: Fingerprint,
-    data: Option<PrecommitData<'_>>,
-    process_timestamp: &chrono::DateTime<chrono::Utc>,
-    db_setup: &db_tracking_setup::TrackingTableSetupState,
-    export_ops: &[AnalyzedExportOp],
-    update_stats: &stats::UpdateStats,
-    pool: &PgPool,
-) -> Result<SkippedOr<PrecommitOutput>> {
-    let mut txn = pool.begin().await?;
+struct TargetKeysDiff {
+    new_target_keys: db_tracking::TrackedTargetKeyForSource,
+    new_staging_target_keys: db_tracking::TrackedTargetKeyForSource,
+    target_mutations: HashMap<i32, ExportTargetMutation>,
+}
 
-    let tracking_info = db_tracking::read_source_tracking_info_for_precommit(
-        source_id,
-        source_key_json,
-        db_setup,
-        &mut *txn,
-    )
-    .await?;
-    if let Some(tracking_info) = &tracking_info {
-        let existing_source_version = SourceVersion::from_stored(
-            tracking_info.processed_source_ordinal,
-            &tracking_info.process_logic_fingerprint,
-            logic_fp,
-        );
-        if existing_source_version.should_skip(source_version, Some(update_stats)) {
-            return Ok(SkippedOr::Skipped(existing_source_version));
-        }
-    }
-    let tracking_info_exists = tracking_info.is_some();
-    let process_ordinal = (tracking_info
-        .as_ref()
-        .map(|info| info.max_process_ordinal)
-        .unwrap_or(0)
-        + 1)
-    .max(process_timestamp.timestamp_millis());
-    let existing_process_ordinal = tracking_info.as_ref().and_then(|info| info.process_ordinal);
+/// Compare target entries collected for the source row against the ones it tracks currently,
+/// and figure out mutations to apply to targets and target keys to track.
+fn diff_target_keys(
+    tracking_info: Option<db_tracking::SourceTrackingInfoForPrecommit>,
+    scope_value: Option<&ScopeValueBuilder>,
+    process_ordinal: i64,
+    export_ops: &[AnalyzedExportOp],
+    op_logic_fingerprints: &db_tracking::OpLogicFingerprints,
+) -> Result<TargetKeysDiff> {
+    // Targets with logic changed since the row was processed last time, which need all entries upserted again.
+    // Other targets only get entries with changed values upserted.
+    // Rows processed before op fingerprints were tracked are fully reprocessed, with all entries upserted again.
+    let logic_changed_target_ids: HashSet<i32> = match tracking_info.as_ref() {
+        Some(info) => export_ops
+            .iter()
+            .filter(|export_op| match &info.process_op_fingerprints {
+                Some(sqlx::types::Json(existing_op_fingerprints)) => {
+                    let key = export_op_logic_key(&export_op.name);
+                    existing_op_fingerprints.get(&key) != op_logic_fingerprints.get(&key)
+                }
+                None => true,
+            })
+            .map(|export_op| export_op.target_id)
+            .collect(),
+        None => HashSet::new(),
+    };
 
     let mut tracking_info_for_targets = HashMap::<i32, TrackingInfoForTarget>::new();
     for export_op in export_ops.iter() {
@@ -222,15 +258,17 @@ async fn precommit_source_tracking_info(
     }
 
     let mut new_target_keys_info = db_tracking::TrackedTargetKeyForSource::default();
-    if let Some(data) = &data {
-        for export_op in export_ops.iter() {
-            let collected_values = data.scope_value.collected_values
+    if let Some(scope_value) = scope_value {
+        // Rows of aggregated targets aren't tracked per source row. See `update_aggregation_contributions()`.
+        for export_op in export_ops.iter().filter(|op| op.aggregation.is_none()) {
+            let collected_values = scope_value.collected_values
                 [export_op.input.collector_idx as usize]
                 .lock()
                 .unwrap();
             let target_info = tracking_info_for_targets
                 .entry(export_op.target_id)
                 .or_default();
+            let logic_changed = logic_changed_target_ids.contains(&export_op.target_id);
             let mut keys_info = Vec::new();
             for value in collected_values.iter() {
                 let primary_key = extract_primary_key(&export_op.primary_key_def, value)?;
@@ -258,10 +296,11 @@ async fn precommit_source_tracking_info(
                 } else {
                     None
                 };
-                if existing_target_keys
-                    .as_ref()
-                    .map(|keys| !keys.is_empty() && keys.iter().all(|(_, fp)| fp == &curr_fp))
-                    .unwrap_or(false)
+                if !logic_changed
+                    && existing_target_keys
+                        .as_ref()
+                        .map(|keys| !keys.is_empty() && keys.iter().all(|(_, fp)| fp == &curr_fp))
+                        .unwrap_or(false)
                     && existing_staging_target_keys
                         .map(|keys| keys.iter().all(|(_, fp)| fp == &curr_fp))
                         .unwrap_or(true)
@@ -323,14 +362,161 @@ async fn precommit_source_tracking_info(
         }
     }
 
-    db_tracking::precommit_source_tracking_info(
+    Ok(TargetKeysDiff {
+        new_target_keys: new_target_keys_info,
+        new_staging_target_keys,
+        target_mutations,
+    })
+}
+
+/// Replace contributions of the source row to groups of aggregated targets.
+/// Returns keys of groups the source row contributed to before or contributes to now, keyed by target ID.
+async fn update_aggregation_contributions(
+    txn: &mut dyn StateStoreTxn,
+    source_id: i32,
+    source_key_json: &serde_json::Value,
+    scope_value: Option<&ScopeValueBuilder>,
+    ordinal: i64,
+    export_ops: &[AnalyzedExportOp],
+    db_setup: &db_tracking_setup::TrackingTableSetupState,
+) -> Result<HashMap<i32, BTreeSet<KeyValue>>> {
+    let mut aggregated_groups = HashMap::<i32, BTreeSet<KeyValue>>::new();
+    if export_ops.iter().all(|op| op.aggregation.is_none()) {
+        return Ok(aggregated_groups);
+    }
+    let existing_groups = txn
+        .delete_aggregation_contributions(source_id, source_key_json, db_setup)
+        .await?;
+    for (target_id, group_key_json) in existing_groups {
+        // Contributions to targets no longer aggregated are dropped.
+        let Some(export_op) = export_ops
+            .iter()
+            .find(|op| op.target_id == target_id && op.aggregation.is_some())
+        else {
+            continue;
+        };
+        let group_key = value::Value::<value::ScopeValue>::from_json(
+            group_key_json,
+            &export_op.primary_key_type,
+        )?
+        .as_key()?;
+        aggregated_groups
+            .entry(target_id)
+            .or_default()
+            .insert(group_key);
+    }
+    if let Some(scope_value) = scope_value {
+        for export_op in export_ops.iter() {
+            let Some(aggregation) = &export_op.aggregation else {
+                continue;
+            };
+            let contributions = {
+                let collected_values = scope_value.collected_values
+                    [export_op.input.collector_idx as usize]
+                    .lock()
+                    .unwrap();
+                aggregation::collect_contributions(
+                    aggregation,
+                    &export_op.primary_key_def,
+                    &collected_values,
+                    ordinal,
+                )?
+            };
+            for (group_key, contribution) in contributions {
+                txn.insert_aggregation_contribution(
+                    export_op.target_id,
+                    &serde_json::to_value(&group_key)?,
+                    source_id,
+                    source_key_json,
+                    &contribution,
+                    db_setup,
+                )
+                .await?;
+                aggregated_groups
+                    .entry(export_op.target_id)
+                    .or_default()
+                    .insert(group_key);
+            }
+        }
+    }
+    Ok(aggregated_groups)
+}
+
+async fn precommit_source_tracking_info(
+    source_id: i32,
+    source_key_json: &serde_json::Value,
+    source_version: &SourceVersion,
+    logic_fp: Fingerprint,
+    data: Option<PrecommitData<'_>>,
+    process_timestamp: &chrono::DateTime<chrono::Utc>,
+    db_setup: &db_tracking_setup::TrackingTableSetupState,
+    export_ops: &[AnalyzedExportOp],
+    op_logic_fingerprints: &db_tracking::OpLogicFingerprints,
+    update_stats: &stats::UpdateStats,
+    state_store: &SharedStateStore,
+) -> Result<SkippedOr<PrecommitOutput>> {
+    let mut txn = state_store.begin().await?;
+
+    txn.lock_source_tracking_info(source_id, source_key_json, db_setup)
+        .await?;
+    let tracking_info = txn
+        .read_source_tracking_info_for_precommit(source_id, source_key_json, db_setup)
+        .await?;
+    if let Some(tracking_info) = &tracking_info {
+        let existing_source_version = SourceVersion::from_stored(
+            tracking_info.processed_source_ordinal,
+            &tracking_info.process_logic_fingerprint,
+            tracking_info.process_op_fingerprints.as_deref(),
+            logic_fp,
+            op_logic_fingerprints,
+        );
+        if existing_source_version.should_skip(source_version, Some(update_stats)) {
+            return Ok(SkippedOr::Skipped(existing_source_version));
+        }
+    }
+    let tracking_info_exists = tracking_info.is_some();
+    let process_ordinal = (tracking_info
+        .as_ref()
+        .map(|info| info.max_process_ordinal)
+        .unwrap_or(0)
+        + 1)
+    .max(process_timestamp.timestamp_millis());
+    let existing_process_ordinal = tracking_info.as_ref().and_then(|info| info.process_ordinal);
+
+    let TargetKeysDiff {
+        new_target_keys: new_target_keys_info,
+        new_staging_target_keys,
+        target_mutations,
+    } = diff_target_keys(
+        tracking_info,
+        data.as_ref().map(|data| data.scope_value),
+        process_ordinal,
+        export_ops,
+        op_logic_fingerprints,
+    )?;
+
+    let aggregated_groups = update_aggregation_contributions(
+        txn.as_mut(),
+        source_id,
+        source_key_json,
+        data.as_ref().map(|data| data.scope_value),
+        // Rows of last-by-ordinal reducers are ordered by source ordinals if available.
+        source_version
+            .ordinal
+            .map(i64::from)
+            .unwrap_or(process_ordinal),
+        export_ops,
+        db_setup,
+    )
+    .await?;
+
+    txn.precommit_source_tracking_info(
         source_id,
         source_key_json,
         process_ordinal,
         new_staging_target_keys,
         data.as_ref().map(|data| data.memoization_info),
         db_setup,
-        &mut *txn,
         if tracking_info_exists {
             WriteAction::Update
         } else {
@@ -349,28 +535,110 @@ async fn precommit_source_tracking_info(
             new_target_keys: new_target_keys_info,
         },
         target_mutations,
+        aggregated_groups,
     }))
 }
 
+fn record_target_mutation_stats(
+    export_ops: &[AnalyzedExportOp],
+    target_mutations: &HashMap<i32, ExportTargetMutation>,
+    update_stats: &stats::UpdateStats,
+) {
+    for export_op in export_ops.iter() {
+        if let Some(mutation) = target_mutations.get(&export_op.target_id) {
+            if mutation.is_empty() {
+                continue;
+            }
+            let op_stats = update_stats.ops.get(&export_op.name);
+            op_stats
+                .num_target_upserts
+                .inc(mutation.upserts.len() as i64);
+            op_stats
+                .num_target_deletions
+                .inc(mutation.delete_keys.len() as i64);
+        }
+    }
+}
+
+/// Recompute rows of the aggregated target for the groups from contributions of all source rows, and apply them.
+async fn apply_aggregated_groups(
+    export_op_group: &AnalyzedExportTargetOpGroup,
+    export_op: &AnalyzedExportOp,
+    aggregation: &AnalyzedAggregation,
+    group_keys: BTreeSet<KeyValue>,
+    db_setup: &db_tracking_setup::TrackingTableSetupState,
+    state_store: &SharedStateStore,
+    update_stats: &stats::UpdateStats,
+) -> Result<()> {
+    // Contributions are read and rows are applied while holding the groups, both within the process and across
+    // processes through the state store. Otherwise, a row computed from earlier contributions may be applied after
+    // (and overwrite) one computed from later contributions, by another row of the same group finishing first.
+    let _group_guards = aggregation.group_locks.lock(group_keys.iter()).await;
+    let group_keys_json = group_keys
+        .iter()
+        .map(serde_json::to_value)
+        .collect::<Result<Vec<_>, _>>()?;
+    let group_locks_txn = state_store
+        .lock_aggregation_groups(export_op.target_id, &group_keys_json, db_setup)
+        .await?;
+    let mut mutation = ExportTargetMutation::default();
+    for (group_key, group_key_json) in group_keys.into_iter().zip(group_keys_json.iter()) {
+        let contributions = state_store
+            .list_aggregation_contributions(export_op.target_id, group_key_json, db_setup)
+            .await?;
+        match aggregation::aggregate_contributions(aggregation, contributions)? {
+            Some(value) => mutation.upserts.push(ExportTargetUpsertEntry {
+                key: group_key,
+                value,
+            }),
+            None => mutation.delete_keys.push(group_key),
+        }
+    }
+
+    let op_stats = update_stats.ops.get(&export_op.name);
+    op_stats
+        .num_target_upserts
+        .inc(mutation.upserts.len() as i64);
+    op_stats
+        .num_target_deletions
+        .inc(mutation.delete_keys.len() as i64);
+    let batch_size = mutation.upserts.len() + mutation.delete_keys.len();
+    let start_time = std::time::Instant::now();
+    export_op_group
+        .target_factory
+        .apply_mutation(vec![interface::ExportTargetMutationWithContext {
+            mutation,
+            export_context: export_op.export_context.as_ref(),
+        }])
+        .await?;
+    op_stats
+        .apply_latency
+        .observe(start_time.elapsed().as_secs_f64());
+    op_stats.apply_batch_size.observe(batch_size as f64);
+    if let Some(group_locks_txn) = group_locks_txn {
+        group_locks_txn.commit().await?;
+    }
+    Ok(())
+}
+
 async fn commit_source_tracking_info(
     source_id: i32,
     source_key_json: &serde_json::Value,
     source_version: &SourceVersion,
     logic_fingerprint: &[u8],
+    op_logic_fingerprints: &db_tracking::OpLogicFingerprints,
     precommit_metadata: PrecommitMetadata,
     process_timestamp: &chrono::DateTime<chrono::Utc>,
     db_setup: &db_tracking_setup::TrackingTableSetupState,
-    pool: &PgPool,
+    state_store: &SharedStateStore,
 ) -> Result<()> {
-    let mut txn = pool.begin().await?;
+    let mut txn = state_store.begin().await?;
 
-    let tracking_info = db_tracking::read_source_tracking_info_for_commit(
-        source_id,
-        source_key_json,
-        db_setup,
-        &mut *txn,
-    )
-    .await?;
+    txn.lock_source_tracking_info(source_id, source_key_json, db_setup)
+        .await?;
+    let tracking_info = txn
+        .read_source_tracking_info_for_commit(source_id, source_key_json, db_setup)
+        .await?;
     let tracking_info_exists = tracking_info.is_some();
     if tracking_info.as_ref().and_then(|info| info.process_ordinal)
         >= Some(precommit_metadata.process_ordinal)
@@ -401,19 +669,14 @@ async fn commit_source_tracking_info(
         })
         .unwrap_or_default();
     if !precommit_metadata.source_entry_exists && cleaned_staging_target_keys.is_empty() {
-        // TODO: When we support distributed execution in the future, we'll need to leave a tombstone for a while
+        // TODO: With multiple workers, we'll need to leave a tombstone for a while
         // to prevent an earlier update causing the record reappear because of out-of-order processing.
         if tracking_info_exists {
-            db_tracking::delete_source_tracking_info(
-                source_id,
-                source_key_json,
-                db_setup,
-                &mut *txn,
-            )
-            .await?;
+            txn.delete_source_tracking_info(source_id, source_key_json, db_setup)
+                .await?;
         }
     } else {
-        db_tracking::commit_source_tracking_info(
+        txn.commit_source_tracking_info(
             source_id,
             source_key_json,
             cleaned_staging_target_keys,
@@ -422,8 +685,8 @@ async fn commit_source_tracking_info(
             precommit_metadata.process_ordinal,
             process_timestamp.timestamp_micros(),
             precommit_metadata.new_target_keys,
+            op_logic_fingerprints,
             db_setup,
-            &mut *txn,
             if tracking_info_exists {
                 WriteAction::Update
             } else {
@@ -444,32 +707,106 @@ pub async fn evaluate_source_entry_with_memory(
     schema: &schema::DataSchema,
     key: &value::KeyValue,
     options: EvaluationMemoryOptions,
-    pool: &PgPool,
+    state_store: &SharedStateStore,
 ) -> Result<Option<ScopeValueBuilder>> {
     let stored_info = if options.enable_cache || !options.evaluation_only {
         let source_key_json = serde_json::to_value(key)?;
-        let existing_tracking_info = read_source_tracking_info_for_processing(
-            import_op.source_id,
-            &source_key_json,
-            &plan.tracking_table_setup,
-            pool,
-        )
-        .await?;
+        let existing_tracking_info = state_store
+            .read_source_tracking_info_for_processing(
+                import_op.source_id,
+                &source_key_json,
+                &plan.tracking_table_setup,
+            )
+            .await?;
         existing_tracking_info
             .and_then(|info| info.memoization_info.map(|info| info.0))
             .flatten()
     } else {
         None
     };
-    let memory = EvaluationMemory::new(chrono::Utc::now(), stored_info, options);
+    let memory = EvaluationMemory::new(
+        chrono::Utc::now(),
+        stored_info,
+        Some(SharedFunctionCache::new(
+            plan.tracking_table_setup.clone(),
+            state_store.clone(),
+        )),
+        options,
+    );
     let source_value = match import_op.executor.get_value(key).await? {
         Some(d) => d,
         None => return Ok(None),
     };
-    let output = evaluate_source_entry(plan, import_op, schema, key, source_value, &memory).await?;
+    let trace_info = SourceRowTraceInfo::new(plan, import_op, key)?;
+    let output = evaluate_source_entry(
+        plan,
+        import_op,
+        schema,
+        &trace_info,
+        source_value,
+        &memory,
+        None,
+    )
+    .await?;
     Ok(Some(output))
 }
 
+/// Evaluate the source row and compare the result against target keys it tracks currently,
+/// recording mutations that would be applied to targets in `update_stats`. Nothing is written.
+pub async fn dry_run_source_row(
+    plan: &ExecutionPlan,
+    import_op: &AnalyzedImportOp,
+    schema: &schema::DataSchema,
+    key: &value::KeyValue,
+    state_store: &SharedStateStore,
+    update_stats: &stats::UpdateStats,
+) -> Result<()> {
+    let output = evaluate_source_entry_with_memory(
+        plan,
+        import_op,
+        schema,
+        key,
+        EvaluationMemoryOptions {
+            enable_cache: true,
+            evaluation_only: true,
+        },
+        state_store,
+    )
+    .await?;
+    let tracking_info = state_store
+        .begin()
+        .await?
+        .read_source_tracking_info_for_precommit(
+            import_op.source_id,
+            &serde_json::to_value(key)?,
+            &plan.tracking_table_setup,
+        )
+        .await?;
+    let process_ordinal = tracking_info
+        .as_ref()
+        .map(|info| info.max_process_ordinal)
+        .unwrap_or(0)
+        + 1;
+    let diff = diff_target_keys(
+        tracking_info,
+        output.as_ref(),
+        process_ordinal,
+        &plan.export_ops,
+        &plan.op_logic_fingerprints,
+    )?;
+    record_target_mutation_stats(&plan.export_ops, &diff.target_mutations, update_stats);
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "update_source_row",
+    skip_all,
+    fields(
+        flow = plan.flow_name.as_str(),
+        source = import_op.name.as_str(),
+        key_fingerprint = tracing::field::Empty,
+    )
+)]
 pub async fn update_source_row(
     plan: &ExecutionPlan,
     import_op: &AnalyzedImportOp,
@@ -477,27 +814,32 @@ pub async fn update_source_row(
     key: &value::KeyValue,
     source_value: Option<FieldValues>,
     source_version: &SourceVersion,
-    pool: &PgPool,
+    bypass_cache: bool,
+    state_store: &SharedStateStore,
     update_stats: &stats::UpdateStats,
 ) -> Result<SkippedOr<()>> {
     let source_key_json = serde_json::to_value(key)?;
     let process_timestamp = chrono::Utc::now();
+    let trace_info = SourceRowTraceInfo::new(plan, import_op, key)?;
+    tracing::Span::current().record("key_fingerprint", trace_info.key_fingerprint.as_str());
 
     // Phase 1: Evaluate with memoization info.
-    let existing_tracking_info = read_source_tracking_info_for_processing(
-        import_op.source_id,
-        &source_key_json,
-        &plan.tracking_table_setup,
-        pool,
-    )
-    .await?;
+    let existing_tracking_info = state_store
+        .read_source_tracking_info_for_processing(
+            import_op.source_id,
+            &source_key_json,
+            &plan.tracking_table_setup,
+        )
+        .await?;
     let already_exists = existing_tracking_info.is_some();
     let memoization_info = match existing_tracking_info {
         Some(info) => {
             let existing_version = SourceVersion::from_stored(
                 info.processed_source_ordinal,
                 &info.process_logic_fingerprint,
-                plan.logic_fingerprint,
+                info.process_op_fingerprints.as_deref(),
+                import_op.logic_fingerprint,
+                &plan.op_logic_fingerprints,
             );
             if existing_version.should_skip(source_version, Some(update_stats)) {
                 return Ok(SkippedOr::Skipped(existing_version));
@@ -506,11 +848,30 @@ pub async fn update_source_row(
         }
         None => Default::default(),
     };
+    // When bypassing the cache, memoized function results are ignored, but generated UUIDs are kept stable.
+    let (memoization_info, shared_cache) = if bypass_cache {
+        (
+            memoization_info.map(|info| StoredMemoizationInfo {
+                uuids: info.uuids,
+                ..Default::default()
+            }),
+            SharedFunctionCache::new_write_only(
+                plan.tracking_table_setup.clone(),
+                state_store.clone(),
+            ),
+        )
+    } else {
+        (
+            memoization_info,
+            SharedFunctionCache::new(plan.tracking_table_setup.clone(), state_store.clone()),
+        )
+    };
     let (output, stored_mem_info) = match source_value {
         Some(source_value) => {
             let evaluation_memory = EvaluationMemory::new(
                 process_timestamp,
                 memoization_info,
+                Some(shared_cache),
                 EvaluationMemoryOptions {
                     enable_cache: true,
                     evaluation_only: false,
@@ -520,10 +881,12 @@ pub async fn update_source_row(
                 plan,
                 import_op,
                 schema,
-                key,
+                &trace_info,
                 source_value,
                 &evaluation_memory,
+                Some(update_stats),
             )
+            .instrument(tracing::info_span!("evaluate"))
             .await?;
             (Some(output), evaluation_memory.into_stored()?)
         }
@@ -535,7 +898,7 @@ pub async fn update_source_row(
         import_op.source_id,
         &source_key_json,
         source_version,
-        plan.logic_fingerprint,
+        import_op.logic_fingerprint,
         output.as_ref().map(|scope_value| PrecommitData {
             scope_value,
             memoization_info: &stored_mem_info,
@@ -543,9 +906,11 @@ pub async fn update_source_row(
         &process_timestamp,
         &plan.tracking_table_setup,
         &plan.export_ops,
+        &plan.op_logic_fingerprints,
         update_stats,
-        pool,
+        state_store,
     )
+    .instrument(tracing::info_span!("precommit"))
     .await?;
     let precommit_output = match precommit_output {
         SkippedOr::Normal(output) => output,
@@ -554,7 +919,10 @@ pub async fn update_source_row(
 
     // Phase 3: Apply changes to the target storage, including upserting new target records and removing existing ones.
     let mut target_mutations = precommit_output.target_mutations;
+    record_target_mutation_stats(&plan.export_ops, &target_mutations, update_stats);
     let apply_futs = plan.export_op_groups.iter().filter_map(|export_op_group| {
+        // Names and batch sizes of export ops with mutations in the group.
+        let mut batch_sizes = Vec::new();
         let mutations_w_ctx: Vec<_> = export_op_group
             .op_idx
             .iter()
@@ -563,33 +931,78 @@ pub async fn update_source_row(
                 target_mutations
                     .remove(&export_op.target_id)
                     .filter(|m| !m.is_empty())
-                    .map(|mutation| interface::ExportTargetMutationWithContext {
-                        mutation,
-                        export_context: export_op.export_context.as_ref(),
+                    .map(|mutation| {
+                        batch_sizes.push((
+                            &export_op.name,
+                            mutation.upserts.len() + mutation.delete_keys.len(),
+                        ));
+                        interface::ExportTargetMutationWithContext {
+                            mutation,
+                            export_context: export_op.export_context.as_ref(),
+                        }
                     })
             })
             .collect();
-        (!mutations_w_ctx.is_empty()).then(|| {
+        (!mutations_w_ctx.is_empty()).then(|| async move {
+            let start_time = std::time::Instant::now();
             export_op_group
                 .target_factory
                 .apply_mutation(mutations_w_ctx)
+                .await?;
+            let latency = start_time.elapsed().as_secs_f64();
+            for (name, batch_size) in batch_sizes {
+                let op_stats = update_stats.ops.get(name);
+                op_stats.apply_latency.observe(latency);
+                op_stats.apply_batch_size.observe(batch_size as f64);
+            }
+            anyhow::Ok(())
         })
     });
 
     // TODO: Handle errors.
-    try_join_all(apply_futs).await?;
+    try_join_all(apply_futs)
+        .instrument(tracing::info_span!("export"))
+        .await?;
+
+    // Phase 3b: Recompute rows of aggregated targets for groups the source row contributed to before or contributes to now.
+    let mut aggregated_groups = precommit_output.aggregated_groups;
+    let mut aggregation_futs = Vec::new();
+    for export_op_group in plan.export_op_groups.iter() {
+        for export_op_idx in export_op_group.op_idx.iter() {
+            let export_op = &plan.export_ops[*export_op_idx];
+            if let (Some(aggregation), Some(group_keys)) = (
+                &export_op.aggregation,
+                aggregated_groups.remove(&export_op.target_id),
+            ) {
+                aggregation_futs.push(apply_aggregated_groups(
+                    export_op_group,
+                    export_op,
+                    aggregation,
+                    group_keys,
+                    &plan.tracking_table_setup,
+                    state_store,
+                    update_stats,
+                ));
+            }
+        }
+    }
+    try_join_all(aggregation_futs)
+        .instrument(tracing::info_span!("aggregate"))
+        .await?;
 
     // Phase 4: Update the tracking record.
     commit_source_tracking_info(
         import_op.source_id,
         &source_key_json,
         source_version,
-        &plan.logic_fingerprint.0,
+        &import_op.logic_fingerprint.0,
+        &plan.op_logic_fingerprints,
         precommit_output.metadata,
         &process_timestamp,
         &plan.tracking_table_setup,
-        pool,
+        state_store,
     )
+    .instrument(tracing::info_span!("commit"))
     .await?;
 
     if already_exists {
@@ -604,3 +1017,92 @@ pub async fn update_source_row(
 
     Ok(SkippedOr::Normal(()))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint(s: &str) -> Fingerprint {
+        Fingerprinter::default().with(s).unwrap().into_fingerprint()
+    }
+
+    fn op_fps(ops: &[(&str, &str)]) -> db_tracking::OpLogicFingerprints {
+        ops.iter()
+            .map(|(op, logic)| (op.to_string(), fingerprint(logic)))
+            .collect()
+    }
+
+    fn stored_kind(
+        stored_fp: Option<&str>,
+        stored_op_fps: Option<&db_tracking::OpLogicFingerprints>,
+        curr_op_fps: &db_tracking::OpLogicFingerprints,
+    ) -> SourceVersionKind {
+        SourceVersion::from_stored(
+            Some(1),
+            &stored_fp.map(|fp| fingerprint(fp).0.to_vec()),
+            stored_op_fps,
+            fingerprint("source"),
+            curr_op_fps,
+        )
+        .kind
+    }
+
+    #[test]
+    fn source_version_from_stored_op_fingerprints() {
+        let curr = op_fps(&[("transform:a", "a"), ("export:t", "t")]);
+        assert_eq!(
+            stored_kind(Some("source"), Some(&curr), &curr),
+            SourceVersionKind::CurrentLogic
+        );
+
+        // Ops removed, nothing in the current flow depends on.
+        let stored = op_fps(&[
+            ("transform:a", "a"),
+            ("transform:b", "b"),
+            ("export:t", "t"),
+        ]);
+        assert_eq!(
+            stored_kind(Some("source"), Some(&stored), &curr),
+            SourceVersionKind::CurrentLogic
+        );
+        assert!(changed_op_logic(&stored, &curr).next().is_none());
+
+        // Ops changed or added.
+        let stored = op_fps(&[("transform:a", "a0"), ("export:t", "t")]);
+        assert_eq!(
+            stored_kind(Some("source"), Some(&stored), &curr),
+            SourceVersionKind::DifferentLogic
+        );
+        assert_eq!(
+            changed_op_logic(&stored, &curr).collect::<Vec<_>>(),
+            vec!["transform:a"]
+        );
+        let stored = op_fps(&[("transform:a", "a")]);
+        assert_eq!(
+            stored_kind(Some("source"), Some(&stored), &curr),
+            SourceVersionKind::DifferentLogic
+        );
+        assert_eq!(
+            changed_op_logic(&stored, &curr).collect::<Vec<_>>(),
+            vec!["export:t"]
+        );
+    }
+
+    #[test]
+    fn source_version_from_stored_source_fingerprint() {
+        let curr = op_fps(&[("transform:a", "a")]);
+        assert_eq!(
+            stored_kind(Some("source0"), Some(&curr), &curr),
+            SourceVersionKind::DifferentLogic
+        );
+        // Processed before op fingerprints were tracked, which is reprocessed once.
+        assert_eq!(
+            stored_kind(Some("source"), None, &curr),
+            SourceVersionKind::DifferentLogic
+        );
+        assert_eq!(
+            stored_kind(None, None, &curr),
+            SourceVersionKind::NonExistent
+        );
+    }
+}
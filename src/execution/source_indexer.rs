@@ -1,14 +1,37 @@
 use crate::{ops::interface::SourceValueChange, prelude::*};
 
-use sqlx::PgPool;
+use crate::state_store::SharedStateStore;
 use std::collections::{hash_map, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::time::{Duration, Instant};
 use tokio::{sync::Semaphore, task::JoinSet};
 
 use super::{
-    db_tracking,
+    db_tracking, evaluator,
     row_indexer::{self, SkippedOr, SourceVersion, SourceVersionKind},
-    stats,
+    stats, LiveUpdaterControl,
 };
+use crate::utils::rate_limiter::RateLimiter;
+
+const DEFAULT_MAX_ROW_RETRIES: usize = 3;
+const ROW_RETRY_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const ROW_RETRY_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Unreferenced entries of the shared function cache created within this period are kept,
+/// as rows referencing them may not be committed yet.
+const SHARED_CACHE_GC_GRACE_PERIOD: Duration = Duration::from_secs(3600);
+
+/// Identifies this process among workers holding leases of source keys.
+static WORKER_ID: LazyLock<String> = LazyLock::new(|| uuid::Uuid::new_v4().to_string());
+
+/// Whether the error is transient, i.e. processing the row again may succeed.
+fn is_transient_error(err: &anyhow::Error) -> bool {
+    retriable::is_retryable_in_chain::<crate::llm::LlmApiError>(err)
+        || retriable::is_retryable_in_chain::<reqwest::Error>(err)
+        || retriable::is_retryable_in_chain::<sqlx::Error>(err)
+        || retriable::is_retryable_in_chain::<neo4rs::Error>(err)
+}
+
 struct SourceRowIndexingState {
     source_version: SourceVersion,
     processing_sem: Arc<Semaphore>,
@@ -28,25 +51,30 @@ impl Default for SourceRowIndexingState {
 struct SourceIndexingState {
     rows: HashMap<value::KeyValue, SourceRowIndexingState>,
     scan_generation: usize,
+    /// Keys with entries in the error table.
+    error_keys: HashSet<value::KeyValue>,
 }
 pub struct SourceIndexingContext {
     flow: Arc<builder::AnalyzedFlow>,
     source_idx: usize,
     state: Mutex<SourceIndexingState>,
+    rows_concurrency_controller: Option<Semaphore>,
+    reads_rate_limiter: Option<RateLimiter>,
+    max_row_retries: usize,
+    key_lease_ttl: Option<Duration>,
 }
 
 impl SourceIndexingContext {
     pub async fn load(
         flow: Arc<builder::AnalyzedFlow>,
         source_idx: usize,
-        pool: &PgPool,
+        state_store: &SharedStateStore,
     ) -> Result<Self> {
         let plan = flow.get_execution_plan().await?;
         let import_op = &plan.import_ops[source_idx];
-        let mut list_state = db_tracking::ListTrackedSourceKeyMetadataState::new();
         let mut rows = HashMap::new();
-        let mut key_metadata_stream =
-            list_state.list(import_op.source_id, &plan.tracking_table_setup, pool);
+        let mut key_metadata_stream = state_store
+            .list_tracked_source_key_metadata(import_op.source_id, &plan.tracking_table_setup);
         let scan_generation = 0;
         while let Some(key_metadata) = key_metadata_stream.next().await {
             let key_metadata = key_metadata?;
@@ -61,102 +89,374 @@ impl SourceIndexingContext {
                     source_version: SourceVersion::from_stored(
                         key_metadata.processed_source_ordinal,
                         &key_metadata.process_logic_fingerprint,
-                        plan.logic_fingerprint,
+                        key_metadata.process_op_fingerprints.as_deref(),
+                        import_op.logic_fingerprint,
+                        &plan.op_logic_fingerprints,
                     ),
                     processing_sem: Arc::new(Semaphore::new(1)),
                     touched_generation: scan_generation,
                 },
             );
         }
+        let refresh_options = &import_op.refresh_options;
+        let rows_concurrency_controller = match refresh_options.max_concurrent_rows {
+            Some(0) => api_bail!("`max_concurrent_rows` must be positive"),
+            Some(n) => Some(Semaphore::new(n)),
+            None => None,
+        };
+        let reads_rate_limiter = match refresh_options.max_reads_per_second {
+            Some(0) => api_bail!("`max_reads_per_second` must be positive"),
+            Some(n) => Some(RateLimiter::per_second(n)),
+            None => None,
+        };
+        if let Some(ratio) = refresh_options.max_deletion_ratio {
+            if !(0.0..=1.0).contains(&ratio) {
+                api_bail!("`max_deletion_ratio` must be between 0 and 1");
+            }
+        }
+        if refresh_options.key_lease_ttl == Some(Duration::ZERO) {
+            api_bail!("`key_lease_ttl` must be positive");
+        }
+        let error_keys = state_store
+            .list_source_row_errors(import_op.source_id, &plan.tracking_table_setup)
+            .await?
+            .into_iter()
+            .map(|row_error| {
+                value::Value::<value::ScopeValue>::from_json(
+                    row_error.source_key,
+                    &import_op.primary_key_type,
+                )?
+                .into_key()
+            })
+            .collect::<Result<HashSet<_>>>()?;
         Ok(Self {
             flow,
             source_idx,
             state: Mutex::new(SourceIndexingState {
                 rows,
                 scan_generation,
+                error_keys,
             }),
+            rows_concurrency_controller,
+            reads_rate_limiter,
+            max_row_retries: refresh_options
+                .max_row_retries
+                .unwrap_or(DEFAULT_MAX_ROW_RETRIES),
+            key_lease_ttl: refresh_options.key_lease_ttl,
         })
     }
 
+    /// Run `fut` while holding the lease of the source key, renewing it in the background, if leases are enabled.
+    /// Returns `None` without running it if the key is leased by another worker.
+    /// Also returns `None` if the lease is lost in the middle, i.e. taken over by another worker or not renewed within its TTL,
+    /// in which case `fut` is dropped before it's done, so the row isn't processed by two workers at the same time.
+    async fn run_with_key_lease<T>(
+        &self,
+        plan: &plan::ExecutionPlan,
+        key: &value::KeyValue,
+        state_store: &SharedStateStore,
+        fut: impl Future<Output = T>,
+    ) -> Result<Option<T>> {
+        let lease_ttl = match self.key_lease_ttl {
+            Some(lease_ttl) => lease_ttl,
+            None => return Ok(Some(fut.await)),
+        };
+        let source_id = plan.import_ops[self.source_idx].source_id;
+        let source_key_json = serde_json::to_value(key)?;
+        let try_lease = || {
+            state_store.try_lease_source_key(
+                source_id,
+                &source_key_json,
+                &WORKER_ID,
+                lease_ttl,
+                &plan.tracking_table_setup,
+            )
+        };
+        if !try_lease().await? {
+            return Ok(None);
+        }
+
+        tokio::pin!(fut);
+        let mut last_renewed = Instant::now();
+        let output = loop {
+            tokio::select! {
+                output = &mut fut => break Some(output),
+                _ = tokio::time::sleep(lease_ttl / 3) => {
+                    match try_lease().await {
+                        Ok(true) => last_renewed = Instant::now(),
+                        Ok(false) => {
+                            warn!(
+                                "Lease of source key {key} is taken over by another worker, as it's not renewed in time. Stop processing it"
+                            );
+                            break None;
+                        }
+                        Err(err) => {
+                            error!("{:?}", err.context("Error in renewing a source key lease"));
+                            if last_renewed.elapsed() >= lease_ttl {
+                                warn!(
+                                    "Lease of source key {key} is not renewed within its TTL. Stop processing it"
+                                );
+                                break None;
+                            }
+                        }
+                    }
+                }
+            }
+        };
+
+        if let Err(err) = state_store
+            .release_source_key_lease(
+                source_id,
+                &source_key_json,
+                &WORKER_ID,
+                &plan.tracking_table_setup,
+            )
+            .await
+        {
+            error!("{:?}", err.context("Error in releasing a source key lease"));
+        }
+        Ok(output)
+    }
+
+    /// Process the source row once, without retries.
+    async fn process_source_key_once(
+        &self,
+        plan: &plan::ExecutionPlan,
+        key: &value::KeyValue,
+        source_version: &SourceVersion,
+        value: Option<value::FieldValues>,
+        bypass_cache: bool,
+        update_stats: &stats::UpdateStats,
+        state_store: &SharedStateStore,
+    ) -> Result<()> {
+        let import_op = &plan.import_ops[self.source_idx];
+        let source_value = if source_version.kind == row_indexer::SourceVersionKind::Deleted {
+            None
+        } else if let Some(value) = value {
+            Some(value)
+        } else {
+            // Even if the source version kind is not Deleted, the source value might be gone one polling.
+            // In this case, we still use the current source version even if it's already stale - actually this version skew
+            // also happens for update cases and there's no way to keep them always in sync for many sources.
+            //
+            // We only need source version <= actual version for value.
+            if let Some(rate_limiter) = &self.reads_rate_limiter {
+                rate_limiter.acquire(1).await;
+            }
+            import_op.executor.get_value(key).await?
+        };
+        let schema = &self.flow.data_schema;
+        let result = row_indexer::update_source_row(
+            plan,
+            import_op,
+            schema,
+            key,
+            source_value,
+            source_version,
+            bypass_cache,
+            state_store,
+            update_stats,
+        )
+        .await?;
+        let target_source_version = if let SkippedOr::Skipped(existing_source_version) = result {
+            Some(existing_source_version)
+        } else if source_version.kind == row_indexer::SourceVersionKind::Deleted {
+            Some(source_version.clone())
+        } else {
+            None
+        };
+        if let Some(target_source_version) = target_source_version {
+            let mut state = self.state.lock().unwrap();
+            let scan_generation = state.scan_generation;
+            let entry = state.rows.entry(key.clone());
+            match entry {
+                hash_map::Entry::Occupied(mut entry) => {
+                    if !entry
+                        .get()
+                        .source_version
+                        .should_skip(&target_source_version, None)
+                    {
+                        if target_source_version.kind == row_indexer::SourceVersionKind::Deleted {
+                            entry.remove();
+                        } else {
+                            let mut_entry = entry.get_mut();
+                            mut_entry.source_version = target_source_version;
+                            mut_entry.touched_generation = scan_generation;
+                        }
+                    }
+                }
+                hash_map::Entry::Vacant(entry) => {
+                    entry.insert(SourceRowIndexingState {
+                        source_version: target_source_version,
+                        touched_generation: scan_generation,
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep the error of the source row in the error table, after all attempts failed.
+    async fn record_source_row_error(
+        &self,
+        plan: &plan::ExecutionPlan,
+        key: &value::KeyValue,
+        err: &anyhow::Error,
+        num_attempts: usize,
+        state_store: &SharedStateStore,
+    ) -> Result<()> {
+        let row_error = db_tracking::SourceRowError {
+            source_id: plan.import_ops[self.source_idx].source_id,
+            source_key: serde_json::to_value(key)?,
+            op_name: evaluator::failed_transform_op_name(err).map(|name| name.to_string()),
+            error_message: format!("{err:#}"),
+            num_attempts: num_attempts as i32,
+            last_attempt_time_micros: chrono::Utc::now().timestamp_micros(),
+        };
+        state_store
+            .upsert_source_row_error(&row_error, &plan.tracking_table_setup)
+            .await?;
+        self.state.lock().unwrap().error_keys.insert(key.clone());
+        Ok(())
+    }
+
+    async fn clear_source_row_error(
+        &self,
+        plan: &plan::ExecutionPlan,
+        key: &value::KeyValue,
+        state_store: &SharedStateStore,
+    ) -> Result<()> {
+        if !self.state.lock().unwrap().error_keys.contains(key) {
+            return Ok(());
+        }
+        state_store
+            .delete_source_row_error(
+                plan.import_ops[self.source_idx].source_id,
+                &serde_json::to_value(key)?,
+                &plan.tracking_table_setup,
+            )
+            .await?;
+        self.state.lock().unwrap().error_keys.remove(key);
+        Ok(())
+    }
+
     async fn process_source_key(
         self: Arc<Self>,
         key: value::KeyValue,
         source_version: SourceVersion,
         value: Option<value::FieldValues>,
+        bypass_cache: bool,
+        control: Option<Arc<LiveUpdaterControl>>,
         update_stats: Arc<stats::UpdateStats>,
         processing_sem: Arc<Semaphore>,
-        pool: PgPool,
+        state_store: SharedStateStore,
     ) {
+        update_stats.num_queued_rows.inc(1);
+        let mut started = false;
         let process = async {
+            if let Some(control) = &control {
+                if !control.wait_running().await {
+                    // Drained before the row is started. Process it again in the next pass.
+                    if let Some(row_state) = self.state.lock().unwrap().rows.get_mut(&key) {
+                        row_state.source_version = SourceVersion::default();
+                    }
+                    return Ok(());
+                }
+            }
             let permit = processing_sem.acquire().await?;
-            let plan = self.flow.get_execution_plan().await?;
-            let import_op = &plan.import_ops[self.source_idx];
-            let source_value = if source_version.kind == row_indexer::SourceVersionKind::Deleted {
-                None
-            } else if let Some(value) = value {
-                Some(value)
-            } else {
-                // Even if the source version kind is not Deleted, the source value might be gone one polling.
-                // In this case, we still use the current source version even if it's already stale - actually this version skew
-                // also happens for update cases and there's no way to keep them always in sync for many sources.
-                //
-                // We only need source version <= actual version for value.
-                import_op.executor.get_value(&key).await?
+            let _row_permit = match &self.rows_concurrency_controller {
+                Some(controller) => Some(controller.acquire().await?),
+                None => None,
             };
-            let schema = &self.flow.data_schema;
-            let result = row_indexer::update_source_row(
-                &plan,
-                import_op,
-                schema,
-                &key,
-                source_value,
-                &source_version,
-                &pool,
-                &update_stats,
-            )
-            .await?;
-            let target_source_version = if let SkippedOr::Skipped(existing_source_version) = result
+            update_stats.num_queued_rows.inc(-1);
+            update_stats.num_active_rows.inc(1);
+            started = true;
+
+            let plan = self.flow.get_execution_plan().await?;
+            let num_attempts = AtomicUsize::new(0);
+            let process_with_retries = retriable::run(
+                || async {
+                    num_attempts.fetch_add(1, Relaxed);
+                    self.process_source_key_once(
+                        &plan,
+                        &key,
+                        &source_version,
+                        value.clone(),
+                        bypass_cache,
+                        &update_stats,
+                        &state_store,
+                    )
+                    .await
+                    .map_err(|err| {
+                        let is_retryable = is_transient_error(&err);
+                        retriable::Error::new(err, is_retryable)
+                    })
+                },
+                retriable::RunOptions {
+                    max_retries: self.max_row_retries,
+                    initial_backoff: ROW_RETRY_INITIAL_BACKOFF,
+                    max_backoff: ROW_RETRY_MAX_BACKOFF,
+                },
+            );
+            let result = match self
+                .run_with_key_lease(&plan, &key, &state_store, process_with_retries)
+                .await?
             {
-                Some(existing_source_version)
-            } else if source_version.kind == row_indexer::SourceVersionKind::Deleted {
-                Some(source_version)
-            } else {
-                None
+                Some(result) => result.map_err(anyhow::Error::from),
+                None => {
+                    // Being processed by another worker, or the lease is lost in the middle.
+                    // Check it again in the next pass, in case it's not done by then.
+                    if let Some(row_state) = self.state.lock().unwrap().rows.get_mut(&key) {
+                        row_state.source_version = SourceVersion::default();
+                    }
+                    update_stats.num_skipped.inc(1);
+                    return Ok(());
+                }
             };
-            if let Some(target_source_version) = target_source_version {
-                let mut state = self.state.lock().unwrap();
-                let scan_generation = state.scan_generation;
-                let entry = state.rows.entry(key.clone());
-                match entry {
-                    hash_map::Entry::Occupied(mut entry) => {
-                        if !entry
-                            .get()
-                            .source_version
-                            .should_skip(&target_source_version, None)
-                        {
-                            if target_source_version.kind == row_indexer::SourceVersionKind::Deleted
-                            {
-                                entry.remove();
-                            } else {
-                                let mut_entry = entry.get_mut();
-                                mut_entry.source_version = target_source_version;
-                                mut_entry.touched_generation = scan_generation;
-                            }
-                        }
+            match result {
+                Ok(()) => {
+                    self.clear_source_row_error(&plan, &key, &state_store)
+                        .await?
+                }
+                Err(err) => {
+                    // Process the row again in the next pass, even if listed with the same ordinal.
+                    // The ordinal is kept for redriving the row.
+                    if let Some(row_state) = self.state.lock().unwrap().rows.get_mut(&key) {
+                        row_state.source_version = SourceVersion {
+                            ordinal: source_version.ordinal,
+                            kind: SourceVersionKind::NonExistent,
+                        };
                     }
-                    hash_map::Entry::Vacant(entry) => {
-                        entry.insert(SourceRowIndexingState {
-                            source_version: target_source_version,
-                            touched_generation: scan_generation,
-                            ..Default::default()
-                        });
+                    if let Err(record_err) = self
+                        .record_source_row_error(
+                            &plan,
+                            &key,
+                            &err,
+                            num_attempts.load(Relaxed),
+                            &state_store,
+                        )
+                        .await
+                    {
+                        error!(
+                            "{:?}",
+                            record_err.context("Error in recording a source row error")
+                        );
                     }
+                    return Err(err);
                 }
             }
             drop(permit);
             anyhow::Ok(())
         };
-        if let Err(e) = process.await {
+        let result = process.await;
+        if started {
+            update_stats.num_active_rows.inc(-1);
+        } else {
+            update_stats.num_queued_rows.inc(-1);
+        }
+        update_stats.last_progress_time.mark();
+        if let Err(e) = result {
             update_stats.num_errors.inc(1);
             error!("{:?}", e.context("Error in processing a source row"));
         }
@@ -167,8 +467,9 @@ impl SourceIndexingContext {
         key: value::KeyValue,
         source_version: SourceVersion,
         value: Option<value::FieldValues>,
+        control: Option<Arc<LiveUpdaterControl>>,
         update_stats: &Arc<stats::UpdateStats>,
-        pool: &PgPool,
+        state_store: &SharedStateStore,
     ) -> Option<impl Future<Output = ()> + Send + 'static> {
         let processing_sem = {
             let mut state = self.state.lock().unwrap();
@@ -188,16 +489,79 @@ impl SourceIndexingContext {
             key,
             source_version,
             value,
+            false,
+            control,
             update_stats.clone(),
             processing_sem,
-            pool.clone(),
+            state_store.clone(),
         ))
     }
 
+    /// Returns the reason if deleting the given number of rows out of the tracked ones looks suspicious,
+    /// e.g. the source is temporarily inaccessible and returns nothing.
+    fn check_mass_deletion(
+        refresh_options: &spec::SourceRefreshOptions,
+        num_tracked: usize,
+        num_listed: usize,
+        num_deleted: usize,
+    ) -> Option<String> {
+        if num_deleted == 0 {
+            return None;
+        }
+        if num_listed == 0 {
+            return Some(format!(
+                "the source returned no rows while {num_tracked} rows were tracked"
+            ));
+        }
+        if let Some(max_deletions) = refresh_options.max_deletions {
+            if num_deleted > max_deletions {
+                return Some(format!(
+                    "{num_deleted} rows to delete exceeds `max_deletions` ({max_deletions})"
+                ));
+            }
+        }
+        if let Some(max_deletion_ratio) = refresh_options.max_deletion_ratio {
+            if num_deleted as f64 > num_tracked as f64 * max_deletion_ratio {
+                return Some(format!(
+                    "{num_deleted} out of {num_tracked} tracked rows to delete exceeds `max_deletion_ratio` ({max_deletion_ratio})"
+                ));
+            }
+        }
+        None
+    }
+
+    fn check_mass_deletion_allowed(
+        &self,
+        import_op: &plan::AnalyzedImportOp,
+        num_tracked: usize,
+        num_listed: usize,
+        num_deleted: usize,
+    ) -> Result<()> {
+        if let Some(reason) = Self::check_mass_deletion(
+            &import_op.refresh_options,
+            num_tracked,
+            num_listed,
+            num_deleted,
+        ) {
+            api_bail!(
+                "Refused to delete rows of source `{}` in flow `{}`: {reason}. Update with mass deletion allowed if it's expected.",
+                import_op.name,
+                self.flow.flow_instance.name
+            );
+        }
+        Ok(())
+    }
+
+    /// Scan the source and process changed rows.
+    /// Rows gone from the source are deleted. It fails without deleting anything if it looks like a mass deletion,
+    /// unless `allow_mass_deletion` is true.
+    /// The scan waits while `control` is paused, and stops early without deleting anything once it's drained.
     pub async fn update(
         self: &Arc<Self>,
-        pool: &PgPool,
+        state_store: &SharedStateStore,
         update_stats: &Arc<stats::UpdateStats>,
+        allow_mass_deletion: bool,
+        control: &Arc<LiveUpdaterControl>,
     ) -> Result<()> {
         let plan = self.flow.get_execution_plan().await?;
         let import_op = &plan.import_ops[self.source_idx];
@@ -207,19 +571,29 @@ impl SourceIndexingContext {
                 include_ordinal: true,
             });
         let mut join_set = JoinSet::new();
-        let scan_generation = {
+        let (scan_generation, num_tracked) = {
             let mut state = self.state.lock().unwrap();
             state.scan_generation += 1;
-            state.scan_generation
+            (state.scan_generation, state.rows.len())
         };
+        let mut num_listed = 0;
+        let mut drained = false;
         while let Some(row) = rows_stream.next().await {
-            for row in row? {
+            if !control.wait_running().await {
+                drained = true;
+                break;
+            }
+            let rows = row?;
+            update_stats.num_listed_rows.inc(rows.len() as i64);
+            for row in rows {
+                num_listed += 1;
                 self.process_source_key_if_newer(
                     row.key,
                     SourceVersion::from_current(row.ordinal),
                     None,
+                    Some(control.clone()),
                     update_stats,
-                    pool,
+                    state_store,
                 )
                 .map(|fut| join_set.spawn(fut));
             }
@@ -231,6 +605,10 @@ impl SourceIndexingContext {
                 }
             }
         }
+        if drained {
+            // Rows not seen by an incomplete scan cannot be considered deleted.
+            return Ok(());
+        }
 
         let deleted_key_versions = {
             let mut deleted_key_versions = Vec::new();
@@ -246,14 +624,198 @@ impl SourceIndexingContext {
             }
             deleted_key_versions
         };
+        if !allow_mass_deletion {
+            self.check_mass_deletion_allowed(
+                import_op,
+                num_tracked,
+                num_listed,
+                deleted_key_versions.len(),
+            )?;
+        }
         for (key, source_version, processing_sem) in deleted_key_versions {
             join_set.spawn(self.clone().process_source_key(
                 key,
                 source_version,
                 None,
+                false,
+                Some(control.clone()),
+                update_stats.clone(),
+                processing_sem,
+                state_store.clone(),
+            ));
+        }
+        while let Some(result) = join_set.join_next().await {
+            if let Err(e) = result {
+                if !e.is_cancelled() {
+                    error!("{:?}", e);
+                }
+            }
+        }
+
+        if let Err(e) = self.collect_shared_cache_garbage(&plan, state_store).await {
+            error!(
+                "{:?}",
+                e.context("Error in garbage collecting the shared function cache")
+            );
+        }
+        Ok(())
+    }
+
+    /// Report changes the next update would make in `update_stats`, without processing any row or changing any state.
+    /// Up to `sample_size` rows to be changed are evaluated, to also report changes on targets.
+    /// Fails like `update()` if it looks like a mass deletion and `allow_mass_deletion` is false.
+    pub async fn dry_run(
+        &self,
+        state_store: &SharedStateStore,
+        update_stats: &stats::UpdateStats,
+        sample_size: usize,
+        allow_mass_deletion: bool,
+    ) -> Result<()> {
+        let plan = self.flow.get_execution_plan().await?;
+        let import_op = &plan.import_ops[self.source_idx];
+        let mut rows_stream = import_op
+            .executor
+            .list(interface::SourceExecutorListOptions {
+                include_ordinal: true,
+            });
+        let mut listed_keys = HashSet::new();
+        let mut sample_keys = Vec::new();
+        while let Some(row) = rows_stream.next().await {
+            for row in row? {
+                let to_process = {
+                    let state = self.state.lock().unwrap();
+                    match state.rows.get(&row.key) {
+                        Some(row_state) => {
+                            let skipped = row_state.source_version.should_skip(
+                                &SourceVersion::from_current(row.ordinal),
+                                Some(update_stats),
+                            );
+                            if !skipped {
+                                update_stats.num_repreocesses.inc(1);
+                            }
+                            !skipped
+                        }
+                        None => {
+                            update_stats.num_insertions.inc(1);
+                            true
+                        }
+                    }
+                };
+                if to_process && sample_keys.len() < sample_size {
+                    sample_keys.push(row.key.clone());
+                }
+                listed_keys.insert(row.key);
+            }
+        }
+
+        let (deleted_keys, num_tracked) = {
+            let state = self.state.lock().unwrap();
+            let deleted_keys = state
+                .rows
+                .keys()
+                .filter(|key| !listed_keys.contains(*key))
+                .cloned()
+                .collect::<Vec<_>>();
+            (deleted_keys, state.rows.len())
+        };
+        if !allow_mass_deletion {
+            self.check_mass_deletion_allowed(
+                import_op,
+                num_tracked,
+                listed_keys.len(),
+                deleted_keys.len(),
+            )?;
+        }
+        update_stats.num_deletions.inc(deleted_keys.len() as i64);
+        let num_sampled_deletions = sample_size.saturating_sub(sample_keys.len());
+        sample_keys.extend(deleted_keys.into_iter().take(num_sampled_deletions));
+
+        let schema = &self.flow.data_schema;
+        for key in sample_keys {
+            if let Some(rate_limiter) = &self.reads_rate_limiter {
+                rate_limiter.acquire(1).await;
+            }
+            row_indexer::dry_run_source_row(
+                &plan,
+                import_op,
+                schema,
+                &key,
+                state_store,
+                update_stats,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn collect_shared_cache_garbage(
+        &self,
+        plan: &plan::ExecutionPlan,
+        state_store: &SharedStateStore,
+    ) -> Result<()> {
+        let created_before_micros =
+            chrono::Utc::now().timestamp_micros() - SHARED_CACHE_GC_GRACE_PERIOD.as_micros() as i64;
+        let num_deleted = state_store
+            .delete_unreferenced_shared_cache_entries(
+                created_before_micros,
+                &plan.tracking_table_setup,
+            )
+            .await?;
+        if num_deleted > 0 {
+            trace!(
+                "Deleted {num_deleted} unreferenced entries from the shared function cache of flow `{}`",
+                self.flow.flow_instance.name
+            );
+        }
+        Ok(())
+    }
+
+    /// List entries of the error table for rows of this source.
+    pub async fn list_errors(
+        &self,
+        state_store: &SharedStateStore,
+    ) -> Result<Vec<db_tracking::SourceRowError>> {
+        let plan = self.flow.get_execution_plan().await?;
+        state_store
+            .list_source_row_errors(
+                plan.import_ops[self.source_idx].source_id,
+                &plan.tracking_table_setup,
+            )
+            .await
+    }
+
+    /// Process all rows with entries in the error table again, regardless of their source versions.
+    /// Entries are removed for rows processed successfully.
+    pub async fn redrive_errors(
+        self: &Arc<Self>,
+        state_store: &SharedStateStore,
+        update_stats: &Arc<stats::UpdateStats>,
+    ) -> Result<()> {
+        let rows_to_redrive = {
+            let mut state = self.state.lock().unwrap();
+            let keys = state.error_keys.iter().cloned().collect::<Vec<_>>();
+            keys.into_iter()
+                .map(|key| {
+                    let row_state = state.rows.entry(key.clone()).or_default();
+                    (
+                        key,
+                        row_state.source_version.for_reprocessing(),
+                        row_state.processing_sem.clone(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut join_set = JoinSet::new();
+        for (key, source_version, processing_sem) in rows_to_redrive {
+            join_set.spawn(self.clone().process_source_key(
+                key,
+                source_version,
+                None,
+                false,
+                None,
                 update_stats.clone(),
                 processing_sem,
-                pool.clone(),
+                state_store.clone(),
             ));
         }
         while let Some(result) = join_set.join_next().await {
@@ -263,15 +825,72 @@ impl SourceIndexingContext {
                 }
             }
         }
+        Ok(())
+    }
+
+    /// Tracked keys of string type starting with the given prefix.
+    pub fn tracked_keys_with_prefix(&self, prefix: &str) -> Vec<value::KeyValue> {
+        let state = self.state.lock().unwrap();
+        state
+            .rows
+            .keys()
+            .filter(|key| matches!(key, value::KeyValue::Str(s) if s.starts_with(prefix)))
+            .cloned()
+            .collect()
+    }
 
+    /// Process the given rows again, regardless of their source versions.
+    /// If `bypass_cache` is true, memoized function results are ignored and evaluated again.
+    pub async fn reprocess(
+        self: &Arc<Self>,
+        keys: Vec<value::KeyValue>,
+        bypass_cache: bool,
+        state_store: &SharedStateStore,
+        update_stats: &Arc<stats::UpdateStats>,
+    ) -> Result<()> {
+        let rows_to_reprocess = {
+            let mut state = self.state.lock().unwrap();
+            keys.into_iter()
+                .map(|key| {
+                    let row_state = state.rows.entry(key.clone()).or_default();
+                    // Keep the known ordinal (last listed, or loaded from the tracking table), to be stored again.
+                    (
+                        key,
+                        row_state.source_version.for_reprocessing(),
+                        row_state.processing_sem.clone(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut join_set = JoinSet::new();
+        for (key, source_version, processing_sem) in rows_to_reprocess {
+            join_set.spawn(self.clone().process_source_key(
+                key,
+                source_version,
+                None,
+                bypass_cache,
+                None,
+                update_stats.clone(),
+                processing_sem,
+                state_store.clone(),
+            ));
+        }
+        while let Some(result) = join_set.join_next().await {
+            if let Err(e) = result {
+                if !e.is_cancelled() {
+                    error!("{:?}", e);
+                }
+            }
+        }
         Ok(())
     }
 
     pub fn process_change(
         self: &Arc<Self>,
         change: interface::SourceChange,
-        pool: &PgPool,
+        state_store: &SharedStateStore,
         update_stats: &Arc<stats::UpdateStats>,
+        control: &Arc<LiveUpdaterControl>,
     ) -> Option<impl Future<Output = ()> + Send + 'static> {
         let (source_version_kind, value) = match change.value {
             SourceValueChange::Upsert(value) => (SourceVersionKind::CurrentLogic, value),
@@ -284,8 +903,79 @@ impl SourceIndexingContext {
                 kind: source_version_kind,
             },
             value,
+            Some(control.clone()),
             update_stats,
-            pool,
+            state_store,
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(
+        max_deletions: Option<usize>,
+        max_deletion_ratio: Option<f64>,
+        num_tracked: usize,
+        num_listed: usize,
+        num_deleted: usize,
+    ) -> Option<String> {
+        let refresh_options = spec::SourceRefreshOptions {
+            max_deletions,
+            max_deletion_ratio,
+            ..Default::default()
+        };
+        SourceIndexingContext::check_mass_deletion(
+            &refresh_options,
+            num_tracked,
+            num_listed,
+            num_deleted,
+        )
+    }
+
+    #[test]
+    fn no_deletion_is_allowed() {
+        assert_eq!(check(Some(0), Some(0.0), 10, 0, 0), None);
+        assert_eq!(check(None, None, 0, 0, 0), None);
+    }
+
+    #[test]
+    fn empty_listing_is_refused() {
+        let reason = check(None, None, 10, 0, 10).unwrap();
+        assert_eq!(
+            reason,
+            "the source returned no rows while 10 rows were tracked"
+        );
+    }
+
+    #[test]
+    fn deletions_without_limits_are_allowed() {
+        assert_eq!(check(None, None, 100, 1, 99), None);
+    }
+
+    #[test]
+    fn max_deletions() {
+        assert_eq!(check(Some(5), None, 100, 95, 5), None);
+        let reason = check(Some(5), None, 100, 94, 6).unwrap();
+        assert!(reason.contains("`max_deletions` (5)"), "got: {reason}");
+    }
+
+    #[test]
+    fn max_deletion_ratio() {
+        // The ratio is against tracked rows, with the boundary allowed.
+        assert_eq!(check(None, Some(0.5), 10, 5, 5), None);
+        let reason = check(None, Some(0.5), 10, 4, 6).unwrap();
+        assert!(
+            reason.contains("6 out of 10 tracked rows") && reason.contains("(0.5)"),
+            "got: {reason}"
+        );
+    }
+
+    #[test]
+    fn both_limits_apply() {
+        assert!(check(Some(100), Some(0.1), 100, 80, 20).is_some());
+        assert!(check(Some(10), Some(0.5), 100, 80, 20).is_some());
+        assert_eq!(check(Some(20), Some(0.2), 100, 80, 20), None);
+    }
+}
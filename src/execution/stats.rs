@@ -3,6 +3,7 @@ use crate::prelude::*;
 use std::{
     ops::AddAssign,
     sync::atomic::{AtomicI64, Ordering::Relaxed},
+    time::{Duration, Instant, SystemTime},
 };
 
 #[derive(Default, Serialize)]
@@ -50,38 +51,571 @@ impl std::fmt::Debug for Counter {
     }
 }
 
+pub trait HistogramBuckets {
+    /// Upper bounds of buckets, in ascending order. An implicit `+Inf` bucket follows.
+    const BOUNDS: &'static [f64];
+}
+
+/// Buckets for latencies, in seconds.
+pub struct LatencyBuckets;
+
+impl HistogramBuckets for LatencyBuckets {
+    const BOUNDS: &'static [f64] = &[
+        0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0,
+    ];
+}
+
+/// Buckets for numbers of entries in batches.
+pub struct BatchSizeBuckets;
+
+impl HistogramBuckets for BatchSizeBuckets {
+    const BOUNDS: &'static [f64] = &[
+        1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0, 5000.0,
+    ];
+}
+
+#[derive(Debug, Clone)]
+pub struct HistogramData {
+    /// Number of observations in each bucket (not cumulative), with the last one for `+Inf`.
+    pub bucket_counts: Vec<u64>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+/// Histogram of observed values with fixed buckets.
+pub struct Histogram<B: HistogramBuckets>(Mutex<HistogramData>, std::marker::PhantomData<B>);
+
+impl<B: HistogramBuckets> Histogram<B> {
+    pub fn observe(&self, value: f64) {
+        let bucket_idx = B::BOUNDS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(B::BOUNDS.len());
+        let mut data = self.0.lock().unwrap();
+        data.bucket_counts[bucket_idx] += 1;
+        data.sum += value;
+        data.count += 1;
+    }
+
+    pub fn data(&self) -> HistogramData {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn bounds(&self) -> &'static [f64] {
+        B::BOUNDS
+    }
+
+    pub fn delta(&self, base: &Self) -> Self {
+        let mut data = self.data();
+        let base = base.data();
+        for (count, base_count) in data.bucket_counts.iter_mut().zip(base.bucket_counts) {
+            *count -= base_count;
+        }
+        data.sum -= base.sum;
+        data.count -= base.count;
+        Self(Mutex::new(data), std::marker::PhantomData)
+    }
+
+    pub fn merge(&self, other: &Self) {
+        let other = other.data();
+        let mut data = self.0.lock().unwrap();
+        for (count, other_count) in data.bucket_counts.iter_mut().zip(other.bucket_counts) {
+            *count += other_count;
+        }
+        data.sum += other.sum;
+        data.count += other.count;
+    }
+}
+
+impl<B: HistogramBuckets> Default for Histogram<B> {
+    fn default() -> Self {
+        Self(
+            Mutex::new(HistogramData {
+                bucket_counts: vec![0; B::BOUNDS.len() + 1],
+                sum: 0.0,
+                count: 0,
+            }),
+            std::marker::PhantomData,
+        )
+    }
+}
+
+impl<B: HistogramBuckets> Clone for Histogram<B> {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(self.data()), std::marker::PhantomData)
+    }
+}
+
+impl<B: HistogramBuckets> std::fmt::Debug for Histogram<B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let data = self.data();
+        write!(f, "count={}, sum={}", data.count, data.sum)
+    }
+}
+
+/// Stats collected by executors of a specific operation, e.g. a transform op calling LLM.
+#[derive(Debug, Serialize, Default, Clone)]
+pub struct OpStats {
+    /// Number of requests sent to LLM, including re-asks.
+    pub num_llm_attempts: Counter,
+    /// Number of LLM replies that failed to parse or validate.
+    pub num_llm_invalid_outputs: Counter,
+    /// Number of evaluations that still failed after all attempts.
+    pub num_llm_failures: Counter,
+    /// Number of tokens consumed by LLM requests, as reported by LLM APIs.
+    pub num_llm_prompt_tokens: Counter,
+    pub num_llm_completion_tokens: Counter,
+    /// Number of evaluations skipped by reusing memoized results.
+    pub num_cache_hits: Counter,
+    /// Number of evaluations performed as no memoized result is usable.
+    pub num_cache_misses: Counter,
+    /// Number of cache misses caused by expired memoized results.
+    pub num_cache_expirations: Counter,
+    /// Number of entries upserted into / deleted from the target, for export ops.
+    pub num_target_upserts: Counter,
+    pub num_target_deletions: Counter,
+
+    /// Time taken by evaluating the op without memoized results, in seconds, for transform ops.
+    #[serde(skip)]
+    pub latency: Histogram<LatencyBuckets>,
+    /// Time taken by applying mutations to the target, in seconds, for export ops.
+    #[serde(skip)]
+    pub apply_latency: Histogram<LatencyBuckets>,
+    /// Number of entries in each batch of mutations applied to the target, for export ops.
+    #[serde(skip)]
+    pub apply_batch_size: Histogram<BatchSizeBuckets>,
+}
+
+impl OpStats {
+    pub fn delta(&self, base: &Self) -> Self {
+        OpStats {
+            num_llm_attempts: self.num_llm_attempts.delta(&base.num_llm_attempts),
+            num_llm_invalid_outputs: self
+                .num_llm_invalid_outputs
+                .delta(&base.num_llm_invalid_outputs),
+            num_llm_failures: self.num_llm_failures.delta(&base.num_llm_failures),
+            num_llm_prompt_tokens: self
+                .num_llm_prompt_tokens
+                .delta(&base.num_llm_prompt_tokens),
+            num_llm_completion_tokens: self
+                .num_llm_completion_tokens
+                .delta(&base.num_llm_completion_tokens),
+            num_cache_hits: self.num_cache_hits.delta(&base.num_cache_hits),
+            num_cache_misses: self.num_cache_misses.delta(&base.num_cache_misses),
+            num_cache_expirations: self
+                .num_cache_expirations
+                .delta(&base.num_cache_expirations),
+            num_target_upserts: self.num_target_upserts.delta(&base.num_target_upserts),
+            num_target_deletions: self.num_target_deletions.delta(&base.num_target_deletions),
+            latency: self.latency.delta(&base.latency),
+            apply_latency: self.apply_latency.delta(&base.apply_latency),
+            apply_batch_size: self.apply_batch_size.delta(&base.apply_batch_size),
+        }
+    }
+
+    pub fn merge(&self, other: &Self) {
+        self.num_llm_attempts.inc(other.num_llm_attempts.get());
+        self.num_llm_invalid_outputs
+            .inc(other.num_llm_invalid_outputs.get());
+        self.num_llm_failures.inc(other.num_llm_failures.get());
+        self.num_llm_prompt_tokens
+            .inc(other.num_llm_prompt_tokens.get());
+        self.num_llm_completion_tokens
+            .inc(other.num_llm_completion_tokens.get());
+        self.num_cache_hits.inc(other.num_cache_hits.get());
+        self.num_cache_misses.inc(other.num_cache_misses.get());
+        self.num_cache_expirations
+            .inc(other.num_cache_expirations.get());
+        self.num_target_upserts.inc(other.num_target_upserts.get());
+        self.num_target_deletions
+            .inc(other.num_target_deletions.get());
+        self.latency.merge(&other.latency);
+        self.apply_latency.merge(&other.apply_latency);
+        self.apply_batch_size.merge(&other.apply_batch_size);
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.num_llm_attempts.get() == 0
+            && self.num_llm_invalid_outputs.get() == 0
+            && self.num_llm_failures.get() == 0
+            && self.num_llm_prompt_tokens.get() == 0
+            && self.num_llm_completion_tokens.get() == 0
+            && self.num_cache_hits.get() == 0
+            && self.num_cache_misses.get() == 0
+            && self.num_cache_expirations.get() == 0
+            && self.num_target_upserts.get() == 0
+            && self.num_target_deletions.get() == 0
+    }
+}
+
+impl std::fmt::Display for OpStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut messages = Vec::new();
+        let num_llm_attempts = self.num_llm_attempts.get();
+        if num_llm_attempts > 0 {
+            messages.push(format!(
+                "{num_llm_attempts} LLM attempts, {} INVALID outputs, {} FAILED",
+                self.num_llm_invalid_outputs, self.num_llm_failures
+            ));
+        }
+        let num_llm_prompt_tokens = self.num_llm_prompt_tokens.get();
+        let num_llm_completion_tokens = self.num_llm_completion_tokens.get();
+        if num_llm_prompt_tokens > 0 || num_llm_completion_tokens > 0 {
+            messages.push(format!(
+                "{num_llm_prompt_tokens} prompt tokens, {num_llm_completion_tokens} completion tokens"
+            ));
+        }
+        let num_cache_hits = self.num_cache_hits.get();
+        let num_cache_misses = self.num_cache_misses.get();
+        if num_cache_hits > 0 || num_cache_misses > 0 {
+            let num_cache_expirations = self.num_cache_expirations.get();
+            if num_cache_expirations > 0 {
+                messages.push(format!(
+                    "{num_cache_hits} CACHED, {num_cache_misses} NOT CACHED ({num_cache_expirations} EXPIRED)"
+                ));
+            } else {
+                messages.push(format!(
+                    "{num_cache_hits} CACHED, {num_cache_misses} NOT CACHED"
+                ));
+            }
+        }
+        let num_target_upserts = self.num_target_upserts.get();
+        let num_target_deletions = self.num_target_deletions.get();
+        if num_target_upserts > 0 || num_target_deletions > 0 {
+            messages.push(format!(
+                "{num_target_upserts} target entries UPSERTED, {num_target_deletions} DELETED"
+            ));
+        }
+        write!(f, "{}", messages.join("; "))
+    }
+}
+
+/// Stats of all operations, keyed by op name.
+#[derive(Default)]
+pub struct OpStatsMap(Mutex<BTreeMap<String, Arc<OpStats>>>);
+
+impl OpStatsMap {
+    pub fn get(&self, op_name: &str) -> Arc<OpStats> {
+        let mut ops = self.0.lock().unwrap();
+        if let Some(op_stats) = ops.get(op_name) {
+            return op_stats.clone();
+        }
+        let op_stats = Arc::new(OpStats::default());
+        ops.insert(op_name.to_string(), op_stats.clone());
+        op_stats
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, OpStats> {
+        let ops = self.0.lock().unwrap();
+        ops.iter()
+            .map(|(name, op_stats)| (name.clone(), op_stats.as_ref().clone()))
+            .collect()
+    }
+
+    pub fn delta(&self, base: &Self) -> Self {
+        let base = base.snapshot();
+        let ops = self
+            .snapshot()
+            .into_iter()
+            .map(|(name, op_stats)| {
+                let delta = match base.get(&name) {
+                    Some(base_op_stats) => op_stats.delta(base_op_stats),
+                    None => op_stats,
+                };
+                (name, Arc::new(delta))
+            })
+            .collect();
+        Self(Mutex::new(ops))
+    }
+
+    pub fn merge(&self, other: &Self) {
+        for (name, op_stats) in other.snapshot() {
+            self.get(&name).merge(&op_stats);
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        let ops = self.0.lock().unwrap();
+        ops.values().all(|op_stats| op_stats.is_zero())
+    }
+}
+
+impl Clone for OpStatsMap {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(
+            self.snapshot()
+                .into_iter()
+                .map(|(name, op_stats)| (name, Arc::new(op_stats)))
+                .collect(),
+        ))
+    }
+}
+
+impl Serialize for OpStatsMap {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.snapshot().serialize(serializer)
+    }
+}
+
+impl std::fmt::Debug for OpStatsMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&self.snapshot(), f)
+    }
+}
+
+tokio::task_local! {
+    static CURRENT_OP_STATS: Arc<OpStats>;
+}
+
+/// Run the future with the given op stats, which can be updated by executors through `record_op_stats()`.
+pub async fn with_op_stats<Fut: Future>(op_stats: Option<Arc<OpStats>>, fut: Fut) -> Fut::Output {
+    match op_stats {
+        Some(op_stats) => CURRENT_OP_STATS.scope(op_stats, fut).await,
+        None => fut.await,
+    }
+}
+
+/// Update stats of the op currently being evaluated. No-op if no stats are being collected.
+pub fn record_op_stats(f: impl FnOnce(&OpStats)) {
+    let _ = CURRENT_OP_STATS.try_with(|op_stats| f(op_stats));
+}
+
+/// Weight of the latest sample in the moving average of throughput.
+const THROUGHPUT_SMOOTHING_FACTOR: f64 = 0.3;
+/// Samples closer than this to the previous one are ignored, as they're too noisy.
+const MIN_THROUGHPUT_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Default, Clone)]
+struct ThroughputState {
+    last_sample: Option<(Instant, i64)>,
+    rows_per_sec: Option<f64>,
+}
+
+/// Number of source rows processed per second, as an exponential moving average.
+#[derive(Default)]
+pub struct Throughput(Mutex<ThroughputState>);
+
+impl Throughput {
+    /// Update the moving average with the total number of rows processed so far.
+    pub fn sample(&self, num_processed: i64) {
+        self.sample_at(Instant::now(), num_processed);
+    }
+
+    fn sample_at(&self, now: Instant, num_processed: i64) {
+        let mut state = self.0.lock().unwrap();
+        if let Some((last_time, last_num_processed)) = state.last_sample {
+            let elapsed = now.duration_since(last_time);
+            if elapsed < MIN_THROUGHPUT_SAMPLE_INTERVAL {
+                return;
+            }
+            let rows_per_sec = (num_processed - last_num_processed) as f64 / elapsed.as_secs_f64();
+            state.rows_per_sec = Some(match state.rows_per_sec {
+                Some(prev) => {
+                    THROUGHPUT_SMOOTHING_FACTOR * rows_per_sec
+                        + (1.0 - THROUGHPUT_SMOOTHING_FACTOR) * prev
+                }
+                None => rows_per_sec,
+            });
+        }
+        state.last_sample = Some((now, num_processed));
+    }
+
+    pub fn rows_per_sec(&self) -> Option<f64> {
+        self.0.lock().unwrap().rows_per_sec
+    }
+}
+
+impl Clone for Throughput {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(self.0.lock().unwrap().clone()))
+    }
+}
+
+impl Serialize for Throughput {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.rows_per_sec().serialize(serializer)
+    }
+}
+
+impl std::fmt::Debug for Throughput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&self.rows_per_sec(), f)
+    }
+}
+
+/// Time when the last source row was done, no matter whether succeeded, skipped or failed.
+#[derive(Default)]
+pub struct LastProgressTime(Mutex<Option<SystemTime>>);
+
+impl LastProgressTime {
+    pub fn mark(&self) {
+        *self.0.lock().unwrap() = Some(SystemTime::now());
+    }
+
+    pub fn get(&self) -> Option<SystemTime> {
+        *self.0.lock().unwrap()
+    }
+
+    /// Keep the later one of the two times.
+    pub fn merge(&self, other: &Self) {
+        let other = other.get();
+        let mut time = self.0.lock().unwrap();
+        if other > *time {
+            *time = other;
+        }
+    }
+}
+
+impl Clone for LastProgressTime {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(self.get()))
+    }
+}
+
+impl std::fmt::Debug for LastProgressTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&self.get(), f)
+    }
+}
+
+/// Progress of processing source rows discovered by listing the source.
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct UpdateProgress {
+    /// Percentage of listed source rows already processed.
+    pub percent: f64,
+    /// Estimated time to process the remaining listed rows, based on recent throughput.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eta_secs: Option<f64>,
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h {}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{secs}s")
+    }
+}
+
+impl std::fmt::Display for UpdateProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.1}% done", self.percent)?;
+        if let Some(eta_secs) = self.eta_secs {
+            write!(
+                f,
+                ", ETA {}",
+                format_duration(Duration::from_secs_f64(eta_secs))
+            )?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Serialize, Default, Clone)]
 pub struct UpdateStats {
+    /// Number of source rows discovered by listing the source.
+    pub num_listed_rows: Counter,
+
     pub num_skipped: Counter,
     pub num_insertions: Counter,
     pub num_deletions: Counter,
     pub num_repreocesses: Counter,
     pub num_errors: Counter,
+
+    /// Numbers of source rows being processed and waiting to be processed at the moment.
+    /// Unlike other counters, deltas of them are still their current values.
+    pub num_active_rows: Counter,
+    pub num_queued_rows: Counter,
+
+    /// Source rows processed per second recently. Like gauges, deltas of it are still its current value.
+    pub throughput: Throughput,
+    /// Like gauges, deltas of it are still its current value.
+    #[serde(skip)]
+    pub last_progress_time: LastProgressTime,
+
+    #[serde(skip_serializing_if = "OpStatsMap::is_zero")]
+    pub ops: OpStatsMap,
 }
 
 impl UpdateStats {
     pub fn delta(&self, base: &Self) -> Self {
         UpdateStats {
+            num_listed_rows: self.num_listed_rows.delta(&base.num_listed_rows),
             num_skipped: self.num_skipped.delta(&base.num_skipped),
             num_insertions: self.num_insertions.delta(&base.num_insertions),
             num_deletions: self.num_deletions.delta(&base.num_deletions),
             num_repreocesses: self.num_repreocesses.delta(&base.num_repreocesses),
             num_errors: self.num_errors.delta(&base.num_errors),
+            num_active_rows: self.num_active_rows.clone(),
+            num_queued_rows: self.num_queued_rows.clone(),
+            throughput: self.throughput.clone(),
+            last_progress_time: self.last_progress_time.clone(),
+            ops: self.ops.delta(&base.ops),
         }
     }
 
+    /// Accumulate stats of another update into this one.
+    /// Gauges and throughput are left as is, as they only make sense while the update is running.
+    pub fn merge(&self, other: &Self) {
+        self.num_listed_rows.inc(other.num_listed_rows.get());
+        self.num_skipped.inc(other.num_skipped.get());
+        self.num_insertions.inc(other.num_insertions.get());
+        self.num_deletions.inc(other.num_deletions.get());
+        self.num_repreocesses.inc(other.num_repreocesses.get());
+        self.num_errors.inc(other.num_errors.get());
+        self.last_progress_time.merge(&other.last_progress_time);
+        self.ops.merge(&other.ops);
+    }
+
+    /// Number of source rows done, no matter whether succeeded, skipped or failed.
+    pub fn num_done_rows(&self) -> i64 {
+        self.num_skipped.get()
+            + self.num_insertions.get()
+            + self.num_repreocesses.get()
+            + self.num_errors.get()
+    }
+
+    /// Progress of listed source rows, if any of them is not done yet.
+    pub fn progress(&self) -> Option<UpdateProgress> {
+        let num_listed_rows = self.num_listed_rows.get();
+        // Rows from change streams are also counted as done, so it may exceed the number of listed rows.
+        let num_remaining_rows = num_listed_rows - self.num_done_rows();
+        if num_listed_rows == 0 || num_remaining_rows <= 0 {
+            return None;
+        }
+        Some(UpdateProgress {
+            percent: (num_listed_rows - num_remaining_rows) as f64 * 100.0 / num_listed_rows as f64,
+            eta_secs: self
+                .throughput
+                .rows_per_sec()
+                .filter(|rows_per_sec| *rows_per_sec > 0.0)
+                .map(|rows_per_sec| num_remaining_rows as f64 / rows_per_sec),
+        })
+    }
+
     pub fn is_zero(&self) -> bool {
-        self.num_skipped.get() == 0
+        self.num_listed_rows.get() == 0
+            && self.num_skipped.get() == 0
             && self.num_insertions.get() == 0
             && self.num_deletions.get() == 0
             && self.num_repreocesses.get() == 0
             && self.num_errors.get() == 0
+            && self.num_active_rows.get() == 0
+            && self.num_queued_rows.get() == 0
+            && self.ops.is_zero()
     }
 }
 
 impl std::fmt::Display for UpdateStats {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut messages = Vec::new();
+        let num_listed_rows = self.num_listed_rows.get();
+        if num_listed_rows > 0 {
+            messages.push(format!("{num_listed_rows} source rows LISTED"));
+        }
+
         let num_errors = self.num_errors.get();
         if num_errors > 0 {
             messages.push(format!("{num_errors} source rows FAILED"));
@@ -102,6 +636,20 @@ impl std::fmt::Display for UpdateStats {
             ));
         }
 
+        let num_active_rows = self.num_active_rows.get();
+        let num_queued_rows = self.num_queued_rows.get();
+        if num_active_rows > 0 || num_queued_rows > 0 {
+            messages.push(format!(
+                "{num_active_rows} source rows IN PROGRESS, {num_queued_rows} QUEUED"
+            ));
+        }
+
+        for (op_name, op_stats) in self.ops.snapshot() {
+            if !op_stats.is_zero() {
+                messages.push(format!("op `{op_name}`: {op_stats}"));
+            }
+        }
+
         if !messages.is_empty() {
             write!(f, "{}", messages.join("; "))?;
         } else {
@@ -116,11 +664,28 @@ impl std::fmt::Display for UpdateStats {
 pub struct SourceUpdateInfo {
     pub source_name: String,
     pub stats: UpdateStats,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<UpdateProgress>,
+}
+
+impl SourceUpdateInfo {
+    pub fn new(source_name: String, stats: UpdateStats) -> Self {
+        let progress = stats.progress();
+        Self {
+            source_name,
+            stats,
+            progress,
+        }
+    }
 }
 
 impl std::fmt::Display for SourceUpdateInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}: {}", self.source_name, self.stats)
+        write!(f, "{}: {}", self.source_name, self.stats)?;
+        if let Some(progress) = &self.progress {
+            write!(f, " ({progress})")?;
+        }
+        Ok(())
     }
 }
 
@@ -137,3 +702,158 @@ impl std::fmt::Display for IndexUpdateInfo {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throughput_moving_average() {
+        let throughput = Throughput::default();
+        let start = Instant::now();
+        throughput.sample_at(start, 0);
+        assert_eq!(throughput.rows_per_sec(), None);
+
+        throughput.sample_at(start + Duration::from_secs(2), 20);
+        assert_eq!(throughput.rows_per_sec(), Some(10.0));
+
+        // Too close to the previous sample.
+        throughput.sample_at(start + Duration::from_millis(2500), 100);
+        assert_eq!(throughput.rows_per_sec(), Some(10.0));
+
+        // 20 rows/s recently, weighted against the previous average.
+        throughput.sample_at(start + Duration::from_secs(4), 60);
+        let rows_per_sec = throughput.rows_per_sec().unwrap();
+        assert!((rows_per_sec - 13.0).abs() < 1e-9, "got {rows_per_sec}");
+    }
+
+    fn update_stats(num_listed_rows: i64, num_done_rows: i64) -> UpdateStats {
+        let update_stats = UpdateStats::default();
+        update_stats.num_listed_rows.inc(num_listed_rows);
+        update_stats.num_insertions.inc(num_done_rows);
+        update_stats
+    }
+
+    #[test]
+    fn progress_with_eta() {
+        let update_stats = update_stats(200, 50);
+        update_stats.num_errors.inc(10);
+        update_stats.num_skipped.inc(20);
+        update_stats.num_repreocesses.inc(20);
+        let start = Instant::now();
+        update_stats.throughput.sample_at(start, 0);
+        update_stats
+            .throughput
+            .sample_at(start + Duration::from_secs(10), 100);
+
+        let progress = update_stats.progress().unwrap();
+        assert_eq!(progress.percent, 50.0);
+        assert_eq!(progress.eta_secs, Some(10.0));
+        assert_eq!(progress.to_string(), "50.0% done, ETA 10s");
+    }
+
+    #[test]
+    fn progress_without_throughput() {
+        let progress = update_stats(3, 1).progress().unwrap();
+        assert!((progress.percent - 100.0 / 3.0).abs() < 1e-9);
+        assert_eq!(progress.eta_secs, None);
+        assert_eq!(progress.to_string(), "33.3% done");
+    }
+
+    #[test]
+    fn no_progress_if_nothing_remaining() {
+        assert!(update_stats(0, 0).progress().is_none());
+        assert!(update_stats(0, 5).progress().is_none());
+        assert!(update_stats(10, 10).progress().is_none());
+        // Rows from change streams are counted as done too.
+        assert!(update_stats(10, 12).progress().is_none());
+    }
+
+    #[test]
+    fn no_eta_if_stalled() {
+        let update_stats = update_stats(10, 5);
+        let start = Instant::now();
+        update_stats.throughput.sample_at(start, 5);
+        update_stats
+            .throughput
+            .sample_at(start + Duration::from_secs(5), 5);
+        assert_eq!(update_stats.throughput.rows_per_sec(), Some(0.0));
+        assert_eq!(update_stats.progress().unwrap().eta_secs, None);
+    }
+
+    #[test]
+    fn format_durations() {
+        assert_eq!(format_duration(Duration::from_secs(42)), "42s");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2m 5s");
+        assert_eq!(format_duration(Duration::from_secs(7530)), "2h 5m");
+    }
+
+    #[test]
+    fn histogram_buckets() {
+        let histogram = Histogram::<BatchSizeBuckets>::default();
+        for value in [1.0, 1.5, 2.0, 7.0, 10000.0] {
+            histogram.observe(value);
+        }
+        let data = histogram.data();
+        assert_eq!(data.bucket_counts.len(), BatchSizeBuckets::BOUNDS.len() + 1);
+        // Upper bounds are inclusive.
+        assert_eq!(&data.bucket_counts[..4], &[1, 2, 0, 1]);
+        assert_eq!(data.bucket_counts.last(), Some(&1));
+        assert_eq!(data.count, 5);
+        assert_eq!(data.sum, 10011.5);
+    }
+
+    #[test]
+    fn histogram_delta_and_merge() {
+        let base = Histogram::<LatencyBuckets>::default();
+        base.observe(0.001);
+        let histogram = base.clone();
+        histogram.observe(0.001);
+        histogram.observe(0.2);
+
+        let delta = histogram.delta(&base).data();
+        assert_eq!(delta.count, 2);
+        assert_eq!(delta.bucket_counts[0], 1);
+        assert_eq!(delta.bucket_counts[5], 1);
+        assert!((delta.sum - 0.201).abs() < 1e-9);
+
+        histogram.merge(&base);
+        let merged = histogram.data();
+        assert_eq!(merged.count, 4);
+        assert_eq!(merged.bucket_counts[0], 3);
+        assert!((merged.sum - 0.203).abs() < 1e-9);
+    }
+
+    #[test]
+    fn update_stats_merge() {
+        let cumulative = update_stats(10, 4);
+        cumulative.ops.get("op1").num_cache_hits.inc(2);
+        let other = update_stats(5, 5);
+        other.num_active_rows.inc(3);
+        other.ops.get("op1").num_cache_hits.inc(1);
+        other.ops.get("op2").latency.observe(1.0);
+        other.last_progress_time.mark();
+
+        cumulative.merge(&other);
+        assert_eq!(cumulative.num_listed_rows.get(), 15);
+        assert_eq!(cumulative.num_insertions.get(), 9);
+        // Gauges only make sense for running updates.
+        assert_eq!(cumulative.num_active_rows.get(), 0);
+        let ops = cumulative.ops.snapshot();
+        assert_eq!(ops["op1"].num_cache_hits.get(), 3);
+        assert_eq!(ops["op2"].latency.data().count, 1);
+        assert_eq!(
+            cumulative.last_progress_time.get(),
+            other.last_progress_time.get()
+        );
+
+        // The later progress time is kept.
+        let earlier = UpdateStats::default();
+        *earlier.last_progress_time.0.lock().unwrap() = Some(SystemTime::UNIX_EPOCH);
+        cumulative.merge(&earlier);
+        assert_eq!(
+            cumulative.last_progress_time.get(),
+            other.last_progress_time.get()
+        );
+    }
+}
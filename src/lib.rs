@@ -10,4 +10,5 @@ mod server;
 mod service;
 mod settings;
 mod setup;
+mod state_store;
 mod utils;
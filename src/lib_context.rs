@@ -1,43 +1,126 @@
 use crate::prelude::*;
 
-use crate::execution::source_indexer::SourceIndexingContext;
+use crate::execution::{source_indexer::SourceIndexingContext, stats, LiveUpdaterControl};
 use crate::service::error::ApiError;
 use crate::settings;
 use crate::setup;
+use crate::state_store::{self, SharedStateStore};
+use crate::utils;
 use crate::{builder::AnalyzedFlow, execution::query::SimpleSemanticsQueryHandler};
 use axum::http::StatusCode;
-use sqlx::PgPool;
 use std::collections::BTreeMap;
 use tokio::runtime::Runtime;
 
+/// A live updater of the flow, registered for control and monitoring.
+pub struct LiveUpdaterHandle {
+    pub control: Arc<LiveUpdaterControl>,
+    /// Stats of each source, in the same order as import ops of the flow.
+    pub sources_update_stats: Vec<Arc<stats::UpdateStats>>,
+}
+
+#[derive(Default)]
+struct UpdateStatsRegistry {
+    live_updaters: Vec<(Weak<LiveUpdaterControl>, Vec<Arc<stats::UpdateStats>>)>,
+    /// Stats of each source accumulated from updates already finished.
+    finished: Vec<stats::UpdateStats>,
+}
+
+impl UpdateStatsRegistry {
+    /// Move stats of live updaters already dropped into the finished ones.
+    fn retire_dropped_live_updaters(&mut self) {
+        let (alive, dropped) = std::mem::take(&mut self.live_updaters)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(control, _)| control.strong_count() > 0);
+        self.live_updaters = alive;
+        for (_, sources_update_stats) in dropped {
+            for (finished, update_stats) in self.finished.iter().zip(sources_update_stats.iter()) {
+                finished.merge(update_stats);
+            }
+        }
+    }
+}
+
 pub struct FlowContext {
     pub flow: Arc<AnalyzedFlow>,
     pub source_indexing_contexts: Vec<tokio::sync::OnceCell<Arc<SourceIndexingContext>>>,
     pub query_handlers: Mutex<BTreeMap<String, Arc<SimpleSemanticsQueryHandler>>>,
+    update_stats: Mutex<UpdateStatsRegistry>,
 }
 
 impl FlowContext {
     pub fn new(flow: Arc<AnalyzedFlow>) -> Self {
+        let num_sources = flow.flow_instance.import_ops.len();
         let mut source_indexing_contexts = Vec::new();
-        source_indexing_contexts.resize_with(flow.flow_instance.import_ops.len(), || {
-            tokio::sync::OnceCell::new()
-        });
+        source_indexing_contexts.resize_with(num_sources, || tokio::sync::OnceCell::new());
+        let mut update_stats = UpdateStatsRegistry::default();
+        update_stats
+            .finished
+            .resize_with(num_sources, stats::UpdateStats::default);
         Self {
             flow,
             source_indexing_contexts,
             query_handlers: Mutex::new(BTreeMap::new()),
+            update_stats: Mutex::new(update_stats),
+        }
+    }
+
+    pub fn register_live_updater(
+        &self,
+        control: &Arc<LiveUpdaterControl>,
+        sources_update_stats: &[Arc<stats::UpdateStats>],
+    ) {
+        let mut update_stats = self.update_stats.lock().unwrap();
+        update_stats.retire_dropped_live_updaters();
+        update_stats
+            .live_updaters
+            .push((Arc::downgrade(control), sources_update_stats.to_vec()));
+    }
+
+    /// Accumulate stats of a finished update not going through live updaters, e.g. reprocessing.
+    pub fn record_update_stats(&self, source_idx: usize, update_stats: &stats::UpdateStats) {
+        self.update_stats.lock().unwrap().finished[source_idx].merge(update_stats);
+    }
+
+    /// Live updaters of the flow still alive.
+    pub fn live_updaters(&self) -> Vec<LiveUpdaterHandle> {
+        let mut update_stats = self.update_stats.lock().unwrap();
+        update_stats.retire_dropped_live_updaters();
+        update_stats
+            .live_updaters
+            .iter()
+            .filter_map(|(control, sources_update_stats)| {
+                Some(LiveUpdaterHandle {
+                    control: control.upgrade()?,
+                    sources_update_stats: sources_update_stats.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Stats of each source accumulated over all updates of the flow in this process, including
+    /// finished ones, in the same order as import ops of the flow.
+    /// Only counters are accumulated; gauges are available from stats of live updaters.
+    pub fn cumulative_update_stats(&self) -> Vec<stats::UpdateStats> {
+        let mut update_stats = self.update_stats.lock().unwrap();
+        update_stats.retire_dropped_live_updaters();
+        let result = update_stats.finished.clone();
+        for (_, sources_update_stats) in update_stats.live_updaters.iter() {
+            for (cumulative, update_stats) in result.iter().zip(sources_update_stats.iter()) {
+                cumulative.merge(update_stats);
+            }
         }
+        result
     }
 
     pub async fn get_source_indexing_context(
         &self,
         source_idx: usize,
-        pool: &PgPool,
+        state_store: &SharedStateStore,
     ) -> Result<&Arc<SourceIndexingContext>> {
         self.source_indexing_contexts[source_idx]
             .get_or_try_init(|| async move {
                 Ok(Arc::new(
-                    SourceIndexingContext::load(self.flow.clone(), source_idx, pool).await?,
+                    SourceIndexingContext::load(self.flow.clone(), source_idx, state_store).await?,
                 ))
             })
             .await
@@ -62,7 +145,7 @@ static TOKIO_RUNTIME: LazyLock<Runtime> = LazyLock::new(|| Runtime::new().unwrap
 static AUTH_REGISTRY: LazyLock<Arc<AuthRegistry>> = LazyLock::new(|| Arc::new(AuthRegistry::new()));
 
 pub struct LibContext {
-    pub pool: PgPool,
+    pub state_store: SharedStateStore,
     pub flows: Mutex<BTreeMap<String, Arc<FlowContext>>>,
     pub all_setup_states: RwLock<setup::AllSetupState<setup::ExistingMode>>,
 }
@@ -94,18 +177,21 @@ pub fn get_auth_registry() -> &'static Arc<AuthRegistry> {
 static LIB_INIT: OnceLock<()> = OnceLock::new();
 pub fn create_lib_context(settings: settings::Settings) -> Result<LibContext> {
     LIB_INIT.get_or_init(|| {
-        console_subscriber::init();
-        env_logger::init();
+        // Logs from the `log` crate are also emitted through the tracing subscriber.
+        // Only settings at the first initialization take effect, as the subscriber is global.
+        if let Err(e) = utils::telemetry::init_tracing(settings.tracing.as_ref()) {
+            error!("Failed to initialize tracing: {e:?}");
+        }
         pyo3_async_runtimes::tokio::init_with_runtime(get_runtime()).unwrap();
     });
 
-    let (pool, all_setup_states) = get_runtime().block_on(async {
-        let pool = PgPool::connect(&settings.database_url).await?;
-        let existing_ss = setup::get_existing_setup_state(&pool).await?;
-        anyhow::Ok((pool, existing_ss))
+    let (state_store, all_setup_states) = get_runtime().block_on(async {
+        let state_store = state_store::connect(&settings.database_url).await?;
+        let existing_ss = setup::get_existing_setup_state(&state_store).await?;
+        anyhow::Ok((state_store, existing_ss))
     })?;
     Ok(LibContext {
-        pool,
+        state_store,
         all_setup_states: RwLock::new(all_setup_states),
         flows: Mutex::new(BTreeMap::new()),
     })
@@ -128,6 +214,7 @@ pub(crate) fn get_lib_context() -> Result<Arc<LibContext>> {
 }
 
 pub(crate) fn clear_lib_context() {
+    utils::telemetry::flush_tracing();
     let mut lib_context_locked = LIB_CONTEXT.write().unwrap();
     *lib_context_locked = None;
 }
@@ -0,0 +1,283 @@
+use crate::api_bail;
+
+use super::{LlmApiError, LlmGenerationClient};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+pub struct Client {
+    messages_url: String,
+    api_key: String,
+    model: String,
+    reqwest_client: reqwest::Client,
+}
+
+#[derive(Debug, Serialize)]
+struct ImageSource<'a> {
+    #[serde(rename = "type")]
+    source_type: &'static str,
+    media_type: &'a str,
+    data: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentBlock<'a> {
+    Text { text: &'a str },
+    Image { source: ImageSource<'a> },
+}
+
+#[derive(Debug, Serialize)]
+struct Message<'a> {
+    role: &'static str,
+    content: Vec<ContentBlock<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Tool<'a> {
+    name: &'a str,
+    input_schema: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+struct ToolChoice<'a> {
+    #[serde(rename = "type")]
+    choice_type: &'static str,
+    name: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct MessagesRequest<'a> {
+    model: &'a str,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<&'a str>,
+    messages: Vec<Message<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<Tool<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<ToolChoice<'a>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResponseContentBlock {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        input: serde_json::Value,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct Usage {
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessagesResponse {
+    content: Vec<ResponseContentBlock>,
+    usage: Option<Usage>,
+}
+
+const ANTHROPIC_DEFAULT_ADDRESS: &str = "https://api.anthropic.com";
+const ANTHROPIC_API_VERSION: &str = "2023-06-01";
+const ANTHROPIC_MAX_TOKENS: u32 = 8192;
+
+impl Client {
+    pub async fn new(spec: super::LlmSpec) -> Result<Self> {
+        let address = match &spec.address {
+            Some(addr) => addr.trim_end_matches('/'),
+            None => ANTHROPIC_DEFAULT_ADDRESS,
+        };
+        let api_key = match std::env::var("ANTHROPIC_API_KEY") {
+            Ok(api_key) => api_key,
+            Err(_) => api_bail!("ANTHROPIC_API_KEY environment variable must be set"),
+        };
+        Ok(Self {
+            messages_url: format!("{}/v1/messages", address),
+            api_key,
+            model: spec.model,
+            reqwest_client: reqwest::Client::new(),
+        })
+    }
+}
+
+#[async_trait]
+impl LlmGenerationClient for Client {
+    async fn generate<'req>(
+        &self,
+        request: super::LlmGenerateRequest<'req>,
+    ) -> Result<super::LlmGenerateResponse> {
+        let mut content = Vec::new();
+        if let Some(image) = &request.image {
+            content.push(ContentBlock::Image {
+                source: ImageSource {
+                    source_type: "base64",
+                    media_type: image.mime_type.as_ref(),
+                    data: image.to_base64(),
+                },
+            });
+        }
+        if !request.user_prompt.is_empty() {
+            content.push(ContentBlock::Text {
+                text: request.user_prompt.as_ref(),
+            });
+        }
+
+        // Structured output is done by forcing the model to call a tool with the schema as input.
+        let (tools, tool_choice) = match &request.output_format {
+            Some(super::OutputFormat::JsonSchema { name, schema }) => (
+                vec![Tool {
+                    name: name.as_ref(),
+                    input_schema: serde_json::to_value(schema)?,
+                }],
+                Some(ToolChoice {
+                    choice_type: "tool",
+                    name: name.as_ref(),
+                }),
+            ),
+            None => (vec![], None),
+        };
+
+        let req = MessagesRequest {
+            model: &self.model,
+            max_tokens: ANTHROPIC_MAX_TOKENS,
+            system: request.system_prompt.as_ref().map(|s| s.as_ref()),
+            messages: vec![Message {
+                role: "user",
+                content,
+            }],
+            tools,
+            tool_choice,
+        };
+        let res = self
+            .reqwest_client
+            .post(self.messages_url.as_str())
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_API_VERSION)
+            .json(&req)
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            return Err(LlmApiError::from_response("Anthropic", res).await.into());
+        }
+        let body = res.text().await?;
+        let res: MessagesResponse = serde_json::from_str(&body)?;
+        let usage = res.usage.map(|usage| super::LlmTokenUsage {
+            prompt_tokens: usage.input_tokens,
+            completion_tokens: usage.output_tokens,
+        });
+        let mut texts = Vec::new();
+        for block in res.content {
+            match block {
+                ResponseContentBlock::ToolUse { input } => {
+                    return Ok(super::LlmGenerateResponse {
+                        text: input.to_string(),
+                        usage,
+                    });
+                }
+                ResponseContentBlock::Text { text } => texts.push(text),
+                ResponseContentBlock::Other => {}
+            }
+        }
+        if texts.is_empty() {
+            return Err(anyhow!("No response from Anthropic"));
+        }
+        Ok(super::LlmGenerateResponse {
+            text: texts.join(""),
+            usage,
+        })
+    }
+
+    fn json_schema_options(&self) -> super::ToJsonSchemaOptions {
+        super::ToJsonSchemaOptions {
+            fields_always_required: false,
+            supports_format: false,
+            extract_descriptions: false,
+            top_level_must_be_object: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_utils::{assert_rate_limited_error, json_mode_request, StubServer};
+    use super::super::LlmGenerationClient;
+    use super::Client;
+    use serde_json::json;
+
+    fn new_client(address: &str) -> Client {
+        Client {
+            messages_url: format!("{address}/v1/messages"),
+            api_key: "test-key".to_string(),
+            model: "test-model".to_string(),
+            reqwest_client: reqwest::Client::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn json_mode_request_and_response() {
+        let server = StubServer::start(
+            200,
+            &[],
+            json!({
+                "content": [
+                    { "type": "text", "text": "Here it is." },
+                    { "type": "tool_use", "id": "toolu_1", "name": "Person", "input": { "name": "Alice" } },
+                ],
+                "usage": { "input_tokens": 10, "output_tokens": 5 },
+            }),
+        )
+        .await;
+        let response = new_client(&server.address)
+            .generate(json_mode_request())
+            .await
+            .unwrap();
+        assert_eq!(response.text, r#"{"name":"Alice"}"#);
+        let usage = response.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (10, 5));
+
+        let request = server.single_request();
+        assert_eq!(request.path, "/v1/messages");
+        assert_eq!(request.headers["x-api-key"], "test-key");
+        assert_eq!(request.headers["anthropic-version"], "2023-06-01");
+        assert_eq!(request.body["model"], "test-model");
+        assert_eq!(request.body["system"], "Extract the name.");
+        assert_eq!(
+            request.body["messages"],
+            json!([{
+                "role": "user",
+                "content": [{ "type": "text", "text": "My name is Alice." }],
+            }])
+        );
+        assert_eq!(request.body["tools"][0]["name"], "Person");
+        assert_eq!(
+            request.body["tools"][0]["input_schema"]["required"],
+            json!(["name"])
+        );
+        assert_eq!(
+            request.body["tool_choice"],
+            json!({ "type": "tool", "name": "Person" })
+        );
+    }
+
+    #[tokio::test]
+    async fn rate_limited_error() {
+        let server = StubServer::start(
+            429,
+            &[("retry-after", "7")],
+            json!({ "type": "error", "error": { "type": "rate_limit_error", "message": "Rate limited" } }),
+        )
+        .await;
+        let err = new_client(&server.address)
+            .generate(json_mode_request())
+            .await
+            .unwrap_err();
+        assert_rate_limited_error(&err);
+    }
+}
@@ -0,0 +1,290 @@
+use crate::api_bail;
+
+use super::{LlmApiError, LlmGenerationClient};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+pub struct Client {
+    generate_content_url: String,
+    api_key: String,
+    reqwest_client: reqwest::Client,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InlineData<'a> {
+    mime_type: &'a str,
+    data: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum Part<'a> {
+    Text(&'a str),
+    InlineData(InlineData<'a>),
+}
+
+#[derive(Debug, Serialize)]
+struct Content<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<&'static str>,
+    parts: Vec<Part<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerationConfig {
+    response_mime_type: &'static str,
+    response_schema: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateContentRequest<'a> {
+    contents: Vec<Content<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<Content<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation_config: Option<GenerationConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponsePart {
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseContent {
+    #[serde(default)]
+    parts: Vec<ResponsePart>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Candidate {
+    content: Option<ResponseContent>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageMetadata {
+    #[serde(default)]
+    prompt_token_count: u64,
+    #[serde(default)]
+    candidates_token_count: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateContentResponse {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+    usage_metadata: Option<UsageMetadata>,
+}
+
+const GEMINI_DEFAULT_ADDRESS: &str = "https://generativelanguage.googleapis.com";
+
+/// Gemini accepts a subset of OpenAPI schema, which doesn't allow some JSON schema keywords.
+fn to_gemini_schema(mut schema: serde_json::Value) -> serde_json::Value {
+    fn strip_unsupported(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(obj) => {
+                obj.remove("additionalProperties");
+                obj.remove("$schema");
+                obj.values_mut().for_each(strip_unsupported);
+            }
+            serde_json::Value::Array(items) => items.iter_mut().for_each(strip_unsupported),
+            _ => {}
+        }
+    }
+    strip_unsupported(&mut schema);
+    schema
+}
+
+impl Client {
+    pub async fn new(spec: super::LlmSpec) -> Result<Self> {
+        let address = match &spec.address {
+            Some(addr) => addr.trim_end_matches('/'),
+            None => GEMINI_DEFAULT_ADDRESS,
+        };
+        let api_key = match std::env::var("GEMINI_API_KEY") {
+            Ok(api_key) => api_key,
+            Err(_) => api_bail!("GEMINI_API_KEY environment variable must be set"),
+        };
+        Ok(Self {
+            generate_content_url: format!(
+                "{}/v1beta/models/{}:generateContent",
+                address, spec.model
+            ),
+            api_key,
+            reqwest_client: reqwest::Client::new(),
+        })
+    }
+}
+
+#[async_trait]
+impl LlmGenerationClient for Client {
+    async fn generate<'req>(
+        &self,
+        request: super::LlmGenerateRequest<'req>,
+    ) -> Result<super::LlmGenerateResponse> {
+        let mut parts = Vec::new();
+        if let Some(image) = &request.image {
+            parts.push(Part::InlineData(InlineData {
+                mime_type: image.mime_type.as_ref(),
+                data: image.to_base64(),
+            }));
+        }
+        if !request.user_prompt.is_empty() {
+            parts.push(Part::Text(request.user_prompt.as_ref()));
+        }
+
+        let req = GenerateContentRequest {
+            contents: vec![Content {
+                role: Some("user"),
+                parts,
+            }],
+            system_instruction: request.system_prompt.as_ref().map(|system| Content {
+                role: None,
+                parts: vec![Part::Text(system.as_ref())],
+            }),
+            generation_config: match &request.output_format {
+                Some(super::OutputFormat::JsonSchema { schema, .. }) => Some(GenerationConfig {
+                    response_mime_type: "application/json",
+                    response_schema: to_gemini_schema(serde_json::to_value(schema)?),
+                }),
+                None => None,
+            },
+        };
+        let res = self
+            .reqwest_client
+            .post(self.generate_content_url.as_str())
+            .header("x-goog-api-key", &self.api_key)
+            .json(&req)
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            return Err(LlmApiError::from_response("Gemini", res).await.into());
+        }
+        let body = res.text().await?;
+        let res: GenerateContentResponse = serde_json::from_str(&body)?;
+        let usage = res.usage_metadata.map(|usage| super::LlmTokenUsage {
+            prompt_tokens: usage.prompt_token_count,
+            completion_tokens: usage.candidates_token_count,
+        });
+        let text = res
+            .candidates
+            .into_iter()
+            .next()
+            .and_then(|candidate| candidate.content)
+            .map(|content| {
+                content
+                    .parts
+                    .into_iter()
+                    .filter_map(|part| part.text)
+                    .collect::<String>()
+            })
+            .filter(|text| !text.is_empty())
+            .ok_or_else(|| anyhow!("No response from Gemini"))?;
+        Ok(super::LlmGenerateResponse { text, usage })
+    }
+
+    fn json_schema_options(&self) -> super::ToJsonSchemaOptions {
+        super::ToJsonSchemaOptions {
+            fields_always_required: false,
+            supports_format: false,
+            extract_descriptions: false,
+            top_level_must_be_object: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_utils::{assert_rate_limited_error, json_mode_request, StubServer};
+    use super::super::LlmGenerationClient;
+    use super::Client;
+    use serde_json::json;
+
+    fn new_client(address: &str) -> Client {
+        Client {
+            generate_content_url: format!("{address}/v1beta/models/test-model:generateContent"),
+            api_key: "test-key".to_string(),
+            reqwest_client: reqwest::Client::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn json_mode_request_and_response() {
+        let server = StubServer::start(
+            200,
+            &[],
+            json!({
+                "candidates": [{
+                    "content": { "role": "model", "parts": [{ "text": "{\"name\":" }, { "text": "\"Alice\"}" }] },
+                }],
+                "usageMetadata": { "promptTokenCount": 10, "candidatesTokenCount": 5 },
+            }),
+        )
+        .await;
+        let response = new_client(&server.address)
+            .generate(json_mode_request())
+            .await
+            .unwrap();
+        assert_eq!(response.text, r#"{"name":"Alice"}"#);
+        let usage = response.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (10, 5));
+
+        let request = server.single_request();
+        assert_eq!(request.path, "/v1beta/models/test-model:generateContent");
+        assert_eq!(request.headers["x-goog-api-key"], "test-key");
+        assert_eq!(
+            request.body["contents"],
+            json!([{ "role": "user", "parts": [{ "text": "My name is Alice." }] }])
+        );
+        assert_eq!(
+            request.body["systemInstruction"],
+            json!({ "parts": [{ "text": "Extract the name." }] })
+        );
+        let generation_config = &request.body["generationConfig"];
+        assert_eq!(generation_config["responseMimeType"], "application/json");
+        assert_eq!(
+            generation_config["responseSchema"]["required"],
+            json!(["name"])
+        );
+    }
+
+    #[test]
+    fn gemini_schema_strips_unsupported_keywords() {
+        let schema = super::to_gemini_schema(json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "type": "object",
+            "properties": {
+                "tags": { "type": "array", "items": { "type": "object", "additionalProperties": false } },
+            },
+            "additionalProperties": false,
+        }));
+        assert_eq!(
+            schema,
+            json!({
+                "type": "object",
+                "properties": { "tags": { "type": "array", "items": { "type": "object" } } },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn rate_limited_error() {
+        let server = StubServer::start(
+            429,
+            &[("retry-after", "7")],
+            json!({ "error": { "code": 429, "status": "RESOURCE_EXHAUSTED" } }),
+        )
+        .await;
+        let err = new_client(&server.address)
+            .generate(json_mode_request())
+            .await
+            .unwrap_err();
+        assert_rate_limited_error(&err);
+    }
+}
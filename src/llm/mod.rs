@@ -1,16 +1,21 @@
 use std::borrow::Cow;
+use std::time::Duration;
 
 use anyhow::Result;
 use async_trait::async_trait;
+use base64::prelude::*;
 use schemars::schema::SchemaObject;
 use serde::{Deserialize, Serialize};
 
 use crate::base::json_schema::ToJsonSchemaOptions;
+use crate::utils::retriable;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum LlmApiType {
     Ollama,
     OpenAi,
+    Anthropic,
+    Gemini,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -18,9 +23,25 @@ pub struct LlmSpec {
     api_type: LlmApiType,
     address: Option<String>,
     model: String,
+
+    /// Limits below are shared by all clients with the same API type, address and model.
+    max_requests_per_minute: Option<u64>,
+    max_tokens_per_minute: Option<u64>,
+    max_inflight_requests: Option<usize>,
 }
 
-#[derive(Debug)]
+impl LlmSpec {
+    /// Remove the rate limits from a serialized `LlmSpec`. They don't affect generated outputs.
+    pub fn strip_rate_limits(spec: &mut serde_json::Value) {
+        if let Some(spec) = spec.as_object_mut() {
+            spec.remove("max_requests_per_minute");
+            spec.remove("max_tokens_per_minute");
+            spec.remove("max_inflight_requests");
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum OutputFormat<'a> {
     JsonSchema {
         name: Cow<'a, str>,
@@ -28,16 +49,121 @@ pub enum OutputFormat<'a> {
     },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+pub struct LlmImage<'a> {
+    pub data: Cow<'a, [u8]>,
+    pub mime_type: Cow<'a, str>,
+}
+
+impl LlmImage<'_> {
+    pub fn to_base64(&self) -> String {
+        BASE64_STANDARD.encode(self.data.as_ref())
+    }
+
+    pub fn to_data_url(&self) -> String {
+        format!("data:{};base64,{}", self.mime_type, self.to_base64())
+    }
+}
+
+/// Detect the MIME type of an image from its leading bytes, for commonly used formats.
+pub fn detect_image_mime_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(b"\xff\xd8\xff") {
+        Some("image/jpeg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct LlmGenerateRequest<'a> {
     pub system_prompt: Option<Cow<'a, str>>,
     pub user_prompt: Cow<'a, str>,
+    pub image: Option<LlmImage<'a>>,
     pub output_format: Option<OutputFormat<'a>>,
 }
 
+/// Rough number of tokens per image, as most APIs charge a near-fixed amount for each one.
+const ESTIMATED_TOKENS_PER_IMAGE: u64 = 1000;
+
+impl LlmGenerateRequest<'_> {
+    /// Rough estimation of input tokens, used for rate limiting before the request is sent.
+    pub fn estimated_input_tokens(&self) -> u64 {
+        let num_chars = self.system_prompt.as_ref().map_or(0, |s| s.len()) + self.user_prompt.len();
+        let num_image_tokens = if self.image.is_some() {
+            ESTIMATED_TOKENS_PER_IMAGE
+        } else {
+            0
+        };
+        (num_chars as u64).div_ceil(4) + num_image_tokens
+    }
+}
+
+/// Number of tokens consumed by a request, as reported by the LLM API.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LlmTokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
 #[derive(Debug)]
 pub struct LlmGenerateResponse {
     pub text: String,
+    /// `None` if the LLM API doesn't report token usage.
+    pub usage: Option<LlmTokenUsage>,
+}
+
+/// Error for a non-success HTTP response from an LLM API.
+#[derive(Debug)]
+pub struct LlmApiError {
+    pub status: reqwest::StatusCode,
+    pub retry_after: Option<Duration>,
+    pub message: String,
+}
+
+impl LlmApiError {
+    pub async fn from_response(api_name: &str, response: reqwest::Response) -> Self {
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<f64>().ok())
+            .filter(|secs| secs.is_finite() && *secs >= 0.0)
+            .map(Duration::from_secs_f64);
+        let body = response.text().await.unwrap_or_default();
+        Self {
+            status,
+            retry_after,
+            message: format!("{api_name} API error ({status}): {body}"),
+        }
+    }
+}
+
+impl std::fmt::Display for LlmApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for LlmApiError {}
+
+impl retriable::IsRetryable for LlmApiError {
+    fn is_retryable(&self) -> bool {
+        // 529 is used by Anthropic for overloaded servers.
+        self.status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            || self.status.is_server_error()
+            || self.status.as_u16() == 529
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
 }
 
 #[async_trait]
@@ -50,10 +176,16 @@ pub trait LlmGenerationClient: Send + Sync {
     fn json_schema_options(&self) -> ToJsonSchemaOptions;
 }
 
+mod anthropic;
+mod gemini;
 mod ollama;
 mod openai;
+mod rate_limit;
+#[cfg(test)]
+mod test_utils;
 
 pub async fn new_llm_generation_client(spec: LlmSpec) -> Result<Box<dyn LlmGenerationClient>> {
+    let limiter = rate_limit::get_endpoint_limiter(&spec);
     let client = match spec.api_type {
         LlmApiType::Ollama => {
             Box::new(ollama::Client::new(spec).await?) as Box<dyn LlmGenerationClient>
@@ -61,6 +193,12 @@ pub async fn new_llm_generation_client(spec: LlmSpec) -> Result<Box<dyn LlmGener
         LlmApiType::OpenAi => {
             Box::new(openai::Client::new(spec).await?) as Box<dyn LlmGenerationClient>
         }
+        LlmApiType::Anthropic => {
+            Box::new(anthropic::Client::new(spec).await?) as Box<dyn LlmGenerationClient>
+        }
+        LlmApiType::Gemini => {
+            Box::new(gemini::Client::new(spec).await?) as Box<dyn LlmGenerationClient>
+        }
     };
-    Ok(client)
+    Ok(Box::new(rate_limit::Client::new(client, limiter)))
 }
@@ -1,4 +1,4 @@
-use super::LlmGenerationClient;
+use super::{LlmApiError, LlmGenerationClient};
 use anyhow::Result;
 use async_trait::async_trait;
 use schemars::schema::SchemaObject;
@@ -22,12 +22,16 @@ struct OllamaRequest<'a> {
     pub prompt: &'a str,
     pub format: Option<OllamaFormat<'a>>,
     pub system: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub images: Option<Vec<String>>,
     pub stream: Option<bool>,
 }
 
 #[derive(Debug, Deserialize)]
 struct OllamaResponse {
     pub response: String,
+    pub prompt_eval_count: Option<u64>,
+    pub eval_count: Option<u64>,
 }
 
 const OLLAMA_DEFAULT_ADDRESS: &str = "http://localhost:11434";
@@ -61,6 +65,7 @@ impl LlmGenerationClient for Client {
                 },
             ),
             system: request.system_prompt.as_ref().map(|s| s.as_ref()),
+            images: request.image.as_ref().map(|image| vec![image.to_base64()]),
             stream: Some(false),
         };
         let res = self
@@ -69,10 +74,21 @@ impl LlmGenerationClient for Client {
             .json(&req)
             .send()
             .await?;
+        if !res.status().is_success() {
+            return Err(LlmApiError::from_response("Ollama", res).await.into());
+        }
         let body = res.text().await?;
         let json: OllamaResponse = serde_json::from_str(&body)?;
+        let usage = match (json.prompt_eval_count, json.eval_count) {
+            (None, None) => None,
+            (prompt_tokens, completion_tokens) => Some(super::LlmTokenUsage {
+                prompt_tokens: prompt_tokens.unwrap_or_default(),
+                completion_tokens: completion_tokens.unwrap_or_default(),
+            }),
+        };
         Ok(super::LlmGenerateResponse {
             text: json.response,
+            usage,
         })
     }
 
@@ -85,3 +101,60 @@ impl LlmGenerationClient for Client {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_utils::{
+        assert_rate_limited_error, json_mode_request, llm_spec, StubServer,
+    };
+    use super::super::{LlmApiType, LlmGenerationClient};
+    use super::Client;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn json_mode_request_and_response() {
+        let server = StubServer::start(
+            200,
+            &[],
+            json!({
+                "model": "test-model",
+                "response": "{\"name\":\"Alice\"}",
+                "done": true,
+                "prompt_eval_count": 10,
+                "eval_count": 5,
+            }),
+        )
+        .await;
+        let client = Client::new(llm_spec(LlmApiType::Ollama, &server.address))
+            .await
+            .unwrap();
+        let response = client.generate(json_mode_request()).await.unwrap();
+        assert_eq!(response.text, r#"{"name":"Alice"}"#);
+        let usage = response.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (10, 5));
+
+        let request = server.single_request();
+        assert_eq!(request.path, "/api/generate");
+        assert_eq!(request.body["model"], "test-model");
+        assert_eq!(request.body["prompt"], "My name is Alice.");
+        assert_eq!(request.body["system"], "Extract the name.");
+        assert_eq!(request.body["stream"], false);
+        assert_eq!(request.body["format"]["required"], json!(["name"]));
+        assert!(request.body.get("images").is_none());
+    }
+
+    #[tokio::test]
+    async fn rate_limited_error() {
+        let server = StubServer::start(
+            429,
+            &[("retry-after", "7")],
+            json!({ "error": "too many requests" }),
+        )
+        .await;
+        let client = Client::new(llm_spec(LlmApiType::Ollama, &server.address))
+            .await
+            .unwrap();
+        let err = client.generate(json_mode_request()).await.unwrap_err();
+        assert_rate_limited_error(&err);
+    }
+}
@@ -1,37 +1,44 @@
 use crate::api_bail;
 
-use super::LlmGenerationClient;
+use super::{LlmApiError, LlmGenerationClient};
 use anyhow::Result;
 use async_openai::{
-    config::OpenAIConfig,
+    config::{Config, OpenAIConfig},
     types::{
-        ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage,
+        ChatCompletionRequestMessage, ChatCompletionRequestMessageContentPartImage,
+        ChatCompletionRequestMessageContentPartText, ChatCompletionRequestSystemMessage,
         ChatCompletionRequestSystemMessageContent, ChatCompletionRequestUserMessage,
-        ChatCompletionRequestUserMessageContent, CreateChatCompletionRequest, ResponseFormat,
+        ChatCompletionRequestUserMessageContent, ChatCompletionRequestUserMessageContentPart,
+        CreateChatCompletionRequest, CreateChatCompletionResponse, ImageUrl, ResponseFormat,
         ResponseFormatJsonSchema,
     },
-    Client as OpenAIClient,
 };
 use async_trait::async_trait;
 
 pub struct Client {
-    client: async_openai::Client<OpenAIConfig>,
+    config: OpenAIConfig,
     model: String,
+    reqwest_client: reqwest::Client,
 }
 
 impl Client {
     pub async fn new(spec: super::LlmSpec) -> Result<Self> {
-        if let Some(address) = spec.address {
-            api_bail!("OpenAI doesn't support custom API address: {address}");
-        }
-        // Verify API key is set
-        if std::env::var("OPENAI_API_KEY").is_err() {
-            api_bail!("OPENAI_API_KEY environment variable must be set");
-        }
+        let config = match spec.address {
+            // OpenAI-compatible servers (e.g. vLLM, LiteLLM), which may not require an API key.
+            Some(address) => OpenAIConfig::new().with_api_base(address.trim_end_matches('/')),
+            None => {
+                // Verify API key is set
+                if std::env::var("OPENAI_API_KEY").is_err() {
+                    api_bail!("OPENAI_API_KEY environment variable must be set");
+                }
+                OpenAIConfig::new()
+            }
+        };
         Ok(Self {
-            // OpenAI client will use OPENAI_API_KEY env variable by default
-            client: OpenAIClient::new(),
+            // OpenAI config will use OPENAI_API_KEY env variable by default
+            config,
             model: spec.model,
+            reqwest_client: reqwest::Client::new(),
         })
     }
 }
@@ -54,12 +61,32 @@ impl LlmGenerationClient for Client {
             ));
         }
 
-        // Add user message
+        // Add user message, with the image as a separate content part if provided
+        let user_content = match &request.image {
+            Some(image) => {
+                let mut parts = Vec::new();
+                if !request.user_prompt.is_empty() {
+                    parts.push(ChatCompletionRequestUserMessageContentPart::Text(
+                        ChatCompletionRequestMessageContentPartText {
+                            text: request.user_prompt.into_owned(),
+                        },
+                    ));
+                }
+                parts.push(ChatCompletionRequestUserMessageContentPart::ImageUrl(
+                    ChatCompletionRequestMessageContentPartImage {
+                        image_url: ImageUrl {
+                            url: image.to_data_url(),
+                            detail: None,
+                        },
+                    },
+                ));
+                ChatCompletionRequestUserMessageContent::Array(parts)
+            }
+            None => ChatCompletionRequestUserMessageContent::Text(request.user_prompt.into_owned()),
+        };
         messages.push(ChatCompletionRequestMessage::User(
             ChatCompletionRequestUserMessage {
-                content: ChatCompletionRequestUserMessageContent::Text(
-                    request.user_prompt.into_owned(),
-                ),
+                content: user_content,
                 ..Default::default()
             },
         ));
@@ -84,8 +111,26 @@ impl LlmGenerationClient for Client {
             ..Default::default()
         };
 
-        // Send request and get response
-        let response = self.client.chat().create(request).await?;
+        // Send request and get response.
+        // Sent directly instead of through `async_openai::Client`, which drops the HTTP status and
+        // `Retry-After` header of failed responses, needed for retrying and pacing.
+        let res = self
+            .reqwest_client
+            .post(self.config.url("/chat/completions"))
+            .query(&self.config.query())
+            .headers(self.config.headers())
+            .json(&request)
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            return Err(LlmApiError::from_response("OpenAI", res).await.into());
+        }
+        let response: CreateChatCompletionResponse = res.json().await?;
+
+        let usage = response.usage.as_ref().map(|usage| super::LlmTokenUsage {
+            prompt_tokens: usage.prompt_tokens as u64,
+            completion_tokens: usage.completion_tokens as u64,
+        });
 
         // Extract the response text from the first choice
         let text = response
@@ -95,7 +140,7 @@ impl LlmGenerationClient for Client {
             .and_then(|choice| choice.message.content)
             .ok_or_else(|| anyhow::anyhow!("No response from OpenAI"))?;
 
-        Ok(super::LlmGenerateResponse { text })
+        Ok(super::LlmGenerateResponse { text, usage })
     }
 
     fn json_schema_options(&self) -> super::ToJsonSchemaOptions {
@@ -107,3 +152,75 @@ impl LlmGenerationClient for Client {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_utils::{
+        assert_rate_limited_error, json_mode_request, llm_spec, StubServer,
+    };
+    use super::super::{LlmApiType, LlmGenerationClient};
+    use super::Client;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn json_mode_request_and_response() {
+        let server = StubServer::start(
+            200,
+            &[],
+            json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 0,
+                "model": "test-model",
+                "choices": [{
+                    "index": 0,
+                    "message": { "role": "assistant", "content": "{\"name\":\"Alice\"}" },
+                    "finish_reason": "stop",
+                }],
+                "usage": { "prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15 },
+            }),
+        )
+        .await;
+        let client = Client::new(llm_spec(LlmApiType::OpenAi, &server.address))
+            .await
+            .unwrap();
+        let response = client.generate(json_mode_request()).await.unwrap();
+        assert_eq!(response.text, r#"{"name":"Alice"}"#);
+        let usage = response.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (10, 5));
+
+        let request = server.single_request();
+        assert_eq!(request.path, "/chat/completions");
+        assert_eq!(request.body["model"], "test-model");
+        assert_eq!(
+            request.body["messages"],
+            json!([
+                { "role": "system", "content": "Extract the name." },
+                { "role": "user", "content": "My name is Alice." },
+            ])
+        );
+        let response_format = &request.body["response_format"];
+        assert_eq!(response_format["type"], "json_schema");
+        assert_eq!(response_format["json_schema"]["name"], "Person");
+        assert_eq!(response_format["json_schema"]["strict"], true);
+        assert_eq!(
+            response_format["json_schema"]["schema"]["required"],
+            json!(["name"])
+        );
+    }
+
+    #[tokio::test]
+    async fn rate_limited_error() {
+        let server = StubServer::start(
+            429,
+            &[("retry-after", "7")],
+            json!({ "error": { "message": "Rate limit reached", "type": "requests" } }),
+        )
+        .await;
+        let client = Client::new(llm_spec(LlmApiType::OpenAi, &server.address))
+            .await
+            .unwrap();
+        let err = client.generate(json_mode_request()).await.unwrap_err();
+        assert_rate_limited_error(&err);
+    }
+}
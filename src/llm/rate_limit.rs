@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use log::info;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use super::{LlmApiError, LlmApiType, LlmGenerationClient, LlmSpec, LlmTokenUsage};
+use crate::base::json_schema::ToJsonSchemaOptions;
+use crate::execution::stats::record_op_stats;
+use crate::utils::rate_limiter::RateLimiter;
+use crate::utils::retriable;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct EndpointKey {
+    api_type: LlmApiType,
+    address: Option<String>,
+    model: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct EndpointLimits {
+    max_requests_per_minute: Option<u64>,
+    max_tokens_per_minute: Option<u64>,
+    max_inflight_requests: Option<usize>,
+}
+
+/// Limits shared by all clients talking to the same endpoint.
+pub struct EndpointLimiter {
+    limits: EndpointLimits,
+    requests: Option<RateLimiter>,
+    tokens: Option<RateLimiter>,
+    inflight: Option<Semaphore>,
+}
+
+impl EndpointLimiter {
+    fn new(limits: EndpointLimits) -> Self {
+        Self {
+            limits,
+            requests: limits.max_requests_per_minute.map(RateLimiter::per_minute),
+            tokens: limits.max_tokens_per_minute.map(RateLimiter::per_minute),
+            inflight: limits.max_inflight_requests.map(Semaphore::new),
+        }
+    }
+
+    /// Wait until a request with the given estimated tokens can be sent.
+    /// The returned permit must be held until the request finishes.
+    async fn acquire(&self, estimated_tokens: u64) -> Result<Option<SemaphorePermit<'_>>> {
+        let permit = match &self.inflight {
+            Some(inflight) => Some(inflight.acquire().await?),
+            None => None,
+        };
+        if let Some(requests) = &self.requests {
+            requests.acquire(1).await;
+        }
+        if let Some(tokens) = &self.tokens {
+            tokens.acquire(estimated_tokens).await;
+        }
+        Ok(permit)
+    }
+
+    /// Charge tokens reported by the API beyond the estimation acquired before the request,
+    /// i.e. completion tokens and underestimated prompt tokens.
+    /// They're debited without waiting, as the request is already done; later requests wait for them instead.
+    fn charge_actual_tokens(&self, estimated_tokens: u64, usage: &LlmTokenUsage) {
+        if let Some(tokens) = &self.tokens {
+            let extra_tokens =
+                (usage.prompt_tokens + usage.completion_tokens).saturating_sub(estimated_tokens);
+            if extra_tokens > 0 {
+                tokens.debit(extra_tokens);
+            }
+        }
+    }
+}
+
+static ENDPOINT_LIMITERS: LazyLock<Mutex<HashMap<EndpointKey, Arc<EndpointLimiter>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn get_endpoint_limiter(spec: &LlmSpec) -> Arc<EndpointLimiter> {
+    let key = EndpointKey {
+        api_type: spec.api_type.clone(),
+        address: spec.address.clone(),
+        model: spec.model.clone(),
+    };
+    let limits = EndpointLimits {
+        max_requests_per_minute: spec.max_requests_per_minute,
+        max_tokens_per_minute: spec.max_tokens_per_minute,
+        max_inflight_requests: spec.max_inflight_requests,
+    };
+    let mut limiters = ENDPOINT_LIMITERS.lock().unwrap();
+    match limiters.get(&key) {
+        Some(limiter) if limiter.limits == limits => limiter.clone(),
+        existing => {
+            if let Some(limiter) = existing {
+                // Clients built before keep the previous limiter until they're rebuilt, e.g. on flow updates.
+                info!(
+                    "Limits of LLM endpoint ({:?}, model `{}`) changed from {:?} to {:?}",
+                    spec.api_type, spec.model, limiter.limits, limits
+                );
+            }
+            let limiter = Arc::new(EndpointLimiter::new(limits));
+            limiters.insert(key, limiter.clone());
+            limiter
+        }
+    }
+}
+
+const LLM_MAX_RETRIES: usize = 8;
+const LLM_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const LLM_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+fn to_retriable_error(err: anyhow::Error) -> retriable::Error {
+    match err.downcast::<LlmApiError>() {
+        Ok(err) => err.into(),
+        Err(err) => err.into(),
+    }
+}
+
+/// Wraps a client to apply endpoint limits, and retries on rate limiting and transient errors.
+/// Token usage of successful requests is recorded to stats of the current op.
+pub struct Client {
+    inner: Box<dyn LlmGenerationClient>,
+    limiter: Arc<EndpointLimiter>,
+}
+
+impl Client {
+    pub fn new(inner: Box<dyn LlmGenerationClient>, limiter: Arc<EndpointLimiter>) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+#[async_trait]
+impl LlmGenerationClient for Client {
+    async fn generate<'req>(
+        &self,
+        request: super::LlmGenerateRequest<'req>,
+    ) -> Result<super::LlmGenerateResponse> {
+        let estimated_tokens = request.estimated_input_tokens();
+        let response = retriable::run(
+            || async {
+                let _permit = self.limiter.acquire(estimated_tokens).await?;
+                self.inner
+                    .generate(request.clone())
+                    .await
+                    .map_err(to_retriable_error)
+            },
+            retriable::RunOptions {
+                max_retries: LLM_MAX_RETRIES,
+                initial_backoff: LLM_INITIAL_BACKOFF,
+                max_backoff: LLM_MAX_BACKOFF,
+            },
+        )
+        .await?;
+        if let Some(usage) = &response.usage {
+            self.limiter.charge_actual_tokens(estimated_tokens, usage);
+            record_op_stats(|stats| {
+                stats.num_llm_prompt_tokens.inc(usage.prompt_tokens as i64);
+                stats
+                    .num_llm_completion_tokens
+                    .inc(usage.completion_tokens as i64);
+            });
+        }
+        Ok(response)
+    }
+
+    fn json_schema_options(&self) -> ToJsonSchemaOptions {
+        self.inner.json_schema_options()
+    }
+}
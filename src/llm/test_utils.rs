@@ -0,0 +1,138 @@
+//! A local HTTP server standing in for LLM APIs in tests.
+
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Uri},
+};
+
+use super::{LlmApiType, LlmGenerateRequest, LlmSpec, OutputFormat};
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    /// Path and query of the request URL.
+    pub path: String,
+    pub headers: HeaderMap,
+    pub body: serde_json::Value,
+}
+
+struct StubState {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: String,
+    requests: Mutex<Vec<RecordedRequest>>,
+}
+
+/// Replies every request with the same response, and records all requests.
+pub struct StubServer {
+    pub address: String,
+    state: Arc<StubState>,
+}
+
+async fn handle(
+    State(state): State<Arc<StubState>>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, HeaderMap, String) {
+    state.requests.lock().unwrap().push(RecordedRequest {
+        path: uri
+            .path_and_query()
+            .map(|p| p.to_string())
+            .unwrap_or_default(),
+        headers,
+        body: serde_json::from_slice(&body).unwrap_or_default(),
+    });
+    (state.status, state.headers.clone(), state.body.clone())
+}
+
+impl StubServer {
+    pub async fn start(
+        status: u16,
+        headers: &[(&'static str, &str)],
+        body: serde_json::Value,
+    ) -> Self {
+        let mut header_map = HeaderMap::new();
+        header_map.insert(
+            axum::http::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        for (name, value) in headers {
+            header_map.insert(
+                HeaderName::from_static(name),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        let state = Arc::new(StubState {
+            status: StatusCode::from_u16(status).unwrap(),
+            headers: header_map,
+            body: body.to_string(),
+            requests: Mutex::new(Vec::new()),
+        });
+        let router = axum::Router::new()
+            .fallback(handle)
+            .with_state(state.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        Self { address, state }
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    pub fn single_request(&self) -> RecordedRequest {
+        let requests = self.requests();
+        assert_eq!(requests.len(), 1, "expect exactly one request");
+        requests.into_iter().next().unwrap()
+    }
+}
+
+pub fn llm_spec(api_type: LlmApiType, address: &str) -> LlmSpec {
+    LlmSpec {
+        api_type,
+        address: Some(address.to_string()),
+        model: "test-model".to_string(),
+        max_requests_per_minute: None,
+        max_tokens_per_minute: None,
+        max_inflight_requests: None,
+    }
+}
+
+/// A request asking for JSON output following a schema with a single `name` field.
+pub fn json_mode_request() -> LlmGenerateRequest<'static> {
+    let schema = serde_json::from_value(serde_json::json!({
+        "type": "object",
+        "properties": { "name": { "type": "string" } },
+        "required": ["name"],
+    }))
+    .unwrap();
+    LlmGenerateRequest {
+        system_prompt: Some(Cow::Borrowed("Extract the name.")),
+        user_prompt: Cow::Borrowed("My name is Alice."),
+        image: None,
+        output_format: Some(OutputFormat::JsonSchema {
+            name: Cow::Borrowed("Person"),
+            schema: Cow::Owned(schema),
+        }),
+    }
+}
+
+/// Assert the error is an `LlmApiError` for a rate limited response with `Retry-After: 7`.
+pub fn assert_rate_limited_error(err: &anyhow::Error) {
+    use crate::utils::retriable::IsRetryable;
+
+    let api_error = err
+        .downcast_ref::<super::LlmApiError>()
+        .unwrap_or_else(|| panic!("expect an LlmApiError, got: {err:?}"));
+    assert_eq!(api_error.status, reqwest::StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        api_error.retry_after,
+        Some(std::time::Duration::from_secs(7))
+    );
+    assert!(api_error.is_retryable());
+}
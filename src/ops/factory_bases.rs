@@ -133,6 +133,16 @@ impl<'a> OpArgsResolver<'a> {
         }))
     }
 
+    /// Resolve the argument only if it's provided as a keyword argument, leaving positional ones for later.
+    pub fn next_keyword_arg(&mut self, name: &str) -> Option<ResolvedOpArg> {
+        let idx = self.remaining_kwargs.remove(name)?;
+        Some(ResolvedOpArg {
+            name: name.to_string(),
+            typ: self.args[idx].value_type.clone(),
+            idx,
+        })
+    }
+
     pub fn next_arg(&mut self, name: &str) -> Result<ResolvedOpArg> {
         Ok(self
             .next_optional_arg(name)?
@@ -233,6 +243,9 @@ pub trait SimpleFunctionFactoryBase: SimpleFunctionFactory + Send + Sync + 'stat
         context: Arc<FlowInstanceContext>,
     ) -> Result<Box<dyn SimpleFunctionExecutor>>;
 
+    /// See `SimpleFunctionFactory::strip_non_logic_options`.
+    fn strip_non_logic_options(&self, _spec: &mut serde_json::Map<String, serde_json::Value>) {}
+
     fn register(self, registry: &mut ExecutorFactoryRegistry) -> Result<()>
     where
         Self: Sized,
@@ -262,6 +275,10 @@ impl<T: SimpleFunctionFactoryBase> SimpleFunctionFactory for T {
         let executor = self.build_executor(spec, resolved_input_schema, context);
         Ok((output_schema, executor))
     }
+
+    fn strip_non_logic_options(&self, spec: &mut serde_json::Map<String, serde_json::Value>) {
+        SimpleFunctionFactoryBase::strip_non_logic_options(self, spec)
+    }
 }
 
 pub struct TypedExportTargetExecutors<F: StorageFactoryBase + ?Sized> {
@@ -1,10 +1,12 @@
 use crate::prelude::*;
 
+use crate::execution::stats::record_op_stats;
 use crate::llm::{
-    new_llm_generation_client, LlmGenerateRequest, LlmGenerationClient, LlmSpec, OutputFormat,
+    detect_image_mime_type, new_llm_generation_client, LlmGenerateRequest, LlmGenerationClient,
+    LlmImage, LlmSpec, OutputFormat,
 };
 use crate::ops::sdk::*;
-use base::json_schema::build_json_schema;
+use base::json_schema::{build_json_schema, validate_json_value};
 use schemars::schema::SchemaObject;
 use std::borrow::Cow;
 
@@ -13,10 +15,16 @@ pub struct Spec {
     llm_spec: LlmSpec,
     output_type: EnrichedValueType,
     instruction: Option<String>,
+
+    /// Max number of times to re-ask the LLM when its output is not valid, with the validation error fed back.
+    #[serde(default)]
+    max_retries: u32,
 }
 
 pub struct Args {
-    text: ResolvedOpArg,
+    text: Option<ResolvedOpArg>,
+    image: Option<ResolvedOpArg>,
+    image_mime_type: Option<ResolvedOpArg>,
 }
 
 struct Executor {
@@ -25,13 +33,14 @@ struct Executor {
     output_json_schema: SchemaObject,
     system_prompt: String,
     value_extractor: base::json_schema::ValueExtractor,
+    max_retries: u32,
 }
 
 fn get_system_prompt(instructions: &Option<String>, extra_instructions: Option<String>) -> String {
     let mut message =
-        "You are a helpful assistant that extracts structured information from text. \
-Your task is to analyze the input text and output valid JSON that matches the specified schema. \
-Be precise and only include information that is explicitly stated in the text. \
+        "You are a helpful assistant that extracts structured information from text and images. \
+Your task is to analyze the input text and/or image and output valid JSON that matches the specified schema. \
+Be precise and only include information that is explicitly stated in the input. \
 Output only the JSON without any additional messages or explanations."
             .to_string();
 
@@ -48,6 +57,60 @@ Output only the JSON without any additional messages or explanations."
     message
 }
 
+fn strip_code_fence(text: &str) -> &str {
+    let Some(text) = text.strip_prefix("```") else {
+        return text;
+    };
+    // Skip the info string, e.g. "json".
+    let text = text.split_once('\n').map(|(_, rest)| rest).unwrap_or("");
+    let text = text.trim_end();
+    text.strip_suffix("```").unwrap_or(text)
+}
+
+/// Remove commas directly followed by `}` or `]`, outside of strings.
+fn remove_trailing_commas(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut chars = text.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ',' {
+            let next_non_whitespace = text[idx + 1..].trim_start().chars().next();
+            if matches!(next_non_whitespace, Some('}') | Some(']')) {
+                continue;
+            }
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// Parse JSON output from LLM leniently, tolerating code fences, surrounding text and trailing commas.
+fn parse_json_leniently(text: &str) -> Result<serde_json::Value> {
+    let text = strip_code_fence(text.trim()).trim();
+    if let Ok(value) = serde_json::from_str(text) {
+        return Ok(value);
+    }
+    let start = text.find(['{', '[']);
+    let end = text.rfind(['}', ']']);
+    let text = match (start, end) {
+        (Some(start), Some(end)) if start < end => &text[start..=end],
+        _ => text,
+    };
+    let value = serde_json::from_str(&remove_trailing_commas(text))?;
+    Ok(value)
+}
+
 impl Executor {
     async fn new(spec: Spec, args: Args) -> Result<Self> {
         let client = new_llm_generation_client(spec.llm_spec).await?;
@@ -58,8 +121,15 @@ impl Executor {
             output_json_schema: schema_output.schema,
             system_prompt: get_system_prompt(&spec.instruction, schema_output.extra_instructions),
             value_extractor: schema_output.value_extractor,
+            max_retries: spec.max_retries,
         })
     }
+
+    fn parse_output(&self, text: &str) -> Result<Value> {
+        let json_value = parse_json_leniently(text).context("Output is not valid JSON")?;
+        validate_json_value(&self.output_json_schema, &json_value)?;
+        self.value_extractor.extract_value(json_value)
+    }
 }
 
 #[async_trait]
@@ -73,19 +143,67 @@ impl SimpleFunctionExecutor for Executor {
     }
 
     async fn evaluate(&self, input: Vec<Value>) -> Result<Value> {
-        let text = self.args.text.value(&input)?.as_str()?;
-        let req = LlmGenerateRequest {
-            system_prompt: Some(Cow::Borrowed(&self.system_prompt)),
-            user_prompt: Cow::Borrowed(text),
-            output_format: Some(OutputFormat::JsonSchema {
-                name: Cow::Borrowed("ExtractedData"),
-                schema: Cow::Borrowed(&self.output_json_schema),
-            }),
+        let text = self.args.text.value(&input)?.optional();
+        let image = self.args.image.value(&input)?.optional();
+        if text.is_none() && image.is_none() {
+            return Ok(Value::Null);
+        }
+        let image = match image {
+            Some(image) => {
+                let data = image.as_bytes()?;
+                let mime_type = match self.args.image_mime_type.value(&input)?.optional() {
+                    Some(mime_type) => mime_type.as_str()?.as_ref(),
+                    None => detect_image_mime_type(data).ok_or_else(|| {
+                        anyhow!("Unable to detect the image MIME type; please provide `image_mime_type`")
+                    })?,
+                };
+                Some(LlmImage {
+                    data: Cow::Borrowed(data.as_ref()),
+                    mime_type: Cow::Borrowed(mime_type),
+                })
+            }
+            None => None,
+        };
+        let text = match text {
+            Some(text) => text.as_str()?.as_ref(),
+            None => "",
         };
-        let res = self.client.generate(req).await?;
-        let json_value: serde_json::Value = serde_json::from_str(res.text.as_str())?;
-        let value = self.value_extractor.extract_value(json_value)?;
-        Ok(value)
+        let mut user_prompt = Cow::Borrowed(text);
+        let mut num_retries = 0;
+        loop {
+            let req = LlmGenerateRequest {
+                system_prompt: Some(Cow::Borrowed(&self.system_prompt)),
+                user_prompt: Cow::Borrowed(user_prompt.as_ref()),
+                image: image.clone(),
+                output_format: Some(OutputFormat::JsonSchema {
+                    name: Cow::Borrowed("ExtractedData"),
+                    schema: Cow::Borrowed(&self.output_json_schema),
+                }),
+            };
+            record_op_stats(|stats| stats.num_llm_attempts.inc(1));
+            let res = self.client.generate(req).await?;
+            let err = match self.parse_output(&res.text) {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            record_op_stats(|stats| stats.num_llm_invalid_outputs.inc(1));
+            if num_retries >= self.max_retries {
+                record_op_stats(|stats| stats.num_llm_failures.inc(1));
+                return Err(err.context(format!(
+                    "Invalid output from LLM after {} attempts",
+                    num_retries + 1
+                )));
+            }
+            num_retries += 1;
+            trace!("Re-asking LLM (retry #{num_retries}) for invalid output: {err:?}");
+            user_prompt = Cow::Owned(format!(
+                "{text}\n\n\
+Your previous output was:\n{}\n\n\
+It's invalid because of the following error:\n{err:#}\n\n\
+Please output the corrected JSON only.",
+                res.text
+            ));
+        }
     }
 }
 
@@ -106,14 +224,36 @@ impl SimpleFunctionFactoryBase for Factory {
         args_resolver: &mut OpArgsResolver<'_>,
         _context: &FlowInstanceContext,
     ) -> Result<(Args, EnrichedValueType)> {
-        Ok((
-            Args {
-                text: args_resolver
-                    .next_arg("text")?
-                    .expect_type(&ValueType::Basic(BasicValueType::Str))?,
-            },
-            spec.output_type.clone(),
-        ))
+        let mut text = args_resolver.next_optional_arg("text")?;
+        let mut image = args_resolver.next_optional_arg("image")?;
+        // Allow the image to be passed as the first positional argument, e.g. `content.transform(ExtractByLlm(...))` on binary content.
+        if image.is_none()
+            && text
+                .as_ref()
+                .is_some_and(|arg| arg.typ.typ == ValueType::Basic(BasicValueType::Bytes))
+        {
+            image = text.take();
+        }
+        let args = Args {
+            text: text.expect_type(&ValueType::Basic(BasicValueType::Str))?,
+            image: image.expect_type(&ValueType::Basic(BasicValueType::Bytes))?,
+            image_mime_type: args_resolver
+                .next_optional_arg("image_mime_type")?
+                .expect_type(&ValueType::Basic(BasicValueType::Str))?,
+        };
+        if args.text.is_none() && args.image.is_none() {
+            api_bail!("At least one of `text` and `image` must be provided");
+        }
+        let mut output_type = spec.output_type.clone();
+        if args
+            .text
+            .iter()
+            .chain(args.image.iter())
+            .all(|arg| arg.typ.nullable)
+        {
+            output_type.nullable = true;
+        }
+        Ok((args, output_type))
     }
 
     async fn build_executor(
@@ -124,4 +264,92 @@ impl SimpleFunctionFactoryBase for Factory {
     ) -> Result<Box<dyn SimpleFunctionExecutor>> {
         Ok(Box::new(Executor::new(spec, resolved_input_schema).await?))
     }
+
+    fn strip_non_logic_options(&self, spec: &mut serde_json::Map<String, serde_json::Value>) {
+        // Retries only take effect on invalid outputs, which are never cached.
+        spec.remove("max_retries");
+        if let Some(llm_spec) = spec.get_mut("llm_spec") {
+            LlmSpec::strip_rate_limits(llm_spec);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn strip_code_fences() {
+        assert_eq!(strip_code_fence("```json\n{\"a\": 1}\n```"), "{\"a\": 1}\n");
+        assert_eq!(strip_code_fence("```\n[1]\n```\n"), "[1]\n");
+        assert_eq!(strip_code_fence("```json\n{\"a\": 1}"), "{\"a\": 1}");
+        assert_eq!(strip_code_fence("{\"a\": 1}"), "{\"a\": 1}");
+    }
+
+    #[test]
+    fn remove_trailing_commas_outside_strings() {
+        assert_eq!(
+            remove_trailing_commas("{\"a\": [1, 2,], \"b\": 3,\n}"),
+            "{\"a\": [1, 2], \"b\": 3\n}"
+        );
+        assert_eq!(
+            remove_trailing_commas("{\"a\": \",}\", \"b\": \"\\\",]\"}"),
+            "{\"a\": \",}\", \"b\": \"\\\",]\"}"
+        );
+        assert_eq!(remove_trailing_commas("[1, 2]"), "[1, 2]");
+    }
+
+    #[test]
+    fn parse_json_leniently_accepts_common_deviations() {
+        assert_eq!(
+            parse_json_leniently(" {\"a\": 1} ").unwrap(),
+            json!({"a": 1})
+        );
+        assert_eq!(
+            parse_json_leniently("```json\n{\"a\": [1, 2,],}\n```").unwrap(),
+            json!({"a": [1, 2]})
+        );
+        assert_eq!(
+            parse_json_leniently("Here is the result:\n{\"a\": \"x, }\"}\nHope it helps!").unwrap(),
+            json!({"a": "x, }"})
+        );
+        assert_eq!(
+            parse_json_leniently("Items: [\"a\", \"b\",]").unwrap(),
+            json!(["a", "b"])
+        );
+    }
+
+    #[test]
+    fn parse_json_leniently_rejects_invalid_json() {
+        assert!(parse_json_leniently("no JSON here").is_err());
+        assert!(parse_json_leniently("{\"a\": }").is_err());
+        assert!(parse_json_leniently("} {").is_err());
+    }
+
+    #[test]
+    fn strip_non_logic_options_from_spec() {
+        let mut spec = json!({
+            "llm_spec": {
+                "api_type": "OpenAi",
+                "model": "gpt-4o",
+                "address": null,
+                "max_requests_per_minute": 10,
+                "max_tokens_per_minute": 1000,
+                "max_inflight_requests": 2,
+            },
+            "output_type": {"type": {"kind": "Str"}},
+            "instruction": "Extract",
+            "max_retries": 3,
+        });
+        SimpleFunctionFactoryBase::strip_non_logic_options(&Factory, spec.as_object_mut().unwrap());
+        assert_eq!(
+            spec,
+            json!({
+                "llm_spec": {"api_type": "OpenAi", "model": "gpt-4o", "address": null},
+                "output_type": {"type": {"kind": "Str"}},
+                "instruction": "Extract",
+            })
+        );
+    }
 }
@@ -0,0 +1,319 @@
+use crate::prelude::*;
+
+use crate::llm::{new_llm_generation_client, LlmGenerateRequest, LlmGenerationClient, LlmSpec};
+use crate::ops::sdk::*;
+use std::borrow::Cow;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Spec {
+    llm_spec: LlmSpec,
+    /// Template of the user prompt, with `{name}` placeholders for input fields.
+    /// Use `{{` and `}}` for literal braces.
+    prompt: String,
+    system_prompt: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum TemplateSegment<Field> {
+    Literal(String),
+    Field(Field),
+}
+
+pub struct Args {
+    /// Fields are referenced by their indices in `fields`.
+    segments: Vec<TemplateSegment<usize>>,
+    fields: Vec<ResolvedOpArg>,
+}
+
+/// Parse the template into literal segments and placeholder names.
+fn parse_template(template: &str) -> Result<Vec<TemplateSegment<String>>> {
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => api_bail!("Unclosed `{{` in prompt template: {template}"),
+                    }
+                }
+                let name = name.trim();
+                if name.is_empty() {
+                    api_bail!("Empty placeholder in prompt template: {template}");
+                }
+                if !literal.is_empty() {
+                    segments.push(TemplateSegment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(TemplateSegment::Field(name.to_string()));
+            }
+            '}' => api_bail!("Unmatched `}}` in prompt template: {template}"),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        segments.push(TemplateSegment::Literal(literal));
+    }
+    Ok(segments)
+}
+
+fn value_to_prompt_text(value: &Value) -> Result<Cow<'_, str>> {
+    let text = match value {
+        Value::Null => Cow::Borrowed(""),
+        Value::Basic(BasicValue::Str(s)) => Cow::Borrowed(s.as_ref()),
+        v => Cow::Owned(serde_json::to_string(v)?),
+    };
+    Ok(text)
+}
+
+struct Executor {
+    args: Args,
+    client: Box<dyn LlmGenerationClient>,
+    system_prompt: Option<String>,
+}
+
+impl Executor {
+    async fn new(spec: Spec, args: Args) -> Result<Self> {
+        let client = new_llm_generation_client(spec.llm_spec).await?;
+        Ok(Self {
+            args,
+            client,
+            system_prompt: spec.system_prompt,
+        })
+    }
+}
+
+#[async_trait]
+impl SimpleFunctionExecutor for Executor {
+    fn behavior_version(&self) -> Option<u32> {
+        Some(1)
+    }
+
+    fn enable_cache(&self) -> bool {
+        true
+    }
+
+    async fn evaluate(&self, input: Vec<Value>) -> Result<Value> {
+        let mut user_prompt = String::new();
+        for segment in self.args.segments.iter() {
+            match segment {
+                TemplateSegment::Literal(literal) => user_prompt.push_str(literal),
+                TemplateSegment::Field(idx) => {
+                    let value = self.args.fields[*idx].value(&input)?;
+                    user_prompt.push_str(&value_to_prompt_text(value)?);
+                }
+            }
+        }
+        let req = LlmGenerateRequest {
+            system_prompt: self.system_prompt.as_deref().map(Cow::Borrowed),
+            user_prompt: Cow::Owned(user_prompt),
+            image: None,
+            output_format: None,
+        };
+        let res = self.client.generate(req).await?;
+        Ok(Value::Basic(BasicValue::Str(Arc::from(res.text))))
+    }
+}
+
+pub struct Factory;
+
+#[async_trait]
+impl SimpleFunctionFactoryBase for Factory {
+    type Spec = Spec;
+    type ResolvedArgs = Args;
+
+    fn name(&self) -> &str {
+        "GenerateText"
+    }
+
+    fn resolve_schema(
+        &self,
+        spec: &Spec,
+        args_resolver: &mut OpArgsResolver<'_>,
+        _context: &FlowInstanceContext,
+    ) -> Result<(Args, EnrichedValueType)> {
+        // Placeholders provided as keyword arguments are resolved by name.
+        // The others are bound to positional arguments in the order of first appearance,
+        // so the first one can be bound to the data slice being transformed.
+        let template = parse_template(&spec.prompt)?;
+        let mut field_names = Vec::<String>::new();
+        for segment in template.iter() {
+            if let TemplateSegment::Field(name) = segment {
+                if !field_names.contains(name) {
+                    field_names.push(name.clone());
+                }
+            }
+        }
+        let mut fields = field_names
+            .iter()
+            .map(|name| args_resolver.next_keyword_arg(name))
+            .collect::<Vec<_>>();
+        for (name, field) in field_names.iter().zip(fields.iter_mut()) {
+            if field.is_none() {
+                *field = Some(args_resolver.next_arg(name)?);
+            }
+        }
+        let fields = fields.into_iter().flatten().collect();
+        let segments = template
+            .into_iter()
+            .map(|segment| match segment {
+                TemplateSegment::Literal(literal) => TemplateSegment::Literal(literal),
+                TemplateSegment::Field(name) => TemplateSegment::Field(
+                    field_names
+                        .iter()
+                        .position(|n| n == &name)
+                        .unwrap_or_default(),
+                ),
+            })
+            .collect();
+        Ok((
+            Args { segments, fields },
+            make_output_type(BasicValueType::Str),
+        ))
+    }
+
+    async fn build_executor(
+        self: Arc<Self>,
+        spec: Spec,
+        resolved_input_schema: Args,
+        _context: Arc<FlowInstanceContext>,
+    ) -> Result<Box<dyn SimpleFunctionExecutor>> {
+        Ok(Box::new(Executor::new(spec, resolved_input_schema).await?))
+    }
+
+    fn strip_non_logic_options(&self, spec: &mut serde_json::Map<String, serde_json::Value>) {
+        if let Some(llm_spec) = spec.get_mut("llm_spec") {
+            LlmSpec::strip_rate_limits(llm_spec);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::plan::AnalyzedValueMapping;
+    use crate::setup::AuthRegistry;
+    use serde_json::json;
+
+    fn literal(text: &str) -> TemplateSegment<String> {
+        TemplateSegment::Literal(text.to_string())
+    }
+
+    fn field(name: &str) -> TemplateSegment<String> {
+        TemplateSegment::Field(name.to_string())
+    }
+
+    #[test]
+    fn parse_placeholders() {
+        assert_eq!(
+            parse_template("Translate {text} to { lang }.").unwrap(),
+            vec![
+                literal("Translate "),
+                field("text"),
+                literal(" to "),
+                field("lang"),
+                literal("."),
+            ]
+        );
+        assert_eq!(
+            parse_template("{a}{b}").unwrap(),
+            vec![field("a"), field("b")]
+        );
+        assert_eq!(parse_template("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn parse_escaped_braces() {
+        assert_eq!(
+            parse_template("Output {{\"summary\": ...}} for {{{text}}}").unwrap(),
+            vec![
+                literal("Output {\"summary\": ...} for {"),
+                field("text"),
+                literal("}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_invalid_templates() {
+        assert!(parse_template("Summarize {text").is_err());
+        assert!(parse_template("Summarize { }").is_err());
+        assert!(parse_template("Summarize text}").is_err());
+    }
+
+    fn arg(name: Option<&str>) -> OpArgSchema {
+        OpArgSchema {
+            name: OpArgName(name.map(|name| name.to_string())),
+            value_type: make_output_type(BasicValueType::Str),
+            analyzed_value: AnalyzedValueMapping::Constant { value: Value::Null },
+        }
+    }
+
+    fn resolve(prompt: &str, args: &[OpArgSchema]) -> Result<Args> {
+        let spec: Spec = serde_json::from_value(json!({
+            "llm_spec": {"api_type": "OpenAi", "model": "gpt-4o"},
+            "prompt": prompt,
+        }))?;
+        let context = FlowInstanceContext {
+            flow_instance_name: "test_flow".to_string(),
+            auth_registry: Arc::new(AuthRegistry::new()),
+            py_exec_ctx: None,
+        };
+        let mut args_resolver = OpArgsResolver::new(args)?;
+        let (args, _) = Factory.resolve_schema(&spec, &mut args_resolver, &context)?;
+        args_resolver.done()?;
+        Ok(args)
+    }
+
+    fn resolved_fields(args: &Args) -> Vec<(&str, usize)> {
+        args.fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.idx))
+            .collect()
+    }
+
+    #[test]
+    fn resolve_keyword_placeholder_before_positional() {
+        let args = resolve(
+            "Translate to {lang}: {text}. Keep it in {lang}.",
+            &[arg(None), arg(Some("lang"))],
+        )
+        .unwrap();
+        assert_eq!(resolved_fields(&args), vec![("lang", 1), ("text", 0)]);
+        assert!(matches!(
+            args.segments.as_slice(),
+            [
+                TemplateSegment::Literal(_),
+                TemplateSegment::Field(0),
+                TemplateSegment::Literal(_),
+                TemplateSegment::Field(1),
+                TemplateSegment::Literal(_),
+                TemplateSegment::Field(0),
+                TemplateSegment::Literal(_),
+            ]
+        ));
+    }
+
+    #[test]
+    fn resolve_positional_placeholders_in_order() {
+        let args = resolve("{a} and {b}", &[arg(None), arg(None)]).unwrap();
+        assert_eq!(resolved_fields(&args), vec![("a", 0), ("b", 1)]);
+    }
+
+    #[test]
+    fn resolve_mismatched_arguments() {
+        assert!(resolve("{a} and {b}", &[arg(None)]).is_err());
+        assert!(resolve("{a}", &[arg(None), arg(Some("b"))]).is_err());
+    }
+}
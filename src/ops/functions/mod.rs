@@ -1,3 +1,4 @@
 pub mod extract_by_llm;
+pub mod generate_text;
 pub mod parse_json;
 pub mod split_recursively;
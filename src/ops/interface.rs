@@ -119,6 +119,10 @@ pub trait SimpleFunctionFactory {
         EnrichedValueType,
         BoxFuture<'static, Result<Box<dyn SimpleFunctionExecutor>>>,
     )>;
+
+    /// Remove options not affecting the function's output (e.g. rate limits) from the spec.
+    /// They're left out when fingerprinting the op's logic, so changing them keeps cached results.
+    fn strip_non_logic_options(&self, _spec: &mut serde_json::Map<String, serde_json::Value>) {}
 }
 
 #[derive(Debug)]
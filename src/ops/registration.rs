@@ -12,6 +12,7 @@ fn register_executor_factories(registry: &mut ExecutorFactoryRegistry) -> Result
     functions::parse_json::Factory.register(registry)?;
     functions::split_recursively::Factory.register(registry)?;
     functions::extract_by_llm::Factory.register(registry)?;
+    functions::generate_text::Factory.register(registry)?;
 
     Arc::new(storages::postgres::Factory::default()).register(registry)?;
     Arc::new(storages::qdrant::Factory::default()).register(registry)?;
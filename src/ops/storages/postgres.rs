@@ -1059,7 +1059,16 @@ impl Factory {
                         shared_ok(if let Some(database_url) = database_url {
                             PgPool::connect(&database_url).await?
                         } else {
-                            get_lib_context().map_err(SharedError::new)?.pool.clone()
+                            get_lib_context()
+                                .map_err(SharedError::new)?
+                                .state_store
+                                .pg_pool()
+                                .cloned()
+                                .ok_or_else(|| {
+                                    SharedError::new(anyhow!(
+                                        "`database_url` is required for Postgres targets, as CocoIndex doesn't keep its internal state in Postgres"
+                                    ))
+                                })?
                         })
                     }
                 };
@@ -106,13 +106,23 @@ impl IndexUpdateInfo {
     pub fn __repr__(&self) -> String {
         self.__str__()
     }
+
+    /// Stats of each source, including per-op stats such as LLM token usage and cache hits.
+    #[getter]
+    pub fn sources<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        (&Pythonized(&self.0.sources)).into_pyobject(py)
+    }
 }
 
 #[pyclass]
 pub struct Flow(pub Arc<FlowContext>);
 
 #[pyclass]
-pub struct FlowLiveUpdater(pub Arc<tokio::sync::RwLock<execution::FlowLiveUpdater>>);
+pub struct FlowLiveUpdater {
+    updater: Arc<tokio::sync::RwLock<execution::FlowLiveUpdater>>,
+    // Kept separately, as the updater is locked while being waited.
+    control: Arc<execution::LiveUpdaterControl>,
+}
 
 #[pymethods]
 impl FlowLiveUpdater {
@@ -126,17 +136,20 @@ impl FlowLiveUpdater {
         future_into_py(py, async move {
             let live_updater = execution::FlowLiveUpdater::start(
                 flow,
-                &get_lib_context().into_py_result()?.pool,
+                &get_lib_context().into_py_result()?.state_store,
                 options.into_inner(),
             )
             .await
             .into_py_result()?;
-            Ok(Self(Arc::new(tokio::sync::RwLock::new(live_updater))))
+            Ok(Self {
+                control: live_updater.control().clone(),
+                updater: Arc::new(tokio::sync::RwLock::new(live_updater)),
+            })
         })
     }
 
     pub fn wait<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
-        let live_updater = self.0.clone();
+        let live_updater = self.updater.clone();
         future_into_py(py, async move {
             let mut live_updater = live_updater.write().await;
             live_updater.wait().await.into_py_result()
@@ -145,14 +158,30 @@ impl FlowLiveUpdater {
 
     pub fn abort(&self, py: Python<'_>) {
         py.allow_threads(|| {
-            let mut live_updater = self.0.blocking_write();
+            let mut live_updater = self.updater.blocking_write();
             live_updater.abort();
         })
     }
 
+    pub fn pause(&self) {
+        self.control.pause();
+    }
+
+    pub fn resume(&self) {
+        self.control.resume();
+    }
+
+    pub fn drain(&self) {
+        self.control.drain();
+    }
+
+    pub fn status(&self) -> Pythonized<execution::LiveUpdaterStatus> {
+        Pythonized(self.control.status())
+    }
+
     pub fn index_update_info(&self, py: Python<'_>) -> IndexUpdateInfo {
         py.allow_threads(|| {
-            let live_updater = self.0.blocking_read();
+            let live_updater = self.updater.blocking_read();
             IndexUpdateInfo(live_updater.index_update_info())
         })
     }
@@ -185,7 +214,7 @@ impl Flow {
                         &exec_plan,
                         &self.0.flow.data_schema,
                         options.into_inner(),
-                        &get_lib_context()?.pool,
+                        &get_lib_context()?.state_store,
                     )
                     .await
                 })
@@ -193,6 +222,65 @@ impl Flow {
             Ok(())
         })
     }
+
+    pub fn analyze_logic_changes<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let flow_ctx = self.0.clone();
+        future_into_py(py, async move {
+            let changes = execution::logic_changes::analyze_logic_changes(
+                &flow_ctx,
+                &get_lib_context().into_py_result()?.state_store,
+            )
+            .await
+            .into_py_result()?;
+            Ok(Pythonized(changes))
+        })
+    }
+
+    pub fn reprocess_source_rows<'py>(
+        &self,
+        py: Python<'py>,
+        source_name: String,
+        options: Pythonized<execution::reprocess::ReprocessOptions>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let flow_ctx = self.0.clone();
+        future_into_py(py, async move {
+            let update_info = execution::reprocess::reprocess_source_rows(
+                &flow_ctx,
+                &source_name,
+                options.into_inner(),
+                &get_lib_context().into_py_result()?.state_store,
+            )
+            .await
+            .into_py_result()?;
+            Ok(IndexUpdateInfo(update_info))
+        })
+    }
+
+    pub fn list_row_errors<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let flow_ctx = self.0.clone();
+        future_into_py(py, async move {
+            let errors = execution::row_errors::list_row_errors(
+                &flow_ctx,
+                &get_lib_context().into_py_result()?.state_store,
+            )
+            .await
+            .into_py_result()?;
+            Ok(Pythonized(errors))
+        })
+    }
+
+    pub fn redrive_row_errors<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let flow_ctx = self.0.clone();
+        future_into_py(py, async move {
+            let update_info = execution::row_errors::redrive_row_errors(
+                &flow_ctx,
+                &get_lib_context().into_py_result()?.state_store,
+            )
+            .await
+            .into_py_result()?;
+            Ok(IndexUpdateInfo(update_info))
+        })
+    }
 }
 
 #[pyclass]
@@ -336,7 +424,7 @@ fn apply_setup_changes(py: Python<'_>, setup_status: &SetupStatusCheck) -> PyRes
                 setup::apply_changes(
                     &mut std::io::stdout(),
                     &setup_status.0,
-                    &get_lib_context()?.pool,
+                    &get_lib_context()?.state_store,
                 )
                 .await
             })
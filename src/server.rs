@@ -34,6 +34,7 @@ pub async fn init_server(
             .allow_headers([axum::http::header::CONTENT_TYPE]);
     }
     let app = Router::new()
+        .route("/metrics", routing::get(service::metrics::get_metrics))
         .route("/api/flows", routing::get(service::flows::list_flows))
         .route(
             "/api/flows/:flowInstName",
@@ -55,6 +56,38 @@ pub async fn init_server(
             "/api/flows/:flowInstName/update",
             routing::post(service::flows::update),
         )
+        .route(
+            "/api/flows/:flowInstName/live_updaters",
+            routing::get(service::flows::get_live_updaters),
+        )
+        .route(
+            "/api/flows/:flowInstName/live_updaters/pause",
+            routing::post(service::flows::pause_live_updaters),
+        )
+        .route(
+            "/api/flows/:flowInstName/live_updaters/resume",
+            routing::post(service::flows::resume_live_updaters),
+        )
+        .route(
+            "/api/flows/:flowInstName/live_updaters/drain",
+            routing::post(service::flows::drain_live_updaters),
+        )
+        .route(
+            "/api/flows/:flowInstName/sources/:sourceName/reprocess",
+            routing::post(service::flows::reprocess_source_rows),
+        )
+        .route(
+            "/api/flows/:flowInstName/logic_changes",
+            routing::get(service::flows::analyze_logic_changes),
+        )
+        .route(
+            "/api/flows/:flowInstName/errors",
+            routing::get(service::flows::list_row_errors),
+        )
+        .route(
+            "/api/flows/:flowInstName/errors/redrive",
+            routing::post(service::flows::redrive_row_errors),
+        )
         .route(
             "/api/flows/:flowInstName/search",
             routing::get(service::search::search),
@@ -4,7 +4,7 @@ use crate::lib_context::LibContext;
 use crate::{base::schema::DataSchema, ops::interface::SourceExecutorListOptions};
 use crate::{
     execution::memoization,
-    execution::{row_indexer, stats},
+    execution::{logic_changes, reprocess, row_errors, row_indexer, stats},
 };
 use axum::{
     extract::{Path, State},
@@ -155,7 +155,7 @@ pub async fn evaluate_data(
             enable_cache: true,
             evaluation_only: true,
         },
-        &lib_context.pool,
+        &lib_context.state_store,
     )
     .await?
     .ok_or_else(|| api_error!("value not found for source at the specified key: {key:?}"))?;
@@ -166,16 +166,30 @@ pub async fn evaluate_data(
     }))
 }
 
+#[derive(Deserialize)]
+pub struct UpdateParams {
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default)]
+    dry_run_sample_size: usize,
+    #[serde(default)]
+    allow_mass_deletion: bool,
+}
+
 pub async fn update(
     Path(flow_name): Path<String>,
+    Query(query): Query<UpdateParams>,
     State(lib_context): State<Arc<LibContext>>,
 ) -> Result<Json<stats::IndexUpdateInfo>, ApiError> {
     let flow_ctx = lib_context.get_flow_context(&flow_name)?;
     let mut live_updater = execution::FlowLiveUpdater::start(
         flow_ctx.clone(),
-        &lib_context.pool,
+        &lib_context.state_store,
         execution::FlowLiveUpdaterOptions {
             live_mode: false,
+            dry_run: query.dry_run,
+            dry_run_sample_size: query.dry_run_sample_size,
+            allow_mass_deletion: query.allow_mass_deletion,
             ..Default::default()
         },
     )
@@ -183,3 +197,91 @@ pub async fn update(
     live_updater.wait().await?;
     Ok(Json(live_updater.index_update_info()))
 }
+
+pub async fn reprocess_source_rows(
+    Path((flow_name, source_name)): Path<(String, String)>,
+    State(lib_context): State<Arc<LibContext>>,
+    Json(options): Json<reprocess::ReprocessOptions>,
+) -> Result<Json<stats::IndexUpdateInfo>, ApiError> {
+    let flow_ctx = lib_context.get_flow_context(&flow_name)?;
+    let update_info = reprocess::reprocess_source_rows(
+        &flow_ctx,
+        &source_name,
+        options,
+        &lib_context.state_store,
+    )
+    .await?;
+    Ok(Json(update_info))
+}
+
+fn control_live_updaters(
+    flow_name: &str,
+    lib_context: &LibContext,
+    f: impl Fn(&execution::LiveUpdaterControl),
+) -> Result<Json<Vec<execution::LiveUpdaterStatus>>, ApiError> {
+    let flow_ctx = lib_context.get_flow_context(flow_name)?;
+    let statuses = flow_ctx
+        .live_updaters()
+        .iter()
+        .map(|live_updater| {
+            f(&live_updater.control);
+            live_updater.control.status()
+        })
+        .collect();
+    Ok(Json(statuses))
+}
+
+pub async fn get_live_updaters(
+    Path(flow_name): Path<String>,
+    State(lib_context): State<Arc<LibContext>>,
+) -> Result<Json<Vec<execution::LiveUpdaterStatus>>, ApiError> {
+    control_live_updaters(&flow_name, &lib_context, |_| {})
+}
+
+pub async fn pause_live_updaters(
+    Path(flow_name): Path<String>,
+    State(lib_context): State<Arc<LibContext>>,
+) -> Result<Json<Vec<execution::LiveUpdaterStatus>>, ApiError> {
+    control_live_updaters(&flow_name, &lib_context, |control| control.pause())
+}
+
+pub async fn resume_live_updaters(
+    Path(flow_name): Path<String>,
+    State(lib_context): State<Arc<LibContext>>,
+) -> Result<Json<Vec<execution::LiveUpdaterStatus>>, ApiError> {
+    control_live_updaters(&flow_name, &lib_context, |control| control.resume())
+}
+
+pub async fn drain_live_updaters(
+    Path(flow_name): Path<String>,
+    State(lib_context): State<Arc<LibContext>>,
+) -> Result<Json<Vec<execution::LiveUpdaterStatus>>, ApiError> {
+    control_live_updaters(&flow_name, &lib_context, |control| control.drain())
+}
+
+pub async fn list_row_errors(
+    Path(flow_name): Path<String>,
+    State(lib_context): State<Arc<LibContext>>,
+) -> Result<Json<Vec<row_errors::SourceRowErrors>>, ApiError> {
+    let flow_ctx = lib_context.get_flow_context(&flow_name)?;
+    let errors = row_errors::list_row_errors(&flow_ctx, &lib_context.state_store).await?;
+    Ok(Json(errors))
+}
+
+pub async fn redrive_row_errors(
+    Path(flow_name): Path<String>,
+    State(lib_context): State<Arc<LibContext>>,
+) -> Result<Json<stats::IndexUpdateInfo>, ApiError> {
+    let flow_ctx = lib_context.get_flow_context(&flow_name)?;
+    let update_info = row_errors::redrive_row_errors(&flow_ctx, &lib_context.state_store).await?;
+    Ok(Json(update_info))
+}
+
+pub async fn analyze_logic_changes(
+    Path(flow_name): Path<String>,
+    State(lib_context): State<Arc<LibContext>>,
+) -> Result<Json<Vec<logic_changes::SourceLogicChanges>>, ApiError> {
+    let flow_ctx = lib_context.get_flow_context(&flow_name)?;
+    let changes = logic_changes::analyze_logic_changes(&flow_ctx, &lib_context.state_store).await?;
+    Ok(Json(changes))
+}
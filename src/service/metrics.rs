@@ -0,0 +1,575 @@
+use crate::prelude::*;
+
+use crate::execution::stats;
+use crate::state_store::PoolStats;
+use axum::{extract::State, http::header, response::IntoResponse};
+use std::fmt::Write;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+struct MetricFamily {
+    typ: &'static str,
+    help: &'static str,
+    /// Values keyed by (name suffix, formatted labels). Samples with the same key are summed up.
+    samples: IndexMap<(&'static str, String), f64>,
+}
+
+/// Collects metrics and renders them in the Prometheus text exposition format.
+#[derive(Default)]
+struct MetricsWriter {
+    families: IndexMap<&'static str, MetricFamily>,
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels = labels
+        .iter()
+        .map(|(name, value)| format!("{name}=\"{}\"", escape_label_value(value)))
+        .join(",");
+    format!("{{{labels}}}")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+impl MetricsWriter {
+    fn add_sample(
+        &mut self,
+        name: &'static str,
+        typ: &'static str,
+        help: &'static str,
+        suffix: &'static str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        let family = self.families.entry(name).or_insert_with(|| MetricFamily {
+            typ,
+            help,
+            samples: IndexMap::new(),
+        });
+        *family
+            .samples
+            .entry((suffix, format_labels(labels)))
+            .or_default() += value;
+    }
+
+    fn counter(
+        &mut self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        self.add_sample(name, "counter", help, "", labels, value);
+    }
+
+    fn gauge(
+        &mut self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        self.add_sample(name, "gauge", help, "", labels, value);
+    }
+
+    fn histogram(
+        &mut self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        bounds: &[f64],
+        data: &stats::HistogramData,
+    ) {
+        let mut cumulative_count = 0;
+        for (bound, count) in bounds
+            .iter()
+            .copied()
+            .chain(std::iter::once(f64::INFINITY))
+            .zip(data.bucket_counts.iter())
+        {
+            cumulative_count += count;
+            let le = format_value(bound);
+            let bucket_labels = labels
+                .iter()
+                .copied()
+                .chain(std::iter::once(("le", le.as_str())))
+                .collect::<Vec<_>>();
+            self.add_sample(
+                name,
+                "histogram",
+                help,
+                "_bucket",
+                &bucket_labels,
+                cumulative_count as f64,
+            );
+        }
+        self.add_sample(name, "histogram", help, "_sum", labels, data.sum);
+        self.add_sample(name, "histogram", help, "_count", labels, data.count as f64);
+    }
+
+    /// Values of a metric without name suffix, keyed by formatted labels.
+    fn values(&self, name: &str) -> IndexMap<String, f64> {
+        self.families
+            .get(name)
+            .map(|family| {
+                family
+                    .samples
+                    .iter()
+                    .filter(|((suffix, _), _)| suffix.is_empty())
+                    .map(|((_, labels), value)| (labels.clone(), *value))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn render(&self) -> String {
+        let mut output = String::new();
+        for (name, family) in self.families.iter() {
+            let _ = writeln!(output, "# HELP {name} {}", family.help);
+            let _ = writeln!(output, "# TYPE {name} {}", family.typ);
+            for ((suffix, labels), value) in family.samples.iter() {
+                let _ = writeln!(output, "{name}{suffix}{labels} {}", format_value(*value));
+            }
+        }
+        output
+    }
+}
+
+fn write_op_stats(
+    writer: &mut MetricsWriter,
+    flow_name: &str,
+    source_name: &str,
+    op_name: &str,
+    op_stats: &stats::OpStats,
+) {
+    let labels = [
+        ("flow", flow_name),
+        ("source", source_name),
+        ("op", op_name),
+    ];
+    let counters: [(&'static str, &'static str, &stats::Counter); 7] = [
+        (
+            "cocoindex_op_cache_hits_total",
+            "Evaluations skipped by reusing memoized results.",
+            &op_stats.num_cache_hits,
+        ),
+        (
+            "cocoindex_op_cache_misses_total",
+            "Evaluations performed as no memoized result is usable.",
+            &op_stats.num_cache_misses,
+        ),
+        (
+            "cocoindex_op_cache_expirations_total",
+            "Cache misses caused by expired memoized results.",
+            &op_stats.num_cache_expirations,
+        ),
+        (
+            "cocoindex_op_llm_attempts_total",
+            "Requests sent to LLM, including re-asks.",
+            &op_stats.num_llm_attempts,
+        ),
+        (
+            "cocoindex_op_llm_failures_total",
+            "Evaluations calling LLM that still failed after all attempts.",
+            &op_stats.num_llm_failures,
+        ),
+        (
+            "cocoindex_op_llm_prompt_tokens_total",
+            "Prompt tokens consumed by LLM requests.",
+            &op_stats.num_llm_prompt_tokens,
+        ),
+        (
+            "cocoindex_op_llm_completion_tokens_total",
+            "Completion tokens consumed by LLM requests.",
+            &op_stats.num_llm_completion_tokens,
+        ),
+    ];
+    for (name, help, counter) in counters {
+        if counter.get() > 0 {
+            writer.counter(name, help, &labels, counter.get() as f64);
+        }
+    }
+    let latency = op_stats.latency.data();
+    if latency.count > 0 {
+        writer.histogram(
+            "cocoindex_op_latency_seconds",
+            "Time taken by evaluating the transform op without memoized results.",
+            &labels,
+            op_stats.latency.bounds(),
+            &latency,
+        );
+    }
+
+    let target_labels = [
+        ("flow", flow_name),
+        ("source", source_name),
+        ("target", op_name),
+    ];
+    let num_target_upserts = op_stats.num_target_upserts.get();
+    let num_target_deletions = op_stats.num_target_deletions.get();
+    if num_target_upserts > 0 || num_target_deletions > 0 {
+        writer.counter(
+            "cocoindex_target_upserts_total",
+            "Entries upserted into the target.",
+            &target_labels,
+            num_target_upserts as f64,
+        );
+        writer.counter(
+            "cocoindex_target_deletions_total",
+            "Entries deleted from the target.",
+            &target_labels,
+            num_target_deletions as f64,
+        );
+    }
+    let apply_latency = op_stats.apply_latency.data();
+    if apply_latency.count > 0 {
+        writer.histogram(
+            "cocoindex_target_apply_latency_seconds",
+            "Time taken by applying a batch of mutations to the target.",
+            &target_labels,
+            op_stats.apply_latency.bounds(),
+            &apply_latency,
+        );
+        writer.histogram(
+            "cocoindex_target_apply_batch_size",
+            "Number of entries in each batch of mutations applied to the target.",
+            &target_labels,
+            op_stats.apply_batch_size.bounds(),
+            &op_stats.apply_batch_size.data(),
+        );
+    }
+}
+
+/// Counters of the source, accumulated over all updates of the flow.
+fn write_source_counters(
+    writer: &mut MetricsWriter,
+    flow_name: &str,
+    source_name: &str,
+    update_stats: &stats::UpdateStats,
+) {
+    let labels = [("flow", flow_name), ("source", source_name)];
+    let counters: [(&'static str, &'static str, &stats::Counter); 6] = [
+        (
+            "cocoindex_source_rows_listed_total",
+            "Source rows discovered by listing the source.",
+            &update_stats.num_listed_rows,
+        ),
+        (
+            "cocoindex_source_rows_skipped_total",
+            "Source rows skipped as they're unchanged.",
+            &update_stats.num_skipped,
+        ),
+        (
+            "cocoindex_source_rows_inserted_total",
+            "Source rows added.",
+            &update_stats.num_insertions,
+        ),
+        (
+            "cocoindex_source_rows_deleted_total",
+            "Source rows removed.",
+            &update_stats.num_deletions,
+        ),
+        (
+            "cocoindex_source_rows_reprocessed_total",
+            "Existing source rows processed again.",
+            &update_stats.num_repreocesses,
+        ),
+        (
+            "cocoindex_source_rows_failed_total",
+            "Source rows failed to process.",
+            &update_stats.num_errors,
+        ),
+    ];
+    for (name, help, counter) in counters {
+        writer.counter(name, help, &labels, counter.get() as f64);
+    }
+    if let Some(last_progress_time) = update_stats.last_progress_time.get() {
+        writer.gauge(
+            "cocoindex_source_last_progress_timestamp_seconds",
+            "Unix time when the last source row was done, no matter whether succeeded, skipped or failed.",
+            &labels,
+            last_progress_time
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+        );
+    }
+    for (op_name, op_stats) in update_stats.ops.snapshot() {
+        write_op_stats(writer, flow_name, source_name, &op_name, &op_stats);
+    }
+}
+
+/// Gauges of the source for a live updater. Samples of different live updaters are summed up.
+fn write_source_gauges(
+    writer: &mut MetricsWriter,
+    flow_name: &str,
+    source_name: &str,
+    update_stats: &stats::UpdateStats,
+) {
+    let labels = [("flow", flow_name), ("source", source_name)];
+    writer.gauge(
+        "cocoindex_source_rows_active",
+        "Source rows being processed.",
+        &labels,
+        update_stats.num_active_rows.get() as f64,
+    );
+    writer.gauge(
+        "cocoindex_source_rows_queued",
+        "Source rows waiting to be processed.",
+        &labels,
+        update_stats.num_queued_rows.get() as f64,
+    );
+    if let Some(rows_per_sec) = update_stats.throughput.rows_per_sec() {
+        writer.gauge(
+            "cocoindex_source_throughput_rows_per_second",
+            "Source rows processed per second recently.",
+            &labels,
+            rows_per_sec,
+        );
+    }
+}
+
+/// Derived from counters after they're summed up, as ratios cannot be summed.
+fn write_cache_hit_ratios(writer: &mut MetricsWriter) {
+    let num_hits = writer.values("cocoindex_op_cache_hits_total");
+    let num_misses = writer.values("cocoindex_op_cache_misses_total");
+    let mut ratios = IndexMap::new();
+    for labels in num_hits.keys().chain(num_misses.keys()) {
+        let num_hits = num_hits.get(labels).copied().unwrap_or_default();
+        let num_misses = num_misses.get(labels).copied().unwrap_or_default();
+        if num_hits + num_misses > 0.0 {
+            ratios.insert(labels.clone(), num_hits / (num_hits + num_misses));
+        }
+    }
+    if ratios.is_empty() {
+        return;
+    }
+    writer.families.insert(
+        "cocoindex_op_cache_hit_ratio",
+        MetricFamily {
+            typ: "gauge",
+            help: "Ratio of evaluations reusing memoized results.",
+            samples: ratios
+                .into_iter()
+                .map(|(labels, ratio)| (("", labels), ratio))
+                .collect(),
+        },
+    );
+}
+
+fn write_pool_stats(writer: &mut MetricsWriter, pool_stats: PoolStats) {
+    let size = pool_stats.size as f64;
+    let num_idle = pool_stats.num_idle as f64;
+    let max_connections = pool_stats.max_connections as f64;
+    writer.gauge(
+        "cocoindex_db_pool_connections",
+        "Connections currently open in the pool of the internal state store.",
+        &[],
+        size,
+    );
+    writer.gauge(
+        "cocoindex_db_pool_idle_connections",
+        "Idle connections in the pool of the internal state store.",
+        &[],
+        num_idle,
+    );
+    writer.gauge(
+        "cocoindex_db_pool_max_connections",
+        "Max number of connections of the pool of the internal state store.",
+        &[],
+        max_connections,
+    );
+    if max_connections > 0.0 {
+        writer.gauge(
+            "cocoindex_db_pool_saturation",
+            "Ratio of connections of the pool of the internal state store in use.",
+            &[],
+            (size - num_idle) / max_connections,
+        );
+    }
+}
+
+pub fn render_metrics(lib_context: &LibContext) -> String {
+    let mut writer = MetricsWriter::default();
+    let flows = lib_context
+        .flows
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, flow_ctx)| (name.clone(), flow_ctx.clone()))
+        .collect::<Vec<_>>();
+    for (flow_name, flow_ctx) in flows {
+        let live_updaters = flow_ctx.live_updaters();
+        for status in [
+            execution::LiveUpdaterStatus::Running,
+            execution::LiveUpdaterStatus::Paused,
+            execution::LiveUpdaterStatus::Draining,
+        ] {
+            let status_name = format!("{status:?}").to_lowercase();
+            writer.gauge(
+                "cocoindex_live_updaters",
+                "Live updaters of the flow, by status.",
+                &[("flow", &flow_name), ("status", &status_name)],
+                live_updaters
+                    .iter()
+                    .filter(|live_updater| live_updater.control.status() == status)
+                    .count() as f64,
+            );
+        }
+        let import_ops = &flow_ctx.flow.flow_instance.import_ops;
+        for (import_op, update_stats) in import_ops
+            .iter()
+            .zip(flow_ctx.cumulative_update_stats().iter())
+        {
+            write_source_counters(&mut writer, &flow_name, &import_op.name, update_stats);
+        }
+        for live_updater in live_updaters.iter() {
+            for (import_op, update_stats) in import_ops
+                .iter()
+                .zip(live_updater.sources_update_stats.iter())
+            {
+                write_source_gauges(&mut writer, &flow_name, &import_op.name, update_stats);
+            }
+        }
+    }
+    write_cache_hit_ratios(&mut writer);
+    write_pool_stats(&mut writer, lib_context.state_store.pool_stats());
+    writer.render()
+}
+
+pub async fn get_metrics(State(lib_context): State<Arc<LibContext>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, CONTENT_TYPE)],
+        render_metrics(&lib_context),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_counters_and_gauges() {
+        let mut writer = MetricsWriter::default();
+        writer.counter("requests_total", "Requests.", &[("flow", "f1")], 2.0);
+        writer.gauge("queue_size", "Queue size.", &[], 3.0);
+        // Samples with the same labels are summed up.
+        writer.counter("requests_total", "Requests.", &[("flow", "f1")], 5.0);
+        writer.counter("requests_total", "Requests.", &[("flow", "f2")], 1.0);
+        assert_eq!(
+            writer.render(),
+            "# HELP requests_total Requests.\n\
+             # TYPE requests_total counter\n\
+             requests_total{flow=\"f1\"} 7\n\
+             requests_total{flow=\"f2\"} 1\n\
+             # HELP queue_size Queue size.\n\
+             # TYPE queue_size gauge\n\
+             queue_size 3\n"
+        );
+    }
+
+    #[test]
+    fn escape_labels() {
+        assert_eq!(
+            format_labels(&[("a", "x\"y\\z\nw"), ("b", "")]),
+            r#"{a="x\"y\\z\nw",b=""}"#
+        );
+        assert_eq!(format_labels(&[]), "");
+    }
+
+    #[test]
+    fn render_histogram() {
+        let histogram = stats::Histogram::<stats::BatchSizeBuckets>::default();
+        histogram.observe(1.0);
+        histogram.observe(3.0);
+        histogram.observe(9999.0);
+        let mut writer = MetricsWriter::default();
+        writer.histogram(
+            "batch_size",
+            "Batch sizes.",
+            &[("target", "t1")],
+            histogram.bounds(),
+            &histogram.data(),
+        );
+        let output = writer.render();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "# HELP batch_size Batch sizes.");
+        assert_eq!(lines[1], "# TYPE batch_size histogram");
+        // Buckets are cumulative.
+        assert_eq!(lines[2], r#"batch_size_bucket{target="t1",le="1"} 1"#);
+        assert_eq!(lines[3], r#"batch_size_bucket{target="t1",le="2"} 1"#);
+        assert_eq!(lines[4], r#"batch_size_bucket{target="t1",le="5"} 2"#);
+        let num_buckets = histogram.bounds().len() + 1;
+        assert_eq!(
+            lines[1 + num_buckets],
+            r#"batch_size_bucket{target="t1",le="+Inf"} 3"#
+        );
+        assert_eq!(
+            &lines[2 + num_buckets..],
+            &[
+                r#"batch_size_sum{target="t1"} 10003"#,
+                r#"batch_size_count{target="t1"} 3"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn cache_hit_ratios() {
+        let mut writer = MetricsWriter::default();
+        let op_stats = stats::OpStats::default();
+        op_stats.num_cache_hits.inc(3);
+        op_stats.num_cache_misses.inc(1);
+        write_op_stats(&mut writer, "f1", "s1", "op1", &op_stats);
+        write_op_stats(&mut writer, "f1", "s1", "op1", &op_stats);
+        write_cache_hit_ratios(&mut writer);
+        let labels = r#"{flow="f1",source="s1",op="op1"}"#;
+        assert_eq!(writer.values("cocoindex_op_cache_hits_total")[labels], 6.0);
+        assert_eq!(writer.values("cocoindex_op_cache_hit_ratio")[labels], 0.75);
+    }
+
+    #[test]
+    fn source_counters_and_gauges() {
+        let update_stats = stats::UpdateStats::default();
+        update_stats.num_insertions.inc(4);
+        update_stats.num_queued_rows.inc(2);
+        let mut writer = MetricsWriter::default();
+        write_source_counters(&mut writer, "f1", "s1", &update_stats);
+        assert!(writer
+            .values("cocoindex_source_last_progress_timestamp_seconds")
+            .is_empty());
+        write_source_gauges(&mut writer, "f1", "s1", &update_stats);
+        write_source_gauges(&mut writer, "f1", "s1", &update_stats);
+
+        let labels = r#"{flow="f1",source="s1"}"#;
+        assert_eq!(
+            writer.values("cocoindex_source_rows_inserted_total")[labels],
+            4.0
+        );
+        assert_eq!(writer.values("cocoindex_source_rows_queued")[labels], 4.0);
+
+        update_stats.last_progress_time.mark();
+        let mut writer = MetricsWriter::default();
+        write_source_counters(&mut writer, "f1", "s1", &update_stats);
+        let timestamp = writer.values("cocoindex_source_last_progress_timestamp_seconds")[labels];
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64();
+        assert!(timestamp > 0.0 && timestamp <= now, "got {timestamp}");
+    }
+}
@@ -1,3 +1,4 @@
 pub(crate) mod error;
 pub(crate) mod flows;
+pub(crate) mod metrics;
 pub(crate) mod search;
@@ -1,6 +1,22 @@
 use serde::Deserialize;
 
+/// Settings of exporting tracing spans, e.g. of processing source rows.
+#[derive(Deserialize, Debug, Default)]
+pub struct TracingSettings {
+    /// Endpoint of an OTLP/HTTP collector receiving spans, e.g. `http://localhost:4318/v1/traces`.
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+    /// Path of a file to append spans to, one JSON object per line.
+    #[serde(default)]
+    pub file_path: Option<String>,
+    /// Name of the service reported to the OTLP collector. Defaults to `cocoindex`.
+    #[serde(default)]
+    pub service_name: Option<String>,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Settings {
     pub database_url: String,
+    #[serde(default)]
+    pub tracing: Option<TracingSettings>,
 }
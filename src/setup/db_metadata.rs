@@ -1,11 +1,10 @@
 use crate::prelude::*;
 
 use super::{ResourceSetupInfo, ResourceSetupStatusCheck, SetupChangeType, StateChange};
+use crate::state_store::{SharedStateStore, StateStoreTxn, SETUP_METADATA_TABLE_NAME};
 use crate::utils::db::WriteAction;
 use axum::http::StatusCode;
-use sqlx::PgPool;
 
-const SETUP_METADATA_TABLE_NAME: &str = "cocoindex_setup_metadata";
 pub const FLOW_VERSION_RESOURCE_TYPE: &str = "__FlowVersion";
 
 #[derive(sqlx::FromRow, Debug)]
@@ -25,32 +24,6 @@ pub fn parse_flow_version(state: &Option<serde_json::Value>) -> Option<u64> {
     }
 }
 
-/// Returns None if metadata table doesn't exist.
-pub async fn read_setup_metadata(pool: &PgPool) -> Result<Option<Vec<SetupMetadataRecord>>> {
-    let mut db_conn = pool.acquire().await?;
-    let query_str = format!(
-        "SELECT flow_name, resource_type, key, state, staging_changes FROM {SETUP_METADATA_TABLE_NAME}",
-    );
-    let metadata = sqlx::query_as(&query_str).fetch_all(&mut *db_conn).await;
-    let result = match metadata {
-        Ok(metadata) => Some(metadata),
-        Err(err) => {
-            let exists: Option<bool> = sqlx::query_scalar(
-                "SELECT EXISTS (SELECT 1 FROM pg_tables WHERE schemaname = 'public' AND tablename = $1)",
-            )
-            .bind(SETUP_METADATA_TABLE_NAME)
-            .fetch_one(&mut *db_conn)
-            .await?;
-            if !exists.unwrap_or(false) {
-                None
-            } else {
-                return Err(err.into());
-            }
-        }
-    };
-    Ok(result)
-}
-
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ResourceTypeKey {
     pub resource_type: String,
@@ -70,15 +43,9 @@ static VERSION_RESOURCE_TYPE_ID: LazyLock<ResourceTypeKey> = LazyLock::new(|| Re
 
 async fn read_metadata_records_for_flow(
     flow_name: &str,
-    db_executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    txn: &mut dyn StateStoreTxn,
 ) -> Result<HashMap<ResourceTypeKey, SetupMetadataRecord>> {
-    let query_str = format!(
-        "SELECT flow_name, resource_type, key, state, staging_changes FROM {SETUP_METADATA_TABLE_NAME} WHERE flow_name = $1",
-    );
-    let metadata: Vec<SetupMetadataRecord> = sqlx::query_as(&query_str)
-        .bind(flow_name)
-        .fetch_all(db_executor)
-        .await?;
+    let metadata = txn.read_setup_metadata_for_flow(flow_name).await?;
     let result = metadata
         .into_iter()
         .map(|m| {
@@ -94,99 +61,14 @@ async fn read_metadata_records_for_flow(
     Ok(result)
 }
 
-async fn read_state(
-    flow_name: &str,
-    type_id: &ResourceTypeKey,
-    db_executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
-) -> Result<Option<serde_json::Value>> {
-    let query_str = format!(
-        "SELECT state FROM {SETUP_METADATA_TABLE_NAME} WHERE flow_name = $1 AND resource_type = $2 AND key = $3",
-    );
-    let state: Option<serde_json::Value> = sqlx::query_scalar(&query_str)
-        .bind(flow_name)
-        .bind(&type_id.resource_type)
-        .bind(&type_id.key)
-        .fetch_optional(db_executor)
-        .await?;
-    Ok(state)
-}
-
-async fn upsert_staging_changes(
-    flow_name: &str,
-    type_id: &ResourceTypeKey,
-    staging_changes: Vec<StateChange<serde_json::Value>>,
-    db_executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
-    action: WriteAction,
-) -> Result<()> {
-    let query_str = match action {
-        WriteAction::Insert => format!(
-            "INSERT INTO {SETUP_METADATA_TABLE_NAME} (flow_name, resource_type, key, staging_changes) VALUES ($1, $2, $3, $4)",
-        ),
-        WriteAction::Update => format!(
-            "UPDATE {SETUP_METADATA_TABLE_NAME} SET staging_changes = $4 WHERE flow_name = $1 AND resource_type = $2 AND key = $3",
-        ),
-    };
-    sqlx::query(&query_str)
-        .bind(flow_name)
-        .bind(&type_id.resource_type)
-        .bind(&type_id.key)
-        .bind(sqlx::types::Json(staging_changes))
-        .execute(db_executor)
-        .await?;
-    Ok(())
-}
-
-async fn upsert_state(
-    flow_name: &str,
-    type_id: &ResourceTypeKey,
-    state: serde_json::Value,
-    action: WriteAction,
-    db_executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
-) -> Result<()> {
-    let query_str = match action {
-        WriteAction::Insert => format!(
-            "INSERT INTO {SETUP_METADATA_TABLE_NAME} (flow_name, resource_type, key, state, staging_changes) VALUES ($1, $2, $3, $4, $5)",
-        ),
-        WriteAction::Update => format!(
-            "UPDATE {SETUP_METADATA_TABLE_NAME} SET state = $4, staging_changes = $5 WHERE flow_name = $1 AND resource_type = $2 AND key = $3",
-        ),
-    };
-    sqlx::query(&query_str)
-        .bind(flow_name)
-        .bind(&type_id.resource_type)
-        .bind(&type_id.key)
-        .bind(sqlx::types::Json(state))
-        .bind(sqlx::types::Json(Vec::<serde_json::Value>::new()))
-        .execute(db_executor)
-        .await?;
-    Ok(())
-}
-
-async fn delete_state(
-    flow_name: &str,
-    type_id: &ResourceTypeKey,
-    db_executor: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
-) -> Result<()> {
-    let query_str = format!(
-        "DELETE FROM {SETUP_METADATA_TABLE_NAME} WHERE flow_name = $1 AND resource_type = $2 AND key = $3",
-    );
-    sqlx::query(&query_str)
-        .bind(flow_name)
-        .bind(&type_id.resource_type)
-        .bind(&type_id.key)
-        .execute(db_executor)
-        .await?;
-    Ok(())
-}
-
 pub async fn stage_changes_for_flow(
     flow_name: &str,
     seen_metadata_version: Option<u64>,
     state_updates: &HashMap<ResourceTypeKey, Option<serde_json::Value>>,
-    pool: &PgPool,
+    state_store: &SharedStateStore,
 ) -> Result<u64> {
-    let mut txn = pool.begin().await?;
-    let mut existing_records = read_metadata_records_for_flow(flow_name, &mut *txn).await?;
+    let mut txn = state_store.begin().await?;
+    let mut existing_records = read_metadata_records_for_flow(flow_name, txn.as_mut()).await?;
     let latest_metadata_version = existing_records
         .get(&VERSION_RESOURCE_TYPE_ID)
         .and_then(|m| parse_flow_version(&m.state));
@@ -197,7 +79,7 @@ pub async fn stage_changes_for_flow(
         ))?;
     }
     let new_metadata_version = seen_metadata_version.unwrap_or_default() + 1;
-    upsert_state(
+    txn.upsert_setup_state(
         flow_name,
         &VERSION_RESOURCE_TYPE_ID,
         serde_json::Value::Number(new_metadata_version.into()),
@@ -206,7 +88,6 @@ pub async fn stage_changes_for_flow(
         } else {
             WriteAction::Insert
         },
-        &mut *txn,
     )
     .await?;
 
@@ -221,11 +102,10 @@ pub async fn stage_changes_for_flow(
                 if existing.staging_changes.0.iter().all(|c| c != &change) {
                     let mut staging_changes = existing.staging_changes.0;
                     staging_changes.push(change);
-                    upsert_staging_changes(
+                    txn.upsert_setup_staging_changes(
                         flow_name,
                         type_id,
                         staging_changes,
-                        &mut *txn,
                         WriteAction::Update,
                     )
                     .await?;
@@ -233,11 +113,10 @@ pub async fn stage_changes_for_flow(
             }
             None => {
                 if desired_state.is_some() {
-                    upsert_staging_changes(
+                    txn.upsert_setup_staging_changes(
                         flow_name,
                         type_id,
                         vec![change],
-                        &mut *txn,
                         WriteAction::Insert,
                     )
                     .await?;
@@ -254,11 +133,13 @@ pub async fn commit_changes_for_flow(
     curr_metadata_version: u64,
     state_updates: HashMap<ResourceTypeKey, Option<serde_json::Value>>,
     delete_version: bool,
-    pool: &PgPool,
+    state_store: &SharedStateStore,
 ) -> Result<()> {
-    let mut txn = pool.begin().await?;
-    let latest_metadata_version =
-        parse_flow_version(&read_state(flow_name, &VERSION_RESOURCE_TYPE_ID, &mut *txn).await?);
+    let mut txn = state_store.begin().await?;
+    let latest_metadata_version = parse_flow_version(
+        &txn.read_setup_state(flow_name, &VERSION_RESOURCE_TYPE_ID)
+            .await?,
+    );
     if latest_metadata_version != Some(curr_metadata_version) {
         return Err(ApiError::new(
             "seen newer version in the metadata table",
@@ -268,22 +149,17 @@ pub async fn commit_changes_for_flow(
     for (type_id, desired_state) in state_updates {
         match desired_state {
             Some(desired_state) => {
-                upsert_state(
-                    flow_name,
-                    &type_id,
-                    desired_state,
-                    WriteAction::Update,
-                    &mut *txn,
-                )
-                .await?;
+                txn.upsert_setup_state(flow_name, &type_id, desired_state, WriteAction::Update)
+                    .await?;
             }
             None => {
-                delete_state(flow_name, &type_id, &mut *txn).await?;
+                txn.delete_setup_state(flow_name, &type_id).await?;
             }
         }
     }
     if delete_version {
-        delete_state(flow_name, &VERSION_RESOURCE_TYPE_ID, &mut *txn).await?;
+        txn.delete_setup_state(flow_name, &VERSION_RESOURCE_TYPE_ID)
+            .await?;
     }
     txn.commit().await?;
     Ok(())
@@ -329,20 +205,10 @@ impl ResourceSetupStatusCheck for MetadataTableSetup {
         if !self.metadata_table_missing {
             return Ok(());
         }
-        let pool = &get_lib_context()?.pool;
-        let query_str = format!(
-            "CREATE TABLE IF NOT EXISTS {SETUP_METADATA_TABLE_NAME} (
-                flow_name TEXT NOT NULL,
-                resource_type TEXT NOT NULL,
-                key JSONB NOT NULL,
-                state JSONB,
-                staging_changes JSONB NOT NULL,
-
-                PRIMARY KEY (flow_name, resource_type, key)
-            )
-        ",
-        );
-        sqlx::query(&query_str).execute(pool).await?;
+        get_lib_context()?
+            .state_store
+            .create_setup_metadata_table()
+            .await?;
         Ok(())
     }
 }
@@ -1,8 +1,8 @@
 use crate::{lib_context::get_auth_registry, prelude::*};
 
+use crate::state_store::SharedStateStore;
 use indexmap::IndexMap;
 use serde::de::DeserializeOwned;
-use sqlx::PgPool;
 use std::{
     borrow::Cow,
     collections::{BTreeMap, BTreeSet, HashMap, HashSet},
@@ -74,8 +74,10 @@ fn from_metadata_record<S: DeserializeOwned + Debug + Clone>(
     Ok(CombinedState { current, staging })
 }
 
-pub async fn get_existing_setup_state(pool: &PgPool) -> Result<AllSetupState<ExistingMode>> {
-    let setup_metadata_records = db_metadata::read_setup_metadata(pool).await?;
+pub async fn get_existing_setup_state(
+    state_store: &SharedStateStore,
+) -> Result<AllSetupState<ExistingMode>> {
+    let setup_metadata_records = state_store.read_setup_metadata().await?;
 
     let setup_metadata_records = if let Some(records) = setup_metadata_records {
         records
@@ -381,7 +383,7 @@ async fn maybe_update_resource_setup<K, S, C: ResourceSetupStatusCheck>(
 pub async fn apply_changes(
     write: &mut impl std::io::Write,
     status_check: &AllSetupStatusCheck,
-    pool: &PgPool,
+    state_store: &SharedStateStore,
 ) -> Result<()> {
     maybe_update_resource_setup(write, &status_check.metadata_table).await?;
 
@@ -450,7 +452,7 @@ pub async fn apply_changes(
             flow_name,
             flow_status.seen_flow_metadata_version,
             &state_updates,
-            pool,
+            state_store,
         )
         .await?;
 
@@ -466,7 +468,7 @@ pub async fn apply_changes(
             new_version_id,
             state_updates,
             is_deletion,
-            pool,
+            state_store,
         )
         .await?;
 
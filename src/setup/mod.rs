@@ -6,5 +6,6 @@ mod states;
 pub mod components;
 
 pub use auth_registry::AuthRegistry;
+pub use db_metadata::{ResourceTypeKey, SetupMetadataRecord};
 pub use driver::*;
 pub use states::*;
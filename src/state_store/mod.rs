@@ -0,0 +1,294 @@
+//! Storage of the internal state of CocoIndex, i.e. tracking info of source rows and setup metadata.
+//!
+//! Backed by Postgres or an embedded SQLite database, picked by the scheme of `database_url`.
+
+mod postgres;
+mod sqlite;
+
+use crate::prelude::*;
+
+use crate::execution::{
+    db_tracking::{
+        OpLogicFingerprints, SharedCacheEntry, SourceRowError, SourceRowsWithLogic,
+        SourceTrackingInfoForCommit, SourceTrackingInfoForPrecommit,
+        SourceTrackingInfoForProcessing, TrackedSourceKeyMetadata, TrackedTargetKeyForSource,
+    },
+    db_tracking_setup::TrackingTableSetupState,
+    memoization::StoredMemoizationInfo,
+};
+use crate::setup::{ResourceTypeKey, SetupMetadataRecord, StateChange};
+use crate::utils::db::WriteAction;
+use sqlx::PgPool;
+
+pub const SETUP_METADATA_TABLE_NAME: &str = "cocoindex_setup_metadata";
+
+pub type SharedStateStore = Arc<dyn StateStore>;
+
+/// Usage of the connection pool of the state store.
+pub struct PoolStats {
+    pub size: u32,
+    pub num_idle: usize,
+    pub max_connections: u32,
+}
+
+impl PoolStats {
+    fn of<DB: sqlx::Database>(pool: &sqlx::Pool<DB>) -> Self {
+        Self {
+            size: pool.size(),
+            num_idle: pool.num_idle(),
+            max_connections: pool.options().get_max_connections(),
+        }
+    }
+}
+
+#[async_trait]
+pub trait StateStore: Send + Sync {
+    fn pool_stats(&self) -> PoolStats;
+
+    /// The Postgres pool, if the state store is backed by Postgres.
+    /// Postgres targets without their own `database_url` share it.
+    fn pg_pool(&self) -> Option<&PgPool> {
+        None
+    }
+
+    async fn begin(&self) -> Result<Box<dyn StateStoreTxn>>;
+
+    /// Create or upgrade the tracking table and the tables living next to it.
+    async fn upgrade_tracking_table(
+        &self,
+        table_name: &str,
+        existing_version_id: i32,
+        target_version_id: i32,
+    ) -> Result<()>;
+
+    async fn rename_table_if_exists(&self, table_name: &str, new_table_name: &str) -> Result<()>;
+
+    async fn drop_table_if_exists(&self, table_name: &str) -> Result<()>;
+
+    async fn read_source_tracking_info_for_processing(
+        &self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Option<SourceTrackingInfoForProcessing>>;
+
+    fn list_tracked_source_key_metadata<'a>(
+        &'a self,
+        source_id: i32,
+        db_setup: &'a TrackingTableSetupState,
+    ) -> BoxStream<'a, Result<TrackedSourceKeyMetadata>>;
+
+    async fn upsert_source_row_error(
+        &self,
+        error: &SourceRowError,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()>;
+
+    async fn delete_source_row_error(
+        &self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()>;
+
+    async fn list_source_row_errors(
+        &self,
+        source_id: i32,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Vec<SourceRowError>>;
+
+    /// Lease the source key for the worker, if it's not leased by another worker or the existing lease expired.
+    /// Renews the lease if it's already held by the worker. Returns false if it's leased by another worker.
+    async fn try_lease_source_key(
+        &self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        worker_id: &str,
+        ttl: std::time::Duration,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<bool>;
+
+    async fn release_source_key_lease(
+        &self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        worker_id: &str,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()>;
+
+    async fn read_shared_cache_entry(
+        &self,
+        fingerprint: &str,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Option<SharedCacheEntry>>;
+
+    async fn upsert_shared_cache_entry(
+        &self,
+        fingerprint: &str,
+        value: &serde_json::Value,
+        time_micros: i64,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()>;
+
+    /// Delete entries of the shared cache not referenced by any source row, and created before `created_before_micros`.
+    /// Returns the number of deleted entries.
+    async fn delete_unreferenced_shared_cache_entries(
+        &self,
+        created_before_micros: i64,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<u64>;
+
+    /// Lock groups of the aggregated target until the returned transaction ends,
+    /// so rows of the same group are never recomputed concurrently by multiple processes.
+    /// Returns None if the state store is only used by a single process, where in-process locks are enough.
+    async fn lock_aggregation_groups(
+        &self,
+        target_id: i32,
+        group_keys_json: &[serde_json::Value],
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Option<Box<dyn StateStoreTxn>>>;
+
+    /// List contributions from all source rows to the group of the aggregated target.
+    async fn list_aggregation_contributions(
+        &self,
+        target_id: i32,
+        group_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Vec<serde_json::Value>>;
+
+    /// Count tracked rows of the source, grouped by the logic they were processed with.
+    async fn count_source_rows_by_logic(
+        &self,
+        source_id: i32,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Vec<SourceRowsWithLogic>>;
+
+    /// Returns None if metadata table doesn't exist.
+    async fn read_setup_metadata(&self) -> Result<Option<Vec<SetupMetadataRecord>>>;
+
+    async fn create_setup_metadata_table(&self) -> Result<()>;
+}
+
+/// A transaction on the state store. Rolled back if dropped without commit.
+#[async_trait]
+pub trait StateStoreTxn: Send {
+    /// Lock the tracking record of the source key until the end of the transaction,
+    /// so precommit and commit from multiple writers on the same key (including creating the record) are serialized.
+    async fn lock_source_tracking_info(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()>;
+
+    async fn read_source_tracking_info_for_precommit(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Option<SourceTrackingInfoForPrecommit>>;
+
+    async fn precommit_source_tracking_info(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        max_process_ordinal: i64,
+        staging_target_keys: TrackedTargetKeyForSource,
+        memoization_info: Option<&StoredMemoizationInfo>,
+        db_setup: &TrackingTableSetupState,
+        action: WriteAction,
+    ) -> Result<()>;
+
+    async fn read_source_tracking_info_for_commit(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Option<SourceTrackingInfoForCommit>>;
+
+    async fn commit_source_tracking_info(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        staging_target_keys: TrackedTargetKeyForSource,
+        processed_source_ordinal: Option<i64>,
+        logic_fingerprint: &[u8],
+        process_ordinal: i64,
+        process_time_micros: i64,
+        target_keys: TrackedTargetKeyForSource,
+        op_logic_fingerprints: &OpLogicFingerprints,
+        db_setup: &TrackingTableSetupState,
+        action: WriteAction,
+    ) -> Result<()>;
+
+    async fn delete_source_tracking_info(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()>;
+
+    /// Delete contributions of the source row to all aggregated targets.
+    /// Returns target IDs and group keys of the deleted contributions.
+    async fn delete_aggregation_contributions(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Vec<(i32, serde_json::Value)>>;
+
+    async fn insert_aggregation_contribution(
+        &mut self,
+        target_id: i32,
+        group_key_json: &serde_json::Value,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        contribution: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()>;
+
+    async fn read_setup_metadata_for_flow(
+        &mut self,
+        flow_name: &str,
+    ) -> Result<Vec<SetupMetadataRecord>>;
+
+    async fn read_setup_state(
+        &mut self,
+        flow_name: &str,
+        type_id: &ResourceTypeKey,
+    ) -> Result<Option<serde_json::Value>>;
+
+    async fn upsert_setup_staging_changes(
+        &mut self,
+        flow_name: &str,
+        type_id: &ResourceTypeKey,
+        staging_changes: Vec<StateChange<serde_json::Value>>,
+        action: WriteAction,
+    ) -> Result<()>;
+
+    async fn upsert_setup_state(
+        &mut self,
+        flow_name: &str,
+        type_id: &ResourceTypeKey,
+        state: serde_json::Value,
+        action: WriteAction,
+    ) -> Result<()>;
+
+    async fn delete_setup_state(
+        &mut self,
+        flow_name: &str,
+        type_id: &ResourceTypeKey,
+    ) -> Result<()>;
+
+    async fn commit(self: Box<Self>) -> Result<()>;
+}
+
+/// Connect to the state store. `sqlite:` URLs are for an embedded SQLite database file, others for Postgres.
+pub async fn connect(database_url: &str) -> Result<SharedStateStore> {
+    let state_store: SharedStateStore = if database_url.starts_with("sqlite:") {
+        Arc::new(sqlite::SqliteStateStore::connect(database_url).await?)
+    } else {
+        Arc::new(postgres::PostgresStateStore::connect(database_url).await?)
+    };
+    Ok(state_store)
+}
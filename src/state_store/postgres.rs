@@ -0,0 +1,766 @@
+use crate::prelude::*;
+
+use super::{PoolStats, StateStore, StateStoreTxn, SETUP_METADATA_TABLE_NAME};
+use crate::execution::{
+    db_tracking::{
+        OpLogicFingerprints, SharedCacheEntry, SourceRowError, SourceRowsWithLogic,
+        SourceTrackingInfoForCommit, SourceTrackingInfoForPrecommit,
+        SourceTrackingInfoForProcessing, TrackedSourceKeyMetadata, TrackedTargetKeyForSource,
+    },
+    db_tracking_setup::{
+        aggregation_table_name, error_table_name, lease_table_name, shared_cache_table_name,
+        TrackingTableSetupState,
+    },
+    memoization::StoredMemoizationInfo,
+};
+use crate::setup::{ResourceTypeKey, SetupMetadataRecord, StateChange};
+use crate::utils::db::WriteAction;
+use sqlx::PgPool;
+
+pub struct PostgresStateStore {
+    pool: PgPool,
+}
+
+impl PostgresStateStore {
+    pub async fn connect(database_url: &str) -> Result<Self> {
+        let pool = PgPool::connect(database_url).await?;
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl StateStore for PostgresStateStore {
+    fn pool_stats(&self) -> PoolStats {
+        PoolStats::of(&self.pool)
+    }
+
+    fn pg_pool(&self) -> Option<&PgPool> {
+        Some(&self.pool)
+    }
+
+    async fn begin(&self) -> Result<Box<dyn StateStoreTxn>> {
+        Ok(Box::new(PostgresStateStoreTxn {
+            txn: self.pool.begin().await?,
+        }))
+    }
+
+    async fn upgrade_tracking_table(
+        &self,
+        table_name: &str,
+        existing_version_id: i32,
+        target_version_id: i32,
+    ) -> Result<()> {
+        let pool = &self.pool;
+        if existing_version_id < 1 && target_version_id >= 1 {
+            let query = format!(
+                "CREATE TABLE IF NOT EXISTS {table_name} (
+                    source_id INTEGER NOT NULL,
+                    source_key JSONB NOT NULL,
+
+                    -- Update in the precommit phase: after evaluation done, before really applying the changes to the target storage.
+                    max_process_ordinal BIGINT NOT NULL,
+                    staging_target_keys JSONB NOT NULL,
+                    memoization_info JSONB,
+
+                    -- Update after applying the changes to the target storage.
+                    processed_source_ordinal BIGINT,
+                    process_logic_fingerprint BYTEA,
+                    process_ordinal BIGINT,
+                    process_time_micros BIGINT,
+                    target_keys JSONB,
+
+                    PRIMARY KEY (source_id, source_key)
+                );",
+            );
+            sqlx::query(&query).execute(pool).await?;
+        }
+        if existing_version_id < 2 && target_version_id >= 2 {
+            let query = format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    source_id INTEGER NOT NULL,
+                    source_key JSONB NOT NULL,
+
+                    op_name TEXT,
+                    error_message TEXT NOT NULL,
+                    num_attempts INTEGER NOT NULL,
+                    last_attempt_time_micros BIGINT NOT NULL,
+
+                    PRIMARY KEY (source_id, source_key)
+                );",
+                error_table_name(table_name)
+            );
+            sqlx::query(&query).execute(pool).await?;
+        }
+        if existing_version_id < 3 && target_version_id >= 3 {
+            let query = format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    fingerprint TEXT PRIMARY KEY,
+                    value JSONB NOT NULL,
+                    time_micros BIGINT NOT NULL
+                );",
+                shared_cache_table_name(table_name)
+            );
+            sqlx::query(&query).execute(pool).await?;
+            // For looking up rows referencing entries of the shared cache.
+            let query = format!(
+                "CREATE INDEX IF NOT EXISTS {table_name}__shared_cache_refs ON {table_name} USING GIN ((memoization_info->'shared_cache'));"
+            );
+            sqlx::query(&query).execute(pool).await?;
+        }
+        if existing_version_id < 4 && target_version_id >= 4 {
+            let query = format!(
+                "ALTER TABLE {table_name} ADD COLUMN IF NOT EXISTS process_op_fingerprints JSONB;"
+            );
+            sqlx::query(&query).execute(pool).await?;
+        }
+        if existing_version_id < 5 && target_version_id >= 5 {
+            let query = format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    source_id INTEGER NOT NULL,
+                    source_key JSONB NOT NULL,
+
+                    worker_id TEXT NOT NULL,
+                    expire_time TIMESTAMPTZ NOT NULL,
+
+                    PRIMARY KEY (source_id, source_key)
+                );",
+                lease_table_name(table_name)
+            );
+            sqlx::query(&query).execute(pool).await?;
+        }
+        if existing_version_id < 6 && target_version_id >= 6 {
+            let aggregation_table_name = aggregation_table_name(table_name);
+            let query = format!(
+                "CREATE TABLE IF NOT EXISTS {aggregation_table_name} (
+                    target_id INTEGER NOT NULL,
+                    group_key JSONB NOT NULL,
+                    source_id INTEGER NOT NULL,
+                    source_key JSONB NOT NULL,
+
+                    contribution JSONB NOT NULL,
+
+                    PRIMARY KEY (target_id, group_key, source_id, source_key)
+                );",
+            );
+            sqlx::query(&query).execute(pool).await?;
+            // For looking up contributions of a source row.
+            let query = format!(
+                "CREATE INDEX IF NOT EXISTS {aggregation_table_name}__source ON {aggregation_table_name} (source_id, source_key);"
+            );
+            sqlx::query(&query).execute(pool).await?;
+        }
+        Ok(())
+    }
+
+    async fn rename_table_if_exists(&self, table_name: &str, new_table_name: &str) -> Result<()> {
+        let query = format!("ALTER TABLE IF EXISTS {table_name} RENAME TO {new_table_name}");
+        sqlx::query(&query).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn drop_table_if_exists(&self, table_name: &str) -> Result<()> {
+        let query = format!("DROP TABLE IF EXISTS {table_name}");
+        sqlx::query(&query).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn read_source_tracking_info_for_processing(
+        &self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Option<SourceTrackingInfoForProcessing>> {
+        let query_str = format!(
+            "SELECT memoization_info, processed_source_ordinal, process_logic_fingerprint, process_op_fingerprints FROM {} WHERE source_id = $1 AND source_key = $2",
+            db_setup.table_name
+        );
+        let tracking_info = sqlx::query_as(&query_str)
+            .bind(source_id)
+            .bind(source_key_json)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(tracking_info)
+    }
+
+    fn list_tracked_source_key_metadata<'a>(
+        &'a self,
+        source_id: i32,
+        db_setup: &'a TrackingTableSetupState,
+    ) -> BoxStream<'a, Result<TrackedSourceKeyMetadata>> {
+        try_stream! {
+            let query_str = format!(
+                "SELECT source_key, processed_source_ordinal, process_logic_fingerprint, process_op_fingerprints FROM {} WHERE source_id = $1",
+                db_setup.table_name
+            );
+            let mut rows = sqlx::query_as::<_, TrackedSourceKeyMetadata>(&query_str)
+                .bind(source_id)
+                .fetch(&self.pool);
+            while let Some(row) = rows.next().await {
+                yield row?;
+            }
+        }
+        .boxed()
+    }
+
+    async fn upsert_source_row_error(
+        &self,
+        error: &SourceRowError,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()> {
+        let table_name = error_table_name(&db_setup.table_name);
+        let query_str = format!(
+            "INSERT INTO {table_name} (source_id, source_key, op_name, error_message, num_attempts, last_attempt_time_micros) VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (source_id, source_key) DO UPDATE SET
+                op_name = EXCLUDED.op_name,
+                error_message = EXCLUDED.error_message,
+                num_attempts = {table_name}.num_attempts + EXCLUDED.num_attempts,
+                last_attempt_time_micros = EXCLUDED.last_attempt_time_micros",
+        );
+        sqlx::query(&query_str)
+            .bind(error.source_id)
+            .bind(&error.source_key)
+            .bind(&error.op_name)
+            .bind(&error.error_message)
+            .bind(error.num_attempts)
+            .bind(error.last_attempt_time_micros)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_source_row_error(
+        &self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()> {
+        let query_str = format!(
+            "DELETE FROM {} WHERE source_id = $1 AND source_key = $2",
+            error_table_name(&db_setup.table_name)
+        );
+        sqlx::query(&query_str)
+            .bind(source_id)
+            .bind(source_key_json)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn list_source_row_errors(
+        &self,
+        source_id: i32,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Vec<SourceRowError>> {
+        let query_str = format!(
+            "SELECT source_id, source_key, op_name, error_message, num_attempts, last_attempt_time_micros FROM {} WHERE source_id = $1 ORDER BY last_attempt_time_micros DESC",
+            error_table_name(&db_setup.table_name)
+        );
+        let errors = sqlx::query_as(&query_str)
+            .bind(source_id)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(errors)
+    }
+
+    async fn try_lease_source_key(
+        &self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        worker_id: &str,
+        ttl: std::time::Duration,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<bool> {
+        // Expiration is based on the clock of the database, so it's not affected by clock skew between workers.
+        let table_name = lease_table_name(&db_setup.table_name);
+        let query_str = format!(
+            "INSERT INTO {table_name} (source_id, source_key, worker_id, expire_time) VALUES ($1, $2, $3, now() + make_interval(secs => $4))
+             ON CONFLICT (source_id, source_key) DO UPDATE SET
+                worker_id = EXCLUDED.worker_id,
+                expire_time = EXCLUDED.expire_time
+             WHERE {table_name}.worker_id = EXCLUDED.worker_id OR {table_name}.expire_time < now()",
+        );
+        let result = sqlx::query(&query_str)
+            .bind(source_id)
+            .bind(source_key_json)
+            .bind(worker_id)
+            .bind(ttl.as_secs_f64())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn release_source_key_lease(
+        &self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        worker_id: &str,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()> {
+        let query_str = format!(
+            "DELETE FROM {} WHERE source_id = $1 AND source_key = $2 AND worker_id = $3",
+            lease_table_name(&db_setup.table_name)
+        );
+        sqlx::query(&query_str)
+            .bind(source_id)
+            .bind(source_key_json)
+            .bind(worker_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn read_shared_cache_entry(
+        &self,
+        fingerprint: &str,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Option<SharedCacheEntry>> {
+        let query_str = format!(
+            "SELECT value, time_micros FROM {} WHERE fingerprint = $1",
+            shared_cache_table_name(&db_setup.table_name)
+        );
+        let entry = sqlx::query_as(&query_str)
+            .bind(fingerprint)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(entry)
+    }
+
+    async fn upsert_shared_cache_entry(
+        &self,
+        fingerprint: &str,
+        value: &serde_json::Value,
+        time_micros: i64,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()> {
+        let query_str = format!(
+            "INSERT INTO {} (fingerprint, value, time_micros) VALUES ($1, $2, $3)
+             ON CONFLICT (fingerprint) DO UPDATE SET value = EXCLUDED.value, time_micros = EXCLUDED.time_micros",
+            shared_cache_table_name(&db_setup.table_name)
+        );
+        sqlx::query(&query_str)
+            .bind(fingerprint)
+            .bind(value)
+            .bind(time_micros)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_unreferenced_shared_cache_entries(
+        &self,
+        created_before_micros: i64,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<u64> {
+        let query_str = format!(
+            "DELETE FROM {cache_table} c WHERE c.time_micros < $1 AND NOT EXISTS (
+                SELECT 1 FROM {tracking_table} t WHERE t.memoization_info->'shared_cache' ? c.fingerprint)",
+            cache_table = shared_cache_table_name(&db_setup.table_name),
+            tracking_table = db_setup.table_name,
+        );
+        let result = sqlx::query(&query_str)
+            .bind(created_before_micros)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn lock_aggregation_groups(
+        &self,
+        target_id: i32,
+        group_keys_json: &[serde_json::Value],
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Option<Box<dyn StateStoreTxn>>> {
+        // Locks are always acquired in the same order, so there's no deadlock.
+        let lock_keys: BTreeSet<String> = group_keys_json
+            .iter()
+            .map(|group_key_json| {
+                format!(
+                    "{}:aggregation:{target_id}:{group_key_json}",
+                    db_setup.table_name
+                )
+            })
+            .collect();
+        let mut txn = self.pool.begin().await?;
+        for lock_key in lock_keys {
+            sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended($1, 0))")
+                .bind(lock_key)
+                .execute(&mut *txn)
+                .await?;
+        }
+        Ok(Some(Box::new(PostgresStateStoreTxn { txn })))
+    }
+
+    async fn list_aggregation_contributions(
+        &self,
+        target_id: i32,
+        group_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Vec<serde_json::Value>> {
+        let query_str = format!(
+            "SELECT contribution FROM {} WHERE target_id = $1 AND group_key = $2 ORDER BY source_id, source_key",
+            aggregation_table_name(&db_setup.table_name)
+        );
+        let contributions = sqlx::query_scalar(&query_str)
+            .bind(target_id)
+            .bind(group_key_json)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(contributions)
+    }
+
+    async fn count_source_rows_by_logic(
+        &self,
+        source_id: i32,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Vec<SourceRowsWithLogic>> {
+        let query_str = format!(
+            "SELECT process_logic_fingerprint, process_op_fingerprints, COUNT(*) AS num_rows FROM {} WHERE source_id = $1 GROUP BY 1, 2",
+            db_setup.table_name
+        );
+        let groups = sqlx::query_as(&query_str)
+            .bind(source_id)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(groups)
+    }
+
+    async fn read_setup_metadata(&self) -> Result<Option<Vec<SetupMetadataRecord>>> {
+        let mut db_conn = self.pool.acquire().await?;
+        let query_str = format!(
+            "SELECT flow_name, resource_type, key, state, staging_changes FROM {SETUP_METADATA_TABLE_NAME}",
+        );
+        let metadata = sqlx::query_as(&query_str).fetch_all(&mut *db_conn).await;
+        let result = match metadata {
+            Ok(metadata) => Some(metadata),
+            Err(err) => {
+                let exists: Option<bool> = sqlx::query_scalar(
+                    "SELECT EXISTS (SELECT 1 FROM pg_tables WHERE schemaname = 'public' AND tablename = $1)",
+                )
+                .bind(SETUP_METADATA_TABLE_NAME)
+                .fetch_one(&mut *db_conn)
+                .await?;
+                if !exists.unwrap_or(false) {
+                    None
+                } else {
+                    return Err(err.into());
+                }
+            }
+        };
+        Ok(result)
+    }
+
+    async fn create_setup_metadata_table(&self) -> Result<()> {
+        let query_str = format!(
+            "CREATE TABLE IF NOT EXISTS {SETUP_METADATA_TABLE_NAME} (
+                flow_name TEXT NOT NULL,
+                resource_type TEXT NOT NULL,
+                key JSONB NOT NULL,
+                state JSONB,
+                staging_changes JSONB NOT NULL,
+
+                PRIMARY KEY (flow_name, resource_type, key)
+            )
+        ",
+        );
+        sqlx::query(&query_str).execute(&self.pool).await?;
+        Ok(())
+    }
+}
+
+struct PostgresStateStoreTxn {
+    txn: sqlx::Transaction<'static, sqlx::Postgres>,
+}
+
+#[async_trait]
+impl StateStoreTxn for PostgresStateStoreTxn {
+    async fn lock_source_tracking_info(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()> {
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended($1, 0))")
+            .bind(format!(
+                "{}:{source_id}:{source_key_json}",
+                db_setup.table_name
+            ))
+            .execute(&mut *self.txn)
+            .await?;
+        Ok(())
+    }
+
+    async fn read_source_tracking_info_for_precommit(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Option<SourceTrackingInfoForPrecommit>> {
+        let query_str = format!(
+            "SELECT max_process_ordinal, staging_target_keys, processed_source_ordinal, process_logic_fingerprint, process_ordinal, target_keys, process_op_fingerprints FROM {} WHERE source_id = $1 AND source_key = $2",
+            db_setup.table_name
+        );
+        let precommit_tracking_info = sqlx::query_as(&query_str)
+            .bind(source_id)
+            .bind(source_key_json)
+            .fetch_optional(&mut *self.txn)
+            .await?;
+
+        Ok(precommit_tracking_info)
+    }
+
+    async fn precommit_source_tracking_info(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        max_process_ordinal: i64,
+        staging_target_keys: TrackedTargetKeyForSource,
+        memoization_info: Option<&StoredMemoizationInfo>,
+        db_setup: &TrackingTableSetupState,
+        action: WriteAction,
+    ) -> Result<()> {
+        let query_str = match action {
+            WriteAction::Insert => format!(
+                "INSERT INTO {} (source_id, source_key, max_process_ordinal, staging_target_keys, memoization_info) VALUES ($1, $2, $3, $4, $5)",
+                db_setup.table_name),
+            WriteAction::Update => format!(
+                "UPDATE {} SET max_process_ordinal = $3, staging_target_keys = $4, memoization_info = $5 WHERE source_id = $1 AND source_key = $2",
+                db_setup.table_name
+            ),
+        };
+        sqlx::query(&query_str)
+            .bind(source_id) // $1
+            .bind(source_key_json) // $2
+            .bind(max_process_ordinal) // $3
+            .bind(sqlx::types::Json(staging_target_keys)) // $4
+            .bind(memoization_info.map(sqlx::types::Json)) // $5
+            .execute(&mut *self.txn)
+            .await?;
+        Ok(())
+    }
+
+    async fn read_source_tracking_info_for_commit(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Option<SourceTrackingInfoForCommit>> {
+        let query_str = format!(
+            "SELECT staging_target_keys, process_ordinal FROM {} WHERE source_id = $1 AND source_key = $2",
+            db_setup.table_name
+        );
+        let commit_tracking_info = sqlx::query_as(&query_str)
+            .bind(source_id)
+            .bind(source_key_json)
+            .fetch_optional(&mut *self.txn)
+            .await?;
+        Ok(commit_tracking_info)
+    }
+
+    async fn commit_source_tracking_info(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        staging_target_keys: TrackedTargetKeyForSource,
+        processed_source_ordinal: Option<i64>,
+        logic_fingerprint: &[u8],
+        process_ordinal: i64,
+        process_time_micros: i64,
+        target_keys: TrackedTargetKeyForSource,
+        op_logic_fingerprints: &OpLogicFingerprints,
+        db_setup: &TrackingTableSetupState,
+        action: WriteAction,
+    ) -> Result<()> {
+        let query_str = match action {
+            WriteAction::Insert => format!(
+                "INSERT INTO {} ( \
+                   source_id, source_key, \
+                   max_process_ordinal, staging_target_keys, \
+                   processed_source_ordinal, process_logic_fingerprint, process_ordinal, process_time_micros, target_keys, process_op_fingerprints) \
+                VALUES ($1, $2, $6 + 1, $3, $4, $5, $6, $7, $8, $9)",
+                db_setup.table_name
+            ),
+            WriteAction::Update => format!(
+                "UPDATE {} SET staging_target_keys = $3, processed_source_ordinal = $4, process_logic_fingerprint = $5, process_ordinal = $6, process_time_micros = $7, target_keys = $8, process_op_fingerprints = $9 WHERE source_id = $1 AND source_key = $2",
+                db_setup.table_name
+            ),
+        };
+        sqlx::query(&query_str)
+            .bind(source_id) // $1
+            .bind(source_key_json) // $2
+            .bind(sqlx::types::Json(staging_target_keys)) // $3
+            .bind(processed_source_ordinal) // $4
+            .bind(logic_fingerprint) // $5
+            .bind(process_ordinal) // $6
+            .bind(process_time_micros) // $7
+            .bind(sqlx::types::Json(target_keys)) // $8
+            .bind(sqlx::types::Json(op_logic_fingerprints)) // $9
+            .execute(&mut *self.txn)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_source_tracking_info(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()> {
+        let query_str = format!(
+            "DELETE FROM {} WHERE source_id = $1 AND source_key = $2",
+            db_setup.table_name
+        );
+        sqlx::query(&query_str)
+            .bind(source_id)
+            .bind(source_key_json)
+            .execute(&mut *self.txn)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_aggregation_contributions(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Vec<(i32, serde_json::Value)>> {
+        let query_str = format!(
+            "DELETE FROM {} WHERE source_id = $1 AND source_key = $2 RETURNING target_id, group_key",
+            aggregation_table_name(&db_setup.table_name)
+        );
+        let groups = sqlx::query_as(&query_str)
+            .bind(source_id)
+            .bind(source_key_json)
+            .fetch_all(&mut *self.txn)
+            .await?;
+        Ok(groups)
+    }
+
+    async fn insert_aggregation_contribution(
+        &mut self,
+        target_id: i32,
+        group_key_json: &serde_json::Value,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        contribution: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()> {
+        let query_str = format!(
+            "INSERT INTO {} (target_id, group_key, source_id, source_key, contribution) VALUES ($1, $2, $3, $4, $5)",
+            aggregation_table_name(&db_setup.table_name)
+        );
+        sqlx::query(&query_str)
+            .bind(target_id)
+            .bind(group_key_json)
+            .bind(source_id)
+            .bind(source_key_json)
+            .bind(contribution)
+            .execute(&mut *self.txn)
+            .await?;
+        Ok(())
+    }
+
+    async fn read_setup_metadata_for_flow(
+        &mut self,
+        flow_name: &str,
+    ) -> Result<Vec<SetupMetadataRecord>> {
+        let query_str = format!(
+            "SELECT flow_name, resource_type, key, state, staging_changes FROM {SETUP_METADATA_TABLE_NAME} WHERE flow_name = $1",
+        );
+        let metadata = sqlx::query_as(&query_str)
+            .bind(flow_name)
+            .fetch_all(&mut *self.txn)
+            .await?;
+        Ok(metadata)
+    }
+
+    async fn read_setup_state(
+        &mut self,
+        flow_name: &str,
+        type_id: &ResourceTypeKey,
+    ) -> Result<Option<serde_json::Value>> {
+        let query_str = format!(
+            "SELECT state FROM {SETUP_METADATA_TABLE_NAME} WHERE flow_name = $1 AND resource_type = $2 AND key = $3",
+        );
+        let state: Option<serde_json::Value> = sqlx::query_scalar(&query_str)
+            .bind(flow_name)
+            .bind(&type_id.resource_type)
+            .bind(&type_id.key)
+            .fetch_optional(&mut *self.txn)
+            .await?;
+        Ok(state)
+    }
+
+    async fn upsert_setup_staging_changes(
+        &mut self,
+        flow_name: &str,
+        type_id: &ResourceTypeKey,
+        staging_changes: Vec<StateChange<serde_json::Value>>,
+        action: WriteAction,
+    ) -> Result<()> {
+        let query_str = match action {
+            WriteAction::Insert => format!(
+                "INSERT INTO {SETUP_METADATA_TABLE_NAME} (flow_name, resource_type, key, staging_changes) VALUES ($1, $2, $3, $4)",
+            ),
+            WriteAction::Update => format!(
+                "UPDATE {SETUP_METADATA_TABLE_NAME} SET staging_changes = $4 WHERE flow_name = $1 AND resource_type = $2 AND key = $3",
+            ),
+        };
+        sqlx::query(&query_str)
+            .bind(flow_name)
+            .bind(&type_id.resource_type)
+            .bind(&type_id.key)
+            .bind(sqlx::types::Json(staging_changes))
+            .execute(&mut *self.txn)
+            .await?;
+        Ok(())
+    }
+
+    async fn upsert_setup_state(
+        &mut self,
+        flow_name: &str,
+        type_id: &ResourceTypeKey,
+        state: serde_json::Value,
+        action: WriteAction,
+    ) -> Result<()> {
+        let query_str = match action {
+            WriteAction::Insert => format!(
+                "INSERT INTO {SETUP_METADATA_TABLE_NAME} (flow_name, resource_type, key, state, staging_changes) VALUES ($1, $2, $3, $4, $5)",
+            ),
+            WriteAction::Update => format!(
+                "UPDATE {SETUP_METADATA_TABLE_NAME} SET state = $4, staging_changes = $5 WHERE flow_name = $1 AND resource_type = $2 AND key = $3",
+            ),
+        };
+        sqlx::query(&query_str)
+            .bind(flow_name)
+            .bind(&type_id.resource_type)
+            .bind(&type_id.key)
+            .bind(sqlx::types::Json(state))
+            .bind(sqlx::types::Json(Vec::<serde_json::Value>::new()))
+            .execute(&mut *self.txn)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_setup_state(
+        &mut self,
+        flow_name: &str,
+        type_id: &ResourceTypeKey,
+    ) -> Result<()> {
+        let query_str = format!(
+            "DELETE FROM {SETUP_METADATA_TABLE_NAME} WHERE flow_name = $1 AND resource_type = $2 AND key = $3",
+        );
+        sqlx::query(&query_str)
+            .bind(flow_name)
+            .bind(&type_id.resource_type)
+            .bind(&type_id.key)
+            .execute(&mut *self.txn)
+            .await?;
+        Ok(())
+    }
+
+    async fn commit(self: Box<Self>) -> Result<()> {
+        self.txn.commit().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,1245 @@
+use crate::prelude::*;
+
+use super::{PoolStats, StateStore, StateStoreTxn, SETUP_METADATA_TABLE_NAME};
+use crate::execution::{
+    db_tracking::{
+        OpLogicFingerprints, SharedCacheEntry, SourceRowError, SourceRowsWithLogic,
+        SourceTrackingInfoForCommit, SourceTrackingInfoForPrecommit,
+        SourceTrackingInfoForProcessing, TrackedSourceKeyMetadata, TrackedTargetKeyForSource,
+    },
+    db_tracking_setup::{
+        aggregation_table_name, error_table_name, lease_table_name, shared_cache_table_name,
+        TrackingTableSetupState,
+    },
+    memoization::StoredMemoizationInfo,
+};
+use crate::setup::{ResourceTypeKey, SetupMetadataRecord, StateChange};
+use crate::utils::db::WriteAction;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions};
+use std::str::FromStr;
+
+/// State store in an embedded SQLite database file, for small deployments.
+///
+/// All operations of a process go through a single connection, so its transactions are serialized.
+/// Transactions take the write lock on begin (`BEGIN IMMEDIATE`), so ones from other processes sharing the file
+/// wait for each other (up to the busy timeout), instead of failing with `SQLITE_BUSY` when upgrading a read lock.
+pub struct SqliteStateStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStateStore {
+    pub async fn connect(database_url: &str) -> Result<Self> {
+        let options = SqliteConnectOptions::from_str(database_url)?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await?;
+        Ok(Self { pool })
+    }
+
+    async fn table_exists(&self, table_name: &str) -> Result<bool> {
+        let exists: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+        )
+        .bind(table_name)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(exists)
+    }
+}
+
+#[async_trait]
+impl StateStore for SqliteStateStore {
+    fn pool_stats(&self) -> PoolStats {
+        PoolStats::of(&self.pool)
+    }
+
+    async fn begin(&self) -> Result<Box<dyn StateStoreTxn>> {
+        Ok(Box::new(SqliteStateStoreTxn {
+            txn: self.pool.begin_with("BEGIN IMMEDIATE").await?,
+        }))
+    }
+
+    async fn upgrade_tracking_table(
+        &self,
+        table_name: &str,
+        existing_version_id: i32,
+        target_version_id: i32,
+    ) -> Result<()> {
+        // SQLite support came with version 5 of the tracking table, so tables of all earlier versions are created at once.
+        let mut queries = vec![];
+        if existing_version_id < 1 && target_version_id >= 1 {
+            queries.extend([
+                format!(
+                    "CREATE TABLE IF NOT EXISTS {table_name} (
+                        source_id INTEGER NOT NULL,
+                        source_key TEXT NOT NULL,
+
+                        -- Update in the precommit phase: after evaluation done, before really applying the changes to the target storage.
+                        max_process_ordinal INTEGER NOT NULL,
+                        staging_target_keys TEXT NOT NULL,
+                        memoization_info TEXT,
+
+                        -- Update after applying the changes to the target storage.
+                        processed_source_ordinal INTEGER,
+                        process_logic_fingerprint BLOB,
+                        process_ordinal INTEGER,
+                        process_time_micros INTEGER,
+                        target_keys TEXT,
+                        process_op_fingerprints TEXT,
+
+                        PRIMARY KEY (source_id, source_key)
+                    );",
+                ),
+                format!(
+                    "CREATE TABLE IF NOT EXISTS {} (
+                        source_id INTEGER NOT NULL,
+                        source_key TEXT NOT NULL,
+
+                        op_name TEXT,
+                        error_message TEXT NOT NULL,
+                        num_attempts INTEGER NOT NULL,
+                        last_attempt_time_micros INTEGER NOT NULL,
+
+                        PRIMARY KEY (source_id, source_key)
+                    );",
+                    error_table_name(table_name)
+                ),
+                format!(
+                    "CREATE TABLE IF NOT EXISTS {} (
+                        fingerprint TEXT PRIMARY KEY,
+                        value TEXT NOT NULL,
+                        time_micros INTEGER NOT NULL
+                    );",
+                    shared_cache_table_name(table_name)
+                ),
+                format!(
+                    "CREATE TABLE IF NOT EXISTS {} (
+                        source_id INTEGER NOT NULL,
+                        source_key TEXT NOT NULL,
+
+                        worker_id TEXT NOT NULL,
+                        expire_time_micros INTEGER NOT NULL,
+
+                        PRIMARY KEY (source_id, source_key)
+                    );",
+                    lease_table_name(table_name)
+                ),
+            ]);
+        }
+        if existing_version_id < 6 && target_version_id >= 6 {
+            let aggregation_table_name = aggregation_table_name(table_name);
+            queries.extend([
+                format!(
+                    "CREATE TABLE IF NOT EXISTS {aggregation_table_name} (
+                        target_id INTEGER NOT NULL,
+                        group_key TEXT NOT NULL,
+                        source_id INTEGER NOT NULL,
+                        source_key TEXT NOT NULL,
+
+                        contribution TEXT NOT NULL,
+
+                        PRIMARY KEY (target_id, group_key, source_id, source_key)
+                    );",
+                ),
+                // For looking up contributions of a source row.
+                format!(
+                    "CREATE INDEX IF NOT EXISTS {aggregation_table_name}__source ON {aggregation_table_name} (source_id, source_key);"
+                ),
+            ]);
+        }
+        let mut txn = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+        for query in queries {
+            sqlx::query(&query).execute(&mut *txn).await?;
+        }
+        txn.commit().await?;
+        Ok(())
+    }
+
+    async fn rename_table_if_exists(&self, table_name: &str, new_table_name: &str) -> Result<()> {
+        if self.table_exists(table_name).await? {
+            let query = format!("ALTER TABLE {table_name} RENAME TO {new_table_name}");
+            sqlx::query(&query).execute(&self.pool).await?;
+        }
+        Ok(())
+    }
+
+    async fn drop_table_if_exists(&self, table_name: &str) -> Result<()> {
+        let query = format!("DROP TABLE IF EXISTS {table_name}");
+        sqlx::query(&query).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn read_source_tracking_info_for_processing(
+        &self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Option<SourceTrackingInfoForProcessing>> {
+        let query_str = format!(
+            "SELECT memoization_info, processed_source_ordinal, process_logic_fingerprint, process_op_fingerprints FROM {} WHERE source_id = ?1 AND source_key = ?2",
+            db_setup.table_name
+        );
+        let tracking_info = sqlx::query_as(&query_str)
+            .bind(source_id)
+            .bind(source_key_json)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(tracking_info)
+    }
+
+    fn list_tracked_source_key_metadata<'a>(
+        &'a self,
+        source_id: i32,
+        db_setup: &'a TrackingTableSetupState,
+    ) -> BoxStream<'a, Result<TrackedSourceKeyMetadata>> {
+        try_stream! {
+            // Fetched at once instead of streamed, so the only connection isn't held while consuming the rows.
+            let query_str = format!(
+                "SELECT source_key, processed_source_ordinal, process_logic_fingerprint, process_op_fingerprints FROM {} WHERE source_id = ?1",
+                db_setup.table_name
+            );
+            let rows: Vec<TrackedSourceKeyMetadata> = sqlx::query_as(&query_str)
+                .bind(source_id)
+                .fetch_all(&self.pool)
+                .await?;
+            for row in rows {
+                yield row;
+            }
+        }
+        .boxed()
+    }
+
+    async fn upsert_source_row_error(
+        &self,
+        error: &SourceRowError,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()> {
+        let table_name = error_table_name(&db_setup.table_name);
+        let query_str = format!(
+            "INSERT INTO {table_name} (source_id, source_key, op_name, error_message, num_attempts, last_attempt_time_micros) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT (source_id, source_key) DO UPDATE SET
+                op_name = excluded.op_name,
+                error_message = excluded.error_message,
+                num_attempts = {table_name}.num_attempts + excluded.num_attempts,
+                last_attempt_time_micros = excluded.last_attempt_time_micros",
+        );
+        sqlx::query(&query_str)
+            .bind(error.source_id)
+            .bind(&error.source_key)
+            .bind(&error.op_name)
+            .bind(&error.error_message)
+            .bind(error.num_attempts)
+            .bind(error.last_attempt_time_micros)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_source_row_error(
+        &self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()> {
+        let query_str = format!(
+            "DELETE FROM {} WHERE source_id = ?1 AND source_key = ?2",
+            error_table_name(&db_setup.table_name)
+        );
+        sqlx::query(&query_str)
+            .bind(source_id)
+            .bind(source_key_json)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn list_source_row_errors(
+        &self,
+        source_id: i32,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Vec<SourceRowError>> {
+        let query_str = format!(
+            "SELECT source_id, source_key, op_name, error_message, num_attempts, last_attempt_time_micros FROM {} WHERE source_id = ?1 ORDER BY last_attempt_time_micros DESC",
+            error_table_name(&db_setup.table_name)
+        );
+        let errors = sqlx::query_as(&query_str)
+            .bind(source_id)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(errors)
+    }
+
+    async fn try_lease_source_key(
+        &self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        worker_id: &str,
+        ttl: std::time::Duration,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<bool> {
+        // All workers share the clock of the local machine.
+        let now_micros = Utc::now().timestamp_micros();
+        let table_name = lease_table_name(&db_setup.table_name);
+        let query_str = format!(
+            "INSERT INTO {table_name} (source_id, source_key, worker_id, expire_time_micros) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (source_id, source_key) DO UPDATE SET
+                worker_id = excluded.worker_id,
+                expire_time_micros = excluded.expire_time_micros
+             WHERE {table_name}.worker_id = excluded.worker_id OR {table_name}.expire_time_micros < ?5",
+        );
+        let result = sqlx::query(&query_str)
+            .bind(source_id)
+            .bind(source_key_json)
+            .bind(worker_id)
+            .bind(now_micros + ttl.as_micros() as i64)
+            .bind(now_micros)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn release_source_key_lease(
+        &self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        worker_id: &str,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()> {
+        let query_str = format!(
+            "DELETE FROM {} WHERE source_id = ?1 AND source_key = ?2 AND worker_id = ?3",
+            lease_table_name(&db_setup.table_name)
+        );
+        sqlx::query(&query_str)
+            .bind(source_id)
+            .bind(source_key_json)
+            .bind(worker_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn read_shared_cache_entry(
+        &self,
+        fingerprint: &str,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Option<SharedCacheEntry>> {
+        let query_str = format!(
+            "SELECT value, time_micros FROM {} WHERE fingerprint = ?1",
+            shared_cache_table_name(&db_setup.table_name)
+        );
+        let entry = sqlx::query_as(&query_str)
+            .bind(fingerprint)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(entry)
+    }
+
+    async fn upsert_shared_cache_entry(
+        &self,
+        fingerprint: &str,
+        value: &serde_json::Value,
+        time_micros: i64,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()> {
+        let query_str = format!(
+            "INSERT INTO {} (fingerprint, value, time_micros) VALUES (?1, ?2, ?3)
+             ON CONFLICT (fingerprint) DO UPDATE SET value = excluded.value, time_micros = excluded.time_micros",
+            shared_cache_table_name(&db_setup.table_name)
+        );
+        sqlx::query(&query_str)
+            .bind(fingerprint)
+            .bind(value)
+            .bind(time_micros)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_unreferenced_shared_cache_entries(
+        &self,
+        created_before_micros: i64,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<u64> {
+        let query_str = format!(
+            "DELETE FROM {cache_table} WHERE time_micros < ?1 AND NOT EXISTS (
+                SELECT 1 FROM {tracking_table} t, json_each(t.memoization_info, '$.shared_cache') r
+                WHERE r.value = {cache_table}.fingerprint)",
+            cache_table = shared_cache_table_name(&db_setup.table_name),
+            tracking_table = db_setup.table_name,
+        );
+        let result = sqlx::query(&query_str)
+            .bind(created_before_micros)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn lock_aggregation_groups(
+        &self,
+        _target_id: i32,
+        _group_keys_json: &[serde_json::Value],
+        _db_setup: &TrackingTableSetupState,
+    ) -> Result<Option<Box<dyn StateStoreTxn>>> {
+        Ok(None)
+    }
+
+    async fn list_aggregation_contributions(
+        &self,
+        target_id: i32,
+        group_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Vec<serde_json::Value>> {
+        let query_str = format!(
+            "SELECT contribution FROM {} WHERE target_id = ?1 AND group_key = ?2 ORDER BY source_id, source_key",
+            aggregation_table_name(&db_setup.table_name)
+        );
+        let contributions = sqlx::query_scalar(&query_str)
+            .bind(target_id)
+            .bind(group_key_json)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(contributions)
+    }
+
+    async fn count_source_rows_by_logic(
+        &self,
+        source_id: i32,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Vec<SourceRowsWithLogic>> {
+        let query_str = format!(
+            "SELECT process_logic_fingerprint, process_op_fingerprints, COUNT(*) AS num_rows FROM {} WHERE source_id = ?1 GROUP BY 1, 2",
+            db_setup.table_name
+        );
+        let groups = sqlx::query_as(&query_str)
+            .bind(source_id)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(groups)
+    }
+
+    async fn read_setup_metadata(&self) -> Result<Option<Vec<SetupMetadataRecord>>> {
+        if !self.table_exists(SETUP_METADATA_TABLE_NAME).await? {
+            return Ok(None);
+        }
+        let query_str = format!(
+            "SELECT flow_name, resource_type, key, state, staging_changes FROM {SETUP_METADATA_TABLE_NAME}",
+        );
+        let metadata = sqlx::query_as(&query_str).fetch_all(&self.pool).await?;
+        Ok(Some(metadata))
+    }
+
+    async fn create_setup_metadata_table(&self) -> Result<()> {
+        let query_str = format!(
+            "CREATE TABLE IF NOT EXISTS {SETUP_METADATA_TABLE_NAME} (
+                flow_name TEXT NOT NULL,
+                resource_type TEXT NOT NULL,
+                key TEXT NOT NULL,
+                state TEXT,
+                staging_changes TEXT NOT NULL,
+
+                PRIMARY KEY (flow_name, resource_type, key)
+            )
+        ",
+        );
+        sqlx::query(&query_str).execute(&self.pool).await?;
+        Ok(())
+    }
+}
+
+struct SqliteStateStoreTxn {
+    txn: sqlx::Transaction<'static, sqlx::Sqlite>,
+}
+
+#[async_trait]
+impl StateStoreTxn for SqliteStateStoreTxn {
+    async fn lock_source_tracking_info(
+        &mut self,
+        _source_id: i32,
+        _source_key_json: &serde_json::Value,
+        _db_setup: &TrackingTableSetupState,
+    ) -> Result<()> {
+        // The whole database is already locked for writes since the transaction began.
+        Ok(())
+    }
+
+    async fn read_source_tracking_info_for_precommit(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Option<SourceTrackingInfoForPrecommit>> {
+        let query_str = format!(
+            "SELECT max_process_ordinal, staging_target_keys, processed_source_ordinal, process_logic_fingerprint, process_ordinal, target_keys, process_op_fingerprints FROM {} WHERE source_id = ?1 AND source_key = ?2",
+            db_setup.table_name
+        );
+        let precommit_tracking_info = sqlx::query_as(&query_str)
+            .bind(source_id)
+            .bind(source_key_json)
+            .fetch_optional(&mut *self.txn)
+            .await?;
+
+        Ok(precommit_tracking_info)
+    }
+
+    async fn precommit_source_tracking_info(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        max_process_ordinal: i64,
+        staging_target_keys: TrackedTargetKeyForSource,
+        memoization_info: Option<&StoredMemoizationInfo>,
+        db_setup: &TrackingTableSetupState,
+        action: WriteAction,
+    ) -> Result<()> {
+        let query_str = match action {
+            WriteAction::Insert => format!(
+                "INSERT INTO {} (source_id, source_key, max_process_ordinal, staging_target_keys, memoization_info) VALUES (?1, ?2, ?3, ?4, ?5)",
+                db_setup.table_name),
+            WriteAction::Update => format!(
+                "UPDATE {} SET max_process_ordinal = ?3, staging_target_keys = ?4, memoization_info = ?5 WHERE source_id = ?1 AND source_key = ?2",
+                db_setup.table_name
+            ),
+        };
+        sqlx::query(&query_str)
+            .bind(source_id) // ?1
+            .bind(source_key_json) // ?2
+            .bind(max_process_ordinal) // ?3
+            .bind(sqlx::types::Json(staging_target_keys)) // ?4
+            .bind(memoization_info.map(sqlx::types::Json)) // ?5
+            .execute(&mut *self.txn)
+            .await?;
+        Ok(())
+    }
+
+    async fn read_source_tracking_info_for_commit(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Option<SourceTrackingInfoForCommit>> {
+        let query_str = format!(
+            "SELECT staging_target_keys, process_ordinal FROM {} WHERE source_id = ?1 AND source_key = ?2",
+            db_setup.table_name
+        );
+        let commit_tracking_info = sqlx::query_as(&query_str)
+            .bind(source_id)
+            .bind(source_key_json)
+            .fetch_optional(&mut *self.txn)
+            .await?;
+        Ok(commit_tracking_info)
+    }
+
+    async fn commit_source_tracking_info(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        staging_target_keys: TrackedTargetKeyForSource,
+        processed_source_ordinal: Option<i64>,
+        logic_fingerprint: &[u8],
+        process_ordinal: i64,
+        process_time_micros: i64,
+        target_keys: TrackedTargetKeyForSource,
+        op_logic_fingerprints: &OpLogicFingerprints,
+        db_setup: &TrackingTableSetupState,
+        action: WriteAction,
+    ) -> Result<()> {
+        let query_str = match action {
+            WriteAction::Insert => format!(
+                "INSERT INTO {} ( \
+                   source_id, source_key, \
+                   max_process_ordinal, staging_target_keys, \
+                   processed_source_ordinal, process_logic_fingerprint, process_ordinal, process_time_micros, target_keys, process_op_fingerprints) \
+                VALUES (?1, ?2, ?6 + 1, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                db_setup.table_name
+            ),
+            WriteAction::Update => format!(
+                "UPDATE {} SET staging_target_keys = ?3, processed_source_ordinal = ?4, process_logic_fingerprint = ?5, process_ordinal = ?6, process_time_micros = ?7, target_keys = ?8, process_op_fingerprints = ?9 WHERE source_id = ?1 AND source_key = ?2",
+                db_setup.table_name
+            ),
+        };
+        sqlx::query(&query_str)
+            .bind(source_id) // ?1
+            .bind(source_key_json) // ?2
+            .bind(sqlx::types::Json(staging_target_keys)) // ?3
+            .bind(processed_source_ordinal) // ?4
+            .bind(logic_fingerprint) // ?5
+            .bind(process_ordinal) // ?6
+            .bind(process_time_micros) // ?7
+            .bind(sqlx::types::Json(target_keys)) // ?8
+            .bind(sqlx::types::Json(op_logic_fingerprints)) // ?9
+            .execute(&mut *self.txn)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_source_tracking_info(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()> {
+        let query_str = format!(
+            "DELETE FROM {} WHERE source_id = ?1 AND source_key = ?2",
+            db_setup.table_name
+        );
+        sqlx::query(&query_str)
+            .bind(source_id)
+            .bind(source_key_json)
+            .execute(&mut *self.txn)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_aggregation_contributions(
+        &mut self,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<Vec<(i32, serde_json::Value)>> {
+        let query_str = format!(
+            "DELETE FROM {} WHERE source_id = ?1 AND source_key = ?2 RETURNING target_id, group_key",
+            aggregation_table_name(&db_setup.table_name)
+        );
+        let groups = sqlx::query_as(&query_str)
+            .bind(source_id)
+            .bind(source_key_json)
+            .fetch_all(&mut *self.txn)
+            .await?;
+        Ok(groups)
+    }
+
+    async fn insert_aggregation_contribution(
+        &mut self,
+        target_id: i32,
+        group_key_json: &serde_json::Value,
+        source_id: i32,
+        source_key_json: &serde_json::Value,
+        contribution: &serde_json::Value,
+        db_setup: &TrackingTableSetupState,
+    ) -> Result<()> {
+        let query_str = format!(
+            "INSERT INTO {} (target_id, group_key, source_id, source_key, contribution) VALUES (?1, ?2, ?3, ?4, ?5)",
+            aggregation_table_name(&db_setup.table_name)
+        );
+        sqlx::query(&query_str)
+            .bind(target_id)
+            .bind(group_key_json)
+            .bind(source_id)
+            .bind(source_key_json)
+            .bind(contribution)
+            .execute(&mut *self.txn)
+            .await?;
+        Ok(())
+    }
+
+    async fn read_setup_metadata_for_flow(
+        &mut self,
+        flow_name: &str,
+    ) -> Result<Vec<SetupMetadataRecord>> {
+        let query_str = format!(
+            "SELECT flow_name, resource_type, key, state, staging_changes FROM {SETUP_METADATA_TABLE_NAME} WHERE flow_name = ?1",
+        );
+        let metadata = sqlx::query_as(&query_str)
+            .bind(flow_name)
+            .fetch_all(&mut *self.txn)
+            .await?;
+        Ok(metadata)
+    }
+
+    async fn read_setup_state(
+        &mut self,
+        flow_name: &str,
+        type_id: &ResourceTypeKey,
+    ) -> Result<Option<serde_json::Value>> {
+        let query_str = format!(
+            "SELECT state FROM {SETUP_METADATA_TABLE_NAME} WHERE flow_name = ?1 AND resource_type = ?2 AND key = ?3",
+        );
+        let state: Option<Option<serde_json::Value>> = sqlx::query_scalar(&query_str)
+            .bind(flow_name)
+            .bind(&type_id.resource_type)
+            .bind(&type_id.key)
+            .fetch_optional(&mut *self.txn)
+            .await?;
+        Ok(state.flatten())
+    }
+
+    async fn upsert_setup_staging_changes(
+        &mut self,
+        flow_name: &str,
+        type_id: &ResourceTypeKey,
+        staging_changes: Vec<StateChange<serde_json::Value>>,
+        action: WriteAction,
+    ) -> Result<()> {
+        let query_str = match action {
+            WriteAction::Insert => format!(
+                "INSERT INTO {SETUP_METADATA_TABLE_NAME} (flow_name, resource_type, key, staging_changes) VALUES (?1, ?2, ?3, ?4)",
+            ),
+            WriteAction::Update => format!(
+                "UPDATE {SETUP_METADATA_TABLE_NAME} SET staging_changes = ?4 WHERE flow_name = ?1 AND resource_type = ?2 AND key = ?3",
+            ),
+        };
+        sqlx::query(&query_str)
+            .bind(flow_name)
+            .bind(&type_id.resource_type)
+            .bind(&type_id.key)
+            .bind(sqlx::types::Json(staging_changes))
+            .execute(&mut *self.txn)
+            .await?;
+        Ok(())
+    }
+
+    async fn upsert_setup_state(
+        &mut self,
+        flow_name: &str,
+        type_id: &ResourceTypeKey,
+        state: serde_json::Value,
+        action: WriteAction,
+    ) -> Result<()> {
+        let query_str = match action {
+            WriteAction::Insert => format!(
+                "INSERT INTO {SETUP_METADATA_TABLE_NAME} (flow_name, resource_type, key, state, staging_changes) VALUES (?1, ?2, ?3, ?4, ?5)",
+            ),
+            WriteAction::Update => format!(
+                "UPDATE {SETUP_METADATA_TABLE_NAME} SET state = ?4, staging_changes = ?5 WHERE flow_name = ?1 AND resource_type = ?2 AND key = ?3",
+            ),
+        };
+        sqlx::query(&query_str)
+            .bind(flow_name)
+            .bind(&type_id.resource_type)
+            .bind(&type_id.key)
+            .bind(sqlx::types::Json(state))
+            .bind(sqlx::types::Json(Vec::<serde_json::Value>::new()))
+            .execute(&mut *self.txn)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_setup_state(
+        &mut self,
+        flow_name: &str,
+        type_id: &ResourceTypeKey,
+    ) -> Result<()> {
+        let query_str = format!(
+            "DELETE FROM {SETUP_METADATA_TABLE_NAME} WHERE flow_name = ?1 AND resource_type = ?2 AND key = ?3",
+        );
+        sqlx::query(&query_str)
+            .bind(flow_name)
+            .bind(&type_id.resource_type)
+            .bind(&type_id.key)
+            .execute(&mut *self.txn)
+            .await?;
+        Ok(())
+    }
+
+    async fn commit(self: Box<Self>) -> Result<()> {
+        self.txn.commit().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::db_tracking_setup::CURRENT_TRACKING_TABLE_VERSION;
+    use crate::utils::fingerprint::Fingerprint;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    const SOURCE_ID: i32 = 1;
+
+    /// A store in a database file under the temp directory, removed when dropped.
+    struct TempStore {
+        store: SqliteStateStore,
+        path: PathBuf,
+    }
+
+    impl TempStore {
+        async fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "cocoindex_{name}_{}_{}.db",
+                std::process::id(),
+                Utc::now().timestamp_nanos_opt().unwrap_or_default()
+            ));
+            let store = SqliteStateStore::connect(&format!("sqlite:{}", path.display()))
+                .await
+                .unwrap();
+            Self { store, path }
+        }
+
+        /// A store with the tracking table and tables next to it created.
+        async fn with_tracking_table(name: &str) -> (Self, TrackingTableSetupState) {
+            let temp_store = Self::new(name).await;
+            let db_setup = TrackingTableSetupState {
+                table_name: "tracking".to_string(),
+                version_id: CURRENT_TRACKING_TABLE_VERSION,
+            };
+            temp_store
+                .store
+                .upgrade_tracking_table(&db_setup.table_name, 0, db_setup.version_id)
+                .await
+                .unwrap();
+            (temp_store, db_setup)
+        }
+    }
+
+    impl Drop for TempStore {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let mut path = self.path.clone().into_os_string();
+                path.push(suffix);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    fn target_keys(process_ordinal: i64) -> TrackedTargetKeyForSource {
+        vec![(
+            10,
+            vec![(json!(["a"]), process_ordinal, Some(Fingerprint([1; 16])))],
+        )]
+    }
+
+    #[tokio::test]
+    async fn precommit_and_commit_source_tracking_info() {
+        let (temp_store, db_setup) = TempStore::with_tracking_table("tracking").await;
+        let store = &temp_store.store;
+        let key = json!("key1");
+
+        // Precommit of a new row.
+        let mut txn = store.begin().await.unwrap();
+        txn.lock_source_tracking_info(SOURCE_ID, &key, &db_setup)
+            .await
+            .unwrap();
+        assert!(txn
+            .read_source_tracking_info_for_precommit(SOURCE_ID, &key, &db_setup)
+            .await
+            .unwrap()
+            .is_none());
+        txn.precommit_source_tracking_info(
+            SOURCE_ID,
+            &key,
+            1,
+            target_keys(1),
+            None,
+            &db_setup,
+            WriteAction::Insert,
+        )
+        .await
+        .unwrap();
+        txn.commit().await.unwrap();
+
+        let precommitted = store
+            .read_source_tracking_info_for_processing(SOURCE_ID, &key, &db_setup)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(precommitted.processed_source_ordinal, None);
+        assert_eq!(precommitted.process_logic_fingerprint, None);
+
+        // Commit after applying changes to targets.
+        let op_logic_fingerprints =
+            OpLogicFingerprints::from([("transform/op1".to_string(), Fingerprint([2; 16]))]);
+        let mut txn = store.begin().await.unwrap();
+        let commit_info = txn
+            .read_source_tracking_info_for_commit(SOURCE_ID, &key, &db_setup)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(commit_info.staging_target_keys.0, target_keys(1));
+        assert_eq!(commit_info.process_ordinal, None);
+        txn.commit_source_tracking_info(
+            SOURCE_ID,
+            &key,
+            vec![],
+            Some(3),
+            &[7; 16],
+            1,
+            1000,
+            target_keys(1),
+            &op_logic_fingerprints,
+            &db_setup,
+            WriteAction::Update,
+        )
+        .await
+        .unwrap();
+        txn.commit().await.unwrap();
+
+        let committed = store
+            .read_source_tracking_info_for_processing(SOURCE_ID, &key, &db_setup)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(committed.processed_source_ordinal, Some(3));
+        assert_eq!(committed.process_logic_fingerprint, Some(vec![7; 16]));
+        assert_eq!(
+            committed.process_op_fingerprints.map(|fps| fps.0),
+            Some(op_logic_fingerprints.clone())
+        );
+
+        let mut txn = store.begin().await.unwrap();
+        let precommit_info = txn
+            .read_source_tracking_info_for_precommit(SOURCE_ID, &key, &db_setup)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(precommit_info.max_process_ordinal, 1);
+        assert!(precommit_info.staging_target_keys.0.is_empty());
+        assert_eq!(precommit_info.process_ordinal, Some(1));
+        assert_eq!(
+            precommit_info.target_keys.map(|keys| keys.0),
+            Some(target_keys(1))
+        );
+        drop(txn);
+
+        let tracked_keys = store
+            .list_tracked_source_key_metadata(SOURCE_ID, &db_setup)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(tracked_keys.len(), 1);
+        assert_eq!(tracked_keys[0].source_key, key);
+        assert_eq!(tracked_keys[0].processed_source_ordinal, Some(3));
+
+        let groups = store
+            .count_source_rows_by_logic(SOURCE_ID, &db_setup)
+            .await
+            .unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].num_rows, 1);
+        assert_eq!(groups[0].process_logic_fingerprint, Some(vec![7; 16]));
+    }
+
+    #[tokio::test]
+    async fn commit_directly_and_delete_source_tracking_info() {
+        let (temp_store, db_setup) = TempStore::with_tracking_table("tracking_delete").await;
+        let store = &temp_store.store;
+        let key = json!({ "id": 2 });
+
+        let mut txn = store.begin().await.unwrap();
+        txn.commit_source_tracking_info(
+            SOURCE_ID,
+            &key,
+            vec![],
+            Some(5),
+            &[7; 16],
+            4,
+            1000,
+            target_keys(4),
+            &OpLogicFingerprints::new(),
+            &db_setup,
+            WriteAction::Insert,
+        )
+        .await
+        .unwrap();
+        txn.commit().await.unwrap();
+
+        let mut txn = store.begin().await.unwrap();
+        let precommit_info = txn
+            .read_source_tracking_info_for_precommit(SOURCE_ID, &key, &db_setup)
+            .await
+            .unwrap()
+            .unwrap();
+        // A row inserted at commit reserves the next process ordinal.
+        assert_eq!(precommit_info.max_process_ordinal, 5);
+        assert_eq!(precommit_info.processed_source_ordinal, Some(5));
+
+        // Changes are rolled back if the transaction is dropped without commit.
+        txn.delete_source_tracking_info(SOURCE_ID, &key, &db_setup)
+            .await
+            .unwrap();
+        drop(txn);
+        assert!(store
+            .read_source_tracking_info_for_processing(SOURCE_ID, &key, &db_setup)
+            .await
+            .unwrap()
+            .is_some());
+
+        let mut txn = store.begin().await.unwrap();
+        txn.delete_source_tracking_info(SOURCE_ID, &key, &db_setup)
+            .await
+            .unwrap();
+        txn.commit().await.unwrap();
+        assert!(store
+            .read_source_tracking_info_for_processing(SOURCE_ID, &key, &db_setup)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn source_row_errors() {
+        let (temp_store, db_setup) = TempStore::with_tracking_table("errors").await;
+        let store = &temp_store.store;
+        let error = |key: &str, num_attempts: i32, time_micros: i64| SourceRowError {
+            source_id: SOURCE_ID,
+            source_key: json!(key),
+            op_name: Some("op1".to_string()),
+            error_message: format!("failed at {time_micros}"),
+            num_attempts,
+            last_attempt_time_micros: time_micros,
+        };
+
+        store
+            .upsert_source_row_error(&error("key1", 2, 100), &db_setup)
+            .await
+            .unwrap();
+        store
+            .upsert_source_row_error(&error("key2", 1, 200), &db_setup)
+            .await
+            .unwrap();
+        // Attempts are accumulated, and the latest error message is kept.
+        store
+            .upsert_source_row_error(&error("key1", 3, 300), &db_setup)
+            .await
+            .unwrap();
+
+        let errors = store
+            .list_source_row_errors(SOURCE_ID, &db_setup)
+            .await
+            .unwrap();
+        let summary = errors
+            .iter()
+            .map(|e| {
+                (
+                    e.source_key.clone(),
+                    e.num_attempts,
+                    e.error_message.as_str(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (json!("key1"), 5, "failed at 300"),
+                (json!("key2"), 1, "failed at 200"),
+            ]
+        );
+
+        store
+            .delete_source_row_error(SOURCE_ID, &json!("key1"), &db_setup)
+            .await
+            .unwrap();
+        let errors = store
+            .list_source_row_errors(SOURCE_ID, &db_setup)
+            .await
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].source_key, json!("key2"));
+        assert!(store
+            .list_source_row_errors(SOURCE_ID + 1, &db_setup)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn setup_metadata() {
+        let temp_store = TempStore::new("setup_metadata").await;
+        let store = &temp_store.store;
+        assert!(store.read_setup_metadata().await.unwrap().is_none());
+        store.create_setup_metadata_table().await.unwrap();
+        assert_eq!(store.read_setup_metadata().await.unwrap().unwrap().len(), 0);
+
+        let type_id = ResourceTypeKey::new("Flow".to_string(), json!("flow1"));
+        let mut txn = store.begin().await.unwrap();
+        assert!(txn
+            .read_setup_state("flow1", &type_id)
+            .await
+            .unwrap()
+            .is_none());
+        txn.upsert_setup_staging_changes(
+            "flow1",
+            &type_id,
+            vec![StateChange::Upsert(json!({ "v": 1 }))],
+            WriteAction::Insert,
+        )
+        .await
+        .unwrap();
+        txn.commit().await.unwrap();
+
+        let mut txn = store.begin().await.unwrap();
+        let records = txn.read_setup_metadata_for_flow("flow1").await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].resource_type, "Flow");
+        assert_eq!(records[0].key, json!("flow1"));
+        assert_eq!(records[0].state, None);
+        assert_eq!(
+            records[0].staging_changes.0,
+            vec![StateChange::Upsert(json!({ "v": 1 }))]
+        );
+
+        // Applying the state clears staging changes.
+        txn.upsert_setup_state("flow1", &type_id, json!({ "v": 1 }), WriteAction::Update)
+            .await
+            .unwrap();
+        txn.commit().await.unwrap();
+        let mut txn = store.begin().await.unwrap();
+        assert_eq!(
+            txn.read_setup_state("flow1", &type_id).await.unwrap(),
+            Some(json!({ "v": 1 }))
+        );
+        let records = txn.read_setup_metadata_for_flow("flow1").await.unwrap();
+        assert!(records[0].staging_changes.0.is_empty());
+        assert!(txn
+            .read_setup_metadata_for_flow("flow2")
+            .await
+            .unwrap()
+            .is_empty());
+
+        txn.delete_setup_state("flow1", &type_id).await.unwrap();
+        txn.commit().await.unwrap();
+        assert!(store
+            .read_setup_metadata()
+            .await
+            .unwrap()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn transactions_from_other_connections_wait() {
+        let (temp_store, db_setup) = TempStore::with_tracking_table("txn_wait").await;
+        let other_store = Arc::new(
+            SqliteStateStore::connect(&format!("sqlite:{}", temp_store.path.display()))
+                .await
+                .unwrap(),
+        );
+        let key = json!("key1");
+
+        let mut txn = temp_store.store.begin().await.unwrap();
+        txn.lock_source_tracking_info(SOURCE_ID, &key, &db_setup)
+            .await
+            .unwrap();
+        let other_txn = tokio::spawn({
+            let other_store = other_store.clone();
+            async move { other_store.begin().await.map(|_| ()) }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(!other_txn.is_finished());
+
+        txn.precommit_source_tracking_info(
+            SOURCE_ID,
+            &key,
+            1,
+            target_keys(1),
+            None,
+            &db_setup,
+            WriteAction::Insert,
+        )
+        .await
+        .unwrap();
+        txn.commit().await.unwrap();
+        other_txn.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn source_key_leases() {
+        let (temp_store, db_setup) = TempStore::with_tracking_table("leases").await;
+        let store = &temp_store.store;
+        let key = json!(["k", 1]);
+        let ttl = std::time::Duration::from_secs(60);
+        let lease = |worker_id: &'static str, ttl| {
+            let key = &key;
+            let db_setup = &db_setup;
+            async move {
+                store
+                    .try_lease_source_key(SOURCE_ID, key, worker_id, ttl, db_setup)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        assert!(lease("w1", ttl).await);
+        assert!(!lease("w2", ttl).await);
+        // Renewed by the same worker.
+        assert!(lease("w1", ttl).await);
+
+        // Only released by the holder.
+        store
+            .release_source_key_lease(SOURCE_ID, &key, "w2", &db_setup)
+            .await
+            .unwrap();
+        assert!(!lease("w2", ttl).await);
+        store
+            .release_source_key_lease(SOURCE_ID, &key, "w1", &db_setup)
+            .await
+            .unwrap();
+        assert!(lease("w2", std::time::Duration::ZERO).await);
+
+        // Taken over once expired.
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        assert!(lease("w1", ttl).await);
+    }
+
+    #[tokio::test]
+    async fn shared_cache_entries() {
+        let (temp_store, db_setup) = TempStore::with_tracking_table("shared_cache").await;
+        let store = &temp_store.store;
+        let referenced = Fingerprint([1; 16]).to_base64();
+        let unreferenced = Fingerprint([2; 16]).to_base64();
+        for fingerprint in [&referenced, &unreferenced] {
+            store
+                .upsert_shared_cache_entry(fingerprint, &json!({ "v": 1 }), 100, &db_setup)
+                .await
+                .unwrap();
+        }
+        store
+            .upsert_shared_cache_entry(&referenced, &json!({ "v": 2 }), 150, &db_setup)
+            .await
+            .unwrap();
+        let entry = store
+            .read_shared_cache_entry(&referenced, &db_setup)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((entry.value, entry.time_micros), (json!({ "v": 2 }), 150));
+
+        let memoization_info = StoredMemoizationInfo {
+            shared_cache: vec![Fingerprint([1; 16])],
+            ..Default::default()
+        };
+        let mut txn = store.begin().await.unwrap();
+        txn.precommit_source_tracking_info(
+            SOURCE_ID,
+            &json!("key1"),
+            1,
+            vec![],
+            Some(&memoization_info),
+            &db_setup,
+            WriteAction::Insert,
+        )
+        .await
+        .unwrap();
+        txn.commit().await.unwrap();
+
+        // Entries created after the cutoff are kept even if unreferenced.
+        assert_eq!(
+            store
+                .delete_unreferenced_shared_cache_entries(100, &db_setup)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            store
+                .delete_unreferenced_shared_cache_entries(200, &db_setup)
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(store
+            .read_shared_cache_entry(&referenced, &db_setup)
+            .await
+            .unwrap()
+            .is_some());
+        assert!(store
+            .read_shared_cache_entry(&unreferenced, &db_setup)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -1,5 +1,7 @@
 pub mod db;
 pub mod fingerprint;
 pub mod immutable;
+pub mod rate_limiter;
 pub mod retriable;
+pub mod telemetry;
 pub mod yaml_ser;
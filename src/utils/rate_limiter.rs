@@ -0,0 +1,69 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+struct BucketState {
+    available: f64,
+    last_refill: Instant,
+}
+
+/// A token bucket limiting the amount consumed per period, e.g. requests or tokens per minute.
+///
+/// Waiters are served in FIFO order, so large acquisitions are not starved by small ones.
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_sec: f64,
+    state: Mutex<BucketState>,
+    /// Amount consumed by `debit()` and not yet deducted from the bucket.
+    debt: AtomicU64,
+}
+
+impl RateLimiter {
+    pub fn new(amount_per_period: u64, period: Duration) -> Self {
+        let capacity = amount_per_period.max(1) as f64;
+        Self {
+            capacity,
+            refill_per_sec: capacity / period.as_secs_f64(),
+            state: Mutex::new(BucketState {
+                available: capacity,
+                last_refill: Instant::now(),
+            }),
+            debt: AtomicU64::new(0),
+        }
+    }
+
+    pub fn per_second(amount: u64) -> Self {
+        Self::new(amount, Duration::from_secs(1))
+    }
+
+    pub fn per_minute(amount: u64) -> Self {
+        Self::new(amount, Duration::from_secs(60))
+    }
+
+    /// Wait until `amount` is available and consume it.
+    /// Amounts larger than the capacity are capped to it, so they can still go through.
+    pub async fn acquire(&self, amount: u64) {
+        let amount = (amount as f64).min(self.capacity);
+        let mut state = self.state.lock().await;
+        loop {
+            let now = Instant::now();
+            let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+            state.available = (state.available + elapsed * self.refill_per_sec).min(self.capacity)
+                - self.debt.swap(0, Ordering::Relaxed) as f64;
+            state.last_refill = now;
+            if state.available >= amount {
+                state.available -= amount;
+                return;
+            }
+            let wait = (amount - state.available) / self.refill_per_sec;
+            tokio::time::sleep(Duration::from_secs_f64(wait)).await;
+        }
+    }
+
+    /// Consume `amount` without waiting, e.g. for usage only known after the fact.
+    /// The bucket may go negative, so the cost is paid by later acquisitions.
+    pub fn debit(&self, amount: u64) {
+        self.debt.fetch_add(amount, Ordering::Relaxed);
+    }
+}
@@ -3,11 +3,44 @@ use std::{future::Future, time::Duration};
 
 pub trait IsRetryable {
     fn is_retryable(&self) -> bool;
+
+    /// Minimum delay before the next retry, when requested by the remote side (e.g. `Retry-After`).
+    fn retry_after(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl IsRetryable for reqwest::Error {
+    fn is_retryable(&self) -> bool {
+        self.is_timeout()
+            || self.is_connect()
+            || self.status().is_some_and(|status| {
+                status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            })
+    }
+}
+
+impl IsRetryable for sqlx::Error {
+    fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::WorkerCrashed
+        )
+    }
+}
+
+/// Whether there's an error of type `E` in the error chain that's retryable.
+pub fn is_retryable_in_chain<E: IsRetryable + std::error::Error + 'static>(
+    err: &anyhow::Error,
+) -> bool {
+    err.chain()
+        .any(|e| e.downcast_ref::<E>().is_some_and(|e| e.is_retryable()))
 }
 
 pub struct Error {
     error: anyhow::Error,
     is_retryable: bool,
+    retry_after: Option<Duration>,
 }
 
 impl std::fmt::Display for Error {
@@ -26,6 +59,20 @@ impl IsRetryable for Error {
     fn is_retryable(&self) -> bool {
         self.is_retryable
     }
+
+    fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+}
+
+impl Error {
+    pub fn new(error: anyhow::Error, is_retryable: bool) -> Self {
+        Self {
+            error,
+            is_retryable,
+            retry_after: None,
+        }
+    }
 }
 
 impl From<anyhow::Error> for Error {
@@ -33,6 +80,7 @@ impl From<anyhow::Error> for Error {
         Self {
             error,
             is_retryable: false,
+            retry_after: None,
         }
     }
 }
@@ -47,6 +95,7 @@ impl<E: IsRetryable + std::error::Error + Send + Sync + 'static> From<E> for Err
     fn from(error: E) -> Self {
         Self {
             is_retryable: error.is_retryable(),
+            retry_after: error.retry_after(),
             error: anyhow::Error::new(error),
         }
     }
@@ -95,13 +144,17 @@ pub async fn run<
                     return Result::Err(err);
                 }
                 retries += 1;
+                let delay = match err.retry_after() {
+                    Some(retry_after) => std::cmp::max(backoff, retry_after),
+                    None => backoff,
+                };
                 trace!(
                     "Will retry #{} in {}ms for error: {}",
                     retries,
-                    backoff.as_millis(),
+                    delay.as_millis(),
                     err
                 );
-                tokio::time::sleep(backoff).await;
+                tokio::time::sleep(delay).await;
                 if backoff < options.max_backoff {
                     backoff = std::cmp::min(
                         Duration::from_micros(
@@ -0,0 +1,90 @@
+use crate::prelude::*;
+
+use crate::settings::TracingSettings;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing_subscriber::{
+    filter, fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt, Layer,
+};
+
+const DEFAULT_SERVICE_NAME: &str = "cocoindex";
+
+static TRACER_PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// Only spans of the engine are exported, e.g. not those of the Tokio runtime.
+fn exported_spans_filter() -> filter::Targets {
+    filter::Targets::new().with_target(env!("CARGO_CRATE_NAME"), tracing::Level::INFO)
+}
+
+/// Install the global tracing subscriber, which also exports spans to destinations configured by the settings.
+pub fn init_tracing(settings: Option<&TracingSettings>) -> Result<()> {
+    let log_layer = tracing_subscriber::fmt::layer().with_filter(
+        filter::EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| filter::EnvFilter::new("error")),
+    );
+
+    let otlp_layer = match settings.and_then(|settings| settings.otlp_endpoint.as_ref()) {
+        Some(endpoint) => {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_http()
+                .with_endpoint(endpoint)
+                .build()?;
+            let service_name = settings
+                .and_then(|settings| settings.service_name.clone())
+                .unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_string());
+            let provider = SdkTracerProvider::builder()
+                .with_batch_exporter(exporter)
+                .with_resource(
+                    opentelemetry_sdk::Resource::builder()
+                        .with_service_name(service_name)
+                        .build(),
+                )
+                .build();
+            let tracer = provider.tracer(DEFAULT_SERVICE_NAME);
+            opentelemetry::global::set_tracer_provider(provider.clone());
+            let _ = TRACER_PROVIDER.set(provider);
+            Some(
+                tracing_opentelemetry::layer()
+                    .with_tracer(tracer)
+                    .with_filter(exported_spans_filter()),
+            )
+        }
+        None => None,
+    };
+
+    let file_layer = match settings.and_then(|settings| settings.file_path.as_ref()) {
+        Some(file_path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file_path)
+                .with_context(|| format!("Failed to open tracing file: {file_path}"))?;
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .with_span_events(FmtSpan::CLOSE)
+                    .with_writer(Mutex::new(file))
+                    .with_filter(exported_spans_filter()),
+            )
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(console_subscriber::spawn())
+        .with(log_layer)
+        .with(otlp_layer)
+        .with(file_layer)
+        .try_init()?;
+    Ok(())
+}
+
+/// Export spans not exported yet. No-op if no OTLP exporter is configured.
+pub fn flush_tracing() {
+    if let Some(provider) = TRACER_PROVIDER.get() {
+        if let Err(e) = provider.force_flush() {
+            warn!("Failed to flush tracing spans: {e}");
+        }
+    }
+}